@@ -17,7 +17,7 @@ fn main() -> std::io::Result<()> {
         ("BOOTX64.EFI", loader.as_path()),
         ("KERNEL.EFI", kernel.as_path()),
     ];
-    let sectors = create_fat_image(&fat_img, &files, 0)?;
+    let sectors = create_fat_image(&fat_img, &files, 0, None, None, None)?;
     println!(
         "Created FAT image at {:?} ({} sectors, {} bytes)",
         fat_img,
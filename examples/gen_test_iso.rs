@@ -27,10 +27,11 @@ fn main() -> io::Result<()> {
             bios_boot: None,
             uefi_boot: Some(isobemak::UefiBootInfo {
                 boot_image: boot,
-                kernel_image: kern,
+                kernel_image: Some(kern),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
-                additional_efi_boot_files: vec![],
-                grub_cfg_content: None,
+                // The loader above is a placeholder, not a PE image.
+                skip_pe_validation: true,
+                ..Default::default()
             }),
         },
         layout_profile: isobemak::IsoLayoutProfile::hardware(),
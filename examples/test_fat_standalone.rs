@@ -10,6 +10,9 @@ fn main() -> std::io::Result<()> {
         &img,
         &[("BOOTX64.EFI", l.as_path()), ("KERNEL.EFI", k.as_path())],
         0,
+        None,
+        None,
+        None,
     )?;
     // Read back immediately without re-creating
     let mut f = std::fs::File::open(&img)?;
@@ -22,7 +25,7 @@ fn main() -> std::io::Result<()> {
     println!("Root dir: {:02x?}", buf);
     // Also dump via shell for certainty
     std::process::Command::new("hexdump")
-        .args(&[
+        .args([
             "-C",
             "-s",
             &format!("{}", cluster2),
@@ -15,6 +15,9 @@ fn main() -> std::io::Result<()> {
         &img,
         &[("BOOTX64.EFI", l.as_path()), ("KERNEL.EFI", k.as_path())],
         0,
+        None,
+        None,
+        None,
     )?;
 
     println!("Image: {img_s}");
@@ -32,13 +35,13 @@ fn main() -> std::io::Result<()> {
 
     println!("=== Root dir (cluster 2) ===");
     let _ = Command::new("hexdump")
-        .args(&["-C", "-s", &format!("{}", data_start * 512), "-n", "160"])
+        .args(["-C", "-s", &format!("{}", data_start * 512), "-n", "160"])
         .arg(img_s)
         .status()?;
 
     println!("=== EFI dir (cluster 3) ===");
     let _ = Command::new("hexdump")
-        .args(&[
+        .args([
             "-C",
             "-s",
             &format!("{}", (data_start + 8) * 512), // cluster 3 = data_start + 8 sectors
@@ -50,7 +53,7 @@ fn main() -> std::io::Result<()> {
 
     println!("=== BOOT dir (cluster 4) ===");
     let _ = Command::new("hexdump")
-        .args(&[
+        .args([
             "-C",
             "-s",
             &format!("{}", (data_start + 16) * 512),
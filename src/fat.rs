@@ -5,17 +5,71 @@
 use std::{
     fs::File,
     io::{self, Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
+use tempfile::NamedTempFile;
 
 const SECTOR: u64 = 512;
-const CLUSTER: u64 = 4096;
-const SEC_PER_CLUS: u64 = 8;
+
+/// Cluster size in bytes used when the caller does not choose one.
+pub const DEFAULT_CLUSTER_SIZE: u16 = 4096;
+
+/// Volume serial number written to the BPB when the caller does not supply
+/// one, so that identical inputs produce byte-identical images.
+pub const DEFAULT_VOLUME_ID: u32 = 0x1234_5678;
+
+/// File names UEFI firmware looks for in `EFI/BOOT/` on removable media
+/// (UEFI spec § 3.5.1.1), one per architecture.
+pub const REMOVABLE_MEDIA_BOOT_FILES: &[&str] = &[
+    "BOOTX64.EFI",
+    "BOOTIA32.EFI",
+    "BOOTAA64.EFI",
+    "BOOTARM.EFI",
+    "BOOTRISCV64.EFI",
+    "BOOTLOONGARCH64.EFI",
+];
+
+/// Normalises `path` (leading `/` dropped, `\` accepted as a separator) and
+/// checks that it is a removable-media default boot path such as
+/// `EFI/BOOT/BOOTX64.EFI`, compared case-insensitively.
+pub fn removable_media_boot_path(path: &str) -> io::Result<String> {
+    let norm = path.replace('\\', "/").trim_start_matches('/').to_string();
+    let upper = norm.to_ascii_uppercase();
+    let ok = upper
+        .strip_prefix("EFI/BOOT/")
+        .is_some_and(|name| REMOVABLE_MEDIA_BOOT_FILES.contains(&name));
+    if !ok {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "UEFI boot path '{path}' is not a removable-media default \
+                 (EFI/BOOT/{{{}}}); firmware would not find it in the ESP",
+                REMOVABLE_MEDIA_BOOT_FILES.join(",")
+            ),
+        ));
+    }
+    Ok(norm)
+}
+
+/// Validates a requested cluster size (`None` selects
+/// [`DEFAULT_CLUSTER_SIZE`]) and returns it in 512-byte sectors.
+fn sectors_per_cluster(cluster_size: Option<u16>) -> io::Result<u64> {
+    let size = cluster_size.unwrap_or(DEFAULT_CLUSTER_SIZE);
+    if size < 512 || !size.is_power_of_two() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("FAT cluster size {size} is not a power of two between 512 and 32768"),
+        ));
+    }
+    Ok(size as u64 / SECTOR)
+}
 
 // ── FAT type selection ──────────────────────────────────────────────────────
 
+/// FAT variant of a generated image.  Chosen automatically from the
+/// payload size unless the caller forces one (see [`create_fat_image`]).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum FatType {
+pub enum FatType {
     Fat12,
     Fat16,
     Fat32,
@@ -179,13 +233,15 @@ struct Alloc {
     fat_type: FatType,
     /// Pre-computed sectors-per-FAT, taken from the layout solver.
     sectors_per_fat: u64,
+    /// Sectors per cluster.
+    spc: u64,
 }
 
 impl Alloc {
-    fn new(total_sectors: u64, sectors_per_fat: u64, fat_type: FatType) -> Self {
+    fn new(total_sectors: u64, sectors_per_fat: u64, fat_type: FatType, spc: u64) -> Self {
         let root_sectors = fat_type.root_dir_sectors();
         let data_start = fat_type.reserved_sectors() + 2 * sectors_per_fat + root_sectors;
-        let clusters = ((total_sectors - data_start) / SEC_PER_CLUS) as usize;
+        let clusters = ((total_sectors - data_start) / spc) as usize;
         let mut fat = vec![0u32; clusters + 2];
         fat[0] = fat_type.eoc_marker();
         fat[1] = fat_type.eoc_chain_end();
@@ -195,6 +251,7 @@ impl Alloc {
             data_start,
             fat_type,
             sectors_per_fat,
+            spc,
         }
     }
 
@@ -227,7 +284,11 @@ impl Alloc {
     }
 
     fn sector_of(&self, cluster: u32) -> u64 {
-        self.data_start + (cluster as u64 - 2) * SEC_PER_CLUS
+        self.data_start + (cluster as u64 - 2) * self.spc
+    }
+
+    fn cluster_bytes(&self) -> u64 {
+        self.spc * SECTOR
     }
 
     /// Number of sectors occupied by the root directory (0 for FAT32).
@@ -298,10 +359,12 @@ fn vol_entry(label: &[u8; 11]) -> [u8; 32] {
 
 // ── BPB / FSInfo writers ────────────────────────────────────────────────────
 
+#[allow(clippy::too_many_arguments)]
 fn write_bpb(
     img: &mut [u8],
     off: u64,
     fat_type: FatType,
+    spc: u64,
     total_sectors: u32,
     fat_sectors: u32,
     hidden: u32,
@@ -313,15 +376,15 @@ fn write_bpb(
     b[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
     b[3..11].copy_from_slice(b"MSWIN4.1");
     b[11..13].copy_from_slice(&512u16.to_le_bytes()); // bytes per sector
-    b[13] = SEC_PER_CLUS as u8; // sectors per cluster
+    b[13] = spc as u8; // sectors per cluster
     b[14..16].copy_from_slice(&(fat_type.reserved_sectors() as u16).to_le_bytes());
     b[16] = 2; // number of FATs
 
     // Root directory entries — 0 for FAT32, non-zero for FAT12/16
     b[17..19].copy_from_slice(&root_dir_entries.to_le_bytes());
 
-    // Total sectors (u16 field) — 0 if >= 65536
-    let total16 = if total_sectors < 65536 {
+    // Total sectors (u16 field) — 0 if >= 65536, and always 0 for FAT32
+    let total16 = if total_sectors < 65536 && fat_type != FatType::Fat32 {
         total_sectors as u16
     } else {
         0
@@ -475,6 +538,190 @@ fn calc_layout(
     (fat_sectors, data)
 }
 
+// ── Directory tree ──────────────────────────────────────────────────────────
+
+/// A directory being laid out in the FAT image.  Children keep insertion
+/// order so the on-disk layout follows the caller's file list.
+#[derive(Default)]
+struct FatDir {
+    children: Vec<(String, FatNode)>,
+    /// First cluster (unused for the FAT12/16 fixed root region).
+    cluster: u32,
+    /// Number of clusters reserved for this directory's entries.
+    clusters: u32,
+}
+
+enum FatNode {
+    Dir(FatDir),
+    /// Index into the caller's file list.
+    File(usize),
+}
+
+impl FatDir {
+    fn insert(&mut self, dest: &str, idx: usize) -> io::Result<()> {
+        let parts: Vec<&str> = dest.split(['/', '\\']).filter(|c| !c.is_empty()).collect();
+        let Some((file, dirs)) = parts.split_last() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("empty FAT destination path {dest:?}"),
+            ));
+        };
+        let mut cur = self;
+        for d in dirs {
+            let pos = match cur
+                .children
+                .iter()
+                .position(|(n, _)| n.eq_ignore_ascii_case(d))
+            {
+                Some(p) => p,
+                None => {
+                    cur.children
+                        .push((d.to_string(), FatNode::Dir(FatDir::default())));
+                    cur.children.len() - 1
+                }
+            };
+            cur = match &mut cur.children[pos].1 {
+                FatNode::Dir(sub) => sub,
+                FatNode::File(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("FAT path component {d:?} in {dest:?} is a file"),
+                    ));
+                }
+            };
+        }
+        if cur
+            .children
+            .iter()
+            .any(|(n, _)| n.eq_ignore_ascii_case(file))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("duplicate FAT destination path {dest:?}"),
+            ));
+        }
+        cur.children.push((file.to_string(), FatNode::File(idx)));
+        Ok(())
+    }
+
+    /// Recursively sizes every subdirectory and returns the total number of
+    /// clusters of `cluster` bytes they need (the root itself is not
+    /// included).
+    fn size_subdirs(&mut self, cluster: u64) -> u64 {
+        let mut total = 0;
+        for (_, node) in self.children.iter_mut() {
+            if let FatNode::Dir(sub) = node {
+                let bytes = 64 + encode_children(sub, &[], &[]).len() as u64;
+                sub.clusters = bytes.div_ceil(cluster).max(1) as u32;
+                total += sub.clusters as u64 + sub.size_subdirs(cluster);
+            }
+        }
+        total
+    }
+
+    /// Allocates subdirectory clusters in pre-order.
+    fn alloc_subdirs(&mut self, alloc: &mut Alloc) -> io::Result<()> {
+        for (name, node) in self.children.iter_mut() {
+            if let FatNode::Dir(sub) = node {
+                sub.cluster = alloc.alloc(sub.clusters).ok_or_else(|| {
+                    io::Error::other(format!("FAT: out of free clusters for directory {name}"))
+                })?;
+                sub.alloc_subdirs(alloc)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Derives a unique 8.3 short name for `name`, appending a `~N` tail on
+/// collision with a name already in `used`.
+fn short_name(name: &str, used: &mut Vec<[u8; 11]>) -> [u8; 11] {
+    let upper = name.to_uppercase();
+    let (stem, ext) = upper.rsplit_once('.').unwrap_or((upper.as_str(), ""));
+    let clean = |s: &str| -> Vec<u8> {
+        s.bytes()
+            .map(|b| {
+                if b.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&b) {
+                    b
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    };
+    let mut stem = clean(stem);
+    if stem.is_empty() {
+        stem.push(b'_');
+    }
+    let ext = clean(ext);
+    let mut short = pack_83(&stem, &ext);
+    let mut n = 1;
+    while used.contains(&short) {
+        let tail = format!("~{n}");
+        let keep = (8 - tail.len()).min(stem.len());
+        let mut s = stem[..keep].to_vec();
+        s.extend_from_slice(tail.as_bytes());
+        short = pack_83(&s, &ext);
+        n += 1;
+    }
+    used.push(short);
+    short
+}
+
+/// Encodes the directory entries (LFN + 8.3) for `dir`'s children.  With
+/// empty `file_starts`/`file_sizes` the result only serves for sizing.
+fn encode_children(dir: &FatDir, file_starts: &[u32], file_sizes: &[u64]) -> Vec<u8> {
+    let mut used = Vec::new();
+    let mut out = Vec::new();
+    for (name, node) in &dir.children {
+        let (attr, clus, size) = match node {
+            FatNode::Dir(sub) => (0x10, sub.cluster, 0),
+            FatNode::File(i) => (
+                0x20,
+                file_starts.get(*i).copied().unwrap_or(0),
+                file_sizes.get(*i).copied().unwrap_or(0) as u32,
+            ),
+        };
+        let short = short_name(name, &mut used);
+        if let Some((lfn, sfn)) = make_lfn(name, &short, attr, clus, size) {
+            out.extend_from_slice(&lfn);
+            out.extend_from_slice(&sfn);
+        } else {
+            out.extend_from_slice(&entry_83(&short, attr, clus, size));
+        }
+    }
+    out
+}
+
+/// Writes `bytes` into the cluster chain starting at `first`.
+fn write_chain(img: &mut [u8], alloc: &Alloc, first: u32, bytes: &[u8]) {
+    let mut cur = first;
+    for chunk in bytes.chunks(alloc.cluster_bytes() as usize) {
+        let off = (alloc.sector_of(cur) * SECTOR) as usize;
+        img[off..off + chunk.len()].copy_from_slice(chunk);
+        cur = alloc.fat[cur as usize];
+    }
+}
+
+/// Writes `dir`'s subdirectories (dot entries + children) recursively.
+fn write_subdirs(
+    img: &mut [u8],
+    alloc: &Alloc,
+    dir: &FatDir,
+    dir_cluster: u32,
+    file_starts: &[u32],
+    file_sizes: &[u64],
+) {
+    for (_, node) in &dir.children {
+        if let FatNode::Dir(sub) = node {
+            let mut bytes = dot_entries(sub.cluster, dir_cluster).to_vec();
+            bytes.extend_from_slice(&encode_children(sub, file_starts, file_sizes));
+            write_chain(img, alloc, sub.cluster, &bytes);
+            write_subdirs(img, alloc, sub, sub.cluster, file_starts, file_sizes);
+        }
+    }
+}
+
 // ── Image builder ───────────────────────────────────────────────────────────
 //
 // Strategy (memory-efficient):
@@ -485,7 +732,19 @@ fn calc_layout(
 //   5. Write BPB last (so no back-patching needed).
 //   6. Return the buffer (already exactly sized).
 
-fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32)> {
+/// Builds a FAT image holding `files`, given as (destination path inside the
+/// FAT, source path).  The image is at least `min_sectors` 512-byte sectors
+/// and uses clusters of `spc` sectors.  `fat_type` forces the FAT variant
+/// instead of picking the smallest one that fits.
+fn build_image(
+    files: &[(String, &Path)],
+    hidden: u32,
+    min_sectors: u64,
+    serial: u32,
+    spc: u64,
+    fat_type: Option<FatType>,
+) -> io::Result<(Vec<u8>, u32)> {
+    let cluster = spc * SECTOR;
     if files.is_empty() {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -493,6 +752,11 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
         ));
     }
 
+    let mut root_dir = FatDir::default();
+    for (idx, (dest, _)) in files.iter().enumerate() {
+        root_dir.insert(dest, idx)?;
+    }
+
     // ── 1. Determine FAT type ──────────────────────────────────────────
     let mut content_size = 0u64;
     for (_, p) in files {
@@ -503,18 +767,21 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
     }
 
     // Compute the exact number of clusters needed for the payload.
-    let needed_data_clusters = content_size.div_ceil(CLUSTER).max(1);
-    // Directory clusters: root (FAT32 only), EFI, BOOT, plus 2 extra for
-    // the volume entry + dot entries in the root if using FAT12/16.
-    let dir_clusters = 3 + 2; // generous over-count
+    let needed_data_clusters = content_size.div_ceil(cluster).max(1);
+    // Directory clusters: root (FAT32 only) plus every subdirectory, with
+    // 2 extra for the volume entry + dot entries in the root if using
+    // FAT12/16.
+    let root_entries_len = 32 + encode_children(&root_dir, &[], &[]).len() as u64;
+    let root_clusters = root_entries_len.div_ceil(cluster).max(1);
+    let dir_clusters = (root_clusters + root_dir.size_subdirs(cluster)).max(3) + 2; // generous over-count
     // Total data clusters including directory overhead.
     let min_data_clusters = needed_data_clusters + dir_clusters;
 
     // Directly compute the required sector count (worst‑case FAT32
     // overhead) and then verify with calc_layout, increasing by 10 %
     // if the first‑pass estimate is insufficient.
-    let data_sectors_est = min_data_clusters * SEC_PER_CLUS;
-    let fat_entries = data_sectors_est.div_ceil(SEC_PER_CLUS) + 2;
+    let data_sectors_est = min_data_clusters * spc;
+    let fat_entries = data_sectors_est.div_ceil(spc) + 2;
     let fat_bytes = fat_entries * (FatType::Fat32.entry_bits() / 8); // bytes per FAT
     let fat_sectors_est = fat_bytes.div_ceil(SECTOR);
     let mut total_est = FatType::Fat32.reserved_sectors() + 2 * fat_sectors_est + data_sectors_est;
@@ -522,14 +789,9 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
 
     let reserved32 = FatType::Fat32.reserved_sectors();
     loop {
-        let (_fat_sectors, data_sectors) = calc_layout(
-            total_est,
-            reserved32,
-            SEC_PER_CLUS,
-            0,
-            FatType::Fat32.entry_bits(),
-        );
-        let data_clusters = data_sectors / SEC_PER_CLUS;
+        let (_fat_sectors, data_sectors) =
+            calc_layout(total_est, reserved32, spc, 0, FatType::Fat32.entry_bits());
+        let data_clusters = data_sectors / spc;
         if data_clusters >= min_data_clusters {
             break;
         }
@@ -543,9 +805,19 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
     // alignment and the FAT type selection may produce slightly fewer
     // data clusters than the FAT32‑only estimation computed.
     let estimated_sectors = estimated_sectors.saturating_add(estimated_sectors / 10);
+    // Honour the caller's minimum; one extra cluster absorbs the rounding
+    // down to a whole cluster in the layout pass below.
+    let estimated_sectors = if min_sectors > 0 {
+        estimated_sectors.max(min_sectors + spc)
+    } else {
+        estimated_sectors
+    };
 
     // Pick the first candidate FAT type, then refine with a layout pass.
-    let candidates = [FatType::Fat12, FatType::Fat16, FatType::Fat32];
+    let candidates = match fat_type {
+        Some(ft) => vec![ft],
+        None => vec![FatType::Fat12, FatType::Fat16, FatType::Fat32],
+    };
     let mut chosen_type = FatType::Fat32; // fallback
     let mut chosen_total: u32 = 0;
     let mut chosen_fat_sectors: u32 = 0;
@@ -554,19 +826,13 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
         let reserved = ft.reserved_sectors();
         let rds = ft.root_dir_sectors();
         // Try the current estimate; if the clusters don't fit then try FAT32.
-        let (fs, ds) = calc_layout(
-            estimated_sectors,
-            reserved,
-            SEC_PER_CLUS,
-            rds,
-            ft.entry_bits(),
-        );
-        let data_aligned = (ds / SEC_PER_CLUS) * SEC_PER_CLUS;
+        let (fs, ds) = calc_layout(estimated_sectors, reserved, spc, rds, ft.entry_bits());
+        let data_aligned = (ds / spc) * spc;
         let total = match u32::try_from(reserved + 2 * fs + rds + data_aligned) {
             Ok(t) => t,
             Err(_) => continue,
         };
-        let clusters = data_aligned / SEC_PER_CLUS;
+        let clusters = data_aligned / spc;
 
         // FAT12 must fit in 65535 sectors (u16 BPB_TotSec16).
         // FAT16 can use the 32-bit sector count for larger volumes.
@@ -590,11 +856,21 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
         }
     }
 
+    if let Some(ft) = fat_type
+        && chosen_total == 0
+        && ft != FatType::Fat32
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("FAT: {estimated_sectors} sectors of content do not fit in {ft:?}"),
+        ));
+    }
+
     // If we still need FAT32, compute final layout with FAT32 parameters.
     if chosen_type == FatType::Fat32 && chosen_total == 0 {
         let reserved = FatType::Fat32.reserved_sectors();
-        let (fs, ds) = calc_layout(estimated_sectors, reserved, SEC_PER_CLUS, 0, 32);
-        let data_aligned = (ds / SEC_PER_CLUS) * SEC_PER_CLUS;
+        let (fs, ds) = calc_layout(estimated_sectors, reserved, spc, 0, 32);
+        let data_aligned = (ds / spc) * spc;
         chosen_total = (reserved + 2 * fs + data_aligned) as u32;
         chosen_fat_sectors = fs as u32;
     }
@@ -602,28 +878,40 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
     let total_sectors = chosen_total;
 
     // ── 2. Allocate buffer ─────────────────────────────────────────────
-    let serial: u32 = rand::random();
     let vol_label = pack_83(b"EFI", b"");
     let mut img = vec![0u8; total_sectors as usize * SECTOR as usize];
 
     // ── 3. Set up allocator ────────────────────────────────────────────
-    let mut alloc = Alloc::new(total_sectors as u64, chosen_fat_sectors as u64, chosen_type);
-    let err = |what| io::Error::other(format!("FAT: out of free clusters for {what}"));
+    let mut alloc = Alloc::new(
+        total_sectors as u64,
+        chosen_fat_sectors as u64,
+        chosen_type,
+        spc,
+    );
+    if !chosen_type.root_is_cluster() && root_entries_len > alloc.root_dir_sectors() * SECTOR {
+        return Err(io::Error::other(format!(
+            "FAT: root directory ({root_entries_len} bytes) exceeds the fixed root region ({} entries)",
+            chosen_type.root_dir_entries()
+        )));
+    }
 
-    // Root directory: cluster for FAT32, fixed region for FAT12/16.
+    // Root directory: cluster chain for FAT32, fixed region for FAT12/16.
     let root = if chosen_type.root_is_cluster() {
-        Some(alloc.alloc(1).ok_or_else(|| err("root directory"))?)
+        Some(
+            alloc
+                .alloc(root_clusters as u32)
+                .ok_or_else(|| io::Error::other("FAT: out of free clusters for root directory"))?,
+        )
     } else {
         None
     };
-    let efi = alloc.alloc(1).ok_or_else(|| err("EFI directory"))?;
-    let boot = alloc.alloc(1).ok_or_else(|| err("BOOT directory"))?;
+    root_dir.alloc_subdirs(&mut alloc)?;
 
     let mut file_starts = Vec::with_capacity(files.len());
     let mut file_sizes = Vec::with_capacity(files.len());
     for (_name, p) in files {
         let sz = p.metadata()?.len();
-        let n = (sz.div_ceil(CLUSTER)).max(1) as u32;
+        let n = (sz.div_ceil(cluster)).max(1) as u32;
         let start = alloc.alloc(n).ok_or_else(|| {
             io::Error::other(format!("FAT: out of free clusters for file (need {n})"))
         })?;
@@ -635,85 +923,51 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
 
     // 4a. Root directory
     let root_parent = 0u32; // FAT12/16 convention: 0 = root
+    let mut root_bytes = vol_entry(&vol_label).to_vec();
+    root_bytes.extend_from_slice(&encode_children(&root_dir, &file_starts, &file_sizes));
     if let Some(root_clus) = root {
-        // FAT32: root is a normal cluster
-        let mut area = vec![0u8; CLUSTER as usize];
-        area[..32].copy_from_slice(&vol_entry(&vol_label));
-        area[32..64].copy_from_slice(&entry_83(&pack_83(b"EFI", b""), 0x10, efi, 0));
-        img[alloc.sector_of(root_clus) as usize * 512..][..CLUSTER as usize].copy_from_slice(&area);
+        // FAT32: root is a normal cluster chain
+        write_chain(&mut img, &alloc, root_clus, &root_bytes);
     } else {
         // FAT12/16: write directly to the fixed root directory region
         let root_start = (alloc.root_dir_start() * SECTOR) as usize;
-        let root_size = (alloc.root_dir_sectors() * SECTOR) as usize;
-        let mut area = vec![0u8; CLUSTER as usize]; // use only as much as needed
-        area[..32].copy_from_slice(&vol_entry(&vol_label));
-        area[32..64].copy_from_slice(&entry_83(&pack_83(b"EFI", b""), 0x10, efi, 0));
-        let copy_len = area.len().min(root_size);
-        img[root_start..root_start + copy_len].copy_from_slice(&area[..copy_len]);
+        img[root_start..root_start + root_bytes.len()].copy_from_slice(&root_bytes);
     }
 
-    // 4b. EFI directory: ".", "..", BOOT subdir
-    {
-        let efi_parent = root.unwrap_or(root_parent);
-        let mut area = vec![0u8; CLUSTER as usize];
-        area[..64].copy_from_slice(&dot_entries(efi, efi_parent));
-        area[64..96].copy_from_slice(&entry_83(&pack_83(b"BOOT", b""), 0x10, boot, 0));
-        img[alloc.sector_of(efi) as usize * 512..][..CLUSTER as usize].copy_from_slice(&area);
-    }
+    // 4b. Subdirectories: ".", "..", children
+    write_subdirs(
+        &mut img,
+        &alloc,
+        &root_dir,
+        root.unwrap_or(root_parent),
+        &file_starts,
+        &file_sizes,
+    );
 
-    // 4c. BOOT directory: ".", "..", file entries + file data
-    {
-        let mut dir = Vec::<u8>::new();
-        dir.extend_from_slice(&dot_entries(boot, efi));
-        for (idx, (dest_name, source_path)) in files.iter().enumerate() {
-            let file_size = file_sizes[idx] as u32;
-            let first_clus = file_starts[idx];
-
-            let upper = dest_name.to_uppercase();
-            let (stem, ext) = upper
-                .rsplit_once('.')
-                .map_or((upper.as_bytes(), b"".as_ref()), |(s, e)| {
-                    (s.as_bytes(), e.as_bytes())
-                });
-            let short = pack_83(stem, ext);
-
-            if let Some((lfn, sfn)) = make_lfn(dest_name, &short, 0x20, first_clus, file_size) {
-                dir.extend_from_slice(&lfn);
-                dir.extend_from_slice(&sfn);
-            } else {
-                dir.extend_from_slice(&entry_83(&short, 0x20, first_clus, file_size));
+    // 4c. File data
+    for (idx, (_dest, source_path)) in files.iter().enumerate() {
+        let file_size = file_sizes[idx] as u32;
+        let mut src = File::open(source_path)?;
+        let mut cur = file_starts[idx];
+        let mut remaining = file_size as u64;
+        while remaining > 0 {
+            let chunk = remaining.min(cluster) as usize;
+            let off = (alloc.sector_of(cur) * SECTOR) as usize;
+            src.read_exact(&mut img[off..off + chunk])?;
+            remaining = remaining.saturating_sub(chunk as u64);
+            if remaining == 0 {
+                break;
             }
-
-            let mut src = File::open(source_path)?;
-            let mut cur = first_clus;
-            let mut remaining = file_size as u64;
-            while remaining > 0 {
-                let chunk = remaining.min(CLUSTER) as usize;
-                let off = (alloc.sector_of(cur) * SECTOR) as usize;
-                src.read_exact(&mut img[off..off + chunk])?;
-                remaining = remaining.saturating_sub(chunk as u64);
-                if remaining == 0 {
-                    break;
-                }
-                let next = alloc.fat[cur as usize];
-                let eoc = chosen_type.eoc_chain_end();
-                if next == eoc {
-                    return Err(io::Error::new(
-                        io::ErrorKind::UnexpectedEof,
-                        "FAT cluster chain too short",
-                    ));
-                }
-                cur = next;
+            let next = alloc.fat[cur as usize];
+            let eoc = chosen_type.eoc_chain_end();
+            if next == eoc {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "FAT cluster chain too short",
+                ));
             }
+            cur = next;
         }
-        if dir.len() > CLUSTER as usize {
-            return Err(io::Error::other(format!(
-                "BOOT dir ({} bytes) exceeds cluster limit ({CLUSTER})",
-                dir.len()
-            )));
-        }
-        dir.resize(CLUSTER as usize, 0);
-        img[alloc.sector_of(boot) as usize * 512..][..CLUSTER as usize].copy_from_slice(&dir);
     }
 
     // ── 5. Write FAT tables ────────────────────────────────────────────
@@ -741,6 +995,7 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
         &mut img,
         0,
         chosen_type,
+        spc,
         total_sectors,
         chosen_fat_sectors,
         hidden,
@@ -754,6 +1009,7 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
             &mut img,
             6 * SECTOR,
             chosen_type,
+            spc,
             total_sectors,
             chosen_fat_sectors,
             hidden,
@@ -765,25 +1021,92 @@ fn build_image(files: &[(&str, &Path)], hidden: u32) -> io::Result<(Vec<u8>, u32
     Ok((img, total_sectors))
 }
 
+fn write_image(fat_img_path: &Path, img: &[u8]) -> io::Result<()> {
+    let mut file = File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(fat_img_path)?;
+    file.write_all(img)?;
+    file.sync_all()
+}
+
 // ── Public API ──────────────────────────────────────────────────────────────
 
+/// Creates a FAT image at `fat_img_path` with every `(name, source)` pair
+/// placed in `EFI/BOOT/`.  Returns the image size in 512-byte sectors.
+///
+/// `volume_id` sets the BPB volume serial number; `None` uses
+/// [`DEFAULT_VOLUME_ID`] so repeated builds are reproducible.
+/// `cluster_size` is in bytes, a power of two from 512 to 32768; `None`
+/// uses [`DEFAULT_CLUSTER_SIZE`].  `fat_type` forces FAT12, FAT16 or FAT32
+/// for firmware that only accepts one of them; `None` picks the smallest
+/// that fits.  A payload too large for a forced FAT12 or FAT16 fails with
+/// `InvalidInput`.  A forced FAT32 image under about 32 MiB has fewer
+/// clusters than the specification's FAT32 minimum: firmware and kernels
+/// that go by the BPB accept it, strict parsers may not.
 pub fn create_fat_image(
     fat_img_path: &Path,
     files: &[(&str, &Path)],
     hidden: u32,
+    volume_id: Option<u32>,
+    cluster_size: Option<u16>,
+    fat_type: Option<FatType>,
 ) -> io::Result<u32> {
-    let (img, total_sectors) = build_image(files, hidden)?;
-    let mut file = File::options()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(fat_img_path)?;
-    file.write_all(&img)?;
-    file.sync_all()?;
-    drop(file);
+    let spc = sectors_per_cluster(cluster_size)?;
+    let entries: Vec<(String, &Path)> = files
+        .iter()
+        .map(|(name, src)| (format!("EFI/BOOT/{name}"), *src))
+        .collect();
+    let serial = volume_id.unwrap_or(DEFAULT_VOLUME_ID);
+    let (img, total_sectors) = build_image(&entries, hidden, 0, serial, spc, fat_type)?;
+    write_image(fat_img_path, &img)?;
     Ok(total_sectors)
 }
 
+/// Builds a standalone FAT EFI System Partition image in a temporary file.
+///
+/// Each entry is `(source, destination)` where `destination` is the path
+/// inside the FAT filesystem (e.g. `EFI/BOOT/BOOTX64.EFI`); intermediate
+/// directories are created as needed.  The image is at least `min_size`
+/// bytes.  The returned [`NamedTempFile`] owns the image and deletes it on
+/// drop.
+pub fn build_esp(
+    entries: &[(PathBuf, String)],
+    min_size: u64,
+) -> io::Result<(PathBuf, NamedTempFile)> {
+    let (path, tf, _) = build_esp_with_hidden(entries, min_size, 0, None)?;
+    Ok((path, tf))
+}
+
+/// Like [`build_esp`], with an explicit BPB hidden-sector count and cluster
+/// size (see [`create_fat_image`]).  Also returns the image size in 512-byte
+/// sectors.
+pub(crate) fn build_esp_with_hidden(
+    entries: &[(PathBuf, String)],
+    min_size: u64,
+    hidden: u32,
+    cluster_size: Option<u16>,
+) -> io::Result<(PathBuf, NamedTempFile, u32)> {
+    let spc = sectors_per_cluster(cluster_size)?;
+    let files: Vec<(String, &Path)> = entries
+        .iter()
+        .map(|(src, dest)| (dest.clone(), src.as_path()))
+        .collect();
+    let (img, total_sectors) = build_image(
+        &files,
+        hidden,
+        min_size.div_ceil(SECTOR),
+        DEFAULT_VOLUME_ID,
+        spc,
+        None,
+    )?;
+    let tf = NamedTempFile::new()?;
+    let path = tf.path().to_path_buf();
+    write_image(&path, &img)?;
+    Ok((path, tf, total_sectors))
+}
+
 // ── Tests ───────────────────────────────────────────────────────────────────
 
 #[cfg(test)]
@@ -792,6 +1115,26 @@ mod tests {
     use std::io::Read;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_removable_media_boot_path() {
+        assert_eq!(
+            removable_media_boot_path("/efi/boot/bootaa64.efi").unwrap(),
+            "efi/boot/bootaa64.efi"
+        );
+        assert_eq!(
+            removable_media_boot_path("EFI\\BOOT\\BOOTX64.EFI").unwrap(),
+            "EFI/BOOT/BOOTX64.EFI"
+        );
+        for bad in [
+            "EFI/FEDORA/grubx64.efi",
+            "EFI/BOOT/GRUBX64.EFI",
+            "BOOTX64.EFI",
+        ] {
+            let err = removable_media_boot_path(bad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
     #[test]
     fn test_layout_fat32() {
         let (fat, data) = calc_layout(532480, 32, 8, 0, 32);
@@ -835,6 +1178,9 @@ mod tests {
             &img,
             &[("BOOTX64.EFI", l.as_path()), ("KERNEL.EFI", k.as_path())],
             0,
+            None,
+            None,
+            None,
         )?;
         // Should be small — well under 255 MiB (522240 sectors)
         assert!(
@@ -845,8 +1191,7 @@ mod tests {
 
         // Verify with fatfs
         let r = File::open(&img)?;
-        let fs = fatfs::FileSystem::new(r, fatfs::FsOptions::new())
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let fs = fatfs::FileSystem::new(r, fatfs::FsOptions::new()).map_err(io::Error::other)?;
         let root = fs.root_dir();
         let mut v = Vec::new();
         root.open_file("EFI/BOOT/BOOTX64.EFI")?
@@ -872,12 +1217,14 @@ mod tests {
             &img,
             &[("BOOTX64.EFI", l.as_path()), ("KERNEL.EFI", k.as_path())],
             0,
+            None,
+            None,
+            None,
         )?;
         assert!(sectors < 65536, "FAT16 must be under 65536 sectors");
         assert!(img.exists());
         let r = File::open(&img)?;
-        let fs = fatfs::FileSystem::new(r, fatfs::FsOptions::new())
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let fs = fatfs::FileSystem::new(r, fatfs::FsOptions::new()).map_err(io::Error::other)?;
         let mut v = Vec::new();
         fs.root_dir()
             .open_file("EFI/BOOT/BOOTX64.EFI")?
@@ -891,6 +1238,40 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_forced_fat_type() -> io::Result<()> {
+        let dir = tempdir()?;
+        let l = dir.path().join("l.efi");
+        // Auto-selection picks FAT16 for this payload.
+        std::fs::write(&l, vec![0x5A; 15 * 1024 * 1024])?;
+        let img = dir.path().join("f.img");
+        let files = [("BOOTX64.EFI", l.as_path())];
+        create_fat_image(&img, &files, 0, None, None, None)?;
+        assert_eq!(&std::fs::read(&img)?[54..62], b"FAT16   ");
+
+        let sectors = create_fat_image(&img, &files, 0, None, None, Some(FatType::Fat32))?;
+        assert!(
+            sectors <= 40 * 2048,
+            "{sectors} sectors for a ~16 MiB image"
+        );
+        let data = std::fs::read(&img)?;
+        assert_eq!(&data[82..90], b"FAT32   ");
+        // A FAT32 BPB: no 16-bit sector counts, root directory in cluster 2.
+        // (fatfs refuses to mount it: at this size the cluster count is
+        // below the FAT32 minimum, which firmware reading the BPB ignores.)
+        assert_eq!(u16::from_le_bytes([data[19], data[20]]), 0);
+        assert_eq!(u16::from_le_bytes([data[22], data[23]]), 0);
+        assert_eq!(
+            u32::from_le_bytes(data[32..36].try_into().unwrap()),
+            sectors
+        );
+        assert_eq!(u32::from_le_bytes(data[44..48].try_into().unwrap()), 2);
+
+        let err = create_fat_image(&img, &files, 0, None, None, Some(FatType::Fat12)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
     #[test]
     fn test_calc_layout_fat32_threshold() {
         // Verify the layout solver works for FAT32-sized parameter sets.
@@ -911,7 +1292,14 @@ mod tests {
         let l = dir.path().join("b.efi");
         std::fs::write(&l, b"BOOT")?;
         let img = dir.path().join("fh.img");
-        create_fat_image(&img, &[("BOOTX64.EFI", l.as_path())], 2048)?;
+        create_fat_image(
+            &img,
+            &[("BOOTX64.EFI", l.as_path())],
+            2048,
+            None,
+            None,
+            None,
+        )?;
         let mut bytes = Vec::new();
         File::open(&img)?.read_to_end(&mut bytes)?;
         assert_eq!(
@@ -919,7 +1307,7 @@ mod tests {
             2048
         );
         let fs = fatfs::FileSystem::new(File::open(&img)?, fatfs::FsOptions::new())
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
         let mut v = Vec::new();
         fs.root_dir()
             .open_file("EFI/BOOT/BOOTX64.EFI")?
@@ -928,6 +1316,127 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_fat_image_is_reproducible() -> io::Result<()> {
+        let dir = tempdir()?;
+        let l = dir.path().join("l.efi");
+        std::fs::write(&l, b"UEFI loader")?;
+        let a = dir.path().join("a.img");
+        let b = dir.path().join("b.img");
+        create_fat_image(&a, &[("BOOTX64.EFI", l.as_path())], 0, None, None, None)?;
+        create_fat_image(&b, &[("BOOTX64.EFI", l.as_path())], 0, None, None, None)?;
+        let (a, b) = (std::fs::read(&a)?, std::fs::read(&b)?);
+        assert_eq!(a, b, "FAT images differ between identical builds");
+        // FAT12/16 keep the volume serial at offset 39.
+        assert_eq!(&a[39..43], &DEFAULT_VOLUME_ID.to_le_bytes());
+
+        let c = dir.path().join("c.img");
+        create_fat_image(
+            &c,
+            &[("BOOTX64.EFI", l.as_path())],
+            0,
+            Some(0xCAFE_F00D),
+            None,
+            None,
+        )?;
+        assert_eq!(&std::fs::read(&c)?[39..43], &0xCAFE_F00Du32.to_le_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cluster_size() -> io::Result<()> {
+        let dir = tempdir()?;
+        let l = dir.path().join("l.efi");
+        std::fs::write(&l, vec![0xA5u8; 20_000])?;
+        for (size, spc) in [(4096u16, 8u8), (512, 1), (32768, 64)] {
+            let img = dir.path().join(format!("c{size}.img"));
+            create_fat_image(
+                &img,
+                &[("BOOTX64.EFI", l.as_path())],
+                0,
+                None,
+                Some(size),
+                None,
+            )?;
+            let bytes = std::fs::read(&img)?;
+            assert_eq!(bytes[13], spc, "sectors per cluster for {size}");
+            let fs = fatfs::FileSystem::new(File::open(&img)?, fatfs::FsOptions::new())
+                .map_err(io::Error::other)?;
+            assert_eq!(fs.cluster_size(), size as u32);
+            let mut v = Vec::new();
+            fs.root_dir()
+                .open_file("EFI/BOOT/BOOTX64.EFI")?
+                .read_to_end(&mut v)?;
+            assert_eq!(v, vec![0xA5u8; 20_000]);
+        }
+        for bad in [0u16, 256, 3000] {
+            let img = dir.path().join("bad.img");
+            let err = create_fat_image(
+                &img,
+                &[("BOOTX64.EFI", l.as_path())],
+                0,
+                None,
+                Some(bad),
+                None,
+            )
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_esp_custom_tree() -> io::Result<()> {
+        let dir = tempdir()?;
+        let loader = dir.path().join("grubx64.efi");
+        let cfg = dir.path().join("grub.cfg");
+        let big = dir.path().join("big.bin");
+        std::fs::write(&loader, b"grub loader")?;
+        std::fs::write(&cfg, b"set timeout=5")?;
+        std::fs::write(&big, vec![0x5Au8; 3 * DEFAULT_CLUSTER_SIZE as usize + 7])?;
+        let mut entries = vec![
+            (loader.clone(), "EFI/FEDORA/grubx64.efi".to_string()),
+            (cfg.clone(), "EFI/FEDORA/grub.cfg".to_string()),
+            (loader.clone(), "EFI/BOOT/BOOTX64.EFI".to_string()),
+            (big.clone(), "data/payload.bin".to_string()),
+        ];
+        // Enough long names to spill the directory over one cluster.
+        for i in 0..80 {
+            entries.push((cfg.clone(), format!("many/long-file-name-{i:03}.conf")));
+        }
+        let (path, _holder) = build_esp(&entries, 8 * 1024 * 1024)?;
+        assert!(std::fs::metadata(&path)?.len() >= 8 * 1024 * 1024);
+
+        let fs = fatfs::FileSystem::new(File::open(&path)?, fatfs::FsOptions::new())
+            .map_err(io::Error::other)?;
+        for (src, dest) in &entries {
+            let mut v = Vec::new();
+            fs.root_dir().open_file(dest)?.read_to_end(&mut v)?;
+            assert_eq!(v, std::fs::read(src)?, "content mismatch for {dest}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_esp_rejects_duplicates() {
+        let dir = tempdir().unwrap();
+        let f = dir.path().join("a.efi");
+        std::fs::write(&f, b"a").unwrap();
+        let entries = vec![
+            (f.clone(), "EFI/BOOT/A.EFI".to_string()),
+            (f.clone(), "efi/boot/a.efi".to_string()),
+        ];
+        assert!(build_esp(&entries, 0).is_err());
+    }
+
+    #[test]
+    fn test_short_name_collisions() {
+        let mut used = Vec::new();
+        assert_eq!(&short_name("long-file-1.conf", &mut used), b"LONG-FILCON");
+        assert_eq!(&short_name("long-file-2.conf", &mut used), b"LONG-F~1CON");
+        assert_eq!(&short_name("a+b.txt", &mut used), b"A_B     TXT");
+    }
+
     #[test]
     fn test_checksum() {
         assert_eq!(lfn_checksum(&pack_83(b"BOOTX64", b"EFI")), 0x1D);
@@ -965,7 +1474,7 @@ mod tests {
         let f = dir.path().join("t.efi");
         std::fs::write(&f, b"hello").unwrap();
         let img = dir.path().join("t.img");
-        create_fat_image(&img, &[("T.EFI", f.as_path())], 0).unwrap();
+        create_fat_image(&img, &[("T.EFI", f.as_path())], 0, None, None, None).unwrap();
 
         let mut bytes = Vec::new();
         File::open(&img).unwrap().read_to_end(&mut bytes).unwrap();
@@ -986,7 +1495,7 @@ mod tests {
         // Verify fatfs can read it
         let r = File::open(&img).unwrap();
         let fs = fatfs::FileSystem::new(r, fatfs::FsOptions::new())
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            .map_err(io::Error::other)
             .unwrap();
         let mut v = Vec::new();
         fs.root_dir()
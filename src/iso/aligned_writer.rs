@@ -0,0 +1,365 @@
+// isobemak/src/iso/aligned_writer.rs
+
+use std::alloc::{self, Layout};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+use crate::iso::iso_writer::SetLen;
+
+/// Default alignment: 4 KiB covers both 512e and 4Kn block devices.
+pub const DEFAULT_ALIGNMENT: usize = 4096;
+/// Blocks buffered per chunk before it is written out.
+const CHUNK_BLOCKS: usize = 64;
+
+/// A read/write/seek wrapper that only touches `inner` in aligned chunks.
+///
+/// Writes and reads go through a single chunk-sized buffer; moving to a
+/// different chunk writes the current one back (if modified) at an aligned
+/// offset, so the many small seeks of a build become a few large aligned
+/// writes, as required by `O_DIRECT` block devices.  Every write is a whole
+/// number of aligned blocks, so if the data does not end on a block boundary
+/// `inner` is zero-extended to the next one; [`Self::len`] reports the
+/// logical length.
+///
+/// The buffer itself starts on an `alignment` boundary, as `O_DIRECT` also
+/// requires of the memory a transfer comes from.
+///
+/// Like `BufWriter`, dropping the writer writes the buffered chunk back but
+/// ignores any error doing so; call [`Write::flush`] or
+/// [`Self::into_inner`] when done to see it.
+pub struct AlignedWriter<W: Read + Write + Seek> {
+    /// Taken only by [`Self::into_inner`].
+    inner: Option<W>,
+    alignment: u64,
+    buf: AlignedBuf,
+    buf_start: Option<u64>,
+    dirty: bool,
+    pos: u64,
+    /// Logical length: the larger of `inner`'s length and the furthest write.
+    len: u64,
+    /// Bytes known to exist in `inner`; nothing is read back beyond this.
+    inner_len: u64,
+}
+
+impl<W: Read + Write + Seek> AlignedWriter<W> {
+    /// Wraps `inner` with [`DEFAULT_ALIGNMENT`].
+    pub fn new(inner: W) -> io::Result<Self> {
+        Self::with_alignment(inner, DEFAULT_ALIGNMENT)
+    }
+
+    /// Wraps `inner`, aligning I/O to `alignment` bytes (a power of two, at
+    /// least 512).
+    pub fn with_alignment(mut inner: W, alignment: usize) -> io::Result<Self> {
+        if alignment < 512 || !alignment.is_power_of_two() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("alignment {alignment} is not a power of two >= 512"),
+            ));
+        }
+        let len = inner.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            inner: Some(inner),
+            alignment: alignment as u64,
+            buf: AlignedBuf::new(alignment * CHUNK_BLOCKS, alignment)?,
+            buf_start: None,
+            dirty: false,
+            pos: 0,
+            len,
+            inner_len: len,
+        })
+    }
+
+    /// Logical length of the data: the furthest byte written or the
+    /// original length of `inner`, whichever is larger.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether [`Self::len`] is zero.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Flushes buffered data and returns the wrapped writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.inner.take().expect("inner is only taken here"))
+    }
+
+    fn inner(&mut self) -> &mut W {
+        self.inner
+            .as_mut()
+            .expect("inner is only taken by into_inner")
+    }
+
+    fn write_back(&mut self) -> io::Result<()> {
+        if let Some(start) = self.buf_start
+            && self.dirty
+        {
+            let n = (self.len - start)
+                .next_multiple_of(self.alignment)
+                .min(self.buf.len() as u64) as usize;
+            let inner = self
+                .inner
+                .as_mut()
+                .expect("inner is only taken by into_inner");
+            inner.seek(SeekFrom::Start(start))?;
+            inner.write_all(&self.buf[..n])?;
+            self.inner_len = self.inner_len.max(start + n as u64);
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Makes the buffer hold the chunk containing `self.pos` and returns the
+    /// offset of `self.pos` within it.
+    fn load(&mut self) -> io::Result<usize> {
+        let chunk = self.buf.len() as u64;
+        let start = self.pos / chunk * chunk;
+        if self.buf_start != Some(start) {
+            self.write_back()?;
+            self.buf.fill(0);
+            if start < self.inner_len {
+                let want = (self.inner_len - start).min(chunk) as usize;
+                let inner = self
+                    .inner
+                    .as_mut()
+                    .expect("inner is only taken by into_inner");
+                inner.seek(SeekFrom::Start(start))?;
+                inner.read_exact(&mut self.buf[..want])?;
+            }
+            self.buf_start = Some(start);
+        }
+        Ok((self.pos - start) as usize)
+    }
+}
+
+impl<W: Read + Write + Seek> Write for AlignedWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let off = self.load()?;
+        let n = data.len().min(self.buf.len() - off);
+        self.buf[off..off + n].copy_from_slice(&data[..n]);
+        self.dirty = true;
+        self.pos += n as u64;
+        self.len = self.len.max(self.pos);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_back()?;
+        self.inner().flush()
+    }
+}
+
+impl<W: Read + Write + Seek> Drop for AlignedWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            // Errors cannot be reported from here; see the type's docs.
+            let _ = self.write_back();
+        }
+    }
+}
+
+impl<W: Read + Write + Seek> Read for AlignedWriter<W> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+        let off = self.load()?;
+        let avail = (self.len - self.pos).min((self.buf.len() - off) as u64) as usize;
+        let n = out.len().min(avail);
+        out[..n].copy_from_slice(&self.buf[off..off + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Read + Write + Seek> Seek for AlignedWriter<W> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let target = match to {
+            SeekFrom::Start(o) => Some(o),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        self.pos = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before start of stream")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Sets the logical length ([`AlignedWriter::len`]); data past it reads
+/// back as zeros and is not written out again.  `inner` itself is not
+/// truncated, as that may be impossible (e.g. for a block device): truncate
+/// it to `len()` after flushing if needed.
+impl<W: Read + Write + Seek> SetLen for AlignedWriter<W> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        if let Some(start) = self.buf_start {
+            if len <= start {
+                self.buf_start = None;
+                self.dirty = false;
+            } else if len - start < self.buf.len() as u64 {
+                self.buf[(len - start) as usize..].fill(0);
+            }
+        }
+        self.len = len;
+        self.inner_len = self.inner_len.min(len);
+        Ok(())
+    }
+}
+
+/// A zeroed heap buffer starting on an `align`-byte boundary, which a
+/// `Vec<u8>` does not guarantee.
+struct AlignedBuf {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+// SAFETY: the buffer is uniquely owned, like a `Box<[u8]>`.
+unsafe impl Send for AlignedBuf {}
+// SAFETY: shared access only hands out `&[u8]`.
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    fn new(len: usize, align: usize) -> io::Result<Self> {
+        let layout = Layout::from_size_align(len, align)
+            .ok()
+            .filter(|l| l.size() > 0)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cannot allocate {len} bytes aligned to {align}"),
+                )
+            })?;
+        // SAFETY: `layout` has a non-zero size.
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        Ok(Self { ptr, layout })
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `layout.size()` initialized bytes owned by
+        // `self`.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `deref`, and `&mut self` makes the access unique.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with this layout.
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Records the offset and length of every write reaching the inner
+    /// stream.
+    struct Recorder {
+        data: Cursor<Vec<u8>>,
+        writes: Vec<(u64, usize)>,
+    }
+
+    impl Read for Recorder {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.data.read(buf)
+        }
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.push((self.data.position(), buf.len()));
+            self.data.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Recorder {
+        fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+            self.data.seek(to)
+        }
+    }
+
+    #[test]
+    fn test_scattered_writes_are_aligned() -> io::Result<()> {
+        let rec = Recorder {
+            data: Cursor::new(Vec::new()),
+            writes: Vec::new(),
+        };
+        let mut w = AlignedWriter::with_alignment(rec, 512)?;
+        let mut expected = Cursor::new(Vec::new());
+        for (off, byte) in [(10u64, 1u8), (70_000, 2), (3, 3), (40_000, 4), (100_001, 5)] {
+            for s in [&mut w as &mut dyn WriteSeek, &mut expected] {
+                s.seek(SeekFrom::Start(off))?;
+                s.write_all(&[byte; 300])?;
+            }
+        }
+        w.seek(SeekFrom::Start(69_990))?;
+        let mut back = [0u8; 20];
+        w.read_exact(&mut back)?;
+        assert_eq!(back[..10], [0; 10]);
+        assert_eq!(back[10..], [2; 10]);
+
+        assert_eq!(w.len(), 100_301);
+        let rec = w.into_inner()?;
+        let written = rec.data.get_ref();
+        assert_eq!(written.len(), 100_352);
+        assert_eq!(written[..100_301], expected.get_ref()[..]);
+        assert!(written[100_301..].iter().all(|&b| b == 0));
+        for &(off, len) in &rec.writes {
+            assert_eq!(
+                off % (512 * CHUNK_BLOCKS as u64),
+                0,
+                "unaligned write at {off}"
+            );
+            assert_eq!(len % 512, 0, "partial block written at {off}");
+        }
+        assert!(AlignedWriter::with_alignment(Cursor::new(Vec::new()), 1000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffer_is_aligned() -> io::Result<()> {
+        for alignment in [512, 4096, 65536] {
+            let w = AlignedWriter::with_alignment(Cursor::new(Vec::new()), alignment)?;
+            assert_eq!(w.buf.as_ptr() as usize % alignment, 0);
+            assert_eq!(w.buf.len(), alignment * CHUNK_BLOCKS);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_writes_back() -> io::Result<()> {
+        let mut out = Cursor::new(Vec::new());
+        let mut w = AlignedWriter::with_alignment(&mut out, 512)?;
+        w.seek(SeekFrom::Start(100))?;
+        w.write_all(b"tail")?;
+        drop(w);
+        let data = out.into_inner();
+        assert_eq!(data.len(), 512);
+        assert_eq!(&data[100..104], b"tail");
+        Ok(())
+    }
+
+    trait WriteSeek: Write + Seek {}
+    impl<T: Write + Seek> WriteSeek for T {}
+}
@@ -0,0 +1,147 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Size of an Apple Partition Map block in bytes.  2048, as xorriso uses
+/// for hybrid images, so that the map entries start at byte 2048 and leave
+/// the GPT header at 512-byte LBA 1 alone.
+pub const APM_BLOCK_SIZE: u64 = 2048;
+/// Size of one encoded partition map entry; the rest of its block is zero.
+pub const PM_ENTRY_SIZE: usize = 512;
+/// GPT partition entries that fit between the start of the primary array
+/// (byte 1024) and the first map entry (byte 2048).
+pub const GPT_ENTRIES_WITH_APM: u32 = ((APM_BLOCK_SIZE - 1024) / 128) as u32;
+
+const PM_SIGNATURE: &[u8; 2] = b"PM";
+const DDM_SIGNATURE: &[u8; 2] = b"ER";
+const PM_NAME_LEN: usize = 32;
+
+/// One entry of an Apple Partition Map.  Block numbers are in
+/// [`APM_BLOCK_SIZE`] units.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApmPartition {
+    pub name: String,
+    pub part_type: String,
+    pub start_block: u32,
+    pub block_count: u32,
+}
+
+impl ApmPartition {
+    pub fn new(name: &str, part_type: &str, start_block: u32, block_count: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            part_type: part_type.to_string(),
+            start_block,
+            block_count,
+        }
+    }
+
+    /// Encodes the entry as the first [`PM_ENTRY_SIZE`] bytes of a
+    /// partition map block.  `map_entries` is the total number of entries
+    /// in the map, stored in every entry.
+    pub fn to_bytes(&self, map_entries: u32) -> io::Result<[u8; PM_ENTRY_SIZE]> {
+        let mut b = [0u8; PM_ENTRY_SIZE];
+        b[0..2].copy_from_slice(PM_SIGNATURE);
+        b[4..8].copy_from_slice(&map_entries.to_be_bytes());
+        b[8..12].copy_from_slice(&self.start_block.to_be_bytes());
+        b[12..16].copy_from_slice(&self.block_count.to_be_bytes());
+        write_name(&mut b[16..16 + PM_NAME_LEN], "name", &self.name)?;
+        write_name(&mut b[48..48 + PM_NAME_LEN], "type", &self.part_type)?;
+        // Logical data area covers the whole partition.
+        b[84..88].copy_from_slice(&self.block_count.to_be_bytes());
+        // Valid | allocated | in use | readable | writable.
+        b[88..92].copy_from_slice(&0x0000_0033u32.to_be_bytes());
+        Ok(b)
+    }
+}
+
+fn write_name(field: &mut [u8], what: &str, value: &str) -> io::Result<()> {
+    if !value.is_ascii() || value.len() >= field.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("APM partition {what} {value:?} must be ASCII and under 32 bytes"),
+        ));
+    }
+    field[..value.len()].copy_from_slice(value.as_bytes());
+    Ok(())
+}
+
+/// Writes an Apple Partition Map starting at block 1.  The map's own
+/// `Apple_partition_map` entry is generated and placed first, followed by
+/// `partitions`.  Only the [`PM_ENTRY_SIZE`] bytes of each entry are
+/// written, at the start of its block.
+///
+/// Block 0 gets the Driver Descriptor Map fields firmware looks for before
+/// the map: the `ER` signature, the block size and the device size in
+/// blocks, taken as the end of the furthest partition.  Only bytes 0-7 are
+/// written, but they are also where x86 MBR boot code starts, so callers
+/// must not combine the map with such code.
+pub fn write_apm<W: Write + Seek>(w: &mut W, partitions: &[ApmPartition]) -> io::Result<()> {
+    let entries = u32::try_from(partitions.len() + 1)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many APM partitions"))?;
+    let map = ApmPartition::new("Apple", "Apple_partition_map", 1, entries);
+    let device_blocks = std::iter::once(&map)
+        .chain(partitions)
+        .map(|p| p.start_block as u64 + p.block_count as u64)
+        .max()
+        .unwrap_or(0);
+    let device_blocks = u32::try_from(device_blocks).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "APM partitions end past 2^32 blocks",
+        )
+    })?;
+    let mut ddm = [0u8; 8];
+    ddm[0..2].copy_from_slice(DDM_SIGNATURE);
+    ddm[2..4].copy_from_slice(&(APM_BLOCK_SIZE as u16).to_be_bytes());
+    ddm[4..8].copy_from_slice(&device_blocks.to_be_bytes());
+    w.seek(SeekFrom::Start(0))?;
+    w.write_all(&ddm)?;
+
+    for (block, p) in (1..).zip(std::iter::once(&map).chain(partitions)) {
+        w.seek(SeekFrom::Start(block * APM_BLOCK_SIZE))?;
+        w.write_all(&p.to_bytes(entries)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_apm() -> io::Result<()> {
+        let bs = APM_BLOCK_SIZE as usize;
+        let mut disk = vec![0u8; 8 * bs];
+        disk[..bs].fill(0xAA);
+        let mut disk = Cursor::new(disk);
+        let parts = [ApmPartition::new("ISO9660", "Apple_HFS", 0, 4000)];
+        write_apm(&mut disk, &parts)?;
+        let d = disk.into_inner();
+
+        // Driver Descriptor Map, with the rest of block 0 left alone.
+        assert_eq!(&d[0..2], b"ER");
+        assert_eq!(u16::from_be_bytes([d[2], d[3]]), 2048);
+        assert_eq!(u32::from_be_bytes(d[4..8].try_into().unwrap()), 4000);
+        assert!(d[8..bs].iter().all(|&b| b == 0xAA));
+
+        for (i, (name, ty)) in [("Apple", "Apple_partition_map"), ("ISO9660", "Apple_HFS")]
+            .iter()
+            .enumerate()
+        {
+            let blk = &d[(i + 1) * bs..(i + 2) * bs];
+            assert_eq!(&blk[0..2], b"PM");
+            assert_eq!(u32::from_be_bytes(blk[4..8].try_into().unwrap()), 2);
+            assert_eq!(&blk[16..16 + name.len()], name.as_bytes());
+            assert_eq!(&blk[48..48 + ty.len()], ty.as_bytes());
+            assert!(blk[PM_ENTRY_SIZE..].iter().all(|&b| b == 0));
+        }
+        let hfs = &d[2 * bs..3 * bs];
+        assert_eq!(u32::from_be_bytes(hfs[8..12].try_into().unwrap()), 0);
+        assert_eq!(u32::from_be_bytes(hfs[12..16].try_into().unwrap()), 4000);
+        assert!(d[3 * bs..].iter().all(|&b| b == 0));
+
+        let bad = [ApmPartition::new(&"X".repeat(40), "Apple_HFS", 0, 1)];
+        assert!(write_apm(&mut Cursor::new(Vec::new()), &bad).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+// isobemak/src/iso/autoconfig.rs
+
+/// Boot loader whose configuration syntax [`AutoConfig`] generates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AutoConfigFormat {
+    /// `grub.cfg`, placed at `boot/grub/grub.cfg`.
+    #[default]
+    Grub,
+    /// `isolinux.cfg`, placed at `isolinux/isolinux.cfg`.
+    Isolinux,
+}
+
+/// A single-entry boot menu generated at build time.  `kernel` and `initrd`
+/// are paths of files added to the ISO.
+#[derive(Debug, Clone, Default)]
+pub struct AutoConfig {
+    pub kernel: String,
+    pub initrd: Option<String>,
+    pub cmdline: String,
+    pub format: AutoConfigFormat,
+}
+
+impl AutoConfig {
+    /// Path of the generated file inside the ISO.
+    pub fn config_path(&self) -> &'static str {
+        match self.format {
+            AutoConfigFormat::Grub => "boot/grub/grub.cfg",
+            AutoConfigFormat::Isolinux => "isolinux/isolinux.cfg",
+        }
+    }
+
+    /// Renders the configuration file with the paths as given, which is
+    /// how loaders see them with Rock Ridge.
+    pub fn render(&self) -> String {
+        self.render_with(|path| path.to_string())
+    }
+
+    /// Renders the configuration file with `path_on_disc` mapping the kernel
+    /// and initrd paths, without their leading `/`, to the names a loader
+    /// finds on disc.
+    pub(crate) fn render_with(&self, path_on_disc: impl Fn(&str) -> String) -> String {
+        let absolute = |path: &str| format!("/{}", path_on_disc(path.trim_start_matches('/')));
+        let kernel = absolute(&self.kernel);
+        let initrd = self.initrd.as_deref().map(absolute);
+        let cmdline = self.cmdline.trim();
+        let mut out = String::new();
+        match self.format {
+            AutoConfigFormat::Grub => {
+                out.push_str("set default=0\nset timeout=5\n\nmenuentry \"Linux\" {\n");
+                out.push_str(format!("    linux {kernel} {cmdline}").trim_end());
+                out.push('\n');
+                if let Some(initrd) = initrd {
+                    out.push_str(&format!("    initrd {initrd}\n"));
+                }
+                out.push_str("}\n");
+            }
+            AutoConfigFormat::Isolinux => {
+                out.push_str("DEFAULT linux\nPROMPT 0\nTIMEOUT 50\n\nLABEL linux\n");
+                out.push_str(&format!("    KERNEL {kernel}\n"));
+                if let Some(initrd) = initrd {
+                    out.push_str(&format!("    INITRD {initrd}\n"));
+                }
+                if !cmdline.is_empty() {
+                    out.push_str(&format!("    APPEND {cmdline}\n"));
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(format: AutoConfigFormat) -> AutoConfig {
+        AutoConfig {
+            kernel: "boot/vmlinuz".into(),
+            initrd: Some("/boot/initrd.img".into()),
+            cmdline: "quiet splash".into(),
+            format,
+        }
+    }
+
+    #[test]
+    fn test_render_grub() {
+        let cfg = config(AutoConfigFormat::Grub);
+        let text = cfg.render();
+        assert_eq!(cfg.config_path(), "boot/grub/grub.cfg");
+        assert!(text.contains("\n    linux /boot/vmlinuz quiet splash\n"));
+        assert!(text.contains("\n    initrd /boot/initrd.img\n"));
+    }
+
+    #[test]
+    fn test_render_isolinux() {
+        let mut cfg = config(AutoConfigFormat::Isolinux);
+        cfg.initrd = None;
+        let text = cfg.render();
+        assert_eq!(cfg.config_path(), "isolinux/isolinux.cfg");
+        assert!(text.contains("\n    KERNEL /boot/vmlinuz\n"));
+        assert!(text.contains("\n    APPEND quiet splash\n"));
+        assert!(!text.contains("INITRD"));
+    }
+
+    #[test]
+    fn test_render_with() {
+        let text = config(AutoConfigFormat::Grub).render_with(str::to_uppercase);
+        assert!(text.contains("\n    linux /BOOT/VMLINUZ quiet splash\n"));
+        assert!(text.contains("\n    initrd /BOOT/INITRD.IMG\n"));
+    }
+}
@@ -1,6 +1,5 @@
 use crate::utils::ISO_SECTOR_SIZE;
-use std::fs::File;
-use std::io::{self, Write};
+use std::io::{self, Seek, Write};
 
 pub const LBA_BOOT_CATALOG: u32 = 19;
 pub const BOOT_CATALOG_HEADER_SIGNATURE: u16 = 0xAA55;
@@ -8,9 +7,109 @@ pub const BOOT_CATALOG_VALIDATION_ENTRY_HEADER_ID: u8 = 1;
 pub const BOOT_CATALOG_BOOT_ENTRY_HEADER_ID: u8 = 0x88;
 pub const BOOT_CATALOG_SECTION_HEADER_MORE_ID: u8 = 0x90;
 pub const BOOT_CATALOG_SECTION_HEADER_FINAL_ID: u8 = 0x91;
+pub const BOOT_CATALOG_X86_PLATFORM_ID: u8 = 0x00;
+pub const BOOT_CATALOG_POWERPC_PLATFORM_ID: u8 = 0x01;
+pub const BOOT_CATALOG_MAC_PLATFORM_ID: u8 = 0x02;
 pub const BOOT_CATALOG_EFI_PLATFORM_ID: u8 = 0xEF;
+/// Boot media types of a boot entry (El Torito 2.2).
+pub const MEDIA_NO_EMULATION: u8 = 0;
+pub const MEDIA_FLOPPY_1_2M: u8 = 1;
+pub const MEDIA_FLOPPY_1_44M: u8 = 2;
+pub const MEDIA_FLOPPY_2_88M: u8 = 3;
+pub const MEDIA_HARD_DISK: u8 = 4;
 const CHECKSUM_OFFSET: usize = 28;
 const ID_OFFSET: usize = 4;
+const ENTRY_SIZE: usize = 32;
+const SELECTION_CRITERIA_OFFSET: usize = 12;
+/// Vendor unique selection criteria bytes that fit after the criteria type
+/// (entry offsets 13–31).
+pub const MAX_SELECTION_CRITERIA_LEN: usize = ENTRY_SIZE - SELECTION_CRITERIA_OFFSET - 1;
+
+/// Maximum number of entries (excluding the validation entry) that fit in
+/// the single boot catalog sector.
+pub const MAX_BOOT_CATALOG_ENTRIES: usize = ISO_SECTOR_SIZE / ENTRY_SIZE - 1;
+
+/// How the BIOS presents a boot image: loaded directly, or as an emulated
+/// floppy or hard disk whose geometry is given in cylinders, heads and
+/// sectors (of 512 bytes) per track.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BootEmulation {
+    #[default]
+    NoEmulation,
+    /// One of the standard 1.2 MB (80/2/15), 1.44 MB (80/2/18) or 2.88 MB
+    /// (80/2/36) floppy disks.
+    Floppy {
+        cylinders: u16,
+        heads: u8,
+        sectors_per_track: u8,
+    },
+    /// A hard disk of up to 1024 cylinders, 255 heads and 63 sectors per
+    /// track, whose image starts with an MBR.
+    HardDisk {
+        cylinders: u16,
+        heads: u8,
+        sectors_per_track: u8,
+    },
+}
+
+impl BootEmulation {
+    /// The boot entry's media type, or `InvalidInput` if the geometry is
+    /// not a standard floppy or exceeds the CHS limits of a hard disk.
+    pub fn media_type(&self) -> io::Result<u8> {
+        let bad = |what: &str| {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported {what} emulation geometry {self:?}"),
+            ))
+        };
+        match *self {
+            Self::NoEmulation => Ok(MEDIA_NO_EMULATION),
+            Self::Floppy {
+                cylinders: 80,
+                heads: 2,
+                sectors_per_track,
+            } => match sectors_per_track {
+                15 => Ok(MEDIA_FLOPPY_1_2M),
+                18 => Ok(MEDIA_FLOPPY_1_44M),
+                36 => Ok(MEDIA_FLOPPY_2_88M),
+                _ => bad("floppy"),
+            },
+            Self::Floppy { .. } => bad("floppy"),
+            Self::HardDisk {
+                cylinders,
+                heads,
+                sectors_per_track,
+            } => {
+                if (1..=1024).contains(&cylinders)
+                    && heads >= 1
+                    && (1..=63).contains(&sectors_per_track)
+                {
+                    Ok(MEDIA_HARD_DISK)
+                } else {
+                    bad("hard disk")
+                }
+            }
+        }
+    }
+
+    /// Size in bytes of the disk the geometry describes; `None` without
+    /// emulation.
+    pub fn image_size(&self) -> Option<u64> {
+        match *self {
+            Self::NoEmulation => None,
+            Self::Floppy {
+                cylinders,
+                heads,
+                sectors_per_track,
+            }
+            | Self::HardDisk {
+                cylinders,
+                heads,
+                sectors_per_track,
+            } => Some(cylinders as u64 * heads as u64 * sectors_per_track as u64 * 512),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BootCatalogEntryType {
@@ -18,33 +117,92 @@ pub enum BootCatalogEntryType {
     SectionHeader { more_follow: bool },
 }
 
+#[derive(Debug, Clone)]
 pub struct BootCatalogEntry {
     pub platform_id: u8,
+    /// First sector of the boot image (entry offset 8) in 2048-byte ISO
+    /// sectors, the unit of every LBA in the volume.
     pub boot_image_lba: u32,
+    /// Load count (entry offset 6) in 512-byte virtual sectors, not ISO
+    /// sectors: a one-sector image at `boot_image_lba` loads with `4`.
     pub boot_image_sectors: u16,
     pub entry_type: BootCatalogEntryType,
+    /// Boot media type (entry offset 1) of a boot entry, e.g.
+    /// [`MEDIA_NO_EMULATION`]; see [`BootEmulation::media_type`].
+    pub media_type: u8,
+    /// Selection criteria type (entry offset 12, e.g. `1` for language and
+    /// version information) and up to [`MAX_SELECTION_CRITERIA_LEN`] vendor
+    /// unique bytes after it.  Boot entries only; `None` leaves them zero.
+    pub selection_criteria: Option<(u8, Vec<u8>)>,
+}
+
+/// Writes the catalog rendered by [`render_boot_catalog`] at the current
+/// position.
+pub fn write_boot_catalog<W: Write + Seek>(
+    iso: &mut W,
+    entries: Vec<BootCatalogEntry>,
+) -> io::Result<()> {
+    iso.write_all(&render_boot_catalog(&entries)?)
+}
+
+/// Renders the 2048-byte catalog sector: the validation entry followed by
+/// `entries`.  The validation entry carries the platform ID of the first
+/// (Initial/Default) entry, except that a UEFI default entry keeps x86 there
+/// and is announced by the section header that follows it; later platforms
+/// need their own section header.  Fails if there are more than [`MAX_BOOT_CATALOG_ENTRIES`].
+pub fn render_boot_catalog(entries: &[BootCatalogEntry]) -> io::Result<[u8; ISO_SECTOR_SIZE]> {
+    render_boot_catalog_with_id(entries, None)
+}
+
+/// The checksum (offsets 28-29, little-endian) that makes the sixteen
+/// 16-bit little-endian words of the validation `entry` sum to zero.  The
+/// checksum field's current contents are ignored.
+pub fn validation_checksum(entry: &[u8; 32]) -> u16 {
+    let sum = entry
+        .chunks_exact(2)
+        .enumerate()
+        .filter(|&(i, _)| i * 2 != CHECKSUM_OFFSET)
+        .fold(0u16, |s, (_, w)| {
+            s.wrapping_add(u16::from_le_bytes([w[0], w[1]]))
+        });
+    0u16.wrapping_sub(sum)
 }
 
-pub fn write_boot_catalog(iso: &mut File, entries: Vec<BootCatalogEntry>) -> io::Result<()> {
+/// Like [`render_boot_catalog`], with `validation_id` (e.g. a manufacturer
+/// string, zero-padded) in the validation entry's ID field instead of
+/// `EL TORITO SPECIFICATION`.  The checksum covers whichever ID is used.
+pub fn render_boot_catalog_with_id(
+    entries: &[BootCatalogEntry],
+    validation_id: Option<[u8; 24]>,
+) -> io::Result<[u8; ISO_SECTOR_SIZE]> {
+    if entries.len() > MAX_BOOT_CATALOG_ENTRIES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Too many boot catalog entries ({} > {MAX_BOOT_CATALOG_ENTRIES})",
+                entries.len()
+            ),
+        ));
+    }
     let mut catalog = [0u8; ISO_SECTOR_SIZE];
     let mut offset = 0;
 
     // Validation Entry
     let mut val = [0u8; 32];
     val[0] = BOOT_CATALOG_VALIDATION_ENTRY_HEADER_ID;
-    val[1] = 0x00;
-    let mut id = [0u8; 24];
-    id[..23].copy_from_slice(b"EL TORITO SPECIFICATION");
+    val[1] = match entries.first() {
+        Some(e) if e.platform_id != BOOT_CATALOG_EFI_PLATFORM_ID => e.platform_id,
+        _ => BOOT_CATALOG_X86_PLATFORM_ID,
+    };
+    let id = validation_id.unwrap_or_else(|| {
+        let mut id = [0u8; 24];
+        id[..23].copy_from_slice(b"EL TORITO SPECIFICATION");
+        id
+    });
     val[ID_OFFSET..ID_OFFSET + 24].copy_from_slice(&id);
     val[30..32].copy_from_slice(&BOOT_CATALOG_HEADER_SIGNATURE.to_le_bytes());
-    let sum: u16 = (0..32)
-        .step_by(2)
-        .filter(|&i| i != CHECKSUM_OFFSET)
-        .fold(0u16, |s, i| {
-            s.wrapping_add(u16::from_le_bytes(val[i..i + 2].try_into().unwrap()))
-        });
-    val[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 2]
-        .copy_from_slice(&(0u16.wrapping_sub(sum)).to_le_bytes());
+    let checksum = validation_checksum(&val);
+    val[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 2].copy_from_slice(&checksum.to_le_bytes());
     catalog[offset..offset + 32].copy_from_slice(&val);
     offset += 32;
 
@@ -75,7 +233,7 @@ pub fn write_boot_catalog(iso: &mut File, entries: Vec<BootCatalogEntry>) -> io:
                 } else {
                     0x00
                 },
-                0x00,
+                entry_data.media_type,
             ),
             BootCatalogEntryType::SectionHeader { more_follow } => (
                 if more_follow {
@@ -103,10 +261,73 @@ pub fn write_boot_catalog(iso: &mut File, entries: Vec<BootCatalogEntry>) -> io:
         };
         e[6..8].copy_from_slice(&entry_data.boot_image_sectors.to_le_bytes());
         e[8..12].copy_from_slice(&entry_data.boot_image_lba.to_le_bytes());
+        if let Some((kind, bytes)) = &entry_data.selection_criteria {
+            if !matches!(
+                entry_data.entry_type,
+                BootCatalogEntryType::BootEntry { .. }
+            ) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Boot catalog entry {idx} is a section header and has no selection criteria"
+                    ),
+                ));
+            }
+            if bytes.len() > MAX_SELECTION_CRITERIA_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Selection criteria of boot catalog entry {idx} too long ({} > {MAX_SELECTION_CRITERIA_LEN} bytes)",
+                        bytes.len()
+                    ),
+                ));
+            }
+            let o = SELECTION_CRITERIA_OFFSET;
+            e[o] = *kind;
+            e[o + 1..o + 1 + bytes.len()].copy_from_slice(bytes);
+        }
         catalog[offset..offset + 32].copy_from_slice(&e);
         offset += 32;
     }
-    iso.write_all(&catalog)
+    Ok(catalog)
+}
+
+/// Checks that the rendered `catalog` opens with the first boot section:
+/// the Initial/Default Entry points at `first_lba`, and the validation
+/// entry names `first_platform`, except that a UEFI default entry keeps x86
+/// there.  Fails with `InvalidData` otherwise.
+pub(crate) fn check_first_section(
+    catalog: &[u8],
+    first_platform: u8,
+    first_lba: u32,
+) -> io::Result<()> {
+    let found = catalog[1];
+    let expected = if first_platform == BOOT_CATALOG_EFI_PLATFORM_ID {
+        BOOT_CATALOG_X86_PLATFORM_ID
+    } else {
+        first_platform
+    };
+    if found != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Boot catalog validation entry has platform ID {found:#04x}, \
+                 but the first boot section is for platform {first_platform:#04x}"
+            ),
+        ));
+    }
+    let default = &catalog[ENTRY_SIZE..2 * ENTRY_SIZE];
+    let lba = u32::from_le_bytes(default[8..12].try_into().unwrap());
+    if lba != first_lba {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Boot catalog default entry points at LBA {lba}, \
+                 but the first boot section's image is at LBA {first_lba}"
+            ),
+        ));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -116,10 +337,64 @@ mod tests {
     use tempfile::NamedTempFile;
 
     fn verify_checksum(ve: &[u8; 32]) {
-        let s = (0..32).step_by(2).fold(0u16, |a, i| {
-            a.wrapping_add(u16::from_le_bytes([ve[i], ve[i + 1]]))
+        assert_eq!(
+            validation_checksum(ve),
+            u16::from_le_bytes([ve[28], ve[29]])
+        );
+    }
+
+    #[test]
+    fn test_validation_checksum() {
+        let mut entry = [0u8; 32];
+        entry[0] = 1;
+        entry[4..27].copy_from_slice(b"EL TORITO SPECIFICATION");
+        entry[28..30].copy_from_slice(&[0xAB, 0xCD]);
+        entry[30..32].copy_from_slice(&[0x55, 0xAA]);
+        let checksum = validation_checksum(&entry);
+        entry[28..30].copy_from_slice(&checksum.to_le_bytes());
+        let sum = entry.chunks_exact(2).fold(0u16, |s, w| {
+            s.wrapping_add(u16::from_le_bytes([w[0], w[1]]))
         });
-        assert_eq!(s, 0);
+        assert_eq!(sum, 0);
+        assert_eq!(validation_checksum(&entry), checksum);
+        assert_eq!(validation_checksum(&[0; 32]), 0);
+    }
+
+    #[test]
+    fn test_check_first_section() -> io::Result<()> {
+        let entry = |platform_id, boot_image_lba, entry_type| BootCatalogEntry {
+            platform_id,
+            boot_image_lba,
+            boot_image_sectors: 4,
+            entry_type,
+            media_type: MEDIA_NO_EMULATION,
+            selection_criteria: None,
+        };
+        let boot = BootCatalogEntryType::BootEntry { bootable: true };
+        let header = BootCatalogEntryType::SectionHeader { more_follow: false };
+        let efi = BOOT_CATALOG_EFI_PLATFORM_ID;
+        let bios_first = render_boot_catalog(&[
+            entry(0, 30, boot),
+            entry(efi, 0, header),
+            entry(efi, 40, boot),
+        ])?;
+        check_first_section(&bios_first, 0, 30)?;
+        let err = check_first_section(&bios_first, BOOT_CATALOG_POWERPC_PLATFORM_ID, 30);
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // The BIOS-before-UEFI regression: UEFI took the default entry.  Its
+        // validation entry still says x86, so only the LBA gives it away.
+        let uefi_first = render_boot_catalog(&[
+            entry(efi, 40, boot),
+            entry(efi, 0, header),
+            entry(efi, 40, boot),
+            entry(0, 0, header),
+            entry(0, 30, boot),
+        ])?;
+        check_first_section(&uefi_first, efi, 40)?;
+        let err = check_first_section(&uefi_first, 0, 30);
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        Ok(())
     }
 
     #[test]
@@ -132,6 +407,8 @@ mod tests {
                 boot_image_lba: 100,
                 boot_image_sectors: 50,
                 entry_type: BootCatalogEntryType::BootEntry { bootable: true },
+                media_type: MEDIA_NO_EMULATION,
+                selection_criteria: None,
             }],
         )?;
         let mut buf = [0u8; ISO_SECTOR_SIZE];
@@ -150,6 +427,116 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_custom_validation_id() -> io::Result<()> {
+        let entries = [BootCatalogEntry {
+            platform_id: 0,
+            boot_image_lba: 30,
+            boot_image_sectors: 4,
+            entry_type: BootCatalogEntryType::BootEntry { bootable: true },
+            media_type: MEDIA_NO_EMULATION,
+            selection_criteria: None,
+        }];
+        let mut id = [0u8; 24];
+        id[..12].copy_from_slice(b"ACME SYSTEMS");
+        let buf = render_boot_catalog_with_id(&entries, Some(id))?;
+        let ve: &[u8; 32] = &buf[0..32].try_into().unwrap();
+        assert_eq!(ve[ID_OFFSET..ID_OFFSET + 24], id);
+        verify_checksum(ve);
+        // Only the validation entry differs from the default rendering.
+        let default = render_boot_catalog(&entries)?;
+        assert_eq!(&default[4..27], b"EL TORITO SPECIFICATION");
+        assert_eq!(buf[32..], default[32..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_matches_write() -> io::Result<()> {
+        let entries = vec![
+            BootCatalogEntry {
+                platform_id: 0,
+                boot_image_lba: 30,
+                boot_image_sectors: 4,
+                entry_type: BootCatalogEntryType::BootEntry { bootable: true },
+                media_type: MEDIA_NO_EMULATION,
+                selection_criteria: None,
+            },
+            BootCatalogEntry {
+                platform_id: BOOT_CATALOG_EFI_PLATFORM_ID,
+                boot_image_lba: 0,
+                boot_image_sectors: 0,
+                entry_type: BootCatalogEntryType::SectionHeader { more_follow: false },
+                media_type: MEDIA_NO_EMULATION,
+                selection_criteria: None,
+            },
+            BootCatalogEntry {
+                platform_id: BOOT_CATALOG_EFI_PLATFORM_ID,
+                boot_image_lba: 40,
+                boot_image_sectors: 8,
+                entry_type: BootCatalogEntryType::BootEntry { bootable: true },
+                media_type: MEDIA_NO_EMULATION,
+                selection_criteria: None,
+            },
+        ];
+        let rendered = render_boot_catalog(&entries)?;
+        verify_checksum(&rendered[..32].try_into().unwrap());
+        assert_eq!(rendered[64], BOOT_CATALOG_SECTION_HEADER_FINAL_ID);
+        assert_eq!(&rendered[66..68], &1u16.to_le_bytes());
+        assert!(rendered[128..].iter().all(|&b| b == 0));
+
+        let mut f = NamedTempFile::new()?;
+        write_boot_catalog(f.as_file_mut(), entries)?;
+        assert_eq!(std::fs::read(f.path())?, rendered);
+        Ok(())
+    }
+
+    #[test]
+    fn test_selection_criteria() -> io::Result<()> {
+        let entry = |criteria: Vec<u8>| BootCatalogEntry {
+            platform_id: 0,
+            boot_image_lba: 30,
+            boot_image_sectors: 4,
+            entry_type: BootCatalogEntryType::BootEntry { bootable: true },
+            media_type: MEDIA_NO_EMULATION,
+            selection_criteria: Some((1, criteria)),
+        };
+        let catalog = render_boot_catalog(&[entry(b"en-US v2".to_vec())])?;
+        let e = &catalog[32..64];
+        assert_eq!(e[12], 1);
+        assert_eq!(&e[13..21], b"en-US v2");
+        assert!(e[21..].iter().all(|&b| b == 0));
+        assert_eq!(&e[8..12], &30u32.to_le_bytes());
+
+        let full = vec![0xAB; MAX_SELECTION_CRITERIA_LEN];
+        assert_eq!(render_boot_catalog(&[entry(full)])?[32 + 31], 0xAB);
+        let err =
+            render_boot_catalog(&[entry(vec![0; MAX_SELECTION_CRITERIA_LEN + 1])]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let header = BootCatalogEntry {
+            entry_type: BootCatalogEntryType::SectionHeader { more_follow: false },
+            ..entry(Vec::new())
+        };
+        let err = render_boot_catalog(&[entry(Vec::new()), header]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_powerpc_default_entry() -> io::Result<()> {
+        let catalog = render_boot_catalog(&[BootCatalogEntry {
+            platform_id: BOOT_CATALOG_POWERPC_PLATFORM_ID,
+            boot_image_lba: 30,
+            boot_image_sectors: 4,
+            entry_type: BootCatalogEntryType::BootEntry { bootable: true },
+            media_type: MEDIA_NO_EMULATION,
+            selection_criteria: None,
+        }])?;
+        assert_eq!(catalog[1], BOOT_CATALOG_POWERPC_PLATFORM_ID);
+        verify_checksum(&catalog[..32].try_into().unwrap());
+        Ok(())
+    }
+
     #[test]
     fn test_non_bootable() -> io::Result<()> {
         let mut f = NamedTempFile::new()?;
@@ -160,6 +547,8 @@ mod tests {
                 boot_image_lba: 200,
                 boot_image_sectors: 20,
                 entry_type: BootCatalogEntryType::BootEntry { bootable: false },
+                media_type: MEDIA_NO_EMULATION,
+                selection_criteria: None,
             }],
         )?;
         let mut buf = [0u8; ISO_SECTOR_SIZE];
@@ -168,4 +557,28 @@ mod tests {
         assert_eq!(buf[32], 0x00);
         Ok(())
     }
+
+    #[test]
+    fn test_too_many_entries() -> io::Result<()> {
+        let mk = || BootCatalogEntry {
+            platform_id: 0,
+            boot_image_lba: 100,
+            boot_image_sectors: 4,
+            entry_type: BootCatalogEntryType::BootEntry { bootable: true },
+            media_type: MEDIA_NO_EMULATION,
+            selection_criteria: None,
+        };
+        let mut f = NamedTempFile::new()?;
+        let err = write_boot_catalog(f.as_file_mut(), (0..70).map(|_| mk()).collect())
+            .expect_err("70 entries must not fit in one catalog sector");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Exactly the maximum still fits.
+        write_boot_catalog(
+            f.as_file_mut(),
+            (0..MAX_BOOT_CATALOG_ENTRIES).map(|_| mk()).collect(),
+        )?;
+        assert_eq!(f.as_file().metadata()?.len(), ISO_SECTOR_SIZE as u64);
+        Ok(())
+    }
 }
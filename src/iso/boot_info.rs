@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use crate::iso::layout_profile::HybridMode;
+
 /// High-level boot information for the ISO.
 #[derive(Clone, Debug)]
 pub struct BootInfo {
@@ -7,25 +9,136 @@ pub struct BootInfo {
     pub uefi_boot: Option<UefiBootInfo>,
 }
 
+/// Which ways a disc built from a [`BootInfo`] can boot; see
+/// [`BootInfo::effective_modes`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BootModes {
+    /// The boot catalog has a BIOS entry (booting from optical media).
+    pub bios: bool,
+    /// The boot catalog has a UEFI entry.
+    pub uefi: bool,
+    /// The MBR carries the BIOS boot image's boot code, so BIOS firmware can
+    /// boot the image written to a disk or USB stick.
+    pub bios_disk: bool,
+    /// A GPT lists an EFI System Partition, so UEFI firmware can boot the
+    /// image written to a disk or USB stick.
+    pub uefi_disk: bool,
+}
+
+impl BootInfo {
+    /// Returns how a disc built by [`crate::build_iso`] with this boot
+    /// information and `hybrid_mode` will be bootable, following the same
+    /// rules as the builder: a catalog entry per configured BIOS or UEFI
+    /// image, BIOS boot code in the MBR for [`HybridMode::BiosMbr`] and
+    /// [`HybridMode::Both`], and an ESP for [`HybridMode::GptUefi`] and
+    /// [`HybridMode::Both`].  A `bool` selects `GptUefi` or `None`.
+    pub fn effective_modes(&self, hybrid_mode: impl Into<HybridMode>) -> BootModes {
+        let hybrid_mode = hybrid_mode.into();
+        let bios = self.bios_boot.is_some();
+        let uefi = self.uefi_boot.is_some();
+        BootModes {
+            bios,
+            uefi,
+            bios_disk: bios && hybrid_mode.has_bios_boot_code(),
+            uefi_disk: uefi && hybrid_mode.uses_gpt_esp(),
+        }
+    }
+}
+
 /// Configuration for BIOS boot (El Torito).
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct BiosBootInfo {
     pub boot_image: PathBuf,
     pub destination_in_iso: String,
+    /// El Torito platform ID of the entry: `0x00` (x86, the usual value),
+    /// `0x01` (PowerPC), `0x02` (Mac) or `0xEF` (EFI).  Only x86 images get
+    /// the isolinux boot info table patched in.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub platform_id: u8,
+    /// Path at which the boot catalog is also listed as a visible one-sector
+    /// file, e.g. `isolinux/boot.cat` (like `mkisofs -c`).  `None` leaves it
+    /// out of the directory tree.
+    pub boot_catalog: Option<String>,
+    /// Load count of the boot entry in 512-byte sectors, e.g. `Some(1)` for
+    /// firmware that should load only the first sector.  `None` loads the
+    /// whole image; an override must not exceed it.
+    pub load_sectors: Option<u16>,
 }
 
 /// Configuration for UEFI boot.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct UefiBootInfo {
     pub boot_image: PathBuf,
-    pub kernel_image: PathBuf,
+    /// Kernel copied into the ESP as `EFI/BOOT/KERNEL.EFI` (isohybrid only).
+    /// `None` leaves it out, e.g. when the loader reads the kernel from the
+    /// ISO 9660 side.
+    pub kernel_image: Option<PathBuf>,
+    /// Path of the boot image.  Isohybrid builds place the loader at the same
+    /// path in the ESP, so it must be a removable-media default such as
+    /// `EFI/BOOT/BOOTX64.EFI`.
     pub destination_in_iso: String,
     /// Additional EFI boot files to include in the ESP FAT image (for isohybrid).
     /// Each entry is (destination_filename, source_path) copied to `EFI/BOOT/` in the ESP.
     /// For example, `("GRUBX64.EFI", path_to_grub)`.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub additional_efi_boot_files: Vec<(String, PathBuf)>,
     /// Optional content for an auto-generated `grub.cfg` placed in `EFI/BOOT/grub.cfg`
     /// in the ESP FAT image. If `None`, no grub.cfg is created.
     /// Example: `Some("set default=0\nset timeout=5\nmenuentry \"Boot\" {\n  chainloader /EFI/BOOT/BOOTX64.EFI\n}")`
     pub grub_cfg_content: Option<String>,
+    /// Skips the check that `boot_image` is a PE32+ (or PE32) EFI application
+    /// before it is copied into the ESP (isohybrid only).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub skip_pe_validation: bool,
+    /// Further payloads copied into the ESP (isohybrid only), each as
+    /// `(source_path, path_in_esp)`, e.g. a recovery kernel at
+    /// `boot/vmlinuz-recovery` next to its initrds.  Parent directories are
+    /// created as needed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub esp_files: Vec<(PathBuf, String)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boot_info(bios: bool, uefi: bool) -> BootInfo {
+        BootInfo {
+            bios_boot: bios.then(|| BiosBootInfo {
+                boot_image: "isolinux.bin".into(),
+                destination_in_iso: "isolinux/isolinux.bin".into(),
+                ..Default::default()
+            }),
+            uefi_boot: uefi.then(|| UefiBootInfo {
+                boot_image: "BOOTX64.EFI".into(),
+                destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_effective_modes() {
+        let modes = |bios, uefi, hybrid: bool| {
+            let m = boot_info(bios, uefi).effective_modes(hybrid);
+            (m.bios, m.uefi, m.bios_disk, m.uefi_disk)
+        };
+        assert_eq!(modes(false, false, false), (false, false, false, false));
+        assert_eq!(modes(false, false, true), (false, false, false, false));
+        assert_eq!(modes(true, false, false), (true, false, false, false));
+        assert_eq!(modes(true, false, true), (true, false, false, false));
+        assert_eq!(modes(false, true, false), (false, true, false, false));
+        assert_eq!(modes(false, true, true), (false, true, false, true));
+        assert_eq!(modes(true, true, false), (true, true, false, false));
+        assert_eq!(modes(true, true, true), (true, true, false, true));
+
+        let both = boot_info(true, true).effective_modes(HybridMode::Both);
+        assert!(both.bios_disk && both.uefi_disk);
+        let mbr = boot_info(true, true).effective_modes(HybridMode::BiosMbr);
+        assert!(mbr.bios_disk && !mbr.uefi_disk);
+    }
 }
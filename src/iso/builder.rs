@@ -1,29 +1,58 @@
+use std::fmt;
 use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use tempfile::NamedTempFile;
 
 use crate::fat;
-use crate::iso::boot_catalog::BootCatalogEntry;
-use crate::iso::boot_catalog::LBA_BOOT_CATALOG;
-use crate::iso::boot_info::BootInfo;
+use crate::iso::aligned_writer::AlignedWriter;
+use crate::iso::apm::{APM_BLOCK_SIZE, ApmPartition, GPT_ENTRIES_WITH_APM, write_apm};
+use crate::iso::autoconfig::AutoConfig;
+use crate::iso::boot_catalog::{
+    BOOT_CATALOG_X86_PLATFORM_ID, BootCatalogEntry, BootCatalogEntryType, BootEmulation,
+    LBA_BOOT_CATALOG, MEDIA_HARD_DISK, MEDIA_NO_EMULATION, check_first_section,
+};
+use crate::iso::boot_info::{BootInfo, UefiBootInfo};
 use crate::iso::builder_utils::{
-    calculate_lbas, create_bios_boot_entry, create_uefi_boot_entry, create_uefi_esp_boot_entry,
-    ensure_directory_path, get_file_metadata, get_file_size_in_iso, get_lba_for_path,
+    LayoutTracker, MAX_DIR_DEPTH, RR_MOVED, calculate_lbas_with_ear, check_name_lengths,
+    collect_name_changes, create_boot_entry, create_boot_entry_with_load_count,
+    create_uefi_boot_entry, create_uefi_esp_boot_entry, create_uefi_raw_esp_boot_entry, dir_count,
+    ensure_directory_path, file_name_in_iso, file_offset_in_tree, first_too_deep,
+    get_file_metadata, get_file_size_in_iso, get_lba_for_path, get_node_for_path,
+    get_parent_dir_mut, iso_path_components, iter_nodes, tree_sectors,
+};
+use crate::iso::constants::{
+    BACKUP_GPT_RESERVED_512, HYBRID_ESP_ALIGN_512, ISO_SECTOR_SIZE, PERSISTENCE_ALIGN_512,
+    SYSTEM_AREA_SIZE,
 };
-use crate::iso::constants::{BACKUP_GPT_RESERVED_512, ISO_SECTOR_SIZE};
+use crate::iso::dir_record::{
+    DirRecordOptions, IsoDirEntry, TimestampSource, is_d_character, recording_date,
+    transliterate_name,
+};
+use crate::iso::discinfo::DiscInfo;
 use crate::iso::disk_layout::DiskLayout;
-use crate::iso::fs_node::{IsoDirectory, IsoFile, IsoFsNode};
-use crate::iso::gpt::main_gpt_functions::write_gpt_structures;
-use crate::iso::gpt::partition_entry::{EFI_SYSTEM_PARTITION_GUID, GptPartitionEntry};
+use crate::iso::fs_node::{IsoDirectory, IsoFile, IsoFsNode, IsoSymlink, ReaderSource};
+use crate::iso::gpt::main_gpt_functions::{GptParams, write_gpt_structures_with};
+use crate::iso::gpt::partition_entry::{
+    EFI_SYSTEM_PARTITION_GUID, GPT_ATTR_ESP_CRC32_MASK, GPT_ATTR_ESP_CRC32_SHIFT,
+    GPT_ATTR_REQUIRED, GptPartitionEntry, LINUX_FILESYSTEM_GUID,
+};
 use crate::iso::iso_image::IsoImage;
 use crate::iso::iso_writer::{
-    copy_files, finalize_iso, write_boot_catalog_to_iso, write_boot_info_table, write_descriptors,
-    write_directories,
+    SetLen, copy_files_prefetched, copy_files_sparse, finalize_iso, write_boot_catalog_to_iso,
+    write_boot_info_table, write_descriptors, write_directories, write_ear_checksums,
+    write_enhanced_directories, write_lba_patch,
 };
-use crate::iso::layout_profile::{HiddenSectorMode, IsoLayoutProfile};
-use crate::iso::mbr::create_mbr_for_gpt_hybrid;
-use crate::iso::volume_descriptor::update_total_sectors_in_pvd;
+use crate::iso::layout_profile::{HiddenSectorMode, HybridMode, IsoLayoutProfile};
+use crate::iso::mbr::{create_mbr_for_bios_hybrid, create_mbr_for_gpt_hybrid};
+use crate::iso::pe;
+use crate::iso::rock_ridge::sl_entry;
+use crate::iso::volume_descriptor::{
+    PVD_APPLICATION_USE_LEN, PvdOptions, VolumeDates, terminator_lba, update_total_sectors_in_pvd,
+    update_total_sectors_in_vd, validate_pvd_identifier, volume_date,
+};
+use crate::utils::SectorWriter;
 
 pub struct IsoBuilder {
     volume_id: Option<String>,
@@ -31,13 +60,105 @@ pub struct IsoBuilder {
     boot_info: Option<BootInfo>,
     iso_data_lba: u32,
     total_sectors: u32,
-    is_isohybrid: bool,
+    hybrid_mode: HybridMode,
     uefi_catalog_path: Option<String>,
     pub esp_lba: Option<u32>,
     pub esp_size_sectors: Option<u32>,
     profile: IsoLayoutProfile,
     disk_layout: Option<DiskLayout>,
     efi_boot_image_iso_path: Option<String>,
+    copy_parallelism: usize,
+    pvd_opts: PvdOptions,
+    mbr_boot_code: Option<Vec<u8>>,
+    /// Disk signature at byte 440 of the hybrid MBR.
+    mbr_disk_signature: u32,
+    system_area: Option<Vec<u8>>,
+    dir_opts: DirRecordOptions,
+    strict_names: bool,
+    apm_volume_name: Option<String>,
+    autoconfig: Option<AutoConfig>,
+    disc_info: Option<DiscInfo>,
+    direct_io: bool,
+    lba_patches: Vec<(String, u64)>,
+    sparse_copy: bool,
+    strict_layout: bool,
+    boot_catalog_id: Option<[u8; 24]>,
+    /// LBA of the boot catalog, if not right after the terminator.
+    boot_catalog_lba: Option<u32>,
+    /// LBA and `(path, platform ID)` entries of a second boot catalog.
+    secondary_boot_catalog: Option<(u32, Vec<(String, u8)>)>,
+    /// Size of the device the hybrid image is meant for, in 512-byte
+    /// sectors.
+    target_device_512: Option<u64>,
+    /// Size in 512-byte sectors and label of the persistence partition.
+    persistence: Option<(u64, String)>,
+    /// Seed of the GPT disk and partition GUIDs; `None` makes them random.
+    build_seed: Option<u64>,
+    /// Timestamp source set by [`Self::set_timestamp_source`]; a build seed
+    /// overrides it with [`TimestampSource::Fixed`] in `write_image`.
+    timestamps: TimestampSource,
+    /// Attribute bits of the hybrid GPT's ESP entry.
+    esp_gpt_attributes: u64,
+    /// Store the ESP's CRC32 in its GPT attributes.
+    esp_integrity_check: bool,
+    /// Media emulation of the BIOS boot entry.
+    bios_emulation: BootEmulation,
+    /// Boot entries written to the catalog by the last build.
+    boot_entries: Vec<BootCatalogEntry>,
+    /// Non-bootable catalog entries as (path in ISO, platform ID).
+    auxiliary_boot_entries: Vec<(String, u8)>,
+    /// Write an ISO 9660:1999 Enhanced Volume Descriptor and its tree.
+    enhanced_vd: bool,
+    /// Interchange level whose identifier lengths are enforced, if any.
+    iso_level: Option<u8>,
+    /// Whether `rr_moved` was added by [`Self::prepare_relocation`].
+    added_rr_moved: bool,
+    /// Path of the entry added by [`Self::place_boot_catalog`].
+    added_boot_catalog: Option<String>,
+    /// Files from [`Self::add_file_lazy`] as (path in ISO, host path).
+    lazy_files: Vec<(String, PathBuf)>,
+    /// Paths added by [`Self::add_generated_file`] for the running build,
+    /// each file before the directories created for it, deepest first.
+    generated_paths: Vec<String>,
+    /// File given extra padding sectors by [`Self::align_hybrid_esp`] for
+    /// the running build, and how many.
+    esp_padding: Option<(String, u32)>,
+    /// Directory where [`Self::add_uefi_esp`] keeps built ESP images.
+    esp_cache_dir: Option<PathBuf>,
+    post_process: Option<PostProcess>,
+}
+
+/// Hook run on the finished image by [`IsoBuilder::build`].
+pub type PostProcess = Box<dyn FnOnce(&Path) -> io::Result<()> + Send>;
+
+/// The FAT ESP image added by [`IsoBuilder::add_uefi_esp`].
+#[derive(Debug)]
+pub struct EspImage {
+    /// Host path of the image, in the cache directory if one is set.
+    pub path: PathBuf,
+    /// Size of the image in 512-byte sectors.
+    pub sectors: u32,
+    /// Whether an image built earlier was reused from the cache directory.
+    pub cache_hit: bool,
+    /// Owns an image built without a cache directory; it is deleted when
+    /// dropped, so keep it until the build has finished.
+    pub temp_file: Option<NamedTempFile>,
+}
+
+/// What [`build_iso`] produced.
+#[derive(Debug)]
+pub struct BuildOutput {
+    /// Path of the ISO image.
+    pub iso_path: PathBuf,
+    /// Owns the temporary FAT ESP image built in GPT hybrid modes; it is
+    /// deleted when dropped.
+    pub esp: Option<NamedTempFile>,
+    /// Open read/write handle to the ISO image.
+    pub file: File,
+    /// Size of the ESP image in 512-byte sectors, if one was built.
+    pub esp_sectors: Option<u32>,
+    /// Total image size in 2048-byte sectors, as [`IsoBuilder::total_sectors`].
+    pub total_sectors: u32,
 }
 
 impl Default for IsoBuilder {
@@ -54,13 +175,48 @@ impl IsoBuilder {
             boot_info: None,
             iso_data_lba: 0,
             total_sectors: 0,
-            is_isohybrid: false,
+            hybrid_mode: HybridMode::None,
             uefi_catalog_path: None,
             esp_lba: None,
             esp_size_sectors: None,
             profile: IsoLayoutProfile::default(),
             disk_layout: None,
             efi_boot_image_iso_path: None,
+            copy_parallelism: 0,
+            pvd_opts: PvdOptions::default(),
+            mbr_boot_code: None,
+            mbr_disk_signature: 0,
+            system_area: None,
+            dir_opts: DirRecordOptions::default(),
+            strict_names: false,
+            apm_volume_name: None,
+            autoconfig: None,
+            disc_info: None,
+            direct_io: false,
+            lba_patches: Vec::new(),
+            sparse_copy: false,
+            strict_layout: false,
+            boot_catalog_id: None,
+            boot_catalog_lba: None,
+            secondary_boot_catalog: None,
+            target_device_512: None,
+            persistence: None,
+            build_seed: None,
+            timestamps: TimestampSource::Fixed,
+            esp_gpt_attributes: GPT_ATTR_REQUIRED,
+            esp_integrity_check: false,
+            bios_emulation: BootEmulation::NoEmulation,
+            boot_entries: Vec::new(),
+            auxiliary_boot_entries: Vec::new(),
+            enhanced_vd: false,
+            iso_level: None,
+            added_rr_moved: false,
+            added_boot_catalog: None,
+            lazy_files: Vec::new(),
+            generated_paths: Vec::new(),
+            esp_padding: None,
+            esp_cache_dir: None,
+            post_process: None,
         }
     }
 
@@ -68,432 +224,5049 @@ impl IsoBuilder {
         self.volume_id = v;
     }
 
-    pub fn add_file(&mut self, path_in_iso: &str, real_path: &Path) -> io::Result<()> {
-        let file_name = Path::new(path_in_iso)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid file name"))?
-            .to_string();
-        let current_dir = ensure_directory_path(&mut self.root, path_in_iso)?;
-        let sz = get_file_metadata(real_path)?.len();
-        current_dir.children.insert(
-            file_name,
-            IsoFsNode::File(IsoFile {
-                path: real_path.to_path_buf(),
-                size: sz,
-                lba: 0,
-            }),
-        );
+    /// Sets the `;N` version suffix on file identifiers.  `None` omits the
+    /// suffix; the default is `Some(1)`.  Fails with `InvalidInput` outside
+    /// 1..=32767 (ECMA-119 7.5.2).
+    pub fn set_file_version(&mut self, v: Option<u16>) -> io::Result<()> {
+        if let Some(n) = v
+            && !(1..=32767).contains(&n)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("File version {n} outside 1..=32767"),
+            ));
+        }
+        self.dir_opts.file_version = v;
         Ok(())
     }
 
-    pub fn set_boot_info(&mut self, bi: BootInfo) {
-        self.boot_info = Some(bi);
-    }
-    pub fn set_profile(&mut self, p: IsoLayoutProfile) {
-        self.profile = p;
+    /// Precedes every file with a one-sector extended attribute record
+    /// holding a CRC32 of the file data.
+    pub fn set_compute_ear_checksums(&mut self, v: bool) {
+        self.dir_opts.ear_checksums = v;
     }
-    pub fn set_isohybrid(&mut self, v: bool) {
-        self.is_isohybrid = v;
+
+    /// In strict mode the build fails if any name contains characters outside
+    /// the ISO 9660 d-character set; otherwise (the default) they are
+    /// replaced, see [`Self::transliterated_names`].
+    pub fn set_strict_names(&mut self, strict: bool) {
+        self.strict_names = strict;
     }
-    pub fn set_disk_layout(&mut self, l: DiskLayout) {
-        self.disk_layout = Some(l);
+
+    /// Sets the character substituted for illegal name characters (default
+    /// `_`).  Must itself be a d-character.
+    pub fn set_name_replacement(&mut self, c: char) -> io::Result<()> {
+        if !is_d_character(c) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("replacement {c:?} is not an ISO 9660 d-character"),
+            ));
+        }
+        self.dir_opts.replacement = c;
+        Ok(())
     }
 
-    fn prepare_boot_entries(
-        &self,
-        esp_lba: Option<u32>,
-        esp_size_sectors: Option<u32>,
-    ) -> io::Result<Vec<BootCatalogEntry>> {
-        use crate::iso::boot_catalog::{BOOT_CATALOG_EFI_PLATFORM_ID, BootCatalogEntryType};
-        let mut entries = Vec::new();
-        let bi = self.boot_info.as_ref();
+    /// Returns `(original, transliterated)` for every path whose ISO 9660
+    /// name differs from the one given to `add_file` beyond upper-casing.
+    pub fn transliterated_names(&self) -> io::Result<Vec<(String, String)>> {
+        let mut out = Vec::new();
+        collect_name_changes(&self.root, "", self.dir_opts.replacement, &mut out)?;
+        Ok(out)
+    }
 
-        let bios_boot_info = bi.and_then(|b| b.bios_boot.as_ref());
-        let uefi_boot_info = bi.and_then(|b| b.uefi_boot.as_ref());
+    /// Every file, directory, symlink and boot catalog node added so far
+    /// with its full path in the ISO, depth-first in sorted order (the
+    /// order files are laid out in), each directory before its contents.
+    pub fn iter_nodes(&self) -> impl Iterator<Item = (String, &IsoFsNode)> {
+        iter_nodes(&self.root)
+    }
 
-        // Validate ESP parameters (always, not only when UEFI boot is requested)
-        match (esp_lba, esp_size_sectors) {
-            (Some(_), None) | (None, Some(_)) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Invalid ESP configuration: esp_lba and esp_size_sectors must both be Some or both be None",
-                ));
+    /// Every host file a build may open, sorted and without duplicates:
+    /// the sources of added and lazily added files, and the boot, kernel
+    /// and ESP files named by the boot information.  Files added with
+    /// [`Self::add_reader`] have no host path and are left out.
+    pub fn source_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = iter_nodes(&self.root)
+            .filter_map(|(_, node)| match node {
+                IsoFsNode::File(file) if file.reader.is_none() => Some(file.path.clone()),
+                _ => None,
+            })
+            .collect();
+        files.extend(self.lazy_files.iter().map(|(_, path)| path.clone()));
+        if let Some(bi) = &self.boot_info {
+            if let Some(bios) = &bi.bios_boot {
+                files.push(bios.boot_image.clone());
             }
-            (Some(_), Some(0)) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Invalid ESP configuration: esp_size_sectors cannot be zero when esp_lba is provided",
-                ));
+            if let Some(uefi) = &bi.uefi_boot {
+                files.push(uefi.boot_image.clone());
+                files.extend(uefi.kernel_image.iter().cloned());
+                files.extend(
+                    uefi.additional_efi_boot_files
+                        .iter()
+                        .map(|(_, p)| p.clone()),
+                );
+                files.extend(uefi.esp_files.iter().map(|(p, _)| p.clone()));
             }
-            _ => {}
         }
+        files.sort();
+        files.dedup();
+        files
+    }
 
-        // Determine effective UEFI LBA/size
-        let (has_uefi, uefi_lba, uefi_size_sectors) =
-            if let (Some(lba), Some(size)) = (esp_lba, esp_size_sectors) {
-                if size > 0 {
-                    (true, lba, size)
-                } else {
-                    (false, 0, 0)
-                }
-            } else {
-                (false, 0, 0)
-            };
+    /// Sets the PVD Publisher Identifier (≤128 a-characters).
+    pub fn set_publisher_id(&mut self, v: &str) -> io::Result<()> {
+        validate_pvd_identifier("Publisher ID", v)?;
+        self.pvd_opts.publisher_id = Some(v.to_string());
+        Ok(())
+    }
 
-        // --- BIOS as Initial/Default Entry (if present) ---
-        // SeaBIOS only checks the Initial/Default Entry; if its platform_id
-        // is 0xEF (UEFI), SeaBIOS skips BIOS boot entirely.  Placing BIOS
-        // here ensures it can boot on legacy firmware while UEFI firmware
-        // discovers the EFI entries via the Section Header with
-        // platform_id=0xEF.
-        if let Some(bios) = bios_boot_info {
-            entries.push(create_bios_boot_entry(
-                &self.root,
-                &bios.destination_in_iso,
-            )?);
+    /// Sets the PVD Data Preparer Identifier (≤128 a-characters).
+    pub fn set_preparer_id(&mut self, v: &str) -> io::Result<()> {
+        validate_pvd_identifier("Data Preparer ID", v)?;
+        self.pvd_opts.preparer_id = Some(v.to_string());
+        Ok(())
+    }
 
-            // UEFI entries follow under a dedicated Section Header
-            if has_uefi {
-                entries.push(BootCatalogEntry {
-                    platform_id: BOOT_CATALOG_EFI_PLATFORM_ID,
-                    boot_image_lba: 0,
-                    boot_image_sectors: 0,
-                    entry_type: BootCatalogEntryType::SectionHeader { more_follow: false },
-                });
-                entries.push(create_uefi_esp_boot_entry(uefi_lba, uefi_size_sectors)?);
-            } else if let Some(u) = uefi_boot_info {
-                // BIOS + non-isohybrid UEFI: UEFI entry under a Section Header
-                entries.push(BootCatalogEntry {
-                    platform_id: BOOT_CATALOG_EFI_PLATFORM_ID,
-                    boot_image_lba: 0,
-                    boot_image_sectors: 0,
-                    entry_type: BootCatalogEntryType::SectionHeader { more_follow: false },
-                });
-                entries.push(create_uefi_boot_entry(&self.root, &u.destination_in_iso)?);
-            }
-        } else {
-            // UEFI-only boot: UEFI BootEntry is the Initial/Default Entry.
-            // El Torito spec requires offset 32 to be a BootEntry, NOT a
-            // SectionHeader.  A Section Header follows for firmware that
-            // requires platform_id=0xEF to discover the entry.
-            if has_uefi {
-                // Initial / Default entry: sector_count MUST be 0 for
-                // no-emulation boot according to El Torito spec § 6.4.
-                entries.push(BootCatalogEntry {
-                    platform_id: BOOT_CATALOG_EFI_PLATFORM_ID,
-                    boot_image_lba: uefi_lba,
-                    boot_image_sectors: 0,
-                    entry_type: BootCatalogEntryType::BootEntry { bootable: true },
-                });
-                entries.push(BootCatalogEntry {
-                    platform_id: BOOT_CATALOG_EFI_PLATFORM_ID,
-                    boot_image_lba: 0,
-                    boot_image_sectors: 0,
-                    entry_type: BootCatalogEntryType::SectionHeader { more_follow: false },
-                });
-                entries.push(create_uefi_esp_boot_entry(uefi_lba, uefi_size_sectors)?);
-            } else if let Some(u) = uefi_boot_info {
-                entries.push(create_uefi_boot_entry(&self.root, &u.destination_in_iso)?);
-            }
+    /// Sets the PVD Application Identifier (≤128 a-characters).
+    pub fn set_application_id(&mut self, v: &str) -> io::Result<()> {
+        validate_pvd_identifier("Application ID", v)?;
+        self.pvd_opts.application_id = Some(v.to_string());
+        Ok(())
+    }
+
+    /// Sets the PVD application-use area (bytes 883–1394, ≤512 bytes), e.g.
+    /// for a build ID or signature.
+    pub fn set_application_use(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() > PVD_APPLICATION_USE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "application use data too large ({} > {PVD_APPLICATION_USE_LEN} bytes)",
+                    data.len()
+                ),
+            ));
         }
-        Ok(entries)
+        self.pvd_opts.application_use = Some(data.to_vec());
+        Ok(())
     }
 
-    fn write_hybrid_structures(
-        &self,
-        iso_file: &mut File,
-        total_lbas: u64,
-        esp_size_sectors: Option<u32>,
-    ) -> io::Result<()> {
-        let raw_512 = total_lbas
-            .checked_mul(4)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "ISO too large"))?;
-        let total_512 = ((raw_512 + BACKUP_GPT_RESERVED_512) + 3) & !3u64;
-        let total_for_mbr = u32::try_from(total_512)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "ISO too large for MBR"))?;
+    /// Sets the PVD's volume creation, modification, expiration and
+    /// effective dates.  Fails with `InvalidInput` if a date is outside
+    /// 1900-2155.
+    pub fn set_volume_dates(&mut self, dates: VolumeDates) -> io::Result<()> {
+        for time in [
+            dates.creation,
+            dates.modification,
+            dates.expiration,
+            dates.effective,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            volume_date(time)?;
+        }
+        self.pvd_opts.dates = dates;
+        Ok(())
+    }
 
-        let (esp_start_512, esp_size_512) =
-            if let (Some(l), Some(s)) = (self.esp_lba, self.esp_size_sectors) {
-                (
-                    u32::try_from(l as u64 * 4).ok(),
-                    u32::try_from(s as u64 * 4).ok(),
-                )
-            } else if let Some(ref layout) = self.disk_layout {
-                layout.esp_partition().map_or((None, None), |esp| {
-                    (
-                        Some(esp.start_lba_512 as u32),
-                        Some(esp.size_lba_512 as u32),
-                    )
-                })
-            } else if let Some(sz) = esp_size_sectors {
-                (Some(self.profile.esp_alignment_lba_512), Some(sz * 4))
-            } else {
-                (None, None)
-            };
+    pub fn add_file(&mut self, path_in_iso: &str, real_path: &Path) -> io::Result<()> {
+        self.add_file_with_padding(path_in_iso, real_path, 0)
+    }
 
-        iso_file.seek(SeekFrom::Start(0))?;
-        if self.profile.use_gpt {
-            create_mbr_for_gpt_hybrid(
-                total_for_mbr,
-                self.is_isohybrid,
-                esp_start_512,
-                esp_size_512,
-            )?
-            .write_to(iso_file)?;
+    /// Like [`Self::add_file`], but reserves `extra_sectors` zero-filled
+    /// sectors after the file, e.g. as slack a bootloader may overwrite in
+    /// place.  The directory record still reports the real file size.
+    pub fn add_file_with_padding(
+        &mut self,
+        path_in_iso: &str,
+        real_path: &Path,
+        extra_sectors: u32,
+    ) -> io::Result<()> {
+        let metadata = get_file_metadata(real_path)
+            .map_err(|e| io::Error::new(e.kind(), format!("Cannot add '{path_in_iso}': {e}")))?;
+        self.insert_file(
+            path_in_iso,
+            IsoFile {
+                path: real_path.to_path_buf(),
+                size: metadata.len(),
+                lba: 0,
+                padding_sectors: extra_sectors,
+                zero_fill: 0,
+                modified: metadata.modified().ok(),
+                reader: None,
+                executable: false,
+            },
+        )
+    }
 
-            let mut parts = Vec::new();
-            let start: u64 = 34;
-            let end: u64 = total_512.saturating_sub(34);
-            if end > start {
-                parts.push(GptPartitionEntry::new(
-                    "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7",
-                    &uuid::Uuid::new_v4().to_string(),
-                    start,
-                    end,
-                    "ISO9660",
-                    0,
-                ));
-            }
-            if let (Some(s), Some(sz)) = (esp_start_512, esp_size_512) {
-                let e = s.saturating_add(sz).saturating_sub(1);
-                if e > s {
-                    parts.push(GptPartitionEntry::new(
-                        EFI_SYSTEM_PARTITION_GUID,
-                        &uuid::Uuid::new_v4().to_string(),
-                        s as u64,
-                        e as u64,
-                        "EFI System Partition",
-                        1,
-                    ));
-                }
-            }
-            if !parts.is_empty() {
-                write_gpt_structures(iso_file, total_512, &parts)?;
-            }
+    /// Like [`Self::add_file`], but `real_path` need not exist yet: it is
+    /// looked up when the image is built, which fails with `NotFound` if it
+    /// is still missing.  Until then the file is not part of the tree, e.g.
+    /// for [`Self::iter_nodes`] or [`Self::min_hybrid_sectors`].
+    pub fn add_file_lazy(&mut self, path_in_iso: &str, real_path: &Path) -> io::Result<()> {
+        file_name_in_iso(path_in_iso)?;
+        self.check_record_lengths(path_in_iso)?;
+        self.lazy_files
+            .push((path_in_iso.to_string(), real_path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Adds the files of [`Self::add_file_lazy`] to the tree.  They stay
+    /// registered, so a later build picks up changed sources again.
+    fn add_lazy_files(&mut self) -> io::Result<()> {
+        for (path_in_iso, real_path) in self.lazy_files.clone() {
+            self.add_file(&path_in_iso, &real_path)?;
         }
-        iso_file.sync_data()?;
         Ok(())
     }
 
-    pub fn build(
+    /// Adds a file whose `size` bytes of data come from `reader` rather than
+    /// a file on disk, e.g. a generated blob.  The reader is consumed when
+    /// the image is built, so the builder can only be built once; a reader
+    /// yielding more or fewer than `size` bytes fails the build with
+    /// `InvalidData`.
+    pub fn add_reader(
         &mut self,
-        iso_file: &mut File,
-        _iso_path: &Path,
-        esp_lba: Option<u32>,
-        esp_size_sectors: Option<u32>,
+        path_in_iso: &str,
+        reader: Box<dyn Read + Send>,
+        size: u64,
     ) -> io::Result<()> {
-        self.esp_lba = esp_lba;
-        self.esp_size_sectors = esp_size_sectors;
-
-        self.iso_data_lba = self
-            .disk_layout
-            .as_ref()
-            .map_or(LBA_BOOT_CATALOG + 1, |l| l.iso_region.data_start_lba);
-        iso_file.seek(SeekFrom::Start(self.iso_data_lba as u64 * ISO_SECTOR_SIZE))?;
-        calculate_lbas(&mut self.iso_data_lba, &mut self.root)?;
+        self.insert_file(
+            path_in_iso,
+            IsoFile {
+                path: PathBuf::new(),
+                size,
+                lba: 0,
+                padding_sectors: 0,
+                zero_fill: 0,
+                modified: None,
+                reader: Some(ReaderSource::new(reader)),
+                executable: false,
+            },
+        )
+    }
 
-        let (resolved_lba, resolved_size) = if let Some(ref ip) = self.efi_boot_image_iso_path {
-            (
-                Some(get_lba_for_path(&self.root, ip)?),
-                Some(get_file_size_in_iso(&self.root, ip)?.div_ceil(ISO_SECTOR_SIZE) as u32),
-            )
-        } else {
-            (esp_lba, esp_size_sectors)
-        };
-        self.esp_lba = resolved_lba;
-        self.esp_size_sectors = resolved_size;
+    fn insert_file(&mut self, path_in_iso: &str, file: IsoFile) -> io::Result<()> {
+        let file_name = file_name_in_iso(path_in_iso)?;
+        self.check_record_lengths(path_in_iso)?;
+        let current_dir = ensure_directory_path(&mut self.root, path_in_iso)?;
+        current_dir
+            .children
+            .insert(file_name, IsoFsNode::File(file));
+        Ok(())
+    }
 
-        write_descriptors(
-            iso_file,
-            self.volume_id.as_deref(),
-            self.root.lba,
-            self.iso_data_lba,
-        )?;
-        write_boot_catalog_to_iso(
-            iso_file,
-            LBA_BOOT_CATALOG,
-            self.prepare_boot_entries(resolved_lba, resolved_size)?,
-        )?;
-        write_directories(iso_file, &self.root, self.root.lba)?;
-        copy_files(iso_file, &self.root)?;
+    /// Fails with `InvalidInput`, naming `path_in_iso`, if one of its
+    /// components does not fit a 255-byte directory record with the name
+    /// options set so far.
+    fn check_record_lengths(&self, path_in_iso: &str) -> io::Result<()> {
+        let components: Vec<_> = iso_path_components(path_in_iso).collect();
+        for (i, name) in components.iter().enumerate() {
+            let entry = IsoDirEntry {
+                lba: 0,
+                size: 0,
+                flags: if i + 1 < components.len() { 0x02 } else { 0 },
+                name,
+            };
+            entry.to_bytes_with(&self.dir_opts).map_err(|e| {
+                io::Error::new(e.kind(), format!("Cannot add '{path_in_iso}': {e}"))
+            })?;
+        }
+        Ok(())
+    }
 
-        // Capture the exact end of the newly written ISO data *before*
-        // patching the boot information table (which seeks back into the
-        // data stream).  Using this saved position in the seek below is
-        // more robust than SeekFrom::End(0) because it does not depend on
-        // whether the underlying file was truncated before being passed in.
-        let end_of_data = iso_file.stream_position()?;
+    /// Writes the file's own LBA, as a little-endian `u32`, into its data at
+    /// byte `offset` when the image is built, so payloads can locate
+    /// themselves on the disc.  The file must already have been added; the
+    /// source file is left untouched.
+    pub fn patch_file_with_lba(&mut self, path_in_iso: &str, offset: u64) -> io::Result<()> {
+        self.lba_patch_target(path_in_iso, offset)?;
+        self.lba_patches.push((path_in_iso.to_string(), offset));
+        Ok(())
+    }
 
-        if let Some(bi) = &self.boot_info
-            && let Some(bios) = &bi.bios_boot
-        {
-            let lba = get_lba_for_path(&self.root, &bios.destination_in_iso)?;
-            let size = get_file_size_in_iso(&self.root, &bios.destination_in_iso)?;
-            write_boot_info_table(iso_file, lba, size)?;
+    /// Returns the LBA of `path_in_iso`, failing if four bytes at `offset`
+    /// do not fit inside the file.
+    fn lba_patch_target(&self, path_in_iso: &str, offset: u64) -> io::Result<u32> {
+        let size = get_file_size_in_iso(&self.root, path_in_iso)?;
+        if offset.checked_add(4).is_none_or(|end| end > size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "LBA patch at offset {offset} does not fit in '{path_in_iso}' ({size} bytes)"
+                ),
+            ));
         }
+        get_lba_for_path(&self.root, path_in_iso)
+    }
 
-        // Seek back to the saved end-of-data position so finalize_iso can
-        // compute the correct total sector count.
-        iso_file.seek(SeekFrom::Start(end_of_data))?;
+    /// Records `path_in_iso` with mode `0o555` instead of `0o444`.  Without
+    /// [`Self::set_rock_ridge`] the file still gets a Rock Ridge `PX` entry,
+    /// and the root announces SUSP and RRIP, but no other entries are
+    /// written.  The file must already have been added.
+    pub fn set_file_executable(&mut self, path_in_iso: &str, executable: bool) -> io::Result<()> {
+        let Some((parent, name)) = get_parent_dir_mut(&mut self.root, path_in_iso)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Path not found: {path_in_iso}"),
+            ));
+        };
+        match parent.children.get_mut(name) {
+            Some(IsoFsNode::File(file)) => {
+                file.executable = executable;
+                Ok(())
+            }
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Path is not a file: {path_in_iso}"),
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Path not found: {path_in_iso}"),
+            )),
+        }
+    }
 
-        finalize_iso(iso_file, &mut self.total_sectors)?;
+    /// Removes the file or symlink at `path_in_iso`.  Returns `false` if
+    /// nothing exists there; fails if the path is a directory.
+    pub fn remove_file(&mut self, path_in_iso: &str) -> io::Result<bool> {
+        let lazy = self.lazy_files.len();
+        self.lazy_files
+            .retain(|(p, _)| !iso_path_components(p).eq(iso_path_components(path_in_iso)));
+        if self.lazy_files.len() < lazy {
+            // Also drop the copy a previous build put in the tree.
+            self.remove_file(path_in_iso)?;
+            return Ok(true);
+        }
+        let Some((parent, name)) = get_parent_dir_mut(&mut self.root, path_in_iso)? else {
+            return Ok(false);
+        };
+        if let Some(IsoFsNode::Directory(_)) = parent.children.get(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Path is a directory: {path_in_iso}"),
+            ));
+        }
+        Ok(parent.children.remove(name).is_some())
+    }
 
-        if self.is_isohybrid {
-            self.write_hybrid_structures(iso_file, self.total_sectors as u64, esp_size_sectors)?;
-            let pos = iso_file.seek(SeekFrom::End(0))?;
-            let rem = pos % ISO_SECTOR_SIZE;
-            if rem != 0 {
-                io::copy(&mut io::repeat(0).take(ISO_SECTOR_SIZE - rem), iso_file)?;
+    /// Removes the directory at `path_in_iso`.  Returns `false` if nothing
+    /// exists there; fails if the path is not a directory, or is non-empty
+    /// and `recursive` is not set.
+    pub fn remove_dir(&mut self, path_in_iso: &str, recursive: bool) -> io::Result<bool> {
+        let Some((parent, name)) = get_parent_dir_mut(&mut self.root, path_in_iso)? else {
+            return Ok(false);
+        };
+        match parent.children.get(name) {
+            None => return Ok(false),
+            Some(IsoFsNode::Directory(d)) => {
+                if !recursive && !d.children.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::DirectoryNotEmpty,
+                        format!("Directory not empty: {path_in_iso}"),
+                    ));
+                }
             }
-            let total = u32::try_from(iso_file.seek(SeekFrom::End(0))?.div_ceil(ISO_SECTOR_SIZE))
-                .map_err(|_| {
-                io::Error::new(
+            Some(_) => {
+                return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
-                    "ISO too large after GPT backup",
-                )
-            })?;
-            update_total_sectors_in_pvd(iso_file, total)?;
-            self.total_sectors = total;
+                    format!("Path is not a directory: {path_in_iso}"),
+                ));
+            }
         }
+        Ok(parent.children.remove(name).is_some())
+    }
+
+    /// Adds a symbolic link at `path_in_iso` pointing to `target`.  With Rock
+    /// Ridge enabled it carries an `SL` entry; plain ISO 9660 readers see an
+    /// empty file.
+    pub fn add_symlink(&mut self, path_in_iso: &str, target: &str) -> io::Result<()> {
+        let file_name = file_name_in_iso(path_in_iso)?;
+        // Reject targets that cannot be encoded before they reach the writer.
+        sl_entry(target)?;
+        self.check_record_lengths(path_in_iso)?;
+        let current_dir = ensure_directory_path(&mut self.root, path_in_iso)?;
+        current_dir.children.insert(
+            file_name,
+            IsoFsNode::Symlink(IsoSymlink {
+                target: target.to_string(),
+            }),
+        );
         Ok(())
     }
-}
 
-pub fn build_iso(
-    iso_path: &Path,
-    image: &IsoImage,
-    is_isohybrid: bool,
-) -> io::Result<(PathBuf, Option<NamedTempFile>, File, Option<u32>)> {
-    let mut b = IsoBuilder::new();
-    b.set_profile(image.layout_profile.clone());
-    b.set_volume_id(image.volume_id.clone());
-    b.set_isohybrid(is_isohybrid);
+    /// Adds Rock Ridge (RRIP 1.10) System Use entries to directory records:
+    /// POSIX modes, original file names and symlink targets.
+    pub fn set_rock_ridge(&mut self, v: bool) {
+        self.dir_opts.rock_ridge = v;
+    }
 
-    let mut fat_holder: Option<NamedTempFile> = None;
-    let mut _grub_holder: Option<NamedTempFile> = None;
-    let mut fat_size_512: Option<u32> = None;
-    let mut iso_file = OpenOptions::new()
+    /// Selects the ISO 9660 edition: `1` (the default) or `2`, which adds an
+    /// ISO 9660:1999 Enhanced Volume Descriptor and a second directory tree
+    /// whose names are kept as given (mixed case, no `;1`, up to 207 bytes).
+    /// Readers that only know the PVD still see the primary tree.
+    pub fn set_iso9660_version(&mut self, version: u8) -> io::Result<()> {
+        self.enhanced_vd = match version {
+            1 => false,
+            2 => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported ISO 9660 version {version} (expected 1 or 2)"),
+                ));
+            }
+        };
+        Ok(())
+    }
+
+    /// Selects the ISO 9660 interchange level, `1`, `2` or `3`.  The build
+    /// then fails if an identifier is too long for it: level 1 allows 8.3
+    /// file names and 8-character directory names, levels 2 and 3 allow 31
+    /// characters.  Level 3 also splits files over 4 GiB into several
+    /// extents.  Without a level, identifier lengths are not checked.
+    pub fn set_iso_level(&mut self, level: u8) -> io::Result<()> {
+        if !(1..=3).contains(&level) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported ISO 9660 level {level} (expected 1, 2 or 3)"),
+            ));
+        }
+        self.iso_level = Some(level);
+        self.dir_opts.multi_extent = level == 3;
+        Ok(())
+    }
+
+    /// Makes the image depend on its inputs alone: the hybrid GPT's disk
+    /// and partition GUIDs are derived from the build seed (see
+    /// [`Self::set_build_seed`], `0` unless set) instead of being random,
+    /// and dates are written as with [`TimestampSource::Fixed`].  The
+    /// FAT volume ID of generated ESPs is always
+    /// [`fat::DEFAULT_VOLUME_ID`].  `false` brings back random GUIDs and
+    /// the source set by [`Self::set_timestamp_source`].
+    pub fn set_reproducible(&mut self, v: bool) {
+        if v {
+            self.set_build_seed(self.build_seed.unwrap_or(0));
+        } else {
+            self.build_seed = None;
+        }
+    }
+
+    /// Like [`Self::set_reproducible`]`(true)`, deriving the GUIDs from
+    /// `seed`: equal seeds give byte-identical images from identical
+    /// inputs, different seeds differ in their GUIDs.
+    pub fn set_build_seed(&mut self, seed: u64) {
+        self.build_seed = Some(seed);
+    }
+
+    /// The `n`th GUID of the hybrid GPT: derived from the build seed, if
+    /// any, or random.
+    fn gpt_guid(&self, n: u64) -> String {
+        let Some(seed) = self.build_seed else {
+            return uuid::Uuid::new_v4().to_string();
+        };
+        // SplitMix64 over the seed, two words per GUID.
+        let mut state = seed ^ n.wrapping_mul(0xD1B5_4A32_D192_ED03);
+        let mut next = || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&next().to_le_bytes());
+        bytes[8..].copy_from_slice(&next().to_le_bytes());
+        uuid::Builder::from_random_bytes(bytes)
+            .into_uuid()
+            .to_string()
+    }
+
+    /// Selects where directory record dates come from.  The default,
+    /// [`TimestampSource::Fixed`], leaves them zero so that identical inputs
+    /// give identical images.
+    pub fn set_timestamp_source(&mut self, source: TimestampSource) {
+        self.timestamps = source;
+    }
+
+    /// Marks the volume as CD-ROM XA: `CD-XA001` is written at PVD byte 1024
+    /// and every directory record carries an XA System Use field declaring
+    /// Mode 2 Form 1 data.  The image itself keeps 2048-byte sectors; the
+    /// mastering tool lays them out as Form 1.
+    pub fn set_cd_xa(&mut self, v: bool) {
+        self.pvd_opts.cd_xa = v;
+        self.dir_opts.cd_xa = v;
+    }
+
+    pub fn set_boot_info(&mut self, bi: BootInfo) {
+        self.boot_info = Some(bi);
+    }
+
+    /// Boots the BIOS image as an emulated floppy or hard disk of the given
+    /// geometry instead of loading it directly.  The image must be exactly
+    /// the size the geometry describes (e.g. 2880 sectors for a 1.44 MB
+    /// floppy), and a hard disk image must start with an MBR; the build
+    /// fails with `InvalidInput` otherwise.  A shorter floppy image is
+    /// zero-padded to the full size on the disc instead.  The boot entry then loads one
+    /// sector unless [`BiosBootInfo::load_sectors`] says otherwise, and no
+    /// boot information table is patched into the image.  Fails with
+    /// `InvalidInput` on a geometry that is no standard floppy or exceeds
+    /// the CHS limits of a hard disk.
+    ///
+    /// [`BiosBootInfo::load_sectors`]: crate::iso::boot_info::BiosBootInfo::load_sectors
+    pub fn set_bios_emulation(&mut self, emulation: BootEmulation) -> io::Result<()> {
+        emulation.media_type()?;
+        self.bios_emulation = emulation;
+        Ok(())
+    }
+    /// Replaces `EL TORITO SPECIFICATION` in the boot catalog's validation
+    /// entry with `id`, e.g. a manufacturer string some firmware expects.
+    /// `None` restores the default.
+    pub fn set_boot_catalog_id(&mut self, id: Option<[u8; 24]>) {
+        self.boot_catalog_id = id;
+    }
+    /// Places the boot catalog at `lba` instead of right after the volume
+    /// descriptor set terminator, e.g. to leave room for descriptors added
+    /// later.  The boot record points at it, the sectors before it are
+    /// zeroed and file data starts after it.  Fails with `InvalidInput`
+    /// below [`LBA_BOOT_CATALOG`]; the build fails the same way if the
+    /// terminator reaches `lba`.  `None` restores the default.
+    pub fn set_boot_catalog_lba(&mut self, lba: Option<u32>) -> io::Result<()> {
+        if let Some(lba) = lba
+            && lba < LBA_BOOT_CATALOG
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("boot catalog LBA {lba} lies inside the volume descriptor set"),
+            ));
+        }
+        self.boot_catalog_lba = lba;
+        Ok(())
+    }
+
+    /// Adds a second boot catalog at `lba`, with its own boot record, for
+    /// firmware that scans every boot record, e.g. on multi-architecture
+    /// discs.  Its first `(path_in_iso, platform_id)` entry is the
+    /// Initial/Default Entry and the others follow under a section header
+    /// per platform, all bootable.  The extra boot record moves the
+    /// terminator, and with it the default catalog, one sector on.  Fails
+    /// with `InvalidInput` below [`LBA_BOOT_CATALOG`] or without entries;
+    /// the build fails the same way if `lba` is not past the terminator or
+    /// is the first catalog's, or if there is no first catalog.  `None`
+    /// removes it.
+    pub fn set_secondary_boot_catalog(
+        &mut self,
+        catalog: Option<(u32, Vec<(String, u8)>)>,
+    ) -> io::Result<()> {
+        if let Some((lba, entries)) = &catalog {
+            if *lba < LBA_BOOT_CATALOG {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("boot catalog LBA {lba} lies inside the volume descriptor set"),
+                ));
+            }
+            if entries.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a secondary boot catalog needs at least one entry",
+                ));
+            }
+        }
+        self.secondary_boot_catalog = catalog;
+        Ok(())
+    }
+
+    /// LBA of the volume descriptor set terminator.  A secondary boot
+    /// catalog adds a boot record before it.
+    fn terminator(&self, el_torito: bool) -> u32 {
+        let secondary = el_torito && self.secondary_boot_catalog.is_some();
+        terminator_lba(el_torito, self.enhanced_vd) + u32::from(secondary)
+    }
+
+    /// LBA of the El Torito boot catalog.
+    fn catalog_lba(&self) -> u32 {
+        self.boot_catalog_lba
+            .unwrap_or_else(|| self.terminator(true) + 1)
+    }
+
+    /// LBAs of the boot catalogs, the first one's first.
+    fn catalog_lbas(&self) -> Vec<u32> {
+        let secondary = self.secondary_boot_catalog.as_ref().map(|(lba, _)| *lba);
+        std::iter::once(self.catalog_lba())
+            .chain(secondary)
+            .collect()
+    }
+
+    /// The catalog at the highest LBA, after which file data may start.
+    fn last_catalog_lba(&self) -> u32 {
+        self.catalog_lbas().into_iter().max().unwrap_or_default()
+    }
+    /// Lists the file at `path_in_iso` in the boot catalog as a non-bootable
+    /// entry (boot indicator 0x00) for `platform_id`, after the bootable
+    /// ones.  Entries keep the order they were added in, and consecutive
+    /// entries for one platform share a section header.  BIOS or UEFI boot
+    /// must be configured too: the catalog still needs a default entry.
+    pub fn add_auxiliary_boot_entry(&mut self, path_in_iso: &str, platform_id: u8) {
+        self.auxiliary_boot_entries
+            .push((path_in_iso.to_string(), platform_id));
+    }
+    /// Generates a boot loader configuration for `cfg` at build time and
+    /// adds it at [`AutoConfig::config_path`] for that build only.  The
+    /// kernel and initrd must have been added as files by then, and nothing
+    /// else may be at the configuration's path.  Without Rock Ridge the
+    /// configuration names them by their ISO 9660 identifiers, e.g.
+    /// `/BOOT/VMLINUZ_6.1` for `boot/vmlinuz-6.1`, without the `;1` version
+    /// that GRUB and ISOLINUX ignore.
+    pub fn set_autoconfig(&mut self, cfg: AutoConfig) {
+        self.autoconfig = Some(cfg);
+    }
+
+    /// Adds the [`AutoConfig`] file, if any, to the tree.
+    fn add_autoconfig(&mut self) -> io::Result<()> {
+        let Some(cfg) = self.autoconfig.clone() else {
+            return Ok(());
+        };
+        for path in std::iter::once(&cfg.kernel).chain(&cfg.initrd) {
+            get_file_size_in_iso(&self.root, path.trim_start_matches('/'))?;
+        }
+        let opts = &self.dir_opts;
+        let text = cfg.render_with(|path| {
+            if opts.rock_ridge {
+                return path.to_string();
+            }
+            let names: Vec<_> = iso_path_components(path).collect();
+            let last = names.len() - 1;
+            names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| transliterate_name(name, i < last, opts.replacement))
+                .collect::<Vec<_>>()
+                .join("/")
+        });
+        self.add_generated_file(cfg.config_path(), &text)
+    }
+
+    /// Generates a [`DiscInfo::PATH`] file at the root from `info` at build
+    /// time, in the tree for that build only.  Fails with `InvalidInput` if
+    /// a field is empty or spans several lines; the build fails the same
+    /// way if the caller added a file at that path.
+    pub fn set_disc_info(&mut self, info: DiscInfo) -> io::Result<()> {
+        info.render()?;
+        self.disc_info = Some(info);
+        Ok(())
+    }
+
+    /// Adds the [`DiscInfo`] file, if any, to the tree.
+    fn add_disc_info(&mut self) -> io::Result<()> {
+        let Some(info) = &self.disc_info else {
+            return Ok(());
+        };
+        let text = info.render()?;
+        self.add_generated_file(DiscInfo::PATH, &text)
+    }
+
+    /// Adds a file holding `text` at `path_in_iso` for the running build;
+    /// [`Self::remove_generated_files`] takes it out again together with
+    /// the directories created for it.  Fails with `InvalidInput` if the
+    /// path is taken, rather than replacing a file the caller added.
+    fn add_generated_file(&mut self, path_in_iso: &str, text: &str) -> io::Result<()> {
+        if get_node_for_path(&self.root, path_in_iso).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{path_in_iso}' is generated by the build but already exists"),
+            ));
+        }
+        let names: Vec<_> = iso_path_components(path_in_iso).collect();
+        let created_dirs: Vec<String> = (1..names.len())
+            .rev()
+            .map(|n| names[..n].join("/"))
+            .filter(|dir| get_node_for_path(&self.root, dir).is_err())
+            .collect();
+        let data = text.as_bytes().to_vec();
+        let size = data.len() as u64;
+        self.add_reader(path_in_iso, Box::new(io::Cursor::new(data)), size)?;
+        self.generated_paths.push(path_in_iso.to_string());
+        self.generated_paths.extend(created_dirs);
+        Ok(())
+    }
+
+    /// Removes what [`Self::add_generated_file`] added, so the tree holds
+    /// only the caller's files again once a build has finished.
+    fn remove_generated_files(&mut self) {
+        for path in std::mem::take(&mut self.generated_paths) {
+            // A directory the caller has since filled stays.
+            let _ = match get_node_for_path(&self.root, &path) {
+                Ok(IsoFsNode::Directory(_)) => self.remove_dir(&path, false),
+                _ => self.remove_file(&path),
+            };
+        }
+    }
+
+    /// Moves the `efi_boot_image_iso_path` file of a hybrid GPT image to a
+    /// 1 MiB boundary, which some firmware and flash controllers expect of
+    /// the ESP partition.  The file laid out just before it gets the gap as
+    /// [`IsoFile::padding_sectors`]; if none precedes it, the returned
+    /// number of sectors must be left free before the root directory.
+    /// Either way the caller lays the tree out again.
+    fn align_hybrid_esp(&mut self) -> io::Result<u32> {
+        let Some(esp_path) = self.hybrid_esp_path() else {
+            return Ok(0);
+        };
+        let esp_lba = get_lba_for_path(&self.root, esp_path)?;
+        let gap = esp_alignment_gap(esp_lba.into());
+        if gap == 0 {
+            return Ok(0);
+        }
+        let before = iter_nodes(&self.root)
+            .filter_map(|(path, node)| match node {
+                IsoFsNode::File(f) if f.lba < esp_lba => Some((f.lba, path)),
+                _ => None,
+            })
+            .max();
+        let Some((_, path)) = before else {
+            return Ok(gap);
+        };
+        if let Some((dir, name)) = get_parent_dir_mut(&mut self.root, &path)?
+            && let Some(IsoFsNode::File(file)) = dir.children.get_mut(name)
+        {
+            file.padding_sectors += gap;
+        }
+        self.esp_padding = Some((path, gap));
+        Ok(0)
+    }
+
+    /// The file a hybrid GPT's ESP partition points at, if any.
+    fn hybrid_esp_path(&self) -> Option<&str> {
+        self.efi_boot_image_iso_path
+            .as_deref()
+            .filter(|_| self.hybrid_mode.uses_gpt_esp())
+    }
+
+    /// Takes back the padding [`Self::align_hybrid_esp`] added.
+    fn remove_esp_padding(&mut self) {
+        if let Some((path, gap)) = self.esp_padding.take()
+            && let Ok(Some((dir, name))) = get_parent_dir_mut(&mut self.root, &path)
+            && let Some(IsoFsNode::File(file)) = dir.children.get_mut(name)
+        {
+            file.padding_sectors -= gap;
+        }
+    }
+
+    pub fn set_profile(&mut self, p: IsoLayoutProfile) {
+        self.profile = p;
+    }
+    /// Shorthand for [`Self::set_hybrid_mode`]: `true` selects
+    /// [`HybridMode::GptUefi`], `false` selects [`HybridMode::None`].
+    pub fn set_isohybrid(&mut self, v: bool) {
+        self.hybrid_mode = v.into();
+    }
+    pub fn set_hybrid_mode(&mut self, m: HybridMode) {
+        self.hybrid_mode = m;
+    }
+    /// Lays the hybrid GPT out for a device of `sectors` 512-byte sectors
+    /// instead of the image itself: the backup header and partition array
+    /// go at the device's last LBAs and the image is extended (as a hole
+    /// where the filesystem allows) to the full device size.  `sectors`
+    /// must be a multiple of 4 and no smaller than the image; otherwise the
+    /// build fails with `InvalidInput`.  Has no effect without a GPT.
+    pub fn set_target_device_size_sectors(&mut self, sectors: u64) {
+        self.target_device_512 = Some(sectors);
+    }
+    /// Appends an empty Linux filesystem partition of `size_sectors`
+    /// 512-byte sectors, named `label`, to the hybrid GPT for the OS to
+    /// format later, e.g. as live-USB persistence.  It starts at the first
+    /// 1 MiB boundary after the image and is followed by the backup GPT, so
+    /// the image (and [`Self::total_sectors`]) grows to include it; the
+    /// region reads back as zeros.  With
+    /// [`Self::set_target_device_size_sectors`] the device must have room
+    /// for it.  Fails with `InvalidInput` on a zero size or a label longer
+    /// than 36 UTF-16 code units.  Has no effect without a GPT.
+    pub fn add_persistence_partition(&mut self, size_sectors: u64, label: &str) -> io::Result<()> {
+        if size_sectors == 0 || label.encode_utf16().count() > 36 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Persistence partition needs a non-zero size and a label of at most \
+                     36 UTF-16 code units (got {size_sectors} sectors, '{label}')"
+                ),
+            ));
+        }
+        self.persistence = Some((size_sectors, label.to_string()));
+        Ok(())
+    }
+    /// Sets the attribute bits of the hybrid GPT's ESP entry, e.g.
+    /// [`GPT_ATTR_HIDDEN`] | [`GPT_ATTR_NO_AUTOMOUNT`] to keep operating
+    /// systems from mounting it.  Defaults to [`GPT_ATTR_REQUIRED`].
+    ///
+    /// [`GPT_ATTR_HIDDEN`]: crate::iso::gpt::partition_entry::GPT_ATTR_HIDDEN
+    /// [`GPT_ATTR_NO_AUTOMOUNT`]: crate::iso::gpt::partition_entry::GPT_ATTR_NO_AUTOMOUNT
+    pub fn set_esp_gpt_attributes(&mut self, attributes: u64) {
+        self.esp_gpt_attributes = attributes;
+    }
+    /// Stores the CRC32 of the ESP partition's bytes, as they stand when
+    /// the GPT is written, in bits 16-47 of its GPT attributes (see
+    /// [`GPT_ATTR_ESP_CRC32_SHIFT`]) for firmware that verifies it.  This is
+    /// not a standard field; those bits are otherwise reserved and zero.
+    pub fn set_esp_integrity_check(&mut self, v: bool) {
+        self.esp_integrity_check = v;
+    }
+    /// Keeps the ESP images built by [`Self::add_uefi_esp`] in `dir`,
+    /// created if missing, and reuses one when none of its inputs changed
+    /// since.  Inputs are compared by host path, size and modification
+    /// time, so a file rewritten within the filesystem's timestamp
+    /// granularity at the same size is not noticed.
+    pub fn set_esp_cache_dir(&mut self, dir: PathBuf) {
+        self.esp_cache_dir = Some(dir);
+    }
+    /// Builds the FAT ESP image for `uefi` and adds it at
+    /// `boot/efiboot.img` as the UEFI El Torito and hybrid GPT ESP image,
+    /// as [`build_iso`] does in GPT hybrid modes.  With
+    /// [`Self::set_esp_cache_dir`] an unchanged image is reused instead of
+    /// being rebuilt.  Fails with `InvalidInput` if `destination_in_iso` is
+    /// not a removable-media boot path, or the loader is not a valid EFI
+    /// application unless `skip_pe_validation` is set.
+    pub fn add_uefi_esp(&mut self, uefi: &UefiBootInfo) -> io::Result<EspImage> {
+        // The loader goes where `destination_in_iso` says, which must be
+        // a path firmware probes on removable media.
+        let boot_path = fat::removable_media_boot_path(&uefi.destination_in_iso)?;
+        if !uefi.skip_pe_validation {
+            pe::validate_efi_application_file(&uefi.boot_image)?;
+        }
+        let mut entries: Vec<(PathBuf, String)> = vec![(uefi.boot_image.clone(), boot_path)];
+        if let Some(kernel) = &uefi.kernel_image {
+            entries.push((kernel.clone(), "EFI/BOOT/KERNEL.EFI".into()));
+        }
+        for (dn, sp) in &uefi.additional_efi_boot_files {
+            entries.push((sp.clone(), format!("EFI/BOOT/{dn}")));
+        }
+        entries.extend(uefi.esp_files.iter().cloned());
+        let hidden = match self.profile.hidden_sectors_mode {
+            HiddenSectorMode::Zero => 0,
+            HiddenSectorMode::PartitionOffset => self.profile.esp_alignment_lba_512,
+        };
+        let cluster_size = self.profile.esp_cluster_size;
+        let grub_cfg = uefi.grub_cfg_content.as_deref();
+        let esp = match &self.esp_cache_dir {
+            Some(dir) => cached_esp(dir, entries, grub_cfg, hidden, cluster_size)?,
+            None => fresh_esp(entries, grub_cfg, hidden, cluster_size)?,
+        };
+        self.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        self.add_file("boot/efiboot.img", &esp.path)?;
+        Ok(esp)
+    }
+    /// Sets the MBR bootstrap code (≤440 bytes) written in every hybrid
+    /// mode, e.g. syslinux's `isohdpfx.bin`.  Takes precedence over the code
+    /// taken from the BIOS boot image.
+    pub fn set_mbr_boot_code(&mut self, code: &[u8]) -> io::Result<()> {
+        if code.len() > 440 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("MBR boot code too large ({} > 440 bytes)", code.len()),
+            ));
+        }
+        self.mbr_boot_code = Some(code.to_vec());
+        Ok(())
+    }
+    /// Sets the 4-byte disk signature at byte 440 of the MBR written in
+    /// every hybrid mode, which Windows and some boot managers use to tell
+    /// disks apart.  Defaults to 0, so images stay reproducible.
+    pub fn set_mbr_disk_signature(&mut self, signature: u32) {
+        self.mbr_disk_signature = signature;
+    }
+    /// Sets the content of the system area (LBA 0–15, ≤32 KiB), e.g. a GRUB
+    /// core image for BIOS boot.  In hybrid modes the MBR partition table and
+    /// any GPT are written over it afterwards; its first 440 bytes then serve
+    /// as MBR boot code unless [`Self::set_mbr_boot_code`] was called.
+    pub fn set_system_area(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() as u64 > SYSTEM_AREA_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "system area too large ({} > {SYSTEM_AREA_SIZE} bytes)",
+                    data.len()
+                ),
+            ));
+        }
+        self.system_area = Some(data.to_vec());
+        Ok(())
+    }
+
+    /// Returns the system area content set by [`Self::set_system_area`].
+    pub fn system_area(&self) -> Option<&[u8]> {
+        self.system_area.as_deref()
+    }
+
+    /// Platform ID, image LBA and sector count of every boot entry (section
+    /// headers excluded) in the catalog written by the last [`Self::build`],
+    /// in catalog order.  Empty before a build.
+    pub fn boot_entry_summary(&self) -> Vec<(u8, u32, u16)> {
+        self.boot_entries
+            .iter()
+            .filter(|e| matches!(e.entry_type, BootCatalogEntryType::BootEntry { .. }))
+            .map(|e| (e.platform_id, e.boot_image_lba, e.boot_image_sectors))
+            .collect()
+    }
+
+    /// Total image size in 2048-byte sectors, as recorded in the PVD.  Valid
+    /// after [`Self::build`]; includes the hybrid GPT backup structures.
+    pub fn total_sectors(&self) -> u32 {
+        self.total_sectors
+    }
+
+    /// Adds an Apple Partition Map (2048-byte blocks from block 1) describing
+    /// the ISO 9660 volume as an `Apple_HFS` partition named `name`, next to
+    /// the map's own `Apple_partition_map` entry.  A hybrid GPT then keeps
+    /// its header at 512-byte LBA 1 and shrinks its partition array to
+    /// [`GPT_ENTRIES_WITH_APM`] entries, ending before the map.  The build
+    /// fails with `InvalidInput` if MBR boot code or a system area would
+    /// be written, since the map's block 0 fields overwrite their first
+    /// bytes.
+    pub fn add_apm_partition(&mut self, name: &str) -> io::Result<()> {
+        ApmPartition::new(name, "Apple_HFS", 0, 0).to_bytes(2)?;
+        self.apm_volume_name = Some(name.to_string());
+        Ok(())
+    }
+    pub fn set_disk_layout(&mut self, l: DiskLayout) {
+        self.disk_layout = Some(l);
+    }
+    /// Prefetch up to `n` 1 MiB chunks of source data on a background
+    /// thread while the current one is written.  `0` (the default) copies
+    /// sequentially.
+    pub fn set_copy_parallelism(&mut self, n: usize) {
+        self.copy_parallelism = n;
+    }
+
+    /// Skips the holes of sparse source files instead of writing their
+    /// zeros.  Needs the `sparse` feature on Linux; elsewhere files are
+    /// copied densely.  Overrides [`Self::set_copy_parallelism`].
+    pub fn set_sparse_copy(&mut self, v: bool) {
+        self.sparse_copy = v;
+    }
+
+    /// Handles directories nested deeper than ISO 9660 allows: with Rock
+    /// Ridge they are relocated into an `rr_moved` directory, which this adds
+    /// to the root; without it the build fails.
+    fn prepare_relocation(&mut self) -> io::Result<()> {
+        // Drop the directory added by a previous build; the tree may have
+        // changed since.
+        if std::mem::take(&mut self.added_rr_moved)
+            && matches!(self.root.children.get(RR_MOVED), Some(IsoFsNode::Directory(d)) if d.children.is_empty())
+        {
+            self.root.children.remove(RR_MOVED);
+        }
+        let Some(deep) = first_too_deep(&self.root) else {
+            return Ok(());
+        };
+        if !self.dir_opts.rock_ridge {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "'{deep}' is nested more than {MAX_DIR_DEPTH} directory levels deep; \
+                     enable Rock Ridge to relocate it"
+                ),
+            ));
+        }
+        match self.root.children.get(RR_MOVED) {
+            None => {
+                self.root
+                    .children
+                    .insert(RR_MOVED.into(), IsoFsNode::Directory(IsoDirectory::new()));
+                self.added_rr_moved = true;
+                Ok(())
+            }
+            Some(IsoFsNode::Directory(d)) if d.children.is_empty() => Ok(()),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{RR_MOVED}' is reserved for relocated directories"),
+            )),
+        }
+    }
+
+    /// Lists the boot catalog at `BiosBootInfo::boot_catalog`, if set, as a
+    /// one-sector file pointing at the catalog.  Parent directories are
+    /// created as needed; an existing node at the path is an error.
+    fn place_boot_catalog(&mut self) -> io::Result<()> {
+        // Drop the entry added by a previous build; the path may have changed.
+        if let Some(old) = self.added_boot_catalog.take()
+            && let Some((parent, name)) = get_parent_dir_mut(&mut self.root, &old)?
+            && matches!(parent.children.get(name), Some(IsoFsNode::BootCatalog(_)))
+        {
+            parent.children.remove(name);
+        }
+        let bios = self.boot_info.as_ref().and_then(|b| b.bios_boot.as_ref());
+        let Some(path) = bios.and_then(|b| b.boot_catalog.clone()) else {
+            return Ok(());
+        };
+        // Unlike other files, the catalog path must be relative and stay
+        // inside the tree.
+        if path.starts_with(['/', '\\'])
+            || iso_path_components(&path).next().is_none()
+            || iso_path_components(&path).any(|c| c == "..")
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid boot catalog path: {path:?}"),
+            ));
+        }
+        let file_name = file_name_in_iso(&path)?;
+        let lba = self.catalog_lba();
+        let dir = ensure_directory_path(&mut self.root, &path)?;
+        if dir.children.contains_key(&file_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Cannot place the boot catalog at '{path}': path already exists"),
+            ));
+        }
+        dir.children.insert(file_name, IsoFsNode::BootCatalog(lba));
+        self.added_boot_catalog = Some(path);
+        Ok(())
+    }
+
+    /// Whether [`Self::prepare_boot_entries`] will produce any entry, i.e.
+    /// whether the image needs a boot record and catalog.
+    fn has_boot_entries(&self, esp_lba: Option<u32>) -> bool {
+        let bi = self.boot_info.as_ref();
+        bi.is_some_and(|b| b.bios_boot.is_some() || b.uefi_boot.is_some())
+            || self.efi_boot_image_iso_path.is_some()
+            || esp_lba.is_some()
+    }
+
+    /// First LBA available for directories and file data.
+    fn default_data_lba(&self, el_torito: bool) -> u32 {
+        // A data-only disc has neither a boot record nor a catalog: the
+        // terminator moves up to LBA 17 and data starts right after it.
+        let default = if el_torito {
+            self.last_catalog_lba() + 1
+        } else {
+            self.terminator(false) + 1
+        };
+        self.disk_layout
+            .as_ref()
+            .map_or(default, |l| l.iso_region.data_start_lba)
+    }
+
+    /// Zero-fills a floppy emulation boot image shorter than its emulated
+    /// disk up to the full size, through [`IsoFile::zero_fill`], so that
+    /// its directory record and the copied data cover the whole floppy.
+    /// Undoes the fill of an earlier build once emulation is turned off.
+    fn pad_floppy_image(&mut self) -> io::Result<()> {
+        let Some(bios) = self.boot_info.as_ref().and_then(|bi| bi.bios_boot.as_ref()) else {
+            return Ok(());
+        };
+        let target = match self.bios_emulation {
+            BootEmulation::Floppy { .. } => self.bios_emulation.image_size(),
+            _ => None,
+        };
+        let Some((dir, name)) = get_parent_dir_mut(&mut self.root, &bios.destination_in_iso)?
+        else {
+            return Ok(());
+        };
+        if let Some(IsoFsNode::File(file)) = dir.children.get_mut(name) {
+            let source = file.size - file.zero_fill;
+            file.zero_fill = match target {
+                Some(size) if source > 0 && source < size => size - source,
+                _ => 0,
+            };
+            file.size = source + file.zero_fill;
+        }
+        Ok(())
+    }
+
+    /// Sets the media type and load count of the BIOS boot `entry` for
+    /// [`Self::set_bios_emulation`], after checking the image at `path`
+    /// against the emulated geometry.
+    fn apply_bios_emulation(
+        &self,
+        entry: &mut BootCatalogEntry,
+        load_sectors: Option<u16>,
+        path: &str,
+    ) -> io::Result<()> {
+        let Some(expected) = self.bios_emulation.image_size() else {
+            return Ok(());
+        };
+        let media_type = self.bios_emulation.media_type()?;
+        let size = get_file_size_in_iso(&self.root, path)?;
+        if size != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Boot image {path} is {size} bytes; {:?} needs exactly {expected}",
+                    self.bios_emulation
+                ),
+            ));
+        }
+        if media_type == MEDIA_HARD_DISK {
+            let mut mbr = [0u8; 512];
+            if let IsoFsNode::File(file) = get_node_for_path(&self.root, path)? {
+                if file.reader.is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Hard disk boot image {path} must be added from a file"),
+                    ));
+                }
+                File::open(&file.path)?.read_exact(&mut mbr)?;
+            }
+            if mbr[510..512] != [0x55, 0xAA] {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Hard disk boot image {path} does not start with an MBR"),
+                ));
+            }
+        }
+        entry.media_type = media_type;
+        entry.boot_image_sectors = load_sectors.unwrap_or(1);
+        Ok(())
+    }
+
+    /// Fails unless `data_lba` lies past the volume descriptor set and,
+    /// with El Torito, the boot catalog.  Only a [`DiskLayout`] with a small
+    /// `data_start_lba` can place it earlier; file data would then overwrite
+    /// those sectors.
+    fn check_data_lba(&self, data_lba: u32, el_torito: bool) -> io::Result<()> {
+        let terminator = self.terminator(el_torito);
+        if let Some((lba, _)) = &self.secondary_boot_catalog {
+            if !el_torito {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a secondary boot catalog needs BIOS or UEFI boot entries",
+                ));
+            }
+            if *lba == self.catalog_lba() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("both boot catalogs are at LBA {lba}"),
+                ));
+            }
+        }
+        for lba in self.catalog_lbas() {
+            if el_torito && lba <= terminator {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "boot catalog LBA {lba} is not past the terminator at LBA {terminator}"
+                    ),
+                ));
+            }
+        }
+        let reserved_end = if el_torito {
+            self.last_catalog_lba()
+        } else {
+            self.terminator(false)
+        };
+        if data_lba <= reserved_end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "data area starts at LBA {data_lba}, inside the volume descriptors \
+                     and boot catalog (LBA 16-{reserved_end})"
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn prepare_boot_entries(
+        &self,
+        esp_lba: Option<u32>,
+        esp_size_sectors: Option<u32>,
+    ) -> io::Result<Vec<BootCatalogEntry>> {
+        use crate::iso::boot_catalog::{
+            BOOT_CATALOG_EFI_PLATFORM_ID, BOOT_CATALOG_MAC_PLATFORM_ID,
+            BOOT_CATALOG_POWERPC_PLATFORM_ID,
+        };
+        let mut entries = Vec::new();
+        let bi = self.boot_info.as_ref();
+
+        let bios_boot_info = bi.and_then(|b| b.bios_boot.as_ref());
+        let uefi_boot_info = bi.and_then(|b| b.uefi_boot.as_ref());
+
+        if let Some(bios) = bios_boot_info
+            && ![
+                BOOT_CATALOG_X86_PLATFORM_ID,
+                BOOT_CATALOG_POWERPC_PLATFORM_ID,
+                BOOT_CATALOG_MAC_PLATFORM_ID,
+                BOOT_CATALOG_EFI_PLATFORM_ID,
+            ]
+            .contains(&bios.platform_id)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown El Torito platform ID {:#04x}", bios.platform_id),
+            ));
+        }
+
+        // Validate ESP parameters (always, not only when UEFI boot is requested)
+        match (esp_lba, esp_size_sectors) {
+            (Some(_), None) | (None, Some(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid ESP configuration: esp_lba and esp_size_sectors must both be Some or both be None",
+                ));
+            }
+            (Some(_), Some(0)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid ESP configuration: esp_size_sectors cannot be zero when esp_lba is provided",
+                ));
+            }
+            _ => {}
+        }
+
+        // Resolve the UEFI entry.  Every target is a path in the ISO tree
+        // except an ESP the caller placed at a raw LBA.
+        let esp_entry = if let Some(p) = &self.efi_boot_image_iso_path {
+            Some(create_uefi_esp_boot_entry(&self.root, p)?)
+        } else if let (Some(lba), Some(_)) = (esp_lba, esp_size_sectors) {
+            Some(create_uefi_raw_esp_boot_entry(lba))
+        } else {
+            None
+        };
+        let uefi_entry = match (&esp_entry, uefi_boot_info) {
+            (Some(e), _) => Some(e.clone()),
+            (None, Some(u)) => Some(create_uefi_boot_entry(&self.root, &u.destination_in_iso)?),
+            (None, None) => None,
+        };
+        let section_header = BootCatalogEntry {
+            platform_id: BOOT_CATALOG_EFI_PLATFORM_ID,
+            boot_image_lba: 0,
+            boot_image_sectors: 0,
+            entry_type: BootCatalogEntryType::SectionHeader { more_follow: false },
+            media_type: MEDIA_NO_EMULATION,
+            selection_criteria: None,
+        };
+
+        // --- BIOS as Initial/Default Entry (if present) ---
+        // SeaBIOS only checks the Initial/Default Entry; if its platform_id
+        // is 0xEF (UEFI), SeaBIOS skips BIOS boot entirely.  Placing BIOS
+        // here ensures it can boot on legacy firmware while UEFI firmware
+        // discovers the EFI entries via the Section Header with
+        // platform_id=0xEF.
+        if let Some(bios) = bios_boot_info {
+            let mut entry = create_boot_entry_with_load_count(
+                &self.root,
+                bios.platform_id,
+                &bios.destination_in_iso,
+                bios.load_sectors,
+            )?;
+            self.apply_bios_emulation(&mut entry, bios.load_sectors, &bios.destination_in_iso)?;
+            entries.push(entry);
+            // UEFI entries follow under a dedicated Section Header
+            if let Some(u) = uefi_entry {
+                entries.push(section_header);
+                entries.push(u);
+            }
+        } else if let Some(esp) = esp_entry {
+            // UEFI-only boot: UEFI BootEntry is the Initial/Default Entry.
+            // El Torito spec requires offset 32 to be a BootEntry, NOT a
+            // SectionHeader.  A Section Header follows for firmware that
+            // requires platform_id=0xEF to discover the entry.  The
+            // Initial/Default entry's sector_count MUST be 0 for
+            // no-emulation boot according to El Torito spec § 6.4.
+            entries.push(esp.clone());
+            entries.push(section_header);
+            entries.push(esp);
+        } else if let Some(u) = uefi_entry {
+            entries.push(u);
+        }
+
+        if self.auxiliary_boot_entries.is_empty() {
+            return Ok(entries);
+        }
+        if entries.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Auxiliary boot entries need a BIOS or UEFI boot entry to follow",
+            ));
+        }
+        let mut section = None;
+        for (path, platform_id) in &self.auxiliary_boot_entries {
+            if section != Some(*platform_id) {
+                entries.push(BootCatalogEntry {
+                    platform_id: *platform_id,
+                    boot_image_lba: 0,
+                    boot_image_sectors: 0,
+                    entry_type: BootCatalogEntryType::SectionHeader { more_follow: false },
+                    media_type: MEDIA_NO_EMULATION,
+                    selection_criteria: None,
+                });
+                section = Some(*platform_id);
+            }
+            let mut entry = create_boot_entry(&self.root, *platform_id, path)?;
+            entry.entry_type = BootCatalogEntryType::BootEntry { bootable: false };
+            entries.push(entry);
+        }
+        // Every section header but the last announces that more follow.
+        let is_header = |e: &BootCatalogEntry| {
+            matches!(e.entry_type, BootCatalogEntryType::SectionHeader { .. })
+        };
+        let last = entries.iter().rposition(is_header).unwrap_or(0);
+        for e in entries[..last].iter_mut().filter(|e| is_header(e)) {
+            e.entry_type = BootCatalogEntryType::SectionHeader { more_follow: true };
+        }
+        Ok(entries)
+    }
+
+    /// Entries of the catalog set with [`Self::set_secondary_boot_catalog`]:
+    /// the first as its Initial/Default Entry, the others under a section
+    /// header per run of one platform.
+    fn prepare_secondary_entries(&self) -> io::Result<Vec<BootCatalogEntry>> {
+        let Some((_, targets)) = &self.secondary_boot_catalog else {
+            return Ok(Vec::new());
+        };
+        let mut entries = Vec::new();
+        let mut section = None;
+        for (i, (path, platform_id)) in targets.iter().enumerate() {
+            if i > 0 && section != Some(*platform_id) {
+                entries.push(BootCatalogEntry {
+                    platform_id: *platform_id,
+                    boot_image_lba: 0,
+                    boot_image_sectors: 0,
+                    entry_type: BootCatalogEntryType::SectionHeader { more_follow: false },
+                    media_type: MEDIA_NO_EMULATION,
+                    selection_criteria: None,
+                });
+                section = Some(*platform_id);
+            }
+            entries.push(create_boot_entry(&self.root, *platform_id, path)?);
+        }
+        let is_header = |e: &BootCatalogEntry| {
+            matches!(e.entry_type, BootCatalogEntryType::SectionHeader { .. })
+        };
+        let last = entries.iter().rposition(is_header).unwrap_or(0);
+        for e in entries[..last].iter_mut().filter(|e| is_header(e)) {
+            e.entry_type = BootCatalogEntryType::SectionHeader { more_follow: true };
+        }
+        Ok(entries)
+    }
+
+    /// Returns the MBR boot code: the explicit [`Self::set_mbr_boot_code`]
+    /// bytes if set, otherwise the first 440 bytes of the BIOS boot image
+    /// when the hybrid mode asks for it.
+    fn mbr_boot_code(&self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(code) = &self.mbr_boot_code {
+            return Ok(Some(code.clone()));
+        }
+        if let Some(area) = &self.system_area {
+            return Ok(Some(area[..area.len().min(440)].to_vec()));
+        }
+        if !self.hybrid_mode.has_bios_boot_code() {
+            return Ok(None);
+        }
+        let Some(bios) = self.boot_info.as_ref().and_then(|b| b.bios_boot.as_ref()) else {
+            return Ok(None);
+        };
+        let mut code = Vec::with_capacity(440);
+        File::open(&bios.boot_image)?
+            .take(440)
+            .read_to_end(&mut code)?;
+        Ok(Some(code))
+    }
+
+    /// Writes a BIOS-only isohybrid MBR (no GPT) covering the whole image.
+    fn write_bios_mbr<W: Write + Seek>(&self, iso_file: &mut W, total_lbas: u64) -> io::Result<()> {
+        let code = self.mbr_boot_code()?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "HybridMode::BiosMbr requires a BIOS boot image or MBR boot code",
+            )
+        })?;
+        let total_512 = u32::try_from(total_lbas * 4)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "ISO too large for MBR"))?;
+        let mut mbr = create_mbr_for_bios_hybrid(total_512, &code)?;
+        mbr.disk_signature = self.mbr_disk_signature;
+        iso_file.seek(SeekFrom::Start(0))?;
+        mbr.write_to(iso_file)
+    }
+
+    /// ESP partition start and size in 512-byte sectors, if any.
+    fn esp_range_512(&self, esp_size_sectors: Option<u32>) -> (Option<u32>, Option<u32>) {
+        if let (Some(l), Some(s)) = (self.esp_lba, self.esp_size_sectors) {
+            (
+                u32::try_from(l as u64 * 4).ok(),
+                u32::try_from(s as u64 * 4).ok(),
+            )
+        } else if let Some(ref layout) = self.disk_layout {
+            layout.esp_partition().map_or((None, None), |esp| {
+                (
+                    Some(esp.start_lba_512 as u32),
+                    Some(esp.size_lba_512 as u32),
+                )
+            })
+        } else if let Some(sz) = esp_size_sectors {
+            (Some(self.profile.esp_alignment_lba_512), Some(sz * 4))
+        } else {
+            (None, None)
+        }
+    }
+
+    /// Minimum hybrid image size in 512-byte sectors: the ISO data or the
+    /// end of the ESP, whichever is further, plus the backup GPT.
+    fn hybrid_required_512(data_end_iso: u64, esp: (Option<u32>, Option<u32>)) -> u64 {
+        let esp_end = match esp {
+            (Some(start), Some(size)) => start as u64 + size as u64,
+            _ => 0,
+        };
+        (data_end_iso * 4).max(esp_end) + BACKUP_GPT_RESERVED_512
+    }
+
+    /// Returns the minimum size, in 512-byte sectors, of the isohybrid image
+    /// for the files and ESP configured so far.  Uses the same arithmetic as
+    /// the size check performed when the hybrid structures are written.
+    pub fn min_hybrid_sectors(&self) -> u32 {
+        let required = Self::hybrid_required_512(
+            self.data_end_lba(),
+            self.esp_range_512(self.esp_size_sectors),
+        );
+        u32::try_from(required).unwrap_or(u32::MAX)
+    }
+
+    /// First ISO sector past the directories and file data laid out for
+    /// the files added so far.
+    fn data_end_lba(&self) -> u64 {
+        let data_start = self.default_data_lba(self.has_boot_entries(self.esp_lba)) as u64;
+        let ear = u32::from(self.dir_opts.ear_checksums);
+        let rr_moved = self.dir_opts.rock_ridge
+            && first_too_deep(&self.root).is_some()
+            && !self.root.children.contains_key(RR_MOVED);
+        let mut root_lba = data_start;
+        if self.enhanced_vd {
+            root_lba += u64::from(dir_count(&self.root)) + u64::from(rr_moved);
+        }
+        let mut data_end = root_lba + tree_sectors(&self.root, ear) + u64::from(rr_moved);
+        if let Some(path) = self.hybrid_esp_path()
+            && let Some(offset) = file_offset_in_tree(&self.root, path, ear)
+        {
+            data_end += u64::from(esp_alignment_gap(root_lba + offset));
+        }
+        data_end
+    }
+
+    /// Size in bytes of the image [`Self::build`] would write for the files
+    /// and settings so far, including the hybrid GPT, a persistence
+    /// partition and the target device size.  Files the build generates
+    /// itself (autoconfig, `.discinfo`) are not counted.
+    pub fn estimated_size_bytes(&self) -> u64 {
+        let data_end = self.data_end_lba();
+        if !self.hybrid_mode.uses_gpt_esp() {
+            return data_end * ISO_SECTOR_SIZE;
+        }
+        let esp = self.esp_range_512(self.esp_size_sectors);
+        let mut total_512 = Self::hybrid_required_512(data_end, esp).next_multiple_of(4);
+        if self.profile.use_gpt {
+            if let Some((size, _)) = &self.persistence {
+                let start = total_512.next_multiple_of(PERSISTENCE_ALIGN_512);
+                total_512 = (start + size + BACKUP_GPT_RESERVED_512).next_multiple_of(4);
+            }
+            if let Some(dev) = self.target_device_512 {
+                total_512 = total_512.max(dev);
+            }
+        }
+        total_512 * 512
+    }
+
+    /// Bytes left on a medium of `target_capacity` bytes (e.g. 700 MB for a
+    /// CD) after the image of [`Self::estimated_size_bytes`]; negative if
+    /// the image does not fit.  Fails with `InvalidInput` if either size
+    /// exceeds `i64::MAX`.
+    pub fn remaining_bytes(&self, target_capacity: u64) -> io::Result<i64> {
+        let to_i64 = |v: u64| {
+            i64::try_from(v).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("size {v} too large"))
+            })
+        };
+        Ok(to_i64(target_capacity)? - to_i64(self.estimated_size_bytes())?)
+    }
+
+    fn write_hybrid_structures<W: Read + Write + Seek>(
+        &self,
+        iso_file: &mut W,
+        total_lbas: u64,
+        esp_size_sectors: Option<u32>,
+    ) -> io::Result<()> {
+        let raw_512 = total_lbas
+            .checked_mul(4)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "ISO too large"))?;
+        let image_512 = ((raw_512 + BACKUP_GPT_RESERVED_512) + 3) & !3u64;
+        // The persistence partition starts on the first 1 MiB boundary
+        // after the image and needs room for the backup GPT behind it.
+        let persistence = match &self.persistence {
+            Some((size, label)) if self.profile.use_gpt => {
+                let start = image_512.next_multiple_of(PERSISTENCE_ALIGN_512);
+                let end = start.checked_add(size - 1).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "persistence partition too large",
+                    )
+                })?;
+                Some((start, end, label))
+            }
+            _ => None,
+        };
+        let min_512 = match persistence {
+            Some((_, end, _)) => (end + 1 + BACKUP_GPT_RESERVED_512).next_multiple_of(4),
+            None => image_512,
+        };
+        let total_512 = match self.target_device_512 {
+            Some(dev) if dev % 4 != 0 || dev < min_512 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Target device of {dev} 512-byte sectors must be a multiple of 4 \
+                         and at least {min_512}"
+                    ),
+                ));
+            }
+            Some(dev) => dev,
+            None => min_512,
+        };
+        let total_for_mbr = u32::try_from(total_512)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "ISO too large for MBR"))?;
+
+        let (esp_start_512, esp_size_512) = self.esp_range_512(esp_size_sectors);
+        let required = Self::hybrid_required_512(total_lbas, (esp_start_512, esp_size_512));
+        if required > total_512 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "ISO too small for hybrid layout ({total_512} < {required} 512-byte sectors)"
+                ),
+            ));
+        }
+
+        iso_file.seek(SeekFrom::Start(0))?;
+        if self.profile.use_gpt {
+            let mut mbr =
+                create_mbr_for_gpt_hybrid(total_for_mbr, true, esp_start_512, esp_size_512)?;
+            if let Some(code) = self.mbr_boot_code()? {
+                mbr.boot_code[..code.len()].copy_from_slice(&code);
+            }
+            mbr.disk_signature = self.mbr_disk_signature;
+            mbr.write_to(iso_file)?;
+
+            let mut parts = Vec::new();
+            let start: u64 = 34;
+            // The ISO 9660 partition ends with the image, not the device.
+            let end: u64 = image_512.saturating_sub(34);
+            if end > start {
+                parts.push(GptPartitionEntry::new(
+                    "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7",
+                    &self.gpt_guid(1),
+                    start,
+                    end,
+                    "ISO9660",
+                    0,
+                ));
+            }
+            if let (Some(s), Some(sz)) = (esp_start_512, esp_size_512) {
+                let e = s.saturating_add(sz).saturating_sub(1);
+                if e > s {
+                    let mut attributes = self.esp_gpt_attributes;
+                    if self.esp_integrity_check {
+                        let crc = crc32_of_sectors(iso_file, s as u64, sz as u64)?;
+                        attributes = (attributes & !GPT_ATTR_ESP_CRC32_MASK)
+                            | (crc as u64) << GPT_ATTR_ESP_CRC32_SHIFT;
+                    }
+                    parts.push(GptPartitionEntry::new(
+                        EFI_SYSTEM_PARTITION_GUID,
+                        &self.gpt_guid(2),
+                        s as u64,
+                        e as u64,
+                        "EFI System Partition",
+                        attributes,
+                    ));
+                }
+            }
+            if let Some((start, end, label)) = persistence {
+                parts.push(GptPartitionEntry::new(
+                    LINUX_FILESYSTEM_GUID,
+                    &self.gpt_guid(3),
+                    start,
+                    end,
+                    label,
+                    0,
+                ));
+            }
+            if !parts.is_empty() {
+                let mut params = GptParams {
+                    disk_guid: Some(self.gpt_guid(0)),
+                    ..GptParams::default()
+                };
+                // The primary array must end before the first Apple
+                // Partition Map entry.
+                if self.apm_volume_name.is_some() {
+                    params.num_entries = GPT_ENTRIES_WITH_APM;
+                }
+                write_gpt_structures_with(iso_file, total_512, &parts, &params)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the written boot catalog back and checks that it opens with
+    /// the first boot section: BIOS when there is a BIOS boot image, since
+    /// legacy firmware only looks at the Initial/Default Entry, otherwise the
+    /// first prepared entry.
+    fn check_boot_catalog<R: Read + Seek>(&self, iso_file: &mut R) -> io::Result<()> {
+        let bios = self.boot_info.as_ref().and_then(|b| b.bios_boot.as_ref());
+        let (first_platform, first_lba) = match (bios, self.boot_entries.first()) {
+            (Some(bios), _) => (
+                bios.platform_id,
+                get_lba_for_path(&self.root, &bios.destination_in_iso)?,
+            ),
+            (None, Some(e)) => (e.platform_id, e.boot_image_lba),
+            (None, None) => return Ok(()),
+        };
+        let mut catalog = [0u8; ISO_SECTOR_SIZE as usize];
+        iso_file.seek(SeekFrom::Start(self.catalog_lba() as u64 * ISO_SECTOR_SIZE))?;
+        iso_file.read_exact(&mut catalog)?;
+        check_first_section(&catalog, first_platform, first_lba)
+    }
+
+    /// Checks before writing that no two structures (volume descriptors,
+    /// boot catalog, directories, files, a raw ESP) share a sector and fails
+    /// the build with `InvalidData` if they do, and after writing that the
+    /// boot catalog opens with the first boot section.  Always on in debug
+    /// builds.
+    pub fn set_strict_layout(&mut self, v: bool) {
+        self.strict_layout = v;
+    }
+
+    /// Records the sectors each structure of the image will occupy and
+    /// fails if any two overlap.
+    fn check_layout(
+        &self,
+        el_torito: bool,
+        enhanced_lba: Option<u32>,
+        raw_esp: Option<(u32, u32)>,
+    ) -> io::Result<()> {
+        let mut t = LayoutTracker::default();
+        let terminator = self.terminator(el_torito) as u64;
+        t.record("volume descriptors", 16, terminator - 15);
+        if el_torito {
+            t.record("boot catalog", self.catalog_lba() as u64, 1);
+            if let Some((lba, _)) = &self.secondary_boot_catalog {
+                t.record("secondary boot catalog", *lba as u64, 1);
+            }
+        }
+        if let Some(lba) = enhanced_lba {
+            let sectors = dir_count(&self.root) as u64;
+            t.record("enhanced directories", lba as u64, sectors);
+        }
+        if let Some((lba, sectors)) = raw_esp {
+            t.record("ESP", lba as u64, sectors as u64);
+        }
+        t.record_tree(&self.root, "", u32::from(self.dir_opts.ear_checksums));
+        t.check()
+    }
+
+    /// Buffers all writes of [`Self::build`] into aligned chunks (see
+    /// [`AlignedWriter`]).  Enabled automatically when the target is a block
+    /// device.
+    pub fn set_direct_io(&mut self, v: bool) {
+        self.direct_io = v;
+    }
+
+    /// Runs `hook` with the image path once [`Self::build`] has finished
+    /// and synced the file, e.g. to call an external `isohybrid` or other
+    /// patcher.  An error from the hook fails the build.  The hook is
+    /// consumed by the next build; [`Self::build_to`] does not run it.
+    pub fn set_post_process(&mut self, hook: PostProcess) {
+        self.post_process = Some(hook);
+    }
+
+    pub fn build(
+        &mut self,
+        iso_file: &mut File,
+        iso_path: &Path,
+        esp_lba: Option<u32>,
+        esp_size_sectors: Option<u32>,
+    ) -> io::Result<()> {
+        let block_device = is_block_device(iso_file)?;
+        if self.direct_io || block_device {
+            let mut w = AlignedWriter::new(&mut *iso_file)?;
+            self.build_to(&mut w, esp_lba, esp_size_sectors)?;
+            w.flush()?;
+            // Drop the zero padding of the last aligned block.
+            let len = w.len();
+            drop(w);
+            if !block_device {
+                iso_file.set_len(len)?;
+            }
+        } else {
+            self.build_to(iso_file, esp_lba, esp_size_sectors)?;
+        }
+        if let Some(hook) = self.post_process.take() {
+            iso_file.sync_data()?;
+            return hook(iso_path);
+        }
+        if self.hybrid_mode != HybridMode::None {
+            iso_file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Like [`Self::build`], writing into any seekable stream, e.g. an
+    /// in-memory buffer or an [`AlignedWriter`].  The stream is truncated
+    /// to the image's length, so nothing of a previous, larger image
+    /// remains.  The caller flushes `w`.
+    pub fn build_to<W: Read + Write + Seek + SetLen>(
+        &mut self,
+        iso_file: &mut W,
+        esp_lba: Option<u32>,
+        esp_size_sectors: Option<u32>,
+    ) -> io::Result<()> {
+        let result = self.write_image(iso_file, esp_lba, esp_size_sectors);
+        self.remove_esp_padding();
+        self.remove_generated_files();
+        result
+    }
+
+    fn write_image<W: Read + Write + Seek + SetLen>(
+        &mut self,
+        iso_file: &mut W,
+        esp_lba: Option<u32>,
+        esp_size_sectors: Option<u32>,
+    ) -> io::Result<()> {
+        self.esp_lba = esp_lba;
+        self.esp_size_sectors = esp_size_sectors;
+        self.dir_opts.timestamps = if self.build_seed.is_some() {
+            TimestampSource::Fixed
+        } else {
+            self.timestamps
+        };
+        self.dir_opts.recorded = match self.dir_opts.timestamps {
+            TimestampSource::Fixed => [0; 7],
+            _ => recording_date(SystemTime::now()),
+        };
+
+        if self.apm_volume_name.is_some()
+            && (self.system_area.is_some()
+                || self.hybrid_mode != HybridMode::None && self.mbr_boot_code()?.is_some())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an Apple Partition Map's Driver Descriptor Map would overwrite \
+                 the MBR boot code or system area",
+            ));
+        }
+
+        self.add_lazy_files()?;
+        self.add_autoconfig()?;
+        self.add_disc_info()?;
+        self.pad_floppy_image()?;
+        self.prepare_relocation()?;
+        self.place_boot_catalog()?;
+
+        let renamed = self.transliterated_names()?;
+        if self.strict_names
+            && let Some((orig, mapped)) = renamed.first()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{orig}' is not a valid ISO 9660 name (would become '{mapped}')"),
+            ));
+        }
+        if let Some(level) = self.iso_level {
+            check_name_lengths(&self.root, "", level, self.dir_opts.replacement)?;
+        }
+
+        let el_torito = self.has_boot_entries(esp_lba);
+        let mut data_lba = self.default_data_lba(el_torito);
+        self.check_data_lba(data_lba, el_torito)?;
+        // The enhanced tree's directories come first, then the primary tree.
+        let enhanced_lba = self.enhanced_vd.then_some(data_lba);
+        if self.enhanced_vd {
+            data_lba += dir_count(&self.root);
+        }
+        iso_file.seek(SeekFrom::Start(data_lba as u64 * ISO_SECTOR_SIZE))?;
+        let ear_sectors = u32::from(self.dir_opts.ear_checksums);
+        self.iso_data_lba = calculate_lbas_with_ear(data_lba, &mut self.root, ear_sectors)?;
+        let data_gap = self.align_hybrid_esp()?;
+        if data_gap > 0 || self.esp_padding.is_some() {
+            self.iso_data_lba =
+                calculate_lbas_with_ear(data_lba + data_gap, &mut self.root, ear_sectors)?;
+        }
+
+        let (resolved_lba, resolved_size) = if let Some(ref ip) = self.efi_boot_image_iso_path {
+            (
+                Some(get_lba_for_path(&self.root, ip)?),
+                Some(get_file_size_in_iso(&self.root, ip)?.div_ceil(ISO_SECTOR_SIZE) as u32),
+            )
+        } else {
+            (esp_lba, esp_size_sectors)
+        };
+        self.esp_lba = resolved_lba;
+        self.esp_size_sectors = resolved_size;
+
+        if cfg!(debug_assertions) || self.strict_layout {
+            let raw_esp = match (&self.efi_boot_image_iso_path, esp_lba, esp_size_sectors) {
+                (None, Some(lba), Some(sectors)) => Some((lba, sectors)),
+                _ => None,
+            };
+            self.check_layout(el_torito, enhanced_lba, raw_esp)?;
+        }
+
+        // Resolved before anything is written so that a boot image too large
+        // for the catalog's 16-bit sector count fails without touching the
+        // output.
+        let boot_entries = self.prepare_boot_entries(esp_lba, esp_size_sectors)?;
+        self.boot_entries = boot_entries.clone();
+        let secondary_entries = self.prepare_secondary_entries()?;
+        let catalog_lbas = self.catalog_lbas();
+
+        write_descriptors(
+            iso_file,
+            self.volume_id.as_deref(),
+            self.root.lba,
+            self.iso_data_lba,
+            &self.pvd_opts,
+            if el_torito { &catalog_lbas } else { &[] },
+            enhanced_lba,
+        )?;
+        if el_torito {
+            let terminator = self.terminator(true);
+            let mut w = SectorWriter::new(&mut *iso_file);
+            for lba in terminator + 1..self.last_catalog_lba() {
+                if !catalog_lbas.contains(&lba) {
+                    w.write_at_lba(lba, &[0; ISO_SECTOR_SIZE as usize])?;
+                }
+            }
+        }
+        write_boot_catalog_to_iso(
+            iso_file,
+            self.catalog_lba(),
+            boot_entries,
+            self.boot_catalog_id,
+        )?;
+        if let Some((lba, _)) = &self.secondary_boot_catalog {
+            write_boot_catalog_to_iso(iso_file, *lba, secondary_entries, self.boot_catalog_id)?;
+        }
+        if cfg!(debug_assertions) || self.strict_layout {
+            self.check_boot_catalog(iso_file)?;
+        }
+        let mut w = SectorWriter::new(&mut *iso_file);
+        for lba in data_lba..data_lba + data_gap {
+            w.write_at_lba(lba, &[0; ISO_SECTOR_SIZE as usize])?;
+        }
+        if let Some(lba) = enhanced_lba {
+            write_enhanced_directories(iso_file, &self.root, lba, &self.dir_opts)?;
+        }
+        write_directories(iso_file, &self.root, self.root.lba, &self.dir_opts)?;
+        if self.sparse_copy {
+            copy_files_sparse(iso_file, &self.root)?;
+        } else {
+            copy_files_prefetched(iso_file, &self.root, self.copy_parallelism)?;
+        }
+
+        // Capture the exact end of the newly written ISO data *before*
+        // patching the boot information table (which seeks back into the
+        // data stream).  Using this saved position in the seek below is
+        // more robust than SeekFrom::End(0) because it does not depend on
+        // whether the underlying file was truncated before being passed in.
+        let end_of_data = iso_file.stream_position()?;
+
+        for (path, offset) in &self.lba_patches {
+            let lba = self.lba_patch_target(path, *offset)?;
+            write_lba_patch(iso_file, lba, *offset)?;
+        }
+
+        // The boot info table is an isolinux convention for x86 images;
+        // other platforms' loaders would be corrupted by it.
+        if let Some(bi) = &self.boot_info
+            && let Some(bios) = &bi.bios_boot
+            && bios.platform_id == BOOT_CATALOG_X86_PLATFORM_ID
+            && self.bios_emulation == BootEmulation::NoEmulation
+        {
+            let lba = get_lba_for_path(&self.root, &bios.destination_in_iso)?;
+            let size = get_file_size_in_iso(&self.root, &bios.destination_in_iso)?;
+            write_boot_info_table(iso_file, lba, size)?;
+        }
+
+        // Checksums cover the data as finally stored, boot info table included.
+        if self.dir_opts.ear_checksums {
+            write_ear_checksums(iso_file, &self.root)?;
+        }
+
+        // Seek back to the saved end-of-data position so finalize_iso can
+        // compute the correct total sector count.  The rest of its sector
+        // is zeroed first; directories laid out after the last file's
+        // data, such as relocated deep ones, end the image too.
+        iso_file.seek(SeekFrom::Start(end_of_data))?;
+        let tail = end_of_data.next_multiple_of(ISO_SECTOR_SIZE) - end_of_data;
+        iso_file.write_all(&vec![0; tail as usize])?;
+        let planned_end = self.iso_data_lba as u64 * ISO_SECTOR_SIZE;
+        iso_file.seek(SeekFrom::Start((end_of_data + tail).max(planned_end)))?;
+
+        finalize_iso(iso_file, &mut self.total_sectors)?;
+
+        // The system area goes in first so the hybrid MBR/GPT below can
+        // take precedence over the overlapping bytes.  It is zero-padded to
+        // its full 32 KiB, and a data-only disc gets an all-zero one, so
+        // nothing left in a reused output shows through.
+        if self.system_area.is_some() || self.hybrid_mode == HybridMode::None {
+            let mut area = vec![0u8; SYSTEM_AREA_SIZE as usize];
+            if let Some(data) = &self.system_area {
+                area[..data.len()].copy_from_slice(data);
+            }
+            iso_file.seek(SeekFrom::Start(0))?;
+            iso_file.write_all(&area)?;
+        }
+
+        if self.hybrid_mode == HybridMode::BiosMbr {
+            self.write_bios_mbr(iso_file, self.total_sectors as u64)?;
+        } else if self.hybrid_mode.uses_gpt_esp() {
+            self.write_hybrid_structures(iso_file, self.total_sectors as u64, esp_size_sectors)?;
+            iso_file.seek(SeekFrom::End(0))?;
+            let mut w = SectorWriter::new(&mut *iso_file);
+            let total = w.current_lba().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "ISO too large after GPT backup",
+                )
+            })?;
+            w.pad_to_lba(total)?;
+            update_total_sectors_in_pvd(iso_file, total)?;
+            self.total_sectors = total;
+        }
+        if self.enhanced_vd {
+            let evd_lba = self.terminator(el_torito) - 1;
+            update_total_sectors_in_vd(iso_file, evd_lba, self.total_sectors)?;
+        }
+        if let Some(name) = &self.apm_volume_name {
+            let blocks = self.total_sectors * (ISO_SECTOR_SIZE / APM_BLOCK_SIZE) as u32;
+            write_apm(iso_file, &[ApmPartition::new(name, "Apple_HFS", 0, blocks)])?;
+        }
+        Ok(())
+    }
+}
+
+/// CRC32 of `sectors` 512-byte sectors of `r` from `start_512`.  Anything
+/// past the end of the stream counts as zeros, as it reads back once the
+/// image is padded.
+fn crc32_of_sectors<R: Read + Seek>(r: &mut R, start_512: u64, sectors: u64) -> io::Result<u32> {
+    r.seek(SeekFrom::Start(start_512 * 512))?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut remaining = sectors * 512;
+    let mut buf = vec![0u8; 64 * 1024];
+    while remaining > 0 {
+        let n = (remaining as usize).min(buf.len());
+        let got = match r.read(&mut buf[..n])? {
+            0 => {
+                buf[..n].fill(0);
+                n
+            }
+            got => got,
+        };
+        hasher.update(&buf[..got]);
+        remaining -= got as u64;
+    }
+    Ok(hasher.finalize())
+}
+
+/// Renders the directory tree in on-disk order, one node per line indented
+/// by depth, with each node's LBA once a build has assigned it and its size.
+impl fmt::Display for IsoBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "/{}", node_suffix(self.root.lba, self.root.size as u64))?;
+        write_tree(f, &self.root, 1)
+    }
+}
+
+/// ` (LBA n, s bytes)`, leaving out an LBA that is still unassigned.
+fn node_suffix(lba: u32, size: u64) -> String {
+    if lba == 0 {
+        format!(" ({size} bytes)")
+    } else {
+        format!(" (LBA {lba}, {size} bytes)")
+    }
+}
+
+fn write_tree(f: &mut fmt::Formatter<'_>, dir: &IsoDirectory, depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    for_sorted_children!(dir, |name, node| {
+        match node {
+            IsoFsNode::File(file) => {
+                writeln!(f, "{indent}{name}{}", node_suffix(file.lba, file.size))?
+            }
+            IsoFsNode::Directory(subdir) => {
+                writeln!(
+                    f,
+                    "{indent}{name}/{}",
+                    node_suffix(subdir.lba, subdir.size as u64)
+                )?;
+                write_tree(f, subdir, depth + 1)?;
+            }
+            IsoFsNode::Symlink(link) => writeln!(f, "{indent}{name} -> {}", link.target)?,
+            IsoFsNode::BootCatalog(lba) => writeln!(
+                f,
+                "{indent}{name} [boot catalog]{}",
+                node_suffix(*lba, ISO_SECTOR_SIZE)
+            )?,
+        }
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_block_device(f: &File) -> io::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+    Ok(f.metadata()?.file_type().is_block_device())
+}
+
+#[cfg(not(unix))]
+fn is_block_device(_f: &File) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Sectors needed in front of a hybrid ESP file at `esp_lba` to move it to
+/// the next [`HYBRID_ESP_ALIGN_512`] boundary.
+fn esp_alignment_gap(esp_lba: u64) -> u32 {
+    let align = HYBRID_ESP_ALIGN_512 * 512 / ISO_SECTOR_SIZE;
+    (esp_lba.next_multiple_of(align) - esp_lba) as u32
+}
+
+/// Builds a FAT ESP image from `entries` plus a `grub.cfg` holding
+/// `grub_cfg`, if given, into a temporary file.
+fn fresh_esp(
+    mut entries: Vec<(PathBuf, String)>,
+    grub_cfg: Option<&str>,
+    hidden: u32,
+    cluster_size: Option<u16>,
+) -> io::Result<EspImage> {
+    // Only needed until its contents are copied into the image.
+    let mut _grub_holder = None;
+    if let Some(cfg) = grub_cfg {
+        let mut t = NamedTempFile::new()?;
+        write!(t, "{}", cfg)?;
+        entries.push((t.path().to_path_buf(), "EFI/BOOT/grub.cfg".into()));
+        _grub_holder = Some(t);
+    }
+    let (path, tf, sectors) = fat::build_esp_with_hidden(&entries, 0, hidden, cluster_size)?;
+    Ok(EspImage {
+        path,
+        sectors,
+        cache_hit: false,
+        temp_file: Some(tf),
+    })
+}
+
+/// Like [`fresh_esp`], but reuses the image in `dir` built from the same
+/// inputs if there is one, and stores a new image there otherwise.  Images
+/// are named by the CRC32 of a key listing every input; the key itself is
+/// kept beside the image and compared in full, so a CRC collision only
+/// costs a rebuild.
+fn cached_esp(
+    dir: &Path,
+    entries: Vec<(PathBuf, String)>,
+    grub_cfg: Option<&str>,
+    hidden: u32,
+    cluster_size: Option<u16>,
+) -> io::Result<EspImage> {
+    use std::fmt::Write as _;
+    let mut key = format!(
+        "isobemak {}\nhidden {hidden}\ncluster {cluster_size:?}\ngrub.cfg {grub_cfg:?}\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    for (src, dest) in &entries {
+        let meta = std::fs::metadata(src)?;
+        let mtime = meta
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let _ = writeln!(key, "{dest}\t{}\t{}\t{mtime}", src.display(), meta.len());
+    }
+    let name = format!("esp-{:08x}", crc32fast::hash(key.as_bytes()));
+    let image_path = dir.join(format!("{name}.img"));
+    let key_path = dir.join(format!("{name}.key"));
+    if std::fs::read_to_string(&key_path).is_ok_and(|k| k == key)
+        && let Ok(meta) = std::fs::metadata(&image_path)
+    {
+        return Ok(EspImage {
+            path: image_path,
+            sectors: (meta.len() / 512) as u32,
+            cache_hit: true,
+            temp_file: None,
+        });
+    }
+
+    std::fs::create_dir_all(dir)?;
+    // The key goes last, so an interrupted store is never taken for a hit.
+    match std::fs::remove_file(&key_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let fresh = fresh_esp(entries, grub_cfg, hidden, cluster_size)?;
+    std::fs::copy(&fresh.path, &image_path)?;
+    std::fs::write(&key_path, key)?;
+    Ok(EspImage {
+        path: image_path,
+        sectors: fresh.sectors,
+        cache_hit: false,
+        temp_file: None,
+    })
+}
+
+pub fn build_iso(
+    iso_path: &Path,
+    image: &IsoImage,
+    hybrid_mode: impl Into<HybridMode>,
+) -> io::Result<BuildOutput> {
+    let hybrid_mode = hybrid_mode.into();
+    let mut b = IsoBuilder::new();
+    b.set_profile(image.layout_profile.clone());
+    b.set_volume_id(image.volume_id.clone());
+    b.set_hybrid_mode(hybrid_mode);
+
+    let mut fat_holder: Option<NamedTempFile> = None;
+    let mut fat_size_512: Option<u32> = None;
+    let mut iso_file = OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
         .truncate(true)
         .open(iso_path)?;
 
-    if let Some(uefi) = &image.boot_info.uefi_boot {
-        b.uefi_catalog_path = Some(uefi.destination_in_iso.clone());
-        if is_isohybrid {
-            let tf = NamedTempFile::new()?;
-            let p = tf.path().to_path_buf();
-            fat_holder = Some(tf);
-
-            let mut ff: Vec<(&str, &Path)> = vec![
-                ("BOOTX64.EFI", uefi.boot_image.as_path()),
-                ("KERNEL.EFI", uefi.kernel_image.as_path()),
-            ];
-            for (dn, sp) in &uefi.additional_efi_boot_files {
-                ff.push((dn, sp));
-            }
-            let _grub_path: Option<PathBuf>;
-            if let Some(cfg) = &uefi.grub_cfg_content {
-                let mut t = NamedTempFile::new()?;
-                write!(t, "{}", cfg)?;
-                _grub_path = Some(t.path().to_path_buf());
-                _grub_holder = Some(t);
-                ff.push(("grub.cfg", _grub_path.as_ref().unwrap()));
-            }
-            let hidden = match b.profile.hidden_sectors_mode {
-                HiddenSectorMode::Zero => 0,
-                HiddenSectorMode::PartitionOffset => b.profile.esp_alignment_lba_512,
-            };
-            fat_size_512 = Some(fat::create_fat_image(&p, &ff, hidden)?);
-            b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
-            b.add_file("boot/efiboot.img", &p)?;
+    if let Some(uefi) = &image.boot_info.uefi_boot {
+        b.uefi_catalog_path = Some(uefi.destination_in_iso.clone());
+        if hybrid_mode.uses_gpt_esp() {
+            let esp = b.add_uefi_esp(uefi)?;
+            fat_holder = esp.temp_file;
+            fat_size_512 = Some(esp.sectors);
+        }
+    }
+
+    for f in &image.files {
+        b.add_file(&f.destination, &f.source)?;
+    }
+    if let Some(bios) = &image.boot_info.bios_boot {
+        b.add_file(&bios.destination_in_iso, &bios.boot_image)?;
+    }
+    b.set_boot_info(image.boot_info.clone());
+    b.build(&mut iso_file, iso_path, b.esp_lba, b.esp_size_sectors)?;
+    Ok(BuildOutput {
+        iso_path: iso_path.to_path_buf(),
+        esp: fat_holder,
+        file: iso_file,
+        esp_sectors: fat_size_512,
+        total_sectors: b.total_sectors(),
+    })
+}
+
+/// [`build_iso`] on tokio's blocking thread pool, so an async service does
+/// not stall a worker thread for the whole build.  Resolves to the same
+/// [`BuildOutput`].  The path and image are
+/// taken by value since the build outlives any borrow of the caller's.
+/// A panic in the build resumes in the awaiting task.
+#[cfg(feature = "tokio")]
+pub async fn build_iso_async(
+    iso_path: PathBuf,
+    image: IsoImage,
+    hybrid_mode: impl Into<HybridMode>,
+) -> io::Result<BuildOutput> {
+    let hybrid_mode = hybrid_mode.into();
+    match tokio::task::spawn_blocking(move || build_iso(&iso_path, &image, hybrid_mode)).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iso::boot_catalog::LBA_BOOT_CATALOG;
+    use crate::iso::builder_utils::calculate_lbas;
+    use crate::utils::read_both_endian_u32;
+    use crate::utils::test_utils::efi_stub;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_add_file() -> io::Result<()> {
+        let mut builder = IsoBuilder::new();
+        let tp = NamedTempFile::new()?.into_temp_path();
+        builder.add_file("root.txt", &tp)?;
+        assert!(builder.root.children.contains_key("root.txt"));
+        builder.add_file("dir1/nested.txt", &tp)?;
+        match builder.root.children.get("dir1") {
+            Some(IsoFsNode::Directory(d)) => assert!(d.children.contains_key("nested.txt")),
+            _ => panic!(),
+        };
+        Ok(())
+    }
+
+    #[test]
+    fn test_calculate_lbas() -> io::Result<()> {
+        let mut root = IsoDirectory::new();
+        let mut subdir = IsoDirectory::new();
+        subdir.children.insert(
+            "file2.txt".into(),
+            IsoFsNode::File(IsoFile {
+                path: PathBuf::new(),
+                size: 3000,
+                lba: 0,
+                padding_sectors: 0,
+                zero_fill: 0,
+                modified: None,
+                reader: None,
+                executable: false,
+            }),
+        );
+        root.children.insert(
+            "file1.txt".into(),
+            IsoFsNode::File(IsoFile {
+                path: PathBuf::new(),
+                size: 1000,
+                lba: 0,
+                padding_sectors: 0,
+                zero_fill: 0,
+                modified: None,
+                reader: None,
+                executable: false,
+            }),
+        );
+        root.children
+            .insert("subdir".into(), IsoFsNode::Directory(subdir));
+        let end = calculate_lbas(20, &mut root)?;
+        assert_eq!(root.lba, 20);
+        assert_eq!(
+            root.children
+                .get("file1.txt")
+                .and_then(|n| if let IsoFsNode::File(f) = n {
+                    Some(f.lba)
+                } else {
+                    None
+                }),
+            Some(21)
+        );
+        let (sl, fl) = match root.children.get("subdir") {
+            Some(IsoFsNode::Directory(d)) => (
+                d.lba,
+                d.children.get("file2.txt").and_then(|n| {
+                    if let IsoFsNode::File(f) = n {
+                        Some(f.lba)
+                    } else {
+                        None
+                    }
+                }),
+            ),
+            _ => panic!(),
+        };
+        assert_eq!(sl, 22);
+        assert_eq!(fl, Some(23));
+        assert_eq!(end, 25);
+
+        // A second pass overwrites every LBA instead of continuing from the
+        // first one.
+        assert_eq!(calculate_lbas(20, &mut root)?, 25);
+        assert_eq!(root.lba, 20);
+        assert_eq!(get_lba_for_path(&root, "file1.txt")?, 21);
+        assert_eq!(get_lba_for_path(&root, "subdir/file2.txt")?, 23);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_file_version() -> io::Result<()> {
+        let mut b = IsoBuilder::new();
+        for v in [0, 32768] {
+            let err = b.set_file_version(Some(v)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(b.dir_opts.file_version, Some(1));
+        b.set_file_version(Some(32767))?;
+        assert_eq!(b.dir_opts.file_version, Some(32767));
+        b.set_file_version(None)?;
+        assert_eq!(b.dir_opts.file_version, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_parallelism_is_byte_identical() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut sources = Vec::new();
+        for (i, len) in [5000usize, 1, 2_500_000, 2048, 0].iter().enumerate() {
+            let p = dir.path().join(format!("f{i}.bin"));
+            std::fs::write(&p, (0..*len).map(|b| (b * 7 + i) as u8).collect::<Vec<_>>())?;
+            sources.push(p);
+        }
+
+        let mut outputs = Vec::new();
+        for n in [0usize, 1, 4] {
+            let mut b = IsoBuilder::new();
+            b.set_copy_parallelism(n);
+            for (i, p) in sources.iter().enumerate() {
+                b.add_file(&format!("D{}/F{i}.BIN", i % 2), p)?;
+            }
+            let path = dir.path().join(format!("out{n}.iso"));
+            let mut f = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)?;
+            b.build(&mut f, &path, None, None)?;
+            outputs.push(std::fs::read(&path)?);
+        }
+        assert_eq!(outputs[0], outputs[1]);
+        assert_eq!(outputs[0], outputs[2]);
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_build_iso_async() -> io::Result<()> {
+        use crate::iso::iso_image::IsoImageFile;
+
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("readme.txt");
+        std::fs::write(&src, b"hello")?;
+        let image = IsoImage {
+            volume_id: Some("ASYNC".into()),
+            files: vec![IsoImageFile {
+                source: src,
+                destination: "README.TXT".into(),
+            }],
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: None,
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let iso_path = dir.path().join("async.iso");
+        let out = build_iso_async(iso_path.clone(), image, false).await?;
+        assert_eq!(out.iso_path, iso_path);
+        assert!(out.esp.is_none());
+        assert_eq!(out.total_sectors as u64, out.file.metadata()?.len() / 2048);
+        let data = std::fs::read(&iso_path)?;
+        assert_eq!(&data[16 * 2048 + 1..16 * 2048 + 6], b"CD001");
+        assert_eq!(&data[16 * 2048 + 40..16 * 2048 + 45], b"ASYNC");
+
+        let missing = IsoImage {
+            volume_id: None,
+            files: vec![IsoImageFile {
+                source: dir.path().join("missing"),
+                destination: "MISSING".into(),
+            }],
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: None,
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        assert!(build_iso_async(iso_path, missing, false).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bios_mbr_hybrid_mode() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let bios = dir.path().join("isolinux.bin");
+        let mut code = vec![0u8; 4096];
+        code[..4].copy_from_slice(&[0xFA, 0xEB, 0x3C, 0x90]);
+        std::fs::write(&bios, &code)?;
+        let image = IsoImage {
+            volume_id: None,
+            files: Vec::new(),
+            boot_info: BootInfo {
+                bios_boot: Some(BiosBootInfo {
+                    boot_image: bios,
+                    destination_in_iso: "isolinux/isolinux.bin".into(),
+                    ..Default::default()
+                }),
+                uefi_boot: None,
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let iso_path = dir.path().join("bios.iso");
+        build_iso(&iso_path, &image, HybridMode::BiosMbr)?;
+
+        let data = std::fs::read(&iso_path)?;
+        assert_eq!(&data[..4], &[0xFA, 0xEB, 0x3C, 0x90]);
+        assert_eq!(u16::from_le_bytes([data[510], data[511]]), 0xAA55);
+        let p0 = &data[446..462];
+        assert_eq!(p0[0], 0x80, "partition must be active");
+        assert_eq!(p0[4], 0x17, "partition type must be 0x17");
+        assert_eq!(u32::from_le_bytes(p0[8..12].try_into().unwrap()), 0);
+        assert_eq!(
+            u32::from_le_bytes(p0[12..16].try_into().unwrap()) as usize,
+            data.len() / 512
+        );
+        // No GPT is written in BIOS-only mode.
+        assert_ne!(&data[512..520], b"EFI PART");
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_slash_needs_file_name() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&src, b"loader")?;
+        let mut b = IsoBuilder::new();
+        for path in ["EFI/BOOT/", "EFI/BOOT//", "/"] {
+            let err = b.add_file(path, &src).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{path}");
+            assert!(err.to_string().contains("file name"), "{err}");
+        }
+        assert!(b.add_symlink("EFI/BOOT/", "x").is_err());
+        assert!(b.root.children.is_empty());
+
+        b.add_file("EFI/BOOT/BOOTX64.EFI", &src)?;
+        assert_eq!(get_file_size_in_iso(&b.root, "EFI/BOOT/BOOTX64.EFI")?, 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_path_separators() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("X.EFI");
+        std::fs::write(&src, b"loader")?;
+        let mut trees = Vec::new();
+        for path in [
+            "EFI/BOOT/X.EFI",
+            "EFI\\BOOT\\X.EFI",
+            "EFI\\BOOT/X.EFI",
+            "/EFI/BOOT/X.EFI",
+        ] {
+            let mut b = IsoBuilder::new();
+            b.add_file(path, &src)?;
+            assert_eq!(
+                get_file_size_in_iso(&b.root, "EFI\\BOOT/X.EFI")?,
+                6,
+                "{path}"
+            );
+            trees.push(b.to_string());
+        }
+        assert!(trees.iter().all(|t| *t == trees[0]), "{trees:?}");
+        assert_eq!(
+            trees[0],
+            "/ (2048 bytes)\n  EFI/ (2048 bytes)\n    BOOT/ (2048 bytes)\n      X.EFI (6 bytes)\n"
+        );
+
+        let mut b = IsoBuilder::new();
+        b.add_file("EFI/BOOT/X.EFI", &src)?;
+        assert!(b.add_file("EFI\\BOOT\\", &src).is_err());
+        assert!(b.remove_file("EFI\\BOOT\\X.EFI")?);
+        assert!(b.remove_dir("EFI\\BOOT", false)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_tree() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.txt");
+        std::fs::write(&src, b"hello")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("docs/readme.txt", &src)?;
+        b.add_file("a.txt", &src)?;
+        b.add_symlink("docs/latest", "readme.txt")?;
+        assert_eq!(
+            b.to_string(),
+            "/ (2048 bytes)\n  a.txt (5 bytes)\n  docs/ (2048 bytes)\n    \
+             latest -> readme.txt\n    readme.txt (5 bytes)\n"
+        );
+
+        build_named(&mut b, dir.path())?;
+        let tree = b.to_string();
+        let root = b.root.lba;
+        assert!(
+            tree.starts_with(&format!("/ (LBA {root}, 2048 bytes)\n")),
+            "{tree}"
+        );
+        let readme = get_lba_for_path(&b.root, "docs/readme.txt")?;
+        assert!(tree.contains(&format!("    readme.txt (LBA {readme}, 5 bytes)\n")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_nodes() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.txt");
+        std::fs::write(&src, b"hello")?;
+        let mut b = IsoBuilder::new();
+        assert_eq!(b.iter_nodes().count(), 0);
+        b.add_file("docs/readme.txt", &src)?;
+        b.add_file("z.txt", &src)?;
+        b.add_file("docs/api/index.txt", &src)?;
+        b.add_file("a.txt", &src)?;
+        b.add_symlink("docs/latest", "readme.txt")?;
+
+        let nodes: Vec<_> = b.iter_nodes().collect();
+        let paths: Vec<_> = nodes.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "a.txt",
+                "docs",
+                "docs/api",
+                "docs/api/index.txt",
+                "docs/latest",
+                "docs/readme.txt",
+                "z.txt",
+            ]
+        );
+        assert!(matches!(nodes[1].1, IsoFsNode::Directory(_)));
+        assert!(matches!(nodes[4].1, IsoFsNode::Symlink(_)));
+        assert_eq!(nodes[6].1.size(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bios_load_count() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("boot.bin");
+        std::fs::write(&boot, vec![0x42; 4096])?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot.bin", &boot)?;
+        let mut build = |load_sectors| {
+            b.set_boot_info(BootInfo {
+                bios_boot: Some(BiosBootInfo {
+                    boot_image: boot.clone(),
+                    destination_in_iso: "boot.bin".into(),
+                    load_sectors,
+                    ..Default::default()
+                }),
+                uefi_boot: None,
+            });
+            build_named(&mut b, dir.path())?;
+            let data = std::fs::read(dir.path().join("names.iso"))?;
+            let entry = LBA_BOOT_CATALOG as usize * 2048 + 32;
+            Ok::<_, io::Error>(u16::from_le_bytes([data[entry + 6], data[entry + 7]]))
+        };
+        assert_eq!(build(None)?, 8);
+        assert_eq!(build(Some(1))?, 1);
+        assert_eq!(build(Some(8))?, 8);
+        for bad in [0, 9] {
+            let err = build(Some(bad)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{bad}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_bios_boot_image() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        for len in [0, 511] {
+            let boot = dir.path().join("boot.bin");
+            std::fs::write(&boot, vec![0x42; len])?;
+            let mut b = IsoBuilder::new();
+            b.add_file("boot.bin", &boot)?;
+            b.set_boot_info(BootInfo {
+                bios_boot: Some(BiosBootInfo {
+                    boot_image: boot,
+                    destination_in_iso: "boot.bin".into(),
+                    ..Default::default()
+                }),
+                uefi_boot: None,
+            });
+            let err = build_named(&mut b, dir.path()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{len}");
+            assert!(err.to_string().contains("boot.bin"), "{err}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_bios_floppy_emulation() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let floppy = BootEmulation::Floppy {
+            cylinders: 80,
+            heads: 2,
+            sectors_per_track: 18,
+        };
+        let build = |len: usize, emulation| {
+            let boot = dir.path().join("floppy.img");
+            let image: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            std::fs::write(&boot, &image)?;
+            let mut b = IsoBuilder::new();
+            b.add_file("floppy.img", &boot)?;
+            b.set_boot_info(BootInfo {
+                bios_boot: Some(BiosBootInfo {
+                    boot_image: boot,
+                    destination_in_iso: "floppy.img".into(),
+                    ..Default::default()
+                }),
+                uefi_boot: None,
+            });
+            b.set_bios_emulation(emulation)?;
+            build_named(&mut b, dir.path())?;
+            Ok::<_, io::Error>((std::fs::read(dir.path().join("names.iso"))?, image))
+        };
+
+        let (data, image) = build(2880 * 512, floppy)?;
+        let entry = LBA_BOOT_CATALOG as usize * 2048 + 32;
+        assert_eq!(data[entry + 1], 2);
+        assert_eq!(u16::from_le_bytes([data[entry + 6], data[entry + 7]]), 1);
+        let lba = u32::from_le_bytes(data[entry + 8..entry + 12].try_into().unwrap()) as usize;
+        // No boot information table is patched into the floppy.
+        assert_eq!(&data[lba * 2048..lba * 2048 + image.len()], &image[..]);
+
+        let err = build(2880 * 512 + 1, floppy).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("floppy.img"), "{err}");
+        let hard_disk = BootEmulation::HardDisk {
+            cylinders: 4,
+            heads: 16,
+            sectors_per_track: 63,
+        };
+        let err = build(4 * 16 * 63 * 512, hard_disk).unwrap_err();
+        assert!(err.to_string().contains("MBR"), "{err}");
+        let bad = BootEmulation::Floppy {
+            cylinders: 40,
+            heads: 2,
+            sectors_per_track: 9,
+        };
+        assert_eq!(
+            IsoBuilder::new()
+                .set_bios_emulation(bad)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_file_missing_source() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let missing = dir.path().join("not-yet.txt");
+        let mut b = IsoBuilder::new();
+        let err = b.add_file("docs/readme.txt", &missing).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let msg = err.to_string();
+        assert!(msg.contains("docs/readme.txt"), "{msg}");
+        assert!(msg.contains(&missing.display().to_string()), "{msg}");
+        assert!(b.root.children.is_empty());
+
+        // Deferred: only the build looks for the source.
+        b.add_file_lazy("docs/readme.txt", &missing)?;
+        assert_eq!(b.iter_nodes().count(), 0);
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("docs/readme.txt"), "{err}");
+
+        std::fs::write(&missing, b"hello")?;
+        build_named(&mut b, dir.path())?;
+        assert_eq!(get_file_size_in_iso(&b.root, "docs/readme.txt")?, 5);
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let start = get_lba_for_path(&b.root, "docs/readme.txt")? as usize * 2048;
+        assert_eq!(&data[start..start + 5], b"hello");
+
+        assert!(b.remove_file("docs/readme.txt")?);
+        build_named(&mut b, dir.path())?;
+        assert!(get_node_for_path(&b.root, "docs/readme.txt").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_reader() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let blob: Vec<u8> = (0..5000u32).map(|i| (i % 253) as u8).collect();
+        for (parallelism, sparse) in [(0, false), (4, false), (0, true)] {
+            let mut b = IsoBuilder::new();
+            let reader = Box::new(io::Cursor::new(blob.clone()));
+            b.add_reader("gen/blob.bin", reader, blob.len() as u64)?;
+            b.set_copy_parallelism(parallelism);
+            b.set_sparse_copy(sparse);
+            build_named(&mut b, dir.path())?;
+            let data = std::fs::read(dir.path().join("names.iso"))?;
+            let lba = get_lba_for_path(&b.root, "gen/blob.bin")?;
+            let gen_lba = match b.root.children.get("gen") {
+                Some(IsoFsNode::Directory(d)) => d.lba,
+                _ => panic!("gen directory missing"),
+            };
+            let rec = find_record(&data, gen_lba, b"BLOB.BIN");
+            assert_eq!(read_both_endian_u32(rec, 2)?, lba);
+            assert_eq!(read_both_endian_u32(rec, 10)?, blob.len() as u32);
+            let start = lba as usize * 2048;
+            assert_eq!(&data[start..start + blob.len()], &blob[..]);
+
+            // The reader was consumed by the first build.
+            let err = build_named(&mut b, dir.path()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let mut b = IsoBuilder::new();
+        b.add_reader("short.bin", Box::new(io::Cursor::new(vec![1u8; 10])), 11)?;
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // An endless reader is read no further than its size, so the
+        // prefetching copy fails instead of buffering it.
+        let mut b = IsoBuilder::new();
+        b.add_reader("endless.bin", Box::new(io::repeat(1)), 10)?;
+        b.set_copy_parallelism(2);
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_boot_catalog_lba() -> io::Result<()> {
+        use crate::iso::boot_catalog::validation_checksum;
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("boot.bin");
+        std::fs::write(&boot, vec![0xAB; 2048])?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot/boot.bin", &boot)?;
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot,
+                destination_in_iso: "boot/boot.bin".into(),
+                boot_catalog: Some("boot/boot.cat".into()),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        });
+        assert_eq!(
+            b.set_boot_catalog_lba(Some(18)).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        b.set_boot_catalog_lba(Some(25))?;
+        let path = dir.path().join("names.iso");
+        std::fs::write(&path, vec![0xEE; 64 * 2048])?;
+        let mut f = OpenOptions::new().read(true).write(true).open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+        let data = std::fs::read(&path)?;
+
+        let brvd = &data[17 * 2048..18 * 2048];
+        assert_eq!(&brvd[7..30], b"EL TORITO SPECIFICATION");
+        assert_eq!(u32::from_le_bytes(brvd[71..75].try_into().unwrap()), 25);
+        let catalog: &[u8; 32] = data[25 * 2048..][..32].try_into().unwrap();
+        assert_eq!(catalog[0], 1);
+        assert_eq!(
+            validation_checksum(catalog),
+            u16::from_le_bytes([catalog[28], catalog[29]])
+        );
+        // The stale bytes between the terminator and the catalog are gone.
+        assert!(data[19 * 2048..25 * 2048].iter().all(|&b| b == 0));
+        assert!(b.root.lba > 25);
+        for (path, node) in b.iter_nodes() {
+            match node {
+                IsoFsNode::BootCatalog(lba) => assert_eq!(*lba, 25, "{path}"),
+                _ if node.size() > 0 => assert!(node.lba() > 25, "{path}"),
+                _ => {}
+            }
+        }
+
+        // An Enhanced Volume Descriptor moves the terminator onto LBA 19.
+        b.set_boot_catalog_lba(Some(LBA_BOOT_CATALOG))?;
+        b.set_iso9660_version(2)?;
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_secondary_boot_catalog() -> io::Result<()> {
+        use crate::iso::boot_catalog::validation_checksum;
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("boot.bin");
+        std::fs::write(&boot, vec![0xAB; 2048])?;
+        let ppc = dir.path().join("ppc.img");
+        std::fs::write(&ppc, vec![0xCD; 4096])?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot/boot.bin", &boot)?;
+        b.add_file("boot/ppc.img", &ppc)?;
+        b.add_file("boot/ppc2.img", &ppc)?;
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot,
+                destination_in_iso: "boot/boot.bin".into(),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        });
+        assert_eq!(
+            b.set_secondary_boot_catalog(Some((22, Vec::new())))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        b.set_secondary_boot_catalog(Some((
+            22,
+            vec![
+                ("boot/ppc.img".into(), 0x01),
+                ("boot/ppc2.img".into(), 0x01),
+            ],
+        )))?;
+        b.set_strict_layout(true);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+
+        // Two boot records at 17 and 18 push the terminator to 19 and the
+        // first catalog to 20.
+        let brvd = |lba: usize| &data[lba * 2048..(lba + 1) * 2048];
+        for (lba, catalog) in [(17, 20u32), (18, 22)] {
+            assert_eq!(brvd(lba)[0], 0);
+            assert_eq!(&brvd(lba)[7..30], b"EL TORITO SPECIFICATION");
+            assert_eq!(brvd(lba)[71..75], catalog.to_le_bytes());
+        }
+        assert_eq!(brvd(19)[0], 255);
+        for (lba, platform) in [(20, 0x00), (22, 0x01)] {
+            let catalog = &data[lba * 2048..(lba + 1) * 2048];
+            let ve: &[u8; 32] = catalog[..32].try_into().unwrap();
+            assert_eq!((ve[0], ve[1]), (1, platform));
+            assert_eq!(
+                validation_checksum(ve),
+                u16::from_le_bytes([ve[28], ve[29]])
+            );
+            assert_eq!(catalog[32], 0x88);
+        }
+        let secondary = &data[22 * 2048..23 * 2048];
+        let ppc_lba = get_lba_for_path(&b.root, "boot/ppc.img")?;
+        assert_eq!(secondary[40..44], ppc_lba.to_le_bytes());
+        assert_eq!(&secondary[64..66], &[0x91, 0x01]);
+        assert_eq!(secondary[96], 0x88);
+        assert!(data[21 * 2048..22 * 2048].iter().all(|&b| b == 0));
+        assert!(b.root.lba > 22);
+
+        // Neither catalog may collide with the other or the descriptors.
+        for lba in [19, 20] {
+            b.set_secondary_boot_catalog(Some((lba, vec![("boot/ppc.img".into(), 0x01)])))?;
+            let err = build_named(&mut b, dir.path()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{lba}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation_entry_platform() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("boot.bin");
+        std::fs::write(&boot, vec![0x42; 2048])?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) = fat::build_esp(&[(loader, "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot.bin", &boot)?;
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot,
+                destination_in_iso: "boot.bin".into(),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        });
+        b.set_strict_layout(true);
+        let mut out = io::Cursor::new(Vec::new());
+        b.build_to(&mut out, None, None)?;
+
+        // BIOS is the first section: the validation entry and the default
+        // entry are x86, and UEFI follows under its own section header.
+        let catalog = &out.get_ref()[LBA_BOOT_CATALOG as usize * 2048..][..2048];
+        assert_eq!(catalog[1], 0);
+        assert_eq!(b.boot_entries[0].platform_id, catalog[1]);
+        assert_eq!(&catalog[64..66], &[0x91, 0xEF]);
+
+        // A catalog that opens with the UEFI entry fails the check.
+        let esp_lba = get_lba_for_path(&b.root, "boot/efiboot.img")?;
+        let default = LBA_BOOT_CATALOG as usize * 2048 + 32;
+        out.get_mut()[default + 8..default + 12].copy_from_slice(&esp_lba.to_le_bytes());
+        let err = b.check_boot_catalog(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let boot_lba = get_lba_for_path(&b.root, "boot.bin")?;
+        out.get_mut()[default + 8..default + 12].copy_from_slice(&boot_lba.to_le_bytes());
+        b.check_boot_catalog(&mut out)?;
+        out.get_mut()[LBA_BOOT_CATALOG as usize * 2048 + 1] = 0xEF;
+        assert!(b.check_boot_catalog(&mut out).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_floppy_image_padding() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("floppy.img");
+        let image: Vec<u8> = (0..1_400_000).map(|i| (i % 251 + 1) as u8).collect();
+        std::fs::write(&boot, &image)?;
+        let mut b = IsoBuilder::new();
+        b.add_file("floppy.img", &boot)?;
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot,
+                destination_in_iso: "floppy.img".into(),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        });
+        b.set_bios_emulation(BootEmulation::Floppy {
+            cylinders: 80,
+            heads: 2,
+            sectors_per_track: 18,
+        })?;
+
+        let mut builds = Vec::new();
+        for (parallelism, sparse) in [(0, false), (4, false), (0, true)] {
+            b.set_copy_parallelism(parallelism);
+            b.set_sparse_copy(sparse);
+            build_named(&mut b, dir.path())?;
+            let data = std::fs::read(dir.path().join("names.iso"))?;
+            let rec = find_record(&data, b.root.lba, b"FLOPPY.IMG");
+            assert_eq!(read_both_endian_u32(rec, 10)?, 1_474_560);
+            let lba = read_both_endian_u32(rec, 2)? as usize * 2048;
+            assert_eq!(&data[lba..lba + image.len()], &image[..]);
+            assert!(
+                data[lba + image.len()..lba + 1_474_560]
+                    .iter()
+                    .all(|&x| x == 0)
+            );
+            builds.push(data);
+        }
+        assert!(builds.iter().all(|d| *d == builds[0]));
+
+        // Without emulation the image is copied as it is.
+        b.set_bios_emulation(BootEmulation::NoEmulation)?;
+        build_named(&mut b, dir.path())?;
+        assert_eq!(get_file_size_in_iso(&b.root, "floppy.img")?, 1_400_000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_boot_entry_summary() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("boot.bin");
+        std::fs::write(&boot, vec![0x42; 2048])?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) = fat::build_esp(&[(loader, "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot.bin", &boot)?;
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot,
+                destination_in_iso: "boot.bin".into(),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        });
+        assert!(b.boot_entry_summary().is_empty());
+        build_named(&mut b, dir.path())?;
+
+        let summary = b.boot_entry_summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0], (0, get_lba_for_path(&b.root, "boot.bin")?, 4));
+        let esp_lba = get_lba_for_path(&b.root, "boot/efiboot.img")?;
+        assert_eq!(summary[1], (0xEF, esp_lba, 0));
+
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let catalog = &data[LBA_BOOT_CATALOG as usize * 2048..][..2048];
+        let from_catalog: Vec<_> = catalog[32..]
+            .chunks(32)
+            .take_while(|e| e[0] != 0)
+            .filter(|e| e[0] == 0x88)
+            .map(|e| {
+                (
+                    e[4],
+                    u32::from_le_bytes(e[8..12].try_into().unwrap()),
+                    u16::from_le_bytes([e[6], e[7]]),
+                )
+            })
+            .collect();
+        assert_eq!(summary, from_catalog);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bios_platform_id() -> io::Result<()> {
+        use crate::iso::boot_catalog::BOOT_CATALOG_POWERPC_PLATFORM_ID;
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("yaboot");
+        std::fs::write(&boot, vec![0x42; 4096])?;
+        let mut b = IsoBuilder::new();
+        b.add_file("ppc/yaboot", &boot)?;
+        let mut info = BiosBootInfo {
+            boot_image: boot,
+            destination_in_iso: "ppc/yaboot".into(),
+            platform_id: BOOT_CATALOG_POWERPC_PLATFORM_ID,
+            ..Default::default()
+        };
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(info.clone()),
+            uefi_boot: None,
+        });
+        build_named(&mut b, dir.path())?;
+
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let catalog = &data[LBA_BOOT_CATALOG as usize * 2048..][..64];
+        assert_eq!(catalog[1], BOOT_CATALOG_POWERPC_PLATFORM_ID);
+        assert_eq!(catalog[32], 0x88);
+        let lba = get_lba_for_path(&b.root, "ppc/yaboot")?;
+        assert_eq!(u32::from_le_bytes(catalog[40..44].try_into().unwrap()), lba);
+        // No isolinux boot info table in a non-x86 image.
+        assert_eq!(&data[lba as usize * 2048..][..4096], &[0x42; 4096][..]);
+
+        info.platform_id = 0x03;
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(info),
+            uefi_boot: None,
+        });
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_auxiliary_boot_entries() -> io::Result<()> {
+        use crate::iso::boot_catalog::BOOT_CATALOG_POWERPC_PLATFORM_ID;
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("boot.bin");
+        std::fs::write(&boot, vec![0x42; 2048])?;
+        let info = dir.path().join("info.txt");
+        std::fs::write(&info, vec![b'i'; 1024])?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot.bin", &boot)?;
+        b.add_file("info.txt", &info)?;
+        b.add_auxiliary_boot_entry("info.txt", BOOT_CATALOG_POWERPC_PLATFORM_ID);
+        assert_eq!(
+            build_named(&mut b, dir.path()).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot.clone(),
+                destination_in_iso: "boot.bin".into(),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        });
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let catalog = &data[LBA_BOOT_CATALOG as usize * 2048..][..128];
+        // Default entry, then a final PowerPC section with one entry.
+        assert_eq!(catalog[32], 0x88);
+        assert_eq!(
+            &catalog[64..68],
+            &[0x91, BOOT_CATALOG_POWERPC_PLATFORM_ID, 1, 0]
+        );
+        let aux = &catalog[96..128];
+        assert_eq!(aux[0], 0x00);
+        assert_eq!(u16::from_le_bytes([aux[6], aux[7]]), 2);
+        let lba = u32::from_le_bytes(aux[8..12].try_into().unwrap()) as usize;
+        assert_eq!(&data[lba * 2048..][..1024], &[b'i'; 1024][..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_isohybrid_without_kernel() -> io::Result<()> {
+        use crate::iso::boot_info::UefiBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let image = IsoImage {
+            volume_id: None,
+            files: Vec::new(),
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: Some(UefiBootInfo {
+                    boot_image: loader,
+                    destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                    ..Default::default()
+                }),
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let iso_path = dir.path().join("nokernel.iso");
+        let fat = build_iso(&iso_path, &image, true)?.esp;
+
+        let fat = fat.expect("isohybrid build must produce an ESP");
+        let fs = fatfs::FileSystem::new(File::open(fat.path())?, fatfs::FsOptions::new())
+            .map_err(io::Error::other)?;
+        let names: Vec<String> = fs
+            .root_dir()
+            .open_dir("EFI/BOOT")?
+            .iter()
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        let names: Vec<&str> = names
+            .iter()
+            .map(String::as_str)
+            .filter(|n| *n != "." && *n != "..")
+            .collect();
+        assert_eq!(names, ["BOOTX64.EFI"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_isohybrid_custom_efi_path() -> io::Result<()> {
+        use crate::iso::boot_info::UefiBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("loader.efi");
+        std::fs::write(&loader, b"arm64 loader")?;
+        let mut image = IsoImage {
+            volume_id: None,
+            files: Vec::new(),
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: Some(UefiBootInfo {
+                    boot_image: loader,
+                    destination_in_iso: "/efi/boot/bootaa64.efi".into(),
+                    skip_pe_validation: true,
+                    ..Default::default()
+                }),
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let iso_path = dir.path().join("aa64.iso");
+        let fat = build_iso(&iso_path, &image, true)?.esp;
+        let fat = fat.expect("isohybrid build must produce an ESP");
+        let fs = fatfs::FileSystem::new(File::open(fat.path())?, fatfs::FsOptions::new())
+            .map_err(io::Error::other)?;
+        let root = fs.root_dir();
+        let mut content = String::new();
+        root.open_file("EFI/BOOT/BOOTAA64.EFI")?
+            .read_to_string(&mut content)?;
+        assert_eq!(content, "arm64 loader");
+        assert!(root.open_file("EFI/BOOT/BOOTX64.EFI").is_err());
+
+        // A distro-specific path is not probed by firmware on removable media.
+        if let Some(uefi) = image.boot_info.uefi_boot.as_mut() {
+            uefi.destination_in_iso = "EFI/FEDORA/grubx64.efi".into();
+        }
+        let err = build_iso(&iso_path, &image, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_isohybrid_rejects_non_efi_loader() -> io::Result<()> {
+        use crate::iso::boot_info::UefiBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, vec![0u8; 4096])?;
+        let mut image = IsoImage {
+            volume_id: None,
+            files: Vec::new(),
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: Some(UefiBootInfo {
+                    boot_image: loader,
+                    destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                    ..Default::default()
+                }),
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let iso_path = dir.path().join("notpe.iso");
+        let err = build_iso(&iso_path, &image, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("BOOTX64.EFI"), "{err}");
+
+        if let Some(uefi) = image.boot_info.uefi_boot.as_mut() {
+            uefi.skip_pe_validation = true;
+        }
+        build_iso(&iso_path, &image, true)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_boot_images_beyond_u16_sectors() -> io::Result<()> {
+        use crate::iso::boot_info::{BiosBootInfo, UefiBootInfo};
+        use crate::iso::reader::IsoReader;
+
+        let dir = tempfile::tempdir()?;
+        let big = dir.path().join("initrd.img");
+        let big_len = (u16::MAX as u64 + 1) * 512;
+        File::create(&big)?.set_len(big_len)?;
+
+        // An ESP past 65535 512-byte sectors is fine: its El Torito entry
+        // carries no sector count and the GPT partition gives the size.
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let mut image = IsoImage {
+            volume_id: None,
+            files: Vec::new(),
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: Some(UefiBootInfo {
+                    boot_image: loader,
+                    destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                    esp_files: vec![(big.clone(), "boot/initrd.img".into())],
+                    ..Default::default()
+                }),
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let iso_path = dir.path().join("big_esp.iso");
+        let out = build_iso(&iso_path, &image, true)?;
+        let esp_sectors = out.esp_sectors.unwrap();
+        assert!(esp_sectors > u16::MAX as u32, "{esp_sectors}");
+        let mut iso = File::open(&iso_path)?;
+        let entry = LBA_BOOT_CATALOG as u64 * ISO_SECTOR_SIZE + 32;
+        let mut e = [0u8; 32];
+        iso.seek(SeekFrom::Start(entry))?;
+        iso.read_exact(&mut e)?;
+        assert_eq!(e[0], 0x88);
+        assert_eq!(e[6..8], [0, 0]);
+        let esp = IsoReader::new(iso).extract_esp()?;
+        assert!(esp.len() as u64 >= esp_sectors as u64 * 512);
+
+        // A no-emulation image whose count does not fit fails before
+        // anything is written.
+        image.boot_info = BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: big.clone(),
+                destination_in_iso: "boot/initrd.img".into(),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        };
+        image.files = vec![crate::iso::iso_image::IsoImageFile {
+            source: big,
+            destination: "boot/initrd.img".into(),
+        }];
+        let iso_path = dir.path().join("big_bios.iso");
+        let err = build_iso(&iso_path, &image, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("too large"), "{err}");
+        assert_eq!(std::fs::metadata(&iso_path)?.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_esp_files() -> io::Result<()> {
+        use crate::iso::boot_info::UefiBootInfo;
+        use crate::iso::reader::IsoReader;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let payloads = [
+            ("vmlinuz", "boot/vmlinuz"),
+            ("vmlinuz-recovery", "boot/recovery/vmlinuz"),
+            ("initrd.img", "boot/initrd.img"),
+            ("initrd-recovery.img", "boot/recovery/initrd.img"),
+        ];
+        let mut esp_files = Vec::new();
+        for (i, (name, dest)) in payloads.iter().enumerate() {
+            let src = dir.path().join(name);
+            std::fs::write(&src, vec![i as u8 + 1; 3000 * (i + 1)])?;
+            esp_files.push((src, dest.to_string()));
+        }
+        let image = IsoImage {
+            volume_id: None,
+            files: Vec::new(),
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: Some(UefiBootInfo {
+                    boot_image: loader,
+                    destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                    esp_files: esp_files.clone(),
+                    ..Default::default()
+                }),
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let iso_path = dir.path().join("payloads.iso");
+        build_iso(&iso_path, &image, true)?;
+
+        let esp = IsoReader::new(File::open(&iso_path)?).extract_esp()?;
+        let fs = fatfs::FileSystem::new(io::Cursor::new(esp), fatfs::FsOptions::new())
+            .map_err(io::Error::other)?;
+        for (src, dest) in &esp_files {
+            let mut content = Vec::new();
+            fs.root_dir().open_file(dest)?.read_to_end(&mut content)?;
+            assert_eq!(content, std::fs::read(src)?, "{dest}");
+        }
+        fs.root_dir().open_file("EFI/BOOT/BOOTX64.EFI")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_layout_detects_overlap() -> io::Result<()> {
+        use crate::iso::disk_layout::DiskLayout;
+
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.bin");
+        std::fs::write(&src, vec![1u8; 3 * ISO_SECTOR_SIZE as usize])?;
+        let path = dir.path().join("overlap.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+
+        // A raw ESP placed on top of the file data.
+        let mut b = IsoBuilder::new();
+        b.set_strict_layout(true);
+        b.add_file("A.BIN", &src)?;
+        let err = b.build(&mut f, &path, Some(22), Some(4)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("ESP"), "{err}");
+        assert!(err.to_string().contains("/A.BIN"), "{err}");
+        b.build(&mut f, &path, Some(40), Some(4))?;
+
+        // Data starting inside the descriptor set / boot catalog.
+        let mut b = IsoBuilder::new();
+        b.set_strict_layout(true);
+        b.add_file("A.BIN", &src)?;
+        b.set_disk_layout(DiskLayout::from_partition_params(0, None, 18));
+        let err = b.build(&mut f, &path, Some(40), Some(4)).unwrap_err();
+        assert!(err.to_string().contains("volume descriptors"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_lba_before_descriptors() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.bin");
+        std::fs::write(&src, b"data")?;
+        let build = |data_start_lba, esp: Option<u32>| {
+            let mut b = IsoBuilder::new();
+            b.add_file("A.BIN", &src)?;
+            b.set_disk_layout(DiskLayout::from_partition_params(0, None, data_start_lba));
+            let mut out = io::Cursor::new(Vec::new());
+            b.build_to(&mut out, esp, esp.map(|_| 4))?;
+            Ok::<_, io::Error>(out.into_inner())
+        };
+
+        // Data-only: PVD at 16, terminator at 17.
+        let err = build(17, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("LBA 16-17"), "{err}");
+        let data = build(18, None)?;
+        assert_eq!(&data[17 * 2048 + 1..17 * 2048 + 6], b"CD001");
+        // The root directory's `.` record.
+        assert_eq!(data[18 * 2048 + 32..18 * 2048 + 34], [1, 0]);
+
+        // El Torito: boot record at 17, terminator at 18, catalog at 19.
+        for lba in [10, LBA_BOOT_CATALOG] {
+            let err = build(lba, Some(40)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains("volume descriptors"), "{err}");
+        }
+        build(LBA_BOOT_CATALOG + 1, Some(40))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_file_with_lba() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut payload = vec![0x11u8; 5000];
+        payload[100..104].copy_from_slice(b"LBA!");
+        let src = dir.path().join("payload.bin");
+        std::fs::write(&src, &payload)?;
+        let other = dir.path().join("a.txt");
+        std::fs::write(&other, b"first")?;
+
+        let mut b = IsoBuilder::new();
+        b.add_file("A.TXT", &other)?;
+        b.add_file("BOOT/PAYLOAD.BIN", &src)?;
+        let err = b.patch_file_with_lba("BOOT/PAYLOAD.BIN", 4997).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(b.patch_file_with_lba("MISSING.BIN", 0).is_err());
+        b.patch_file_with_lba("BOOT/PAYLOAD.BIN", 100)?;
+
+        let path = dir.path().join("patched.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+
+        let lba = get_lba_for_path(&b.root, "BOOT/PAYLOAD.BIN")?;
+        let data = std::fs::read(&path)?;
+        let start = lba as usize * ISO_SECTOR_SIZE as usize;
+        let stored = &data[start..start + payload.len()];
+        assert_eq!(&stored[100..104], &lba.to_le_bytes());
+        assert_eq!(&stored[..100], &payload[..100]);
+        assert_eq!(&stored[104..], &payload[104..]);
+        assert_eq!(std::fs::read(&src)?, payload, "source must be untouched");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sparse_copy_matches_dense() -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        let dir = tempfile::tempdir()?;
+        // Data, a 1 MiB hole, data, then a trailing hole.
+        let sparse = dir.path().join("rootfs.img");
+        let f = File::create(&sparse)?;
+        f.set_len(3 << 20)?;
+        f.write_all_at(&[0xA5; 5000], 0)?;
+        f.write_all_at(&[0x5A; 3000], (1 << 20) + 4096)?;
+        drop(f);
+        let small = dir.path().join("z.txt");
+        std::fs::write(&small, b"after the sparse file")?;
+
+        let build = |sparse_copy: bool, prefill: Option<u8>| -> io::Result<(IsoBuilder, Vec<u8>)> {
+            let mut b = IsoBuilder::new();
+            b.add_file("ROOTFS.IMG", &sparse)?;
+            b.add_file("Z.TXT", &small)?;
+            b.set_sparse_copy(sparse_copy);
+            let path = dir.path().join(format!("{sparse_copy}{prefill:?}.iso"));
+            if let Some(byte) = prefill {
+                std::fs::write(&path, vec![byte; 4 << 20])?;
+            }
+            let mut f = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(prefill.is_none())
+                .open(&path)?;
+            b.build(&mut f, &path, None, None)?;
+            let len = b.total_sectors() as usize * ISO_SECTOR_SIZE as usize;
+            let mut data = std::fs::read(&path)?;
+            data.truncate(len);
+            Ok((b, data))
+        };
+        let (_, dense) = build(false, None)?;
+        assert_eq!(build(true, None)?.1, dense);
+        // Holes over stale bytes must still read back as zeros.
+        let (b, stale) = build(true, Some(0xFF))?;
+        for (name, src) in [("ROOTFS.IMG", &sparse), ("Z.TXT", &small)] {
+            let start = get_lba_for_path(&b.root, name)? as usize * ISO_SECTOR_SIZE as usize;
+            let expected = std::fs::read(src)?;
+            assert!(
+                stale[start..start + expected.len()] == expected[..],
+                "{name} differs"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_area() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.txt");
+        std::fs::write(&src, b"data")?;
+        let area: Vec<u8> = (0..SYSTEM_AREA_SIZE).map(|i| (i % 251) as u8).collect();
+
+        let mut b = IsoBuilder::new();
+        b.add_file("a.txt", &src)?;
+        assert!(
+            b.set_system_area(&vec![0u8; SYSTEM_AREA_SIZE as usize + 1])
+                .is_err()
+        );
+        b.set_system_area(&area)?;
+        assert_eq!(b.system_area(), Some(area.as_slice()));
+        let path = dir.path().join("sa.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+
+        let data = std::fs::read(&path)?;
+        assert_eq!(&data[..area.len()], area.as_slice());
+        assert_eq!(&data[16 * 2048 + 1..16 * 2048 + 6], b"CD001");
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_hybrid_uefi_entry_resolves_path() -> io::Result<()> {
+        use crate::iso::boot_info::UefiBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        let other = dir.path().join("readme.txt");
+        std::fs::write(&loader, vec![0xAB; 3000])?;
+        std::fs::write(&other, b"hello")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("README.TXT", &other)?;
+        b.add_file("EFI/BOOT/BOOTX64.EFI", &loader)?;
+        b.set_boot_info(BootInfo {
+            bios_boot: None,
+            uefi_boot: Some(UefiBootInfo {
+                boot_image: loader.clone(),
+                destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                ..Default::default()
+            }),
+        });
+        let path = dir.path().join("uefi.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+
+        let data = std::fs::read(&path)?;
+        let entry = LBA_BOOT_CATALOG as usize * ISO_SECTOR_SIZE as usize + 32;
+        assert_eq!(data[entry], 0x88, "initial entry must be bootable");
+        assert_eq!(
+            u32::from_le_bytes(data[entry + 8..entry + 12].try_into().unwrap()),
+            get_lba_for_path(&b.root, "EFI/BOOT/BOOTX64.EFI")?
+        );
+        assert_eq!(
+            u16::from_le_bytes([data[entry + 6], data[entry + 7]]),
+            3000u16.div_ceil(512)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_only_iso() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("data.txt");
+        std::fs::write(&src, b"plain data")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("DOCS/DATA.TXT", &src)?;
+        let path = dir.path().join("data.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+
+        let data = std::fs::read(&path)?;
+        let sector = |lba: usize| &data[lba * 2048..(lba + 1) * 2048];
+        assert_eq!(sector(16)[0], 1, "PVD expected at LBA 16");
+        assert_eq!(sector(17)[0], 255, "LBA 17 must be the terminator");
+        assert_eq!(&sector(17)[1..6], b"CD001");
+        assert!(!data.windows(23).any(|w| w == b"EL TORITO SPECIFICATION"));
+        assert_eq!(b.root.lba, 18);
+
+        let lba = get_lba_for_path(&b.root, "DOCS/DATA.TXT")? as usize;
+        assert_eq!(&sector(lba)[..10], b"plain data");
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_file_with_padding() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let kernel = dir.path().join("vmlinuz");
+        let next = dir.path().join("next.txt");
+        std::fs::write(&kernel, vec![0xEEu8; 3000])?;
+        std::fs::write(&next, b"next")?;
+        let mut b = IsoBuilder::new();
+        b.add_file_with_padding("A/VMLINUZ", &kernel, 5)?;
+        b.add_file("B/NEXT.TXT", &next)?;
+        b.add_file_with_padding("Z.BIN", &next, 2)?;
+        let path = dir.path().join("pad.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+
+        let kernel_lba = get_lba_for_path(&b.root, "A/VMLINUZ")?;
+        let next_lba = get_lba_for_path(&b.root, "B/NEXT.TXT")?;
+        // 2 sectors of data + 5 of padding, then the B directory sector.
+        assert_eq!(next_lba, kernel_lba + 2 + 5 + 1);
+        assert_eq!(get_file_size_in_iso(&b.root, "A/VMLINUZ")?, 3000);
+
+        let data = std::fs::read(&path)?;
+        let start = kernel_lba as usize * 2048;
+        assert!(data[start + 3000..start + 7 * 2048].iter().all(|&x| x == 0));
+        // Padding after the last file is part of the image.
+        let z_lba = get_lba_for_path(&b.root, "Z.BIN")? as usize;
+        assert!(data.len() >= (z_lba + 3) * 2048);
+
+        // Padding that would push LBAs past 2^32 fails instead of wrapping.
+        let mut b = IsoBuilder::new();
+        b.add_file_with_padding("HUGE.BIN", &next, u32::MAX - 5)?;
+        let err = b.build(&mut f, &path, None, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("2^32 sectors"), "{err}");
+        Ok(())
+    }
+
+    fn build_named(b: &mut IsoBuilder, dir: &Path) -> io::Result<()> {
+        let path = dir.join("names.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        b.build(&mut f, &path, None, None)
+    }
+
+    #[test]
+    fn test_lenient_names_are_transliterated() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, b"x")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot-files/my-file!.txt", &src)?;
+        b.add_file("plain/readme.txt", &src)?;
+        assert_eq!(
+            b.transliterated_names()?,
+            [
+                ("boot-files".to_string(), "BOOT_FILES".to_string()),
+                (
+                    "boot-files/my-file!.txt".to_string(),
+                    "boot-files/MY_FILE_.TXT".to_string()
+                ),
+            ]
+        );
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        assert!(data.windows(12).any(|w| w == b"MY_FILE_.TXT"));
+
+        b.set_name_replacement('X')?;
+        assert_eq!(b.transliterated_names()?[0].1, "BOOTXFILES");
+        assert!(b.set_name_replacement('-').is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_iso_level_name_lengths() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, b"x")?;
+        let build = |level, path: &str| {
+            let mut b = IsoBuilder::new();
+            b.set_iso_level(level)?;
+            b.add_file(path, &src)?;
+            build_named(&mut b, dir.path())
+        };
+
+        // 20 characters.
+        let twenty = "twenty_chars_long.tx";
+        let err = build(1, twenty).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("TWENTY_CHARS_LONG.TX"), "{err}");
+        build(2, twenty)?;
+        build(3, twenty)?;
+        build(1, "EIGHTCHR/FILENAME.EXT")?;
+        assert!(build(1, "NINECHARS/A.TXT").is_err());
+        assert!(build(1, "A.TEXT").is_err());
+
+        let thirty_one = format!("{}.TXT", "A".repeat(27));
+        build(2, &thirty_one)?;
+        let err = build(2, &format!("B{thirty_one}")).unwrap_err();
+        assert!(err.to_string().contains("31 characters"), "{err}");
+
+        // Unlimited without a level, as before.
+        let mut b = IsoBuilder::new();
+        b.add_file(&format!("{}.TXT", "C".repeat(40)), &src)?;
+        build_named(&mut b, dir.path())?;
+
+        let mut b = IsoBuilder::new();
+        assert!(b.set_iso_level(0).is_err());
+        assert!(b.set_iso_level(4).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_name_is_rejected_by_add_file() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.txt");
+        std::fs::write(&src, b"x")?;
+        let long = "n".repeat(250);
+        let mut b = IsoBuilder::new();
+        for path in [format!("docs/{long}.txt"), format!("{long}/a.txt")] {
+            let err = b.add_file(&path, &src).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains(&path), "{err}");
+        }
+        let err = b.add_symlink(&long, "a.txt").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // Nothing was added, and the longest name that fits builds.
+        assert!(b.root.children.is_empty());
+        b.add_file(&"n".repeat(219), &src)?;
+        build_named(&mut b, dir.path())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_names() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, b"x")?;
+        let mut b = IsoBuilder::new();
+        b.set_strict_names(true);
+        b.add_file("GOOD/FILE_1.TXT", &src)?;
+        build_named(&mut b, dir.path())?;
+
+        let mut b = IsoBuilder::new();
+        b.set_strict_names(true);
+        b.add_file("bad name.txt", &src)?;
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Names that collide after transliteration are rejected in both modes.
+        let mut b = IsoBuilder::new();
+        b.add_file("a-b.txt", &src)?;
+        b.add_file("a_b.txt", &src)?;
+        assert!(b.transliterated_names().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_hybrid_sectors() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        let big = dir.path().join("big.bin");
+        std::fs::write(&loader, efi_stub(4096))?;
+        std::fs::write(&big, vec![7u8; 300_000])?;
+        let (esp, _esp_holder) =
+            fat::build_esp(&[(loader.clone(), "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+
+        let mut b = IsoBuilder::new();
+        b.set_hybrid_mode(HybridMode::GptUefi);
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        b.add_file("DATA/BIG.BIN", &big)?;
+        b.add_file_with_padding("DATA/PAD.BIN", &loader, 10)?;
+        let min = b.min_hybrid_sectors();
+
+        let path = dir.path().join("hybrid.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+        let actual_512 = b.total_sectors * 4;
+        assert!(min <= actual_512, "min {min} > actual {actual_512}");
+        // The estimate covers everything but the final alignment padding.
+        assert!(actual_512 - min < 8, "min {min} too far below {actual_512}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hybrid_esp_alignment() -> io::Result<()> {
+        use crate::iso::gpt::main_gpt_functions::parse_gpt;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) =
+            fat::build_esp(&[(loader.clone(), "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let esp_bytes = std::fs::read(&esp)?;
+
+        // First with only the ESP, then with a file laid out before it.
+        let mut b = IsoBuilder::new();
+        b.set_hybrid_mode(HybridMode::GptUefi);
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        for with_file_before in [false, true] {
+            if with_file_before {
+                b.add_file("a.bin", &loader)?;
+            }
+            let estimate = b.estimated_size_bytes();
+            build_named(&mut b, dir.path())?;
+            let data = std::fs::read(dir.path().join("names.iso"))?;
+            assert_eq!(data.len() as u64, estimate);
+
+            let (_, parts) = parse_gpt(&data, 1, 512)?;
+            let esp_start = { parts[1].starting_lba };
+            assert_eq!(esp_start % 2048, 0, "{with_file_before}");
+            let esp_lba = get_lba_for_path(&b.root, "boot/efiboot.img")?;
+            assert_eq!(esp_start, esp_lba as u64 * 4);
+            let at = esp_start as usize * 512;
+            assert_eq!(&data[at..at + esp_bytes.len()], &esp_bytes[..]);
+
+            // The gap is zero, and the padding given to `a.bin` for it is
+            // taken back after the build.
+            let (gap_start, gap_end) = if with_file_before {
+                let IsoFsNode::File(a) = get_node_for_path(&b.root, "a.bin")? else {
+                    panic!("a.bin is not a file");
+                };
+                assert_eq!(a.padding_sectors, 0);
+                let a_end = a.lba + a.size.div_ceil(ISO_SECTOR_SIZE) as u32;
+                // The `boot` directory sits right before the ESP.
+                (a_end, esp_lba - 1)
+            } else {
+                let data_lba = b.default_data_lba(b.has_boot_entries(b.esp_lba));
+                (data_lba, b.root.lba)
+            };
+            assert!(gap_start < gap_end, "{with_file_before}");
+            let gap = &data[gap_start as usize * 2048..gap_end as usize * 2048];
+            assert!(gap.iter().all(|&x| x == 0));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_device_size() -> io::Result<()> {
+        use crate::iso::gpt::main_gpt_functions::parse_gpt;
+        use crate::iso::gpt::partition_entry::{GPT_ATTR_HIDDEN, GPT_ATTR_NO_AUTOMOUNT};
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) =
+            fat::build_esp(&[(loader.clone(), "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.set_hybrid_mode(HybridMode::GptUefi);
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        build_named(&mut b, dir.path())?;
+        let image_512 = b.total_sectors as u64 * 4;
+
+        for bad in [image_512 - 4, image_512 + 1] {
+            b.set_target_device_size_sectors(bad);
+            let err = build_named(&mut b, dir.path()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{bad}");
+        }
+
+        let device = image_512 + 8192;
+        b.set_target_device_size_sectors(device);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        assert_eq!(data.len() as u64, device * 512);
+        let (primary, parts) = parse_gpt(&data, 1, 512)?;
+        assert_eq!({ primary.backup_lba }, device - 1);
+        assert_eq!({ primary.last_usable_lba }, device - 34);
+        let (backup, _) = parse_gpt(&data, device - 1, 512)?;
+        assert_eq!({ backup.current_lba }, device - 1);
+        // The ISO 9660 partition still ends with the image.
+        assert_eq!({ parts[0].ending_lba }, image_512 - 34);
+        assert_eq!({ parts[1].attributes }, GPT_ATTR_REQUIRED);
+
+        b.set_esp_gpt_attributes(GPT_ATTR_HIDDEN | GPT_ATTR_NO_AUTOMOUNT);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let (_, parts) = parse_gpt(&data, 1, 512)?;
+        assert_eq!(
+            { parts[1].attributes },
+            GPT_ATTR_HIDDEN | GPT_ATTR_NO_AUTOMOUNT
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_remaining_bytes() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut b = IsoBuilder::new();
+        let empty = b.estimated_size_bytes();
+        for i in 0..3 {
+            let src = dir.path().join(format!("f{i}.bin"));
+            std::fs::write(&src, vec![i as u8; 10_000])?;
+            b.add_file(&format!("data/f{i}.bin"), &src)?;
+        }
+        // Three files of 5 sectors each plus the `data` directory.
+        assert_eq!(b.estimated_size_bytes(), empty + 16 * 2048);
+        let estimate = b.estimated_size_bytes();
+        assert_eq!(b.remaining_bytes(estimate + 4096)?, 4096);
+        assert_eq!(b.remaining_bytes(estimate - 2048)?, -2048);
+        assert_eq!(
+            b.remaining_bytes(700 * 1000 * 1000)?,
+            700_000_000 - estimate as i64
+        );
+        let err = b.remaining_bytes(u64::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        build_named(&mut b, dir.path())?;
+        assert_eq!(
+            std::fs::metadata(dir.path().join("names.iso"))?.len(),
+            estimate
+        );
+
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) = fat::build_esp(&[(loader, "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.set_hybrid_mode(HybridMode::GptUefi);
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        let estimate = b.estimated_size_bytes();
+        build_named(&mut b, dir.path())?;
+        assert_eq!(
+            std::fs::metadata(dir.path().join("names.iso"))?.len(),
+            estimate
+        );
+        b.add_persistence_partition(8192, "persistence")?;
+        let estimate = b.estimated_size_bytes();
+        build_named(&mut b, dir.path())?;
+        assert_eq!(
+            std::fs::metadata(dir.path().join("names.iso"))?.len(),
+            estimate
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbr_disk_signature() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) = fat::build_esp(&[(loader, "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        for mode in [HybridMode::BiosMbr, HybridMode::GptUefi] {
+            let mut b = IsoBuilder::new();
+            b.set_hybrid_mode(mode);
+            b.set_mbr_boot_code(&[0xFA; 64])?;
+            b.add_file("boot/efiboot.img", &esp)?;
+            if mode.uses_gpt_esp() {
+                b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+            }
+            build_named(&mut b, dir.path())?;
+            let data = std::fs::read(dir.path().join("names.iso"))?;
+            assert_eq!(&data[440..444], &[0; 4], "{mode:?}");
+
+            b.set_mbr_disk_signature(0x1234_ABCD);
+            build_named(&mut b, dir.path())?;
+            let data = std::fs::read(dir.path().join("names.iso"))?;
+            assert_eq!(&data[440..444], &0x1234_ABCDu32.to_le_bytes(), "{mode:?}");
+            assert_eq!(&data[510..512], &[0x55, 0xAA]);
+            assert_eq!(data[0], 0xFA);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_persistence_partition() -> io::Result<()> {
+        use crate::iso::gpt::main_gpt_functions::parse_gpt;
+        use crate::iso::gpt::partition_entry::uuid_to_gpt_mixed_endian;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) = fat::build_esp(&[(loader, "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.set_hybrid_mode(HybridMode::GptUefi);
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        build_named(&mut b, dir.path())?;
+        let image_512 = b.total_sectors as u64 * 4;
+
+        for (size, label) in [(0, "persistence"), (8, &"x".repeat(37)[..])] {
+            let err = b.add_persistence_partition(size, label).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        b.add_persistence_partition(8192, "persistence")?;
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let (primary, parts) = parse_gpt(&data, 1, 512)?;
+        assert_eq!(parts.len(), 3);
+        let p = &parts[2];
+        let linux = uuid::Uuid::parse_str(LINUX_FILESYSTEM_GUID).unwrap();
+        assert_eq!(p.partition_type_guid, uuid_to_gpt_mixed_endian(&linux));
+        assert_eq!({ p.ending_lba } - { p.starting_lba } + 1, 8192);
+        assert_eq!({ p.starting_lba }, image_512.next_multiple_of(2048));
+        let name: Vec<u16> = "persistence".encode_utf16().collect();
+        assert_eq!({ p.partition_name }[..name.len()], name[..]);
+
+        // The image grows to hold the partition and the backup GPT.
+        assert_eq!(data.len() as u64, b.total_sectors as u64 * 2048);
+        let total_512 = data.len() as u64 / 512;
+        assert_eq!({ primary.backup_lba }, total_512 - 1);
+        assert!({ p.ending_lba } <= { primary.last_usable_lba });
+        let region = { p.starting_lba } as usize * 512..({ p.ending_lba } as usize + 1) * 512;
+        assert!(data[region].iter().all(|&x| x == 0));
+        assert_ne!({ parts[1].unique_partition_guid }, {
+            p.unique_partition_guid
+        });
+
+        b.set_target_device_size_sectors(total_512 - 4);
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_seed() -> io::Result<()> {
+        use crate::iso::gpt::main_gpt_functions::parse_gpt;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) = fat::build_esp(&[(loader, "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.set_hybrid_mode(HybridMode::GptUefi);
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        b.set_timestamp_source(TimestampSource::BuildTime);
+        let build = |b: &mut IsoBuilder, seed: Option<u64>| {
+            match seed {
+                Some(seed) => b.set_build_seed(seed),
+                None => b.set_reproducible(false),
+            }
+            build_named(b, dir.path())?;
+            std::fs::read(dir.path().join("names.iso"))
+        };
+
+        let first = build(&mut b, Some(7))?;
+        assert_eq!(first, build(&mut b, Some(7))?);
+        let other = build(&mut b, Some(8))?;
+        assert_eq!(first.len(), other.len());
+        assert_ne!(first, other);
+        // Only the GUIDs (and the CRCs covering them) depend on the seed.
+        let (h7, p7) = parse_gpt(&first, 1, 512)?;
+        let (h8, p8) = parse_gpt(&other, 1, 512)?;
+        assert_ne!(h7.disk_guid, h8.disk_guid);
+        assert_ne!({ p7[1].unique_partition_guid }, {
+            p8[1].unique_partition_guid
+        });
+        let gpt_end = first.len() - 33 * 512;
+        assert_eq!(first[34 * 512..gpt_end], other[34 * 512..gpt_end]);
+        assert_ne!(build(&mut b, None)?, build(&mut b, None)?);
+        // Dropping the seed brings back the chosen timestamp source.
+        assert_eq!(b.dir_opts.timestamps, TimestampSource::BuildTime);
+        assert_ne!(b.dir_opts.recorded, [0; 7]);
+
+        b.set_reproducible(true);
+        build_named(&mut b, dir.path())?;
+        let zero = std::fs::read(dir.path().join("names.iso"))?;
+        assert_eq!(b.dir_opts.recorded, [0; 7]);
+        assert_eq!(zero, build(&mut b, Some(0))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_esp_integrity_check() -> io::Result<()> {
+        use crate::iso::gpt::main_gpt_functions::parse_gpt;
+        use crate::iso::reader::IsoReader;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) = fat::build_esp(&[(loader, "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.set_hybrid_mode(HybridMode::GptUefi);
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        build_named(&mut b, dir.path())?;
+        let path = dir.path().join("names.iso");
+        let (_, parts) = parse_gpt(&std::fs::read(&path)?, 1, 512)?;
+        assert_eq!({ parts[1].attributes }, GPT_ATTR_REQUIRED);
+
+        b.set_esp_integrity_check(true);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(&path)?;
+        let (_, primary) = parse_gpt(&data, 1, 512)?;
+        let (_, backup) = parse_gpt(&data, data.len() as u64 / 512 - 1, 512)?;
+        let attributes = primary[1].attributes;
+        assert_eq!({ backup[1].attributes }, attributes);
+        assert_eq!(attributes & !GPT_ATTR_ESP_CRC32_MASK, GPT_ATTR_REQUIRED);
+        let stored = ((attributes & GPT_ATTR_ESP_CRC32_MASK) >> GPT_ATTR_ESP_CRC32_SHIFT) as u32;
+        let esp_bytes = IsoReader::new(File::open(&path)?).extract_esp()?;
+        assert_eq!(stored, crc32fast::hash(&esp_bytes));
+        Ok(())
+    }
+
+    #[test]
+    fn test_boot_entry_units() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        // 5000 bytes: ten 512-byte load sectors, three 2048-byte ISO sectors.
+        let image: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let boot = dir.path().join("boot.bin");
+        std::fs::write(&boot, &image)?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) = fat::build_esp(&[(loader, "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot.bin", &boot)?;
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot,
+                destination_in_iso: "boot.bin".into(),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        });
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let catalog = &data[LBA_BOOT_CATALOG as usize * 2048..][..2048];
+
+        // The default entry: load count at offset 6, LBA at offset 8.
+        let default = &catalog[32..64];
+        assert_eq!(u16::from_le_bytes([default[6], default[7]]), 10);
+        let lba = u32::from_le_bytes(default[8..12].try_into().unwrap());
+        assert_eq!(lba, get_lba_for_path(&b.root, "boot.bin")?);
+        // Bytes 8-63 hold the patched boot information table.
+        let written = &data[lba as usize * 2048..][..image.len()];
+        assert!(written[..8] == image[..8] && written[64..] == image[64..]);
+
+        // The ESP entry after its section header has no load count.
+        let esp_entry = &catalog[96..128];
+        assert_eq!(esp_entry[0], 0x88);
+        assert_eq!(&esp_entry[6..8], &[0, 0]);
+        let esp_lba = u32::from_le_bytes(esp_entry[8..12].try_into().unwrap());
+        let esp_bytes = std::fs::read(&esp)?;
+        assert_eq!(&data[esp_lba as usize * 2048..][..512], &esp_bytes[..512]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_files() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        let names = [
+            "a.txt",
+            "b.txt",
+            "lazy.txt",
+            "boot.bin",
+            "BOOTX64.EFI",
+            "kernel",
+        ];
+        for name in names {
+            std::fs::write(path(name), b"data")?;
         }
+        let mut b = IsoBuilder::new();
+        assert!(b.source_files().is_empty());
+        b.add_file("docs/a.txt", &path("a.txt"))?;
+        b.add_file("docs/b.txt", &path("b.txt"))?;
+        b.add_file("copy/a.txt", &path("a.txt"))?;
+        b.add_file_lazy("lazy.txt", &path("lazy.txt"))?;
+        b.add_reader("gen.bin", Box::new(io::Cursor::new(vec![0u8; 4])), 4)?;
+        b.add_file("boot.bin", &path("boot.bin"))?;
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: path("boot.bin"),
+                destination_in_iso: "boot.bin".into(),
+                ..Default::default()
+            }),
+            uefi_boot: Some(UefiBootInfo {
+                boot_image: path("BOOTX64.EFI"),
+                kernel_image: Some(path("kernel")),
+                destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                skip_pe_validation: true,
+                ..Default::default()
+            }),
+        });
+
+        let mut expected: Vec<_> = names.into_iter().map(path).collect();
+        expected.sort();
+        assert_eq!(b.source_files(), expected);
+        Ok(())
     }
 
-    for f in &image.files {
-        b.add_file(&f.destination, &f.source)?;
+    #[test]
+    fn test_esp_cache_dir() -> io::Result<()> {
+        use crate::iso::reader::IsoReader;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let mut uefi = UefiBootInfo {
+            boot_image: loader.clone(),
+            destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+            grub_cfg_content: Some("set timeout=0\n".into()),
+            ..Default::default()
+        };
+        let cache = dir.path().join("cache");
+        let build = |uefi: &UefiBootInfo| -> io::Result<(bool, Vec<u8>)> {
+            let mut b = IsoBuilder::new();
+            b.set_hybrid_mode(HybridMode::GptUefi);
+            b.set_build_seed(7);
+            b.set_esp_cache_dir(cache.clone());
+            let esp = b.add_uefi_esp(uefi)?;
+            assert!(esp.temp_file.is_none() && esp.path.starts_with(&cache));
+            build_named(&mut b, dir.path())?;
+            let path = dir.path().join("names.iso");
+            let esp_bytes = IsoReader::new(File::open(&path)?).extract_esp()?;
+            let image = std::fs::read(&esp.path)?;
+            assert_eq!(image.len() as u64, esp.sectors as u64 * 512);
+            assert_eq!(&esp_bytes[..image.len()], &image[..]);
+            Ok((esp.cache_hit, std::fs::read(&path)?))
+        };
+
+        let (hit, first) = build(&uefi)?;
+        assert!(!hit);
+        let (hit, second) = build(&uefi)?;
+        assert!(hit);
+        assert_eq!(first, second);
+
+        // Any changed input misses.
+        uefi.grub_cfg_content = Some("set timeout=5\n".into());
+        assert!(!build(&uefi)?.0);
+        std::fs::write(&loader, efi_stub(8192))?;
+        assert!(!build(&uefi)?.0);
+        assert!(build(&uefi)?.0);
+        Ok(())
     }
-    if let Some(bios) = &image.boot_info.bios_boot {
-        b.add_file(&bios.destination_in_iso, &bios.boot_image)?;
+
+    #[test]
+    fn test_data_disc_system_area_is_zeroed() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.txt");
+        std::fs::write(&src, b"data")?;
+        // Stale content from a previous use of the output file.
+        let path = dir.path().join("names.iso");
+        std::fs::write(&path, vec![0xAA; 64 * 1024])?;
+        let mut b = IsoBuilder::new();
+        b.add_file("A.TXT", &src)?;
+        let mut f = OpenOptions::new().read(true).write(true).open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+
+        let data = std::fs::read(&path)?;
+        let area = SYSTEM_AREA_SIZE as usize;
+        assert!(data.len() > area);
+        assert!(data[..area].iter().all(|&x| x == 0));
+        assert_eq!(&data[area + 1..area + 6], b"CD001");
+        Ok(())
     }
-    b.set_boot_info(image.boot_info.clone());
-    b.build(&mut iso_file, iso_path, b.esp_lba, b.esp_size_sectors)?;
-    Ok((iso_path.to_path_buf(), fat_holder, iso_file, fat_size_512))
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
+    #[test]
+    fn test_apm_partition() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.txt");
+        std::fs::write(&src, b"data")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("A.TXT", &src)?;
+        assert!(b.add_apm_partition(&"N".repeat(32)).is_err());
+        b.add_apm_partition("ISOBEMAK")?;
+        build_named(&mut b, dir.path())?;
+
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        assert_eq!(&data[0..2], b"ER");
+        assert_eq!(u16::from_be_bytes([data[2], data[3]]), 2048);
+        assert_eq!(
+            u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize,
+            data.len() / 2048
+        );
+        let map = &data[2048..2560];
+        assert_eq!(&map[0..2], b"PM");
+        assert_eq!(u32::from_be_bytes(map[4..8].try_into().unwrap()), 2);
+        let hfs = &data[4096..4608];
+        assert_eq!(&hfs[0..2], b"PM");
+        assert_eq!(&hfs[48..57], b"Apple_HFS");
+        assert_eq!(
+            u32::from_be_bytes(hfs[12..16].try_into().unwrap()) as usize,
+            data.len() / 2048
+        );
+
+        // The map's block 0 fields would clobber MBR boot code.
+        let mut b = IsoBuilder::new();
+        b.add_file("A.TXT", &src)?;
+        b.set_hybrid_mode(HybridMode::BiosMbr);
+        b.set_mbr_boot_code(&[0xEB; 440])?;
+        b.add_apm_partition("ISOBEMAK")?;
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let mut b = IsoBuilder::new();
+        b.add_file("A.TXT", &src)?;
+        b.set_system_area(&[0x90; 512])?;
+        b.add_apm_partition("ISOBEMAK")?;
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
 
     #[test]
-    fn test_add_file() -> io::Result<()> {
-        let mut builder = IsoBuilder::new();
-        let tp = NamedTempFile::new()?.into_temp_path();
-        builder.add_file("root.txt", &tp)?;
-        assert!(builder.root.children.contains_key("root.txt"));
-        builder.add_file("dir1/nested.txt", &tp)?;
-        match builder.root.children.get("dir1") {
-            Some(IsoFsNode::Directory(d)) => assert!(d.children.contains_key("nested.txt")),
-            _ => panic!(),
+    fn test_apm_with_gpt() -> io::Result<()> {
+        use crate::iso::gpt::main_gpt_functions::parse_gpt;
+
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let (esp, _esp_holder) =
+            fat::build_esp(&[(loader.clone(), "EFI/BOOT/BOOTX64.EFI".into())], 0)?;
+        let mut b = IsoBuilder::new();
+        b.set_hybrid_mode(HybridMode::GptUefi);
+        b.add_file("boot/efiboot.img", &esp)?;
+        b.efi_boot_image_iso_path = Some("boot/efiboot.img".into());
+        b.add_apm_partition("ISOBEMAK")?;
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+
+        let (primary, parts) = parse_gpt(&data, 1, 512)?;
+        assert_eq!({ primary.num_partition_entries }, GPT_ENTRIES_WITH_APM);
+        assert_eq!({ primary.partition_entry_lba }, 2);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(data[510..512], [0x55, 0xAA]);
+
+        assert_eq!(&data[0..2], b"ER");
+        assert_eq!(&data[2048..2050], b"PM");
+        let hfs = &data[4096..4608];
+        assert_eq!(&hfs[0..2], b"PM");
+        assert_eq!(&hfs[48..57], b"Apple_HFS");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ear_checksums() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("payload.bin");
+        let payload: Vec<u8> = (0..5000u32).map(|i| (i * 31) as u8).collect();
+        std::fs::write(&src, &payload)?;
+        let mut b = IsoBuilder::new();
+        b.set_compute_ear_checksums(true);
+        b.add_file("PAYLOAD.BIN", &src)?;
+        b.add_file("SUB/COPY.BIN", &src)?;
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+
+        // Locate the file's directory record in the root directory.
+        let root = b.root.lba as usize * 2048;
+        let mut off = root;
+        let rec = loop {
+            let len = data[off] as usize;
+            assert!(len > 0, "PAYLOAD.BIN record not found");
+            if data[off + 33..off + 33 + data[off + 32] as usize].starts_with(b"PAYLOAD.BIN") {
+                break &data[off..off + len];
+            }
+            off += len;
+        };
+        assert_eq!(rec[1], 1, "EAR length must be one block");
+        let extent = read_both_endian_u32(rec, 2)?;
+        assert_eq!(read_both_endian_u32(rec, 10)?, 5000);
+        assert_eq!(extent + 1, get_lba_for_path(&b.root, "PAYLOAD.BIN")?);
+
+        let ear = &data[extent as usize * 2048..(extent as usize + 1) * 2048];
+        assert_eq!(ear[180], 1);
+        assert_eq!(&ear[250..255], b"CRC32");
+        let stored = u32::from_be_bytes(ear[255..259].try_into().unwrap());
+        assert_eq!(stored, crc32fast::hash(&payload));
+        let start = (extent as usize + 1) * 2048;
+        assert_eq!(&data[start..start + 5000], payload.as_slice());
+        Ok(())
+    }
+
+    /// Returns the directory record in the sector at `lba` whose identifier
+    /// starts with `id`.
+    fn find_record<'a>(data: &'a [u8], lba: u32, id: &[u8]) -> &'a [u8] {
+        let mut off = lba as usize * 2048;
+        loop {
+            let len = data[off] as usize;
+            assert!(len > 0, "{} record not found", String::from_utf8_lossy(id));
+            if data[off + 33..off + 33 + data[off + 32] as usize].starts_with(id) {
+                return &data[off..off + len];
+            }
+            off += len;
+        }
+    }
+
+    /// Reassembles a symlink target from the `SL` entries of a record, the
+    /// way a Rock Ridge reader's `readlink` would.
+    fn readlink(rec: &[u8]) -> Option<String> {
+        let mut su = 33 + rec[32] as usize;
+        su += su % 2;
+        let mut parts = Vec::new();
+        let mut found = false;
+        while su + 4 <= rec.len() && rec[su + 2] >= 4 {
+            let len = rec[su + 2] as usize;
+            if &rec[su..su + 2] == b"SL" {
+                found = true;
+                let mut c = su + 5;
+                while c < su + len {
+                    let clen = rec[c + 1] as usize;
+                    parts.push(match rec[c] {
+                        0x02 => ".".to_string(),
+                        0x04 => "..".to_string(),
+                        0x08 => String::new(),
+                        _ => String::from_utf8_lossy(&rec[c + 2..c + 2 + clen]).into_owned(),
+                    });
+                    c += 2 + clen;
+                }
+            }
+            su += len;
+        }
+        found.then(|| match parts.join("/") {
+            s if s.is_empty() => "/".to_string(),
+            s => s,
+        })
+    }
+
+    #[test]
+    fn test_symlink() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("vmlinuz-6.1");
+        std::fs::write(&src, b"kernel")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot/vmlinuz-6.1", &src)?;
+        b.add_symlink("boot/vmlinuz", "vmlinuz-6.1")?;
+        b.add_symlink("lib", "/usr/lib/../lib")?;
+        assert!(b.add_file("lib/x", &src).is_err());
+        assert!(b.add_symlink("empty", "").is_err());
+
+        // Plain ISO 9660: an empty file with no System Use data.
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let boot = match b.root.children.get("boot") {
+            Some(IsoFsNode::Directory(d)) => d.lba,
+            _ => panic!("boot directory missing"),
         };
+        let rec = find_record(&data, boot, b"VMLINUZ;1");
+        assert_eq!(u32::from_le_bytes(rec[10..14].try_into().unwrap()), 0);
+        assert_eq!(readlink(rec), None);
+
+        b.set_rock_ridge(true);
+        b.set_compute_ear_checksums(true);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let root_self = find_record(&data, b.root.lba, &[0]);
+        assert_eq!(&root_self[34..36], b"SP");
+        let rec = find_record(&data, boot, b"VMLINUZ;1");
+        assert_eq!(rec[1], 0, "symlinks have no extended attribute record");
+        assert_eq!(readlink(rec).as_deref(), Some("vmlinuz-6.1"));
+        let rec = find_record(&data, b.root.lba, b"LIB;1");
+        assert_eq!(readlink(rec).as_deref(), Some("/usr/lib/../lib"));
         Ok(())
     }
 
+    /// Lists the identifiers recorded in the directory sector at `lba`.
+    fn record_names(data: &[u8], lba: u32) -> Vec<String> {
+        let mut off = lba as usize * 2048;
+        let mut names = Vec::new();
+        while data[off] > 0 {
+            let id = &data[off + 33..off + 33 + data[off + 32] as usize];
+            names.push(String::from_utf8_lossy(id).into_owned());
+            off += data[off] as usize;
+        }
+        names
+    }
+
     #[test]
-    fn test_calculate_lbas() -> io::Result<()> {
-        let mut root = IsoDirectory::new();
-        let mut lba = 20;
-        let mut subdir = IsoDirectory::new();
-        subdir.children.insert(
-            "file2.txt".into(),
-            IsoFsNode::File(IsoFile {
-                path: PathBuf::new(),
-                size: 3000,
-                lba: 0,
+    fn test_enhanced_volume_descriptor() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let long = format!("Mixed-Case {}.txt", "x".repeat(85));
+        assert_eq!(long.len(), 100);
+        let src = dir.path().join("long.txt");
+        std::fs::write(&src, b"enhanced tree")?;
+        let boot = dir.path().join("boot.img");
+        std::fs::write(&boot, vec![0u8; 2048])?;
+
+        let mut b = IsoBuilder::new();
+        assert!(b.set_iso9660_version(3).is_err());
+        b.set_iso9660_version(2)?;
+        b.add_file(&format!("Docs/{long}"), &src)?;
+        b.add_file("boot.img", &boot)?;
+        b.set_boot_info(BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot.clone(),
+                destination_in_iso: "boot.img".into(),
+                ..Default::default()
             }),
+            uefi_boot: None,
+        });
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let sector = |lba: usize| &data[lba * 2048..(lba + 1) * 2048];
+
+        // PVD, boot record, EVD, terminator; the catalog follows.
+        assert_eq!(sector(16)[0], 1);
+        assert_eq!(sector(17)[0], 0);
+        assert_eq!(sector(17)[71..75], 20u32.to_le_bytes());
+        let evd = sector(18);
+        assert_eq!((evd[0], evd[6], evd[881]), (2, 2, 2));
+        assert_eq!(sector(19)[0], 255);
+        assert_eq!(sector(20)[0], 1, "boot catalog validation entry");
+        assert_eq!(
+            read_both_endian_u32(evd, 80)?,
+            read_both_endian_u32(sector(16), 80)?
         );
-        root.children.insert(
-            "file1.txt".into(),
-            IsoFsNode::File(IsoFile {
-                path: PathBuf::new(),
-                size: 1000,
-                lba: 0,
+
+        // The enhanced tree keeps the name as given.
+        let root = read_both_endian_u32(evd, 156 + 2)?;
+        assert_ne!(root, read_both_endian_u32(sector(16), 156 + 2)?);
+        let docs = find_record(&data, root, b"Docs");
+        let docs_lba = read_both_endian_u32(docs, 2)?;
+        let rec = find_record(&data, docs_lba, long.as_bytes());
+        assert_eq!(rec[32] as usize, long.len());
+        let lba = read_both_endian_u32(rec, 2)? as usize;
+        let size = read_both_endian_u32(rec, 10)? as usize;
+        assert_eq!(&data[lba * 2048..lba * 2048 + size], b"enhanced tree");
+
+        // The primary tree is unchanged.
+        let docs = find_record(&data, b.root.lba, b"DOCS");
+        assert_eq!(read_both_endian_u32(docs, 2)?, dir_lba(&b.root, "Docs"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_process() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.txt");
+        std::fs::write(&src, b"payload")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("a.txt", &src)?;
+        let (tx, rx) = std::sync::mpsc::channel();
+        b.set_post_process(Box::new(move |path| {
+            let data = std::fs::read(path)?;
+            assert_eq!(&data[16 * 2048..16 * 2048 + 6], b"\x01CD001");
+            tx.send(path.to_path_buf()).unwrap();
+            Ok(())
+        }));
+        build_named(&mut b, dir.path())?;
+        assert_eq!(rx.try_recv().unwrap(), dir.path().join("names.iso"));
+
+        // Consumed by the first build; a failing hook fails the build.
+        build_named(&mut b, dir.path())?;
+        b.set_post_process(Box::new(|_| Err(io::Error::other("patcher failed"))));
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.to_string(), "patcher failed");
+        Ok(())
+    }
+
+    #[test]
+    fn test_visible_boot_catalog() -> io::Result<()> {
+        use crate::iso::boot_info::BiosBootInfo;
+
+        let dir = tempfile::tempdir()?;
+        let boot = dir.path().join("isolinux.bin");
+        std::fs::write(&boot, vec![0u8; 2048])?;
+        let bios = |catalog: &str| BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: boot.clone(),
+                destination_in_iso: "isolinux/isolinux.bin".into(),
+                boot_catalog: Some(catalog.into()),
+                ..Default::default()
             }),
+            uefi_boot: None,
+        };
+        let mut b = IsoBuilder::new();
+        b.add_file("isolinux/isolinux.bin", &boot)?;
+        b.set_boot_info(bios("isolinux/boot.cat"));
+        // Building again must replace, not collide with, the first entry.
+        build_named(&mut b, dir.path())?;
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+
+        let isolinux = find_record(&data, b.root.lba, b"ISOLINUX");
+        let isolinux_lba = read_both_endian_u32(isolinux, 2)?;
+        assert!(record_names(&data, isolinux_lba).contains(&"BOOT.CAT;1".to_string()));
+        let rec = find_record(&data, isolinux_lba, b"BOOT.CAT");
+        assert_eq!(read_both_endian_u32(rec, 2)?, LBA_BOOT_CATALOG);
+        assert_eq!(read_both_endian_u32(rec, 10)?, 2048);
+        assert_eq!(rec[25], 0, "file flags");
+        assert_eq!(
+            data[LBA_BOOT_CATALOG as usize * 2048],
+            1,
+            "validation entry"
         );
-        root.children
-            .insert("subdir".into(), IsoFsNode::Directory(subdir));
-        calculate_lbas(&mut lba, &mut root)?;
-        assert_eq!(root.lba, 20);
+
+        for bad in ["", "../boot.cat", "/boot.cat"] {
+            b.set_boot_info(bios(bad));
+            let err = build_named(&mut b, dir.path()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{bad:?}");
+        }
+        b.set_boot_info(bios("isolinux/isolinux.bin"));
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        Ok(())
+    }
+
+    /// Payload of the first SUSP entry with signature `sig` in `rec`.
+    fn susp_entry<'a>(rec: &'a [u8], sig: &[u8; 2]) -> Option<&'a [u8]> {
+        let mut su = 33 + rec[32] as usize;
+        su += su % 2;
+        while su + 4 <= rec.len() && rec[su + 2] >= 4 {
+            let len = rec[su + 2] as usize;
+            if &rec[su..su + 2] == sig {
+                return Some(&rec[su + 4..su + len]);
+            }
+            su += len;
+        }
+        None
+    }
+
+    fn dir_lba(root: &IsoDirectory, path: &str) -> u32 {
+        path.split('/')
+            .fold(root, |d, c| match d.children.get(c) {
+                Some(IsoFsNode::Directory(sub)) => sub,
+                _ => panic!("{path} is not a directory"),
+            })
+            .lba
+    }
+
+    #[test]
+    fn test_deep_directories_are_relocated() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, b"deep")?;
+        // Ten nested directories: levels 2 to 11, counting the root as 1.
+        let deep = "a/b/c/d/e/f/g/h/i/j/deep.txt";
+        let mut b = IsoBuilder::new();
+        b.add_file(deep, &src)?;
+
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("'a/b/c/d/e/f/g/h'"), "{err}");
+
+        b.set_rock_ridge(true);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let moved_lba = dir_lba(&b.root, "rr_moved");
+        let g_lba = dir_lba(&b.root, "a/b/c/d/e/f/g");
+        let h_lba = dir_lba(&b.root, "a/b/c/d/e/f/g/h");
+
+        // `h` would be level 9: `g` keeps a zero-length placeholder pointing
+        // at it.
+        let placeholder = find_record(&data, g_lba, b"H");
+        assert_eq!(placeholder[25] & 0x02, 0, "placeholder must be a file");
+        assert_eq!(read_both_endian_u32(placeholder, 10)?, 0);
         assert_eq!(
-            root.children
-                .get("file1.txt")
-                .and_then(|n| if let IsoFsNode::File(f) = n {
-                    Some(f.lba)
-                } else {
-                    None
-                }),
-            Some(21)
+            read_both_endian_u32(susp_entry(placeholder, b"CL").unwrap(), 0)?,
+            h_lba
         );
-        let (sl, fl) = match root.children.get("subdir") {
-            Some(IsoFsNode::Directory(d)) => (
-                d.lba,
-                d.children.get("file2.txt").and_then(|n| {
-                    if let IsoFsNode::File(f) = n {
-                        Some(f.lba)
-                    } else {
-                        None
-                    }
-                }),
-            ),
-            _ => panic!(),
+
+        // rr_moved lists `h` with RE; its `..` goes there, with PL to `g`.
+        let moved = find_record(&data, moved_lba, h_lba.to_string().as_bytes());
+        assert_eq!(read_both_endian_u32(moved, 2)?, h_lba);
+        assert!(susp_entry(moved, b"RE").is_some());
+        let dotdot = find_record(&data, h_lba, &[1]);
+        assert_eq!(read_both_endian_u32(dotdot, 2)?, moved_lba);
+        assert_eq!(
+            read_both_endian_u32(susp_entry(dotdot, b"PL").unwrap(), 0)?,
+            g_lba
+        );
+
+        // `h` is level 3 once moved, so `i` and `j` stay where they are.
+        let j_lba = dir_lba(&b.root, "a/b/c/d/e/f/g/h/i/j");
+        let i_lba = dir_lba(&b.root, "a/b/c/d/e/f/g/h/i");
+        assert_eq!(
+            read_both_endian_u32(find_record(&data, i_lba, b"J"), 2)?,
+            j_lba
+        );
+        let file = find_record(&data, j_lba, b"DEEP.TXT");
+        let file_lba = read_both_endian_u32(file, 2)? as usize;
+        assert_eq!(&data[file_lba * 2048..file_lba * 2048 + 4], b"deep");
+
+        // Rebuilding reuses the directory instead of adding another.
+        build_named(&mut b, dir.path())?;
+        assert_eq!(dir_lba(&b.root, "rr_moved"), moved_lba);
+        b.remove_dir("a/b/c/d/e/f/g/h", true)?;
+        build_named(&mut b, dir.path())?;
+        assert!(!b.root.children.contains_key(RR_MOVED));
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_file_and_dir() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, b"x")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("docs/a/keep.txt", &src)?;
+        b.add_file("docs/a/drop.txt", &src)?;
+        b.add_file("tmp/x/y.txt", &src)?;
+        b.add_symlink("docs/link", "a/keep.txt")?;
+
+        assert!(b.remove_file("docs/a/drop.txt")?);
+        assert!(!b.remove_file("docs/a/drop.txt")?);
+        assert!(!b.remove_file("nowhere/drop.txt")?);
+        assert!(b.remove_file("docs/link")?);
+        assert_eq!(
+            b.remove_file("docs/a").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            b.remove_dir("tmp", false).unwrap_err().kind(),
+            io::ErrorKind::DirectoryNotEmpty
+        );
+        assert_eq!(
+            b.remove_dir("docs/a/keep.txt", true).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(b.remove_dir("tmp", true)?);
+        assert!(!b.remove_dir("tmp", true)?);
+
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        assert_eq!(record_names(&data, b.root.lba), ["\0", "\u{1}", "DOCS"]);
+        let docs = match b.root.children.get("docs") {
+            Some(IsoFsNode::Directory(d)) => d,
+            _ => panic!("docs directory missing"),
         };
-        assert_eq!(sl, 22);
-        assert_eq!(fl, Some(23));
-        assert_eq!(lba, 25);
+        assert_eq!(record_names(&data, docs.lba)[2..], ["A"]);
+        let a = docs.children.get("a").map(IsoFsNode::lba).unwrap();
+        assert_eq!(record_names(&data, a)[2..], ["KEEP.TXT;1"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cd_xa() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, b"x")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("GAME.BIN", &src)?;
+        b.add_symlink("LINK", "GAME.BIN")?;
+        b.set_cd_xa(true);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        assert_eq!(&data[16 * 2048 + 1024..16 * 2048 + 1032], b"CD-XA001");
+        let rec = find_record(&data, b.root.lba, b"GAME.BIN;1");
+        // "GAME.BIN;1" is 10 bytes, so the System Use area starts at 44.
+        assert_eq!(rec.len(), 44 + 14);
+        assert_eq!(&rec[50..52], b"XA");
+        assert_eq!(u16::from_be_bytes([rec[48], rec[49]]), 0x0D55);
+
+        // With Rock Ridge, SUSP entries follow the XA field and SP skips it.
+        b.set_rock_ridge(true);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let root_self = find_record(&data, b.root.lba, &[0]);
+        assert_eq!(&root_self[40..42], b"XA");
+        assert_eq!(&root_self[48..50], b"SP");
+        assert_eq!(root_self[54], 14);
+        let rec = find_record(&data, b.root.lba, b"LINK;1");
+        assert_eq!(&rec[40 + 6..40 + 8], b"XA");
+        assert_eq!(&rec[40 + 14..40 + 16], b"PX");
+
+        b.set_application_use(&[b'A'; 200])?;
+        assert!(build_named(&mut b, dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_volume_dates() -> io::Result<()> {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let dir = tempfile::tempdir()?;
+        let created = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let mut b = IsoBuilder::new();
+        assert_eq!(
+            b.set_volume_dates(VolumeDates {
+                effective: UNIX_EPOCH.checked_sub(Duration::from_secs(71 * 365 * 86400)),
+                ..Default::default()
+            })
+            .unwrap_err()
+            .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        b.set_volume_dates(VolumeDates {
+            creation: Some(created),
+            expiration: Some(created + Duration::from_secs(365 * 86400)),
+            ..Default::default()
+        })?;
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let pvd = &data[16 * 2048..17 * 2048];
+
+        let field = &pvd[813..830];
+        let digits = std::str::from_utf8(&field[..16]).unwrap();
+        let part = |range: std::ops::Range<usize>| digits[range].parse::<u32>().unwrap();
+        assert_eq!(
+            [
+                part(0..4),
+                part(4..6),
+                part(6..8),
+                part(8..10),
+                part(10..12),
+                part(12..14)
+            ],
+            [2023, 11, 14, 22, 13, 20]
+        );
+        assert_eq!(part(14..16), 50, "hundredths");
+        assert_eq!(field[16], 0, "GMT offset");
+        // Unset dates keep their previous values.
+        assert_eq!(&pvd[830..847], b"2024010100000000\0");
+        assert_eq!(&pvd[847..863], b"2024111322132050");
+        assert!(pvd[864..881].iter().all(|&b| b == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_file_executable() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, b"x")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("RUN.SH", &src)?;
+        b.add_file("DATA.TXT", &src)?;
+        b.set_file_executable("RUN.SH", true)?;
+        assert_eq!(
+            b.set_file_executable("MISSING", true).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        // Mode of the PX entry at the start of a record's System Use area.
+        let px_mode = |rec: &[u8]| -> io::Result<u32> {
+            let mut su = 33 + rec[32] as usize;
+            su += su % 2;
+            assert_eq!(&rec[su..su + 2], b"PX");
+            read_both_endian_u32(rec, su + 4)
+        };
+
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let root_self = find_record(&data, b.root.lba, &[0]);
+        assert_eq!(&root_self[34..36], b"SP");
+        assert_eq!(&root_self[41..43], b"ER");
+        let rec = find_record(&data, b.root.lba, b"RUN.SH;1");
+        assert_eq!(px_mode(rec)?, 0o100_555);
+        // Only the PX entry; no NM or other Rock Ridge entries.
+        assert_eq!(rec.len(), 42 + 36);
+        assert_eq!(find_record(&data, b.root.lba, b"DATA.TXT;1").len(), 44);
+
+        // Full Rock Ridge writes the same PX entry, followed by NM.
+        b.set_rock_ridge(true);
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        let rec = find_record(&data, b.root.lba, b"RUN.SH;1");
+        assert_eq!(px_mode(rec)?, 0o100_555);
+        assert_eq!(&rec[42 + 36..42 + 38], b"NM");
+        let rec = find_record(&data, b.root.lba, b"DATA.TXT;1");
+        assert_eq!(px_mode(rec)?, 0o100_444);
+
+        // Clearing the flag drops the extensions again.
+        b.set_rock_ridge(false);
+        b.set_file_executable("RUN.SH", false)?;
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(dir.path().join("names.iso"))?;
+        assert_eq!(find_record(&data, b.root.lba, &[0]).len(), 34);
+        assert_eq!(find_record(&data, b.root.lba, b"RUN.SH;1").len(), 42);
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_sectors() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("data.bin");
+        std::fs::write(&src, vec![7u8; 10_000])?;
+        for mode in [HybridMode::None, HybridMode::GptUefi] {
+            let mut b = IsoBuilder::new();
+            b.add_file("DATA.BIN", &src)?;
+            b.set_hybrid_mode(mode);
+            assert_eq!(b.total_sectors(), 0);
+            build_named(&mut b, dir.path())?;
+            let len = std::fs::metadata(dir.path().join("names.iso"))?.len();
+            assert_eq!(b.total_sectors() as u64, len / 2048, "{mode:?}");
+        }
+
+        let image = IsoImage {
+            volume_id: None,
+            files: vec![crate::iso::iso_image::IsoImageFile {
+                source: src,
+                destination: "DATA.BIN".into(),
+            }],
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: None,
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let out = build_iso(&dir.path().join("out.iso"), &image, false)?;
+        assert_eq!(out.total_sectors as u64, out.file.metadata()?.len() / 2048);
+        assert!(out.esp.is_none() && out.esp_sectors.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_autoconfig() -> io::Result<()> {
+        use crate::iso::autoconfig::AutoConfigFormat;
+        use crate::iso::reader::IsoReader;
+
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("blob");
+        std::fs::write(&src, b"x")?;
+        let mut b = IsoBuilder::new();
+        b.add_file("boot/vmlinuz-6.1", &src)?;
+        b.set_autoconfig(AutoConfig {
+            kernel: "boot/vmlinuz-6.1".into(),
+            initrd: Some("boot/initrd.img".into()),
+            cmdline: "console=ttyS0".into(),
+            format: AutoConfigFormat::Grub,
+        });
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        b.add_file("boot/initrd.img", &src)?;
+        let paths_before: Vec<_> = b.iter_nodes().map(|(p, _)| p).collect();
+        build_named(&mut b, dir.path())?;
+        // The generated file and its directory are gone from the tree.
+        let paths_after: Vec<_> = b.iter_nodes().map(|(p, _)| p).collect();
+        assert_eq!(paths_after, paths_before);
+
+        let iso = dir.path().join("names.iso");
+        let on_disc = IsoReader::new(File::open(&iso)?).list_paths()?;
+        assert!(on_disc.iter().any(|p| p == "/BOOT/GRUB/GRUB.CFG"));
+        let data = std::fs::read(&iso)?;
+        let start = data
+            .windows(13)
+            .position(|w| w == b"set default=0")
+            .unwrap();
+        let end = start + data[start..].iter().position(|&b| b == b'}').unwrap();
+        let text = std::str::from_utf8(&data[start..end]).unwrap();
+        assert!(text.contains("    linux /BOOT/VMLINUZ_6.1 console=ttyS0\n"));
+        for keyword in ["linux ", "initrd "] {
+            let line = text.lines().find(|l| l.trim_start().starts_with(keyword));
+            let path = line.unwrap().split_whitespace().nth(1).unwrap();
+            assert!(
+                on_disc.iter().any(|p| p == path),
+                "{path} not in {on_disc:?}"
+            );
+        }
+
+        // With Rock Ridge the names are kept as given.
+        b.set_rock_ridge(true);
+        b.set_autoconfig(AutoConfig {
+            kernel: "boot/vmlinuz-6.1".into(),
+            initrd: None,
+            cmdline: String::new(),
+            format: AutoConfigFormat::Isolinux,
+        });
+        build_named(&mut b, dir.path())?;
+        let data = std::fs::read(&iso)?;
+        assert!(
+            data.windows(26)
+                .any(|w| w == b"    KERNEL /boot/vmlinuz-6".as_slice())
+        );
+
+        // A file of the caller's at the configuration's path is kept.
+        b.add_file("isolinux/isolinux.cfg", &src)?;
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(get_file_size_in_iso(&b.root, "isolinux/isolinux.cfg")?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_disc_info() -> io::Result<()> {
+        use crate::iso::reader::IsoReader;
+
+        let dir = tempfile::tempdir()?;
+        let mut b = IsoBuilder::new();
+        let mut info = DiscInfo {
+            timestamp: 1_700_000_000,
+            arch: "x86_64".into(),
+            description: "Example Linux 1.0".into(),
+        };
+        b.set_disc_info(info.clone())?;
+        build_named(&mut b, dir.path())?;
+
+        let iso = dir.path().join("names.iso");
+        let paths = IsoReader::new(File::open(&iso)?).list_paths()?;
+        assert_eq!(paths, ["/.DISCINFO"]);
+        let data = std::fs::read(&iso)?;
+        let rec = find_record(&data, b.root.lba, b".DISCINFO");
+        let lba = read_both_endian_u32(rec, 2)? as usize;
+        let size = read_both_endian_u32(rec, 10)? as usize;
+        assert_eq!(
+            &data[lba * 2048..lba * 2048 + size],
+            b"1700000000\nExample Linux 1.0\nx86_64\n"
+        );
+
+        // The generated file is not left in the tree, and does not replace
+        // one of the caller's.
+        assert_eq!(b.iter_nodes().count(), 0);
+        let src = dir.path().join("discinfo");
+        std::fs::write(&src, b"mine")?;
+        b.add_file(DiscInfo::PATH, &src)?;
+        let err = build_named(&mut b, dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(b.source_files(), [src]);
+
+        info.arch = "x86_64\n".into();
+        let err = IsoBuilder::new().set_disc_info(info).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_into_aligned_writer() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut b = IsoBuilder::new();
+        for (i, size) in [100usize, 5000, 70_000, 3].into_iter().enumerate() {
+            let src = dir.path().join(format!("f{i}.bin"));
+            std::fs::write(
+                &src,
+                (0..size).map(|x| (x * 7 + i) as u8).collect::<Vec<_>>(),
+            )?;
+            b.add_file(&format!("D{i}/F{i}.BIN"), &src)?;
+        }
+        b.set_compute_ear_checksums(true);
+        build_named(&mut b, dir.path())?;
+        let plain = std::fs::read(dir.path().join("names.iso"))?;
+
+        // ISO images end on a 2048-byte boundary, so no padding is added.
+        let mut w = AlignedWriter::with_alignment(std::io::Cursor::new(Vec::new()), 2048)?;
+        b.build_to(&mut w, None, None)?;
+        let aligned = w.into_inner()?.into_inner();
+        assert_eq!(aligned, plain);
+
+        b.set_direct_io(true);
+        build_named(&mut b, dir.path())?;
+        assert_eq!(std::fs::read(dir.path().join("names.iso"))?, plain);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_smaller_image_truncates() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let big = dir.path().join("big.bin");
+        std::fs::write(&big, vec![0xEE; 300_000])?;
+        let small = dir.path().join("small.bin");
+        std::fs::write(&small, b"small")?;
+        let mut large = IsoBuilder::new();
+        large.add_file("BIG.BIN", &big)?;
+        let mut b = IsoBuilder::new();
+        b.add_file("SMALL.BIN", &small)?;
+
+        let mut out = io::Cursor::new(Vec::new());
+        large.build_to(&mut out, None, None)?;
+        b.build_to(&mut out, None, None)?;
+        let len = b.total_sectors() as usize * ISO_SECTOR_SIZE as usize;
+        assert!(len < large.total_sectors() as usize * ISO_SECTOR_SIZE as usize);
+        assert_eq!(out.get_ref().len(), len);
+        assert!(!out.get_ref().contains(&0xEE));
+
+        // The same through an AlignedWriter and over a reused file.
+        let mut w = AlignedWriter::with_alignment(io::Cursor::new(Vec::new()), 4096)?;
+        large.build_to(&mut w, None, None)?;
+        b.build_to(&mut w, None, None)?;
+        assert_eq!(w.len() as usize, len);
+        assert_eq!(w.into_inner()?.into_inner()[..len], out.get_ref()[..]);
+        for direct_io in [false, true] {
+            let path = dir.path().join("reused.iso");
+            large.build(&mut File::create(&path)?, &path, None, None)?;
+            b.set_direct_io(direct_io);
+            let mut f = OpenOptions::new().read(true).write(true).open(&path)?;
+            b.build(&mut f, &path, None, None)?;
+            assert_eq!(std::fs::read(&path)?, *out.get_ref(), "{direct_io}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_mbr_boot_code() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("data.bin");
+        std::fs::write(&src, vec![0x11u8; 4096])?;
+        let code: Vec<u8> = (0..432u32).map(|i| (i % 251) as u8 + 1).collect();
+
+        let mut b = IsoBuilder::new();
+        b.set_isohybrid(true);
+        assert!(b.set_mbr_boot_code(&[0u8; 441]).is_err());
+        b.set_mbr_boot_code(&code)?;
+        b.add_file("DATA.BIN", &src)?;
+        let path = dir.path().join("mbr.iso");
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        b.build(&mut f, &path, None, None)?;
+
+        let data = std::fs::read(&path)?;
+        assert_eq!(&data[..code.len()], &code[..]);
+        assert_eq!(data[446 + 4], 0xEE, "protective partition must be kept");
+        assert_eq!(u16::from_le_bytes([data[510], data[511]]), 0xAA55);
         Ok(())
     }
 
@@ -504,11 +5277,48 @@ mod tests {
         tf.write_all(b"some data")?;
         let tp = tf.into_temp_path();
         builder.add_file("A/B/C.txt", &tp)?;
-        builder.iso_data_lba = 20;
-        calculate_lbas(&mut builder.iso_data_lba, &mut builder.root)?;
+        builder.iso_data_lba = calculate_lbas(20, &mut builder.root)?;
         assert_eq!(get_lba_for_path(&builder.root, "A/B/C.txt")?, 23);
         assert_eq!(get_file_size_in_iso(&builder.root, "A/B/C.txt")?, 9);
         assert!(get_lba_for_path(&builder.root, "A/D.txt").is_err());
         Ok(())
     }
+
+    #[test]
+    fn test_timestamp_source() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("stamp.txt");
+        std::fs::write(&src, b"x")?;
+        // 2000-02-29 12:34:56 UTC
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(951_827_696);
+        File::options()
+            .write(true)
+            .open(&src)?
+            .set_modified(mtime)?;
+        let mtime_date = [100, 2, 29, 12, 34, 56, 0];
+
+        let dates = |source| -> io::Result<([u8; 7], [u8; 7])> {
+            let mut b = IsoBuilder::new();
+            b.add_file("stamp.txt", &src)?;
+            b.add_file("sub/inner.txt", &src)?;
+            b.set_timestamp_source(source);
+            build_named(&mut b, dir.path())?;
+            let data = std::fs::read(dir.path().join("names.iso"))?;
+            let date = |id: &[u8]| {
+                find_record(&data, b.root.lba, id)[18..25]
+                    .try_into()
+                    .unwrap()
+            };
+            Ok((date(b"STAMP.TXT"), date(b"SUB")))
+        };
+
+        assert_eq!(dates(TimestampSource::Fixed)?, ([0; 7], [0; 7]));
+        let (file, sub) = dates(TimestampSource::FromSource)?;
+        assert_eq!(file, mtime_date);
+        assert!(sub[0] >= 124 && sub[1..3] != [0, 0], "{sub:?}");
+        let (file, sub) = dates(TimestampSource::BuildTime)?;
+        assert_eq!(file, sub);
+        assert_ne!(file, mtime_date);
+        Ok(())
+    }
 }
@@ -2,40 +2,283 @@ use std::io::{self};
 use std::path::Path;
 
 use crate::iso::boot_catalog::{
-    BOOT_CATALOG_EFI_PLATFORM_ID, BootCatalogEntry, BootCatalogEntryType,
+    BOOT_CATALOG_EFI_PLATFORM_ID, BootCatalogEntry, BootCatalogEntryType, MEDIA_NO_EMULATION,
 };
-use crate::iso::fs_node::{IsoDirectory, IsoFsNode};
+use crate::iso::dir_record::transliterate_name;
+use crate::iso::fs_node::{IsoDirectory, IsoFile, IsoFsNode};
 use crate::utils::ISO_SECTOR_SIZE;
 
 const EL_TORITO_SECTOR_SIZE: u64 = 512;
 
-pub fn calculate_lbas(current_lba: &mut u32, dir: &mut IsoDirectory) -> io::Result<()> {
-    dir.lba = *current_lba;
-    *current_lba += 1;
-    let mut sorted: Vec<_> = dir.children.iter_mut().collect();
-    sorted.sort_by_key(|(name, _)| *name);
-    for (_, node) in sorted {
+/// Maximum directory nesting allowed by ISO 9660, counting the root as
+/// level 1.
+pub const MAX_DIR_DEPTH: usize = 8;
+/// Root directory that Rock Ridge relocates deeper directories into.
+pub const RR_MOVED: &str = "rr_moved";
+
+/// Number of sectors [`calculate_lbas`] reserves for `file`.  Fails with
+/// `InvalidInput` if that does not fit in a `u32`.
+pub fn file_sectors(file: &IsoFile) -> io::Result<u32> {
+    u32::try_from(file_sectors_u64(file)).map_err(|_| too_many_sectors())
+}
+
+/// [`file_sectors`] without the `u32` limit, for size estimates.
+fn file_sectors_u64(file: &IsoFile) -> u64 {
+    file.size.div_ceil(ISO_SECTOR_SIZE as u64) + u64::from(file.padding_sectors)
+}
+
+fn too_many_sectors() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "image exceeds 2^32 sectors")
+}
+
+/// Number of sectors [`calculate_lbas_with_ear`] assigns to `dir` and
+/// everything below it.
+pub fn tree_sectors(dir: &IsoDirectory, ear_sectors: u32) -> u64 {
+    1 + dir
+        .children
+        .values()
+        .map(|node| match node {
+            IsoFsNode::File(file) => u64::from(ear_sectors) + file_sectors_u64(file),
+            IsoFsNode::Directory(subdir) => tree_sectors(subdir, ear_sectors),
+            IsoFsNode::Symlink(_) | IsoFsNode::BootCatalog(_) => 0,
+        })
+        .sum::<u64>()
+}
+
+/// Sectors from `dir`'s own LBA to the data of the file at `path` below
+/// it, as [`calculate_lbas_with_ear`] lays the tree out, or `None` if
+/// there is no such file.
+pub fn file_offset_in_tree(dir: &IsoDirectory, path: &str, ear_sectors: u32) -> Option<u64> {
+    let path = iso_path_components(path).collect::<Vec<_>>().join("/");
+    let mut offset = 1;
+    for (node_path, node) in iter_nodes(dir) {
         match node {
             IsoFsNode::File(file) => {
-                file.lba = *current_lba;
-                *current_lba += file.size.div_ceil(ISO_SECTOR_SIZE as u64) as u32;
+                offset += u64::from(ear_sectors);
+                if node_path == path {
+                    return Some(offset);
+                }
+                offset += file_sectors_u64(file);
+            }
+            IsoFsNode::Directory(_) => offset += 1,
+            IsoFsNode::Symlink(_) | IsoFsNode::BootCatalog(_) => {}
+        }
+    }
+    None
+}
+
+/// Number of directories in the tree rooted at `dir`, `dir` included.
+pub fn dir_count(dir: &IsoDirectory) -> u32 {
+    1 + dir
+        .children
+        .values()
+        .map(|node| match node {
+            IsoFsNode::Directory(subdir) => dir_count(subdir),
+            _ => 0,
+        })
+        .sum::<u32>()
+}
+
+/// Assigns an LBA to every directory and file under `dir`, starting with
+/// `dir` itself at `start_lba`, and returns the first LBA after the tree.
+/// Every LBA is overwritten, so calling it again with the same `start_lba`
+/// yields the same layout.
+pub fn calculate_lbas(start_lba: u32, dir: &mut IsoDirectory) -> io::Result<u32> {
+    calculate_lbas_with_ear(start_lba, dir, 0)
+}
+
+/// Like [`calculate_lbas`], reserving `ear_sectors` for an extended
+/// attribute record in front of every file.  `IsoFile::lba` still points at
+/// the file data; the EAR occupies the sectors just before it.
+pub fn calculate_lbas_with_ear(
+    start_lba: u32,
+    dir: &mut IsoDirectory,
+    ear_sectors: u32,
+) -> io::Result<u32> {
+    let mut next = start_lba;
+    assign_lbas(&mut next, dir, ear_sectors)?;
+    Ok(next)
+}
+
+/// Depth-first over the sorted children, with an explicit stack so that
+/// arbitrarily deep trees cannot overflow the call stack.  Fails with
+/// `InvalidInput` once an LBA would pass `u32::MAX`.
+fn assign_lbas(next: &mut u32, dir: &mut IsoDirectory, ear_sectors: u32) -> io::Result<()> {
+    let advance = |next: &mut u32, sectors: u32| -> io::Result<()> {
+        *next = next.checked_add(sectors).ok_or_else(too_many_sectors)?;
+        Ok(())
+    };
+    dir.lba = *next;
+    advance(next, 1)?;
+    let mut stack = vec![sorted_children_mut(dir)];
+    while let Some(children) = stack.last_mut() {
+        match children.next() {
+            None => {
+                stack.pop();
+            }
+            Some((_, IsoFsNode::File(file))) => {
+                advance(next, ear_sectors)?;
+                file.lba = *next;
+                advance(next, file_sectors(file)?)?;
             }
-            IsoFsNode::Directory(subdir) => calculate_lbas(current_lba, subdir)?,
+            Some((_, IsoFsNode::Directory(subdir))) => {
+                subdir.lba = *next;
+                advance(next, 1)?;
+                stack.push(sorted_children_mut(subdir));
+            }
+            Some((_, IsoFsNode::Symlink(_) | IsoFsNode::BootCatalog(_))) => {}
         }
     }
     Ok(())
 }
 
-fn get_node_for_path<'a>(root: &'a IsoDirectory, path: &str) -> io::Result<&'a IsoFsNode> {
-    for c in Path::new(path).components() {
-        c.as_os_str()
-            .to_str()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path"))?;
+type SortedChildren<'a, N> = std::vec::IntoIter<(&'a String, N)>;
+
+fn sorted_children(dir: &IsoDirectory) -> SortedChildren<'_, &IsoFsNode> {
+    let mut sorted: Vec<_> = dir.children.iter().collect();
+    sorted.sort_by_key(|(name, _)| *name);
+    sorted.into_iter()
+}
+
+fn sorted_children_mut(dir: &mut IsoDirectory) -> SortedChildren<'_, &mut IsoFsNode> {
+    let mut sorted: Vec<_> = dir.children.iter_mut().collect();
+    sorted.sort_by_key(|(name, _)| *name);
+    sorted.into_iter()
+}
+
+/// Every file under `dir` in the order [`calculate_lbas`] lays them out.
+/// Walks the tree with an explicit stack, like [`calculate_lbas`] itself.
+pub fn files_in_lba_order(dir: &IsoDirectory) -> Vec<&IsoFile> {
+    let mut files = Vec::new();
+    let mut stack = vec![sorted_children(dir)];
+    while let Some(children) = stack.last_mut() {
+        match children.next() {
+            None => {
+                stack.pop();
+            }
+            Some((_, IsoFsNode::File(file))) => files.push(file),
+            Some((_, IsoFsNode::Directory(subdir))) => stack.push(sorted_children(subdir)),
+            Some((_, IsoFsNode::Symlink(_) | IsoFsNode::BootCatalog(_))) => {}
+        }
+    }
+    files
+}
+
+/// Every node under `dir` with its full path (`A/B/C.TXT`, no leading
+/// slash), depth-first with each directory's children in sorted order, a
+/// directory coming right before its contents.  Lazy and non-recursive.
+pub fn iter_nodes(dir: &IsoDirectory) -> impl Iterator<Item = (String, &IsoFsNode)> {
+    let mut stack = vec![(String::new(), sorted_children(dir))];
+    std::iter::from_fn(move || {
+        loop {
+            let (prefix, children) = stack.last_mut()?;
+            let Some((name, node)) = children.next() else {
+                stack.pop();
+                continue;
+            };
+            let path = format!("{prefix}{name}");
+            if let IsoFsNode::Directory(subdir) = node {
+                stack.push((format!("{path}/"), sorted_children(subdir)));
+            }
+            return Some((path, node));
+        }
+    })
+}
+
+/// Path of the first directory, in sorted order, nested deeper than
+/// [`MAX_DIR_DEPTH`] levels below `dir` (taken to be the root).
+pub fn first_too_deep(dir: &IsoDirectory) -> Option<String> {
+    fn walk(dir: &IsoDirectory, prefix: &str, level: usize) -> Option<String> {
+        let mut sorted: Vec<_> = dir.children.iter().collect();
+        sorted.sort_by_key(|(name, _)| *name);
+        sorted.into_iter().find_map(|(name, node)| match node {
+            IsoFsNode::Directory(_) if level + 1 > MAX_DIR_DEPTH => Some(format!("{prefix}{name}")),
+            IsoFsNode::Directory(subdir) => walk(subdir, &format!("{prefix}{name}/"), level + 1),
+            _ => None,
+        })
     }
+    walk(dir, "", 1)
+}
+
+/// Appends `(original, transliterated)` paths for every node under `dir`
+/// whose ISO 9660 identifier differs from its name beyond upper-casing.
+/// Fails if two siblings map to the same identifier.
+pub fn collect_name_changes(
+    dir: &IsoDirectory,
+    prefix: &str,
+    replacement: char,
+    out: &mut Vec<(String, String)>,
+) -> io::Result<()> {
+    let mut seen = std::collections::HashMap::new();
+    for_sorted_children!(dir, |name, node| {
+        let is_dir = matches!(node, IsoFsNode::Directory(_));
+        let mapped = transliterate_name(name, is_dir, replacement);
+        if let Some(other) = seen.insert(mapped.clone(), name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "'{prefix}{other}' and '{prefix}{name}' both map to ISO 9660 name '{mapped}'"
+                ),
+            ));
+        }
+        if mapped != name.to_ascii_uppercase() {
+            out.push((format!("{prefix}{name}"), format!("{prefix}{mapped}")));
+        }
+        if let IsoFsNode::Directory(subdir) = node {
+            collect_name_changes(subdir, &format!("{prefix}{name}/"), replacement, out)?;
+        }
+    });
+    Ok(())
+}
+
+/// Fails with `InvalidInput` on the first identifier under `dir`, as
+/// transliterated with `replacement`, too long for ISO 9660 interchange
+/// `level`: level 1 allows 8.3 file names and 8-character directory names,
+/// levels 2 and 3 up to 31 characters (not counting the `;1` version).
+pub fn check_name_lengths(
+    dir: &IsoDirectory,
+    prefix: &str,
+    level: u8,
+    replacement: char,
+) -> io::Result<()> {
+    for_sorted_children!(dir, |name, node| {
+        let is_dir = matches!(node, IsoFsNode::Directory(_));
+        let mapped = transliterate_name(name, is_dir, replacement);
+        let fits = match (level, mapped.rsplit_once('.')) {
+            (1, _) if is_dir => mapped.len() <= 8,
+            (1, Some((base, ext))) => base.len() <= 8 && ext.len() <= 3,
+            (1, None) => mapped.len() <= 8,
+            _ => mapped.len() <= 31,
+        };
+        if !fits {
+            let limit = if level == 1 { "8.3" } else { "31 characters" };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "'{prefix}{name}' (ISO 9660 name '{mapped}') exceeds the {limit} \
+                     limit of ISO level {level}"
+                ),
+            ));
+        }
+        if let IsoFsNode::Directory(subdir) = node {
+            check_name_lengths(subdir, &format!("{prefix}{name}/"), level, replacement)?;
+        }
+    });
+    Ok(())
+}
+
+/// Splits `path`, a path inside the ISO, on both `/` and `\` so that it
+/// names the same node whatever the host platform's separator.  Empty and
+/// `.` components are dropped, so a leading separator is ignored.
+pub fn iso_path_components(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+}
+
+pub fn get_node_for_path<'a>(root: &'a IsoDirectory, path: &str) -> io::Result<&'a IsoFsNode> {
     let mut current = root;
-    let components: Vec<_> = Path::new(path).components().collect();
-    for (i, comp) in components.iter().enumerate() {
-        let name = comp.as_os_str().to_str().unwrap();
+    let components: Vec<_> = iso_path_components(path).collect();
+    for (i, &name) in components.iter().enumerate() {
         if i == components.len() - 1 {
             return current.children.get(name).ok_or_else(|| {
                 io::Error::new(io::ErrorKind::NotFound, format!("Path not found: {path}"))
@@ -57,23 +300,56 @@ fn get_node_for_path<'a>(root: &'a IsoDirectory, path: &str) -> io::Result<&'a I
     ))
 }
 
+/// Returns the directory holding the last component of `path` together
+/// with that component, or `None` if an intermediate directory is missing.
+pub fn get_parent_dir_mut<'a, 'p>(
+    root: &'a mut IsoDirectory,
+    path: &'p str,
+) -> io::Result<Option<(&'a mut IsoDirectory, &'p str)>> {
+    let names: Vec<_> = iso_path_components(path).collect();
+    let Some((last, parents)) = names.split_last() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid path: {path:?}"),
+        ));
+    };
+    let mut current = root;
+    for name in parents {
+        match current.children.get_mut(*name) {
+            Some(IsoFsNode::Directory(d)) => current = d,
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some((current, last)))
+}
+
 pub fn get_lba_for_path(root: &IsoDirectory, path: &str) -> io::Result<u32> {
     match get_node_for_path(root, path)? {
         IsoFsNode::File(f) => Ok(f.lba),
+        IsoFsNode::BootCatalog(lba) => Ok(*lba),
         IsoFsNode::Directory(_) => Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             format!("Path is a directory: {path}"),
         )),
+        IsoFsNode::Symlink(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Path is a symlink: {path}"),
+        )),
     }
 }
 
 pub fn get_file_size_in_iso(root: &IsoDirectory, path: &str) -> io::Result<u64> {
     match get_node_for_path(root, path)? {
         IsoFsNode::File(f) => Ok(f.size),
+        IsoFsNode::BootCatalog(_) => Ok(ISO_SECTOR_SIZE as u64),
         IsoFsNode::Directory(_) => Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             format!("Path is a directory: {path}"),
         )),
+        IsoFsNode::Symlink(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Path is a symlink: {path}"),
+        )),
     }
 }
 
@@ -86,24 +362,36 @@ pub fn get_file_metadata(path: &Path) -> io::Result<std::fs::Metadata> {
     })
 }
 
+/// Name of the file `path_in_iso` refers to.  A path ending in a separator
+/// names a directory, so it fails with `InvalidInput` rather than creating a
+/// file named after its last directory.
+pub fn file_name_in_iso(path_in_iso: &str) -> io::Result<String> {
+    let name = path_in_iso
+        .rsplit(['/', '\\'])
+        .next()
+        .filter(|n| !matches!(*n, "" | "." | ".."));
+    name.map(str::to_string).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid file name: '{path_in_iso}' does not end in a file name"),
+        )
+    })
+}
+
 pub fn ensure_directory_path<'a>(
     root: &'a mut IsoDirectory,
     path: &str,
 ) -> io::Result<&'a mut IsoDirectory> {
-    let components: Vec<_> = Path::new(path).components().collect();
+    let components: Vec<_> = iso_path_components(path).collect();
     let mut current = root;
-    for comp in components.iter().take(components.len().saturating_sub(1)) {
-        let name = comp
-            .as_os_str()
-            .to_str()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid path component"))?;
+    for &name in components.iter().take(components.len().saturating_sub(1)) {
         current = match current
             .children
             .entry(name.to_string())
             .or_insert_with(|| IsoFsNode::Directory(IsoDirectory::new()))
         {
             IsoFsNode::Directory(d) => d,
-            IsoFsNode::File(_) => {
+            IsoFsNode::File(_) | IsoFsNode::Symlink(_) | IsoFsNode::BootCatalog(_) => {
                 return Err(io::Error::new(
                     io::ErrorKind::AlreadyExists,
                     format!("Path component '{name}' is a file"),
@@ -120,42 +408,157 @@ fn mk_boot_entry(platform_id: u8, lba: u32, sectors: u16) -> BootCatalogEntry {
         boot_image_lba: lba,
         boot_image_sectors: sectors,
         entry_type: BootCatalogEntryType::BootEntry { bootable: true },
+        media_type: MEDIA_NO_EMULATION,
+        selection_criteria: None,
     }
 }
 
-pub fn create_bios_boot_entry(root: &IsoDirectory, path: &str) -> io::Result<BootCatalogEntry> {
+/// Creates a bootable entry for the file at `path` in the ISO tree.  The LBA
+/// is read from the tree, so this must run after [`calculate_lbas`].  Empty
+/// images are rejected, as are non-UEFI images shorter than one 512-byte
+/// sector.
+pub fn create_boot_entry(
+    root: &IsoDirectory,
+    platform_id: u8,
+    path: &str,
+) -> io::Result<BootCatalogEntry> {
+    create_boot_entry_with_load_count(root, platform_id, path, None)
+}
+
+/// Like [`create_boot_entry`], with `load_sectors` 512-byte sectors as the
+/// entry's load count instead of the whole image.  The count must be
+/// non-zero and no larger than the image; the image itself may then exceed
+/// what the 16-bit field could describe.
+pub fn create_boot_entry_with_load_count(
+    root: &IsoDirectory,
+    platform_id: u8,
+    path: &str,
+    load_sectors: Option<u16>,
+) -> io::Result<BootCatalogEntry> {
     let lba = get_lba_for_path(root, path)?;
     let sz = get_file_size_in_iso(root, path)?;
-    let sectors = sz.div_ceil(EL_TORITO_SECTOR_SIZE).max(1);
-    if sectors > u16::MAX as u64 {
+    // A BIOS loads at least one whole 512-byte sector from the image; an
+    // empty image (or a short one for BIOS) can never boot.
+    let min = match platform_id {
+        BOOT_CATALOG_EFI_PLATFORM_ID => 1,
+        _ => EL_TORITO_SECTOR_SIZE,
+    };
+    if sz < min {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
-            "BIOS boot image too large",
+            format!("Boot image {path} is {sz} bytes; at least {min} required"),
         ));
     }
-    Ok(mk_boot_entry(0x00, lba, sectors as u16))
+    let sectors = sz.div_ceil(EL_TORITO_SECTOR_SIZE);
+    let count = match load_sectors {
+        Some(n) if n == 0 || n as u64 > sectors => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Load count {n} outside 1..={sectors} sectors of boot image {path}"),
+            ));
+        }
+        Some(n) => n,
+        None => u16::try_from(sectors).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Boot image too large: {path}"),
+            )
+        })?,
+    };
+    // `lba` counts 2048-byte ISO sectors and `count` 512-byte virtual
+    // sectors; the load must end within the image's ISO extent.
+    debug_assert!(
+        count as u64 * EL_TORITO_SECTOR_SIZE <= sz.next_multiple_of(ISO_SECTOR_SIZE as u64),
+        "load count of {path} exceeds its extent"
+    );
+    Ok(mk_boot_entry(platform_id, lba, count))
+}
+
+pub fn create_bios_boot_entry(root: &IsoDirectory, path: &str) -> io::Result<BootCatalogEntry> {
+    create_boot_entry(root, 0x00, path)
 }
 
 pub fn create_uefi_boot_entry(root: &IsoDirectory, path: &str) -> io::Result<BootCatalogEntry> {
+    create_boot_entry(root, BOOT_CATALOG_EFI_PLATFORM_ID, path)
+}
+
+/// Creates the UEFI entry for an ESP image stored as the file at `path`.
+pub fn create_uefi_esp_boot_entry(root: &IsoDirectory, path: &str) -> io::Result<BootCatalogEntry> {
     let lba = get_lba_for_path(root, path)?;
-    let sz = get_file_size_in_iso(root, path)?;
-    let sectors = sz.div_ceil(EL_TORITO_SECTOR_SIZE).max(1);
-    if sectors > u16::MAX as u64 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "UEFI boot image too large",
-        ));
-    }
-    Ok(mk_boot_entry(
-        BOOT_CATALOG_EFI_PLATFORM_ID,
-        lba,
-        sectors as u16,
-    ))
+    Ok(create_uefi_raw_esp_boot_entry(lba))
 }
 
-pub fn create_uefi_esp_boot_entry(esp_lba: u32, _esp_size: u32) -> io::Result<BootCatalogEntry> {
+/// Creates the UEFI entry for an ESP placed at a raw LBA outside the ISO
+/// tree.  `esp_lba` is in 2048-byte ISO sectors, like every catalog LBA.
+pub fn create_uefi_raw_esp_boot_entry(esp_lba: u32) -> BootCatalogEntry {
     // No-emulation boot entries MUST have sector_count = 0 per El Torito
     // spec § 6.4.  The actual image size is conveyed via the Section Header
     // entry count field.
-    Ok(mk_boot_entry(BOOT_CATALOG_EFI_PLATFORM_ID, esp_lba, 0))
+    mk_boot_entry(BOOT_CATALOG_EFI_PLATFORM_ID, esp_lba, 0)
+}
+
+/// Named LBA ranges of the structures a build writes, used to catch layout
+/// arithmetic that places two of them on the same sectors.
+#[derive(Default)]
+pub(crate) struct LayoutTracker {
+    /// `(start, end, name)`, `end` exclusive.
+    ranges: Vec<(u64, u64, String)>,
+}
+
+impl LayoutTracker {
+    /// Records `sectors` sectors from `start`; empty ranges are ignored.
+    pub(crate) fn record(&mut self, name: impl Into<String>, start: u64, sectors: u64) {
+        if sectors > 0 {
+            self.ranges.push((start, start + sectors, name.into()));
+        }
+    }
+
+    /// Records every directory, extended attribute record and file extent
+    /// under `dir` as laid out by [`calculate_lbas_with_ear`].
+    pub(crate) fn record_tree(&mut self, dir: &IsoDirectory, path: &str, ear_sectors: u32) {
+        let shown = if path.is_empty() { "/" } else { path };
+        self.record(format!("directory {shown}"), dir.lba as u64, 1);
+        for_sorted_children!(dir, |name, node| {
+            let child = format!("{path}/{name}");
+            match node {
+                IsoFsNode::File(file) => {
+                    let lba = file.lba as u64;
+                    self.record(
+                        format!("extended attribute record of {child}"),
+                        lba - ear_sectors as u64,
+                        ear_sectors as u64,
+                    );
+                    self.record(format!("file {child}"), lba, file_sectors_u64(file));
+                }
+                IsoFsNode::Directory(subdir) => self.record_tree(subdir, &child, ear_sectors),
+                // Shares the catalog sector recorded for the descriptors.
+                IsoFsNode::Symlink(_) | IsoFsNode::BootCatalog(_) => {}
+            }
+        });
+    }
+
+    /// Fails with `InvalidData` naming the first two recorded ranges that
+    /// share a sector.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        let mut sorted: Vec<_> = self.ranges.iter().collect();
+        sorted.sort_by_key(|(start, end, _)| (*start, *end));
+        let mut furthest: Option<&(u64, u64, String)> = None;
+        for r in sorted {
+            if let Some(prev) = furthest
+                && r.0 < prev.1
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "layout overlap: {} (LBA {}..{}) and {} (LBA {}..{})",
+                        prev.2, prev.0, prev.1, r.2, r.0, r.1
+                    ),
+                ));
+            }
+            if furthest.is_none_or(|p| r.1 > p.1) {
+                furthest = Some(r);
+            }
+        }
+        Ok(())
+    }
 }
@@ -4,6 +4,13 @@ pub const ISO_SECTOR_SIZE: u64 = 2048;
 /// Size of one disk sector (used by GPT, MBR, FAT BPB) in bytes.
 pub const DISK_SECTOR_SIZE: u64 = 512;
 
+/// Number of ISO sectors (LBA 0–15) in the system area preceding the volume
+/// descriptors.  ISO 9660 leaves its content unspecified.
+pub const SYSTEM_AREA_SECTORS: u32 = 16;
+
+/// Size of the system area in bytes (16 × 2048 = 32 KiB).
+pub const SYSTEM_AREA_SIZE: u64 = SYSTEM_AREA_SECTORS as u64 * ISO_SECTOR_SIZE;
+
 /// The starting LBA for the EFI System Partition in **ISO 2048-byte sectors**.
 ///
 /// LBA 1024 in ISO sectors = 1024 × 2048 = 2 MiB = 512-byte sector 4096.
@@ -44,6 +51,14 @@ pub const GPT_RESERVED_512_SECTORS: u32 = 34;
 /// 1 sector for backup header + 32 sectors for backup partition entries.
 pub const BACKUP_GPT_RESERVED_512: u64 = 33;
 
+/// Alignment, in 512-byte sectors (1 MiB), of the persistence partition
+/// added by [`IsoBuilder::add_persistence_partition`](crate::IsoBuilder::add_persistence_partition).
+pub const PERSISTENCE_ALIGN_512: u64 = 2048;
+
+/// Alignment, in 512-byte sectors (1 MiB), of the ESP partition a hybrid
+/// image points at the `efi_boot_image_iso_path` file.
+pub const HYBRID_ESP_ALIGN_512: u64 = 2048;
+
 /// Convert an ISO 2048-byte sector LBA to the equivalent 512-byte sector LBA.
 ///
 /// 1 ISO sector = 4 × 512-byte sectors.
@@ -1,5 +1,177 @@
 // isobemak/src/iso/dir_record.rs
 
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::utils::ISO_SECTOR_SIZE;
+
+/// Image-wide settings that affect how directory records are encoded.
+#[derive(Debug, Clone)]
+pub struct DirRecordOptions {
+    /// Version number appended to file identifiers as `;N`.  `None` omits
+    /// the suffix entirely.
+    pub file_version: Option<u16>,
+    /// Character substituted for anything outside the d-character set.
+    pub replacement: char,
+    /// Every file is preceded by a one-block extended attribute record
+    /// holding a CRC32 of its data (see [`ear_with_crc32`]).
+    pub ear_checksums: bool,
+    /// Append Rock Ridge System Use entries (see [`crate::iso::rock_ridge`]).
+    pub rock_ridge: bool,
+    /// Open every System Use area with a CD-ROM XA field (see [`xa_field`]).
+    pub cd_xa: bool,
+    /// Write names unchanged (mixed case, any character, up to
+    /// [`MAX_RELAXED_NAME_LEN`] bytes) as ISO 9660:1999 allows, instead of
+    /// transliterating them.
+    pub relaxed_names: bool,
+    /// Where the recording dates of file records come from.
+    pub timestamps: TimestampSource,
+    /// Recording date (see [`recording_date`]) of every record that does
+    /// not take its own from [`IsoFile::modified`](crate::iso::fs_node::IsoFile::modified).
+    /// All zero ("not specified") by default.
+    pub recorded: [u8; 7],
+    /// Files over `u32::MAX` bytes get one record per extent of at most
+    /// [`MAX_EXTENT_SIZE`] bytes (ISO 9660 level 3) instead of failing.
+    pub multi_extent: bool,
+}
+
+/// Source of the recording dates in directory records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampSource {
+    /// All dates are left zero, so the image only depends on its inputs.
+    #[default]
+    Fixed,
+    /// Files carry the modification time of their source; directories
+    /// carry the build time.
+    FromSource,
+    /// Every record carries the time the image was built.
+    BuildTime,
+}
+
+impl Default for DirRecordOptions {
+    fn default() -> Self {
+        Self {
+            file_version: Some(1),
+            replacement: '_',
+            ear_checksums: false,
+            rock_ridge: false,
+            cd_xa: false,
+            relaxed_names: false,
+            timestamps: TimestampSource::Fixed,
+            recorded: [0; 7],
+            multi_extent: false,
+        }
+    }
+}
+
+/// Encodes `time` as a 7-byte directory record date (ECMA-119 9.1.5):
+/// years since 1900, month, day, hour, minute, second and a GMT offset of
+/// zero.  Times before 1900 or after 2155 give all zeros.
+pub fn recording_date(time: SystemTime) -> [u8; 7] {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => {
+            let d = e.duration();
+            -(d.as_secs() as i64) - i64::from(d.subsec_nanos() > 0)
+        }
+    };
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil date from days since 1970-01-01 (proleptic Gregorian).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    match u8::try_from(year - 1900) {
+        Ok(y) => [
+            y,
+            month as u8,
+            day as u8,
+            (rem / 3600) as u8,
+            (rem / 60 % 60) as u8,
+            (rem % 60) as u8,
+            0,
+        ],
+        Err(_) => [0; 7],
+    }
+}
+
+/// Largest extent of a multi-extent file: 4 GiB less one sector, so that
+/// every extent but the last is a whole number of sectors.
+pub const MAX_EXTENT_SIZE: u32 = u32::MAX - (ISO_SECTOR_SIZE as u32 - 1);
+
+/// Longest file identifier ISO 9660:1999 allows.
+pub const MAX_RELAXED_NAME_LEN: usize = 207;
+
+/// Returns whether `c` is an ISO 9660 d-character (`A-Z`, `0-9`, `_`).
+pub fn is_d_character(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'
+}
+
+/// Maps `name` to an ISO 9660 identifier: ASCII letters are upper-cased and
+/// every other character outside the d-character set becomes `replacement`.
+/// File names keep their last `.` as the extension separator.
+pub fn transliterate_name(name: &str, is_dir: bool, replacement: char) -> String {
+    let ext_dot = if is_dir { None } else { name.rfind('.') };
+    name.char_indices()
+        .map(|(i, c)| {
+            let c = c.to_ascii_uppercase();
+            if is_d_character(c) || Some(i) == ext_dot {
+                c
+            } else {
+                replacement
+            }
+        })
+        .collect()
+}
+
+/// Application-use tag identifying the CRC32 stored in an extended
+/// attribute record.
+pub const EAR_CRC32_TAG: &[u8; 5] = b"CRC32";
+/// Offset of the application-use area within an extended attribute record.
+pub const EAR_APPLICATION_USE: usize = 250;
+
+/// Builds a one-block extended attribute record whose application-use area
+/// holds [`EAR_CRC32_TAG`] followed by `crc` (big-endian).
+pub fn ear_with_crc32(crc: u32) -> [u8; ISO_SECTOR_SIZE] {
+    let mut ear = [0u8; ISO_SECTOR_SIZE];
+    // Owner/group identification stay zero.  Permissions: read/execute
+    // bits clear (granted) for every class, reserved bits set.
+    ear[8..10].copy_from_slice(&0xAAAAu16.to_be_bytes());
+    ear[180] = 1; // EAR version
+    let app_use = EAR_CRC32_TAG.len() + 4;
+    ear[246..248].copy_from_slice(&(app_use as u16).to_le_bytes());
+    ear[248..250].copy_from_slice(&(app_use as u16).to_be_bytes());
+    let tag_end = EAR_APPLICATION_USE + EAR_CRC32_TAG.len();
+    ear[EAR_APPLICATION_USE..tag_end].copy_from_slice(EAR_CRC32_TAG);
+    ear[tag_end..tag_end + 4].copy_from_slice(&crc.to_be_bytes());
+    ear
+}
+
+/// Length of the CD-ROM XA System Use field.
+pub const XA_FIELD_LEN: usize = 14;
+const XA_ATTR_FORM1: u16 = 0x0800;
+const XA_ATTR_DIRECTORY: u16 = 0x8000;
+/// Owner/group/world read and execute.
+const XA_ATTR_PERMISSIONS: u16 = 0x0555;
+
+/// Builds the CD-ROM XA System Use field: owner group and user 0,
+/// read/execute permissions, Mode 2 Form 1 data, file number 0.
+pub fn xa_field(is_dir: bool) -> [u8; XA_FIELD_LEN] {
+    let mut attrs = XA_ATTR_PERMISSIONS | XA_ATTR_FORM1;
+    if is_dir {
+        attrs |= XA_ATTR_DIRECTORY;
+    }
+    let mut f = [0u8; XA_FIELD_LEN];
+    f[4..6].copy_from_slice(&attrs.to_be_bytes());
+    f[6..8].copy_from_slice(b"XA");
+    f
+}
+
 /// ISO9660 directory record structure
 pub struct IsoDirEntry<'a> {
     pub lba: u32,
@@ -9,39 +181,87 @@ pub struct IsoDirEntry<'a> {
 }
 
 impl<'a> IsoDirEntry<'a> {
-    /// Creates ISO9660 directory record bytes
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let (file_id, file_id_len) = match self.name {
-            "." => (vec![0x00], 1),
-            ".." => (vec![0x01], 1),
-            _ => {
-                let name_str = if self.flags & 0x02 != 0 {
-                    self.name.to_uppercase()
-                } else {
-                    format!("{};1", self.name.to_uppercase())
-                };
-                let bytes = name_str.into_bytes();
-                let len = bytes.len();
-                (bytes, len)
-            }
+    /// Creates ISO9660 directory record bytes.  Fails with `InvalidInput`
+    /// if the record would exceed 255 bytes.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        self.to_bytes_with(&DirRecordOptions::default())
+    }
+
+    /// Length in bytes of the record [`Self::to_bytes_with_system_use`]
+    /// produces for `opts` and `system_use_len` bytes of System Use (the XA
+    /// field included), padding included, without building it.  Useful for
+    /// planning how records fill directory sectors.  Does not check the
+    /// 255-byte limit.
+    pub fn encoded_len(&self, opts: &DirRecordOptions, system_use_len: usize) -> usize {
+        let file_id_len = self.file_identifier(opts).len();
+        record_layout(file_id_len, system_use_len).1
+    }
+
+    /// The file identifier bytes for `opts`, without length checks.
+    fn file_identifier(&self, opts: &DirRecordOptions) -> Vec<u8> {
+        let is_dir = self.flags & 0x02 != 0;
+        let name = match self.name {
+            "." => return vec![0x00],
+            ".." => return vec![0x01],
+            name if opts.relaxed_names => name.to_string(),
+            name => transliterate_name(name, is_dir, opts.replacement),
         };
+        match opts.file_version {
+            Some(v) if !is_dir => format!("{name};{v}").into_bytes(),
+            _ => name.into_bytes(),
+        }
+    }
+
+    /// Creates ISO9660 directory record bytes using the given options.  With
+    /// `ear_checksums`, file records declare a one-block EAR and `lba` must
+    /// point at it.  Fails with `InvalidInput` if the record would exceed
+    /// 255 bytes.
+    pub fn to_bytes_with(&self, opts: &DirRecordOptions) -> io::Result<Vec<u8>> {
+        self.to_bytes_with_system_use(opts, &[])
+    }
 
-        let mut record_len = 33 + file_id_len;
-        if record_len % 2 != 0 {
-            record_len += 1;
+    /// Like [`Self::to_bytes_with`], appending `system_use` (e.g. SUSP
+    /// entries) after the file identifier.  Fails if the record would
+    /// exceed 255 bytes.
+    pub fn to_bytes_with_system_use(
+        &self,
+        opts: &DirRecordOptions,
+        system_use: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        if opts.relaxed_names
+            && !matches!(self.name, "." | "..")
+            && self.name.len() > MAX_RELAXED_NAME_LEN
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "'{}' exceeds {MAX_RELAXED_NAME_LEN} bytes ({})",
+                    self.name,
+                    self.name.len()
+                ),
+            ));
+        }
+        let file_id = self.file_identifier(opts);
+        let file_id_len = file_id.len();
+        let (su_offset, record_len) = record_layout(file_id_len, system_use.len());
+        if record_len > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Directory record for '{}' exceeds 255 bytes ({record_len})",
+                    self.name
+                ),
+            ));
         }
-        assert!(
-            record_len <= u8::MAX as usize,
-            "Directory record length exceeds 255 bytes"
-        );
         let mut record = vec![0u8; record_len];
         record[0] = record_len as u8;
-        // record[1] is extended attribute record length, 0
+        // Extended attribute record length in logical blocks
+        record[1] = u8::from(opts.ear_checksums && self.flags & 0x02 == 0);
         record[2..6].copy_from_slice(&self.lba.to_le_bytes());
         record[6..10].copy_from_slice(&self.lba.to_be_bytes());
         record[10..14].copy_from_slice(&self.size.to_le_bytes());
         record[14..18].copy_from_slice(&self.size.to_be_bytes());
-        // bytes 18-24 are timestamp, leave as 0
+        record[18..25].copy_from_slice(&opts.recorded);
         record[25] = self.flags;
         // record[26] is file unit size, 0
         // record[27] is interleave gap size, 0
@@ -49,16 +269,140 @@ impl<'a> IsoDirEntry<'a> {
         record[30..32].copy_from_slice(&1u16.to_be_bytes()); // Volume sequence number BE
         record[32] = file_id_len as u8;
         record[33..33 + file_id_len].copy_from_slice(&file_id);
-        // The final byte is for padding if needed, and is already 0 from vec initialization.
+        // Padding bytes are already 0 from vec initialization.
+        record[su_offset..su_offset + system_use.len()].copy_from_slice(system_use);
 
-        record
+        Ok(record)
     }
 }
 
+/// System Use offset and total length of a directory record: the file
+/// identifier is padded to an even offset, and so is the record.
+fn record_layout(file_id_len: usize, system_use_len: usize) -> (usize, usize) {
+    let su_offset = (33 + file_id_len).next_multiple_of(2);
+    (su_offset, (su_offset + system_use_len).next_multiple_of(2))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_encoded_len() {
+        let long = "a_rather_long_file_name_with_many_characters.tar.gz";
+        for (name, flags) in [
+            (".", 0x02),
+            ("..", 0x02),
+            ("a", 0),
+            ("ab", 0),
+            ("file.txt", 0),
+            ("dir", 0x02),
+            ("boot", 0x02),
+            ("ünïcode.bin", 0),
+            (long, 0),
+            (long, 0x02),
+        ] {
+            let entry = IsoDirEntry {
+                lba: 20,
+                size: 2048,
+                flags,
+                name,
+            };
+            assert_eq!(
+                entry.encoded_len(&DirRecordOptions::default(), 0),
+                entry.to_bytes().unwrap().len(),
+                "{name}"
+            );
+        }
+
+        let option_sets = [
+            DirRecordOptions {
+                file_version: None,
+                replacement: '-',
+                ..Default::default()
+            },
+            DirRecordOptions {
+                file_version: Some(32767),
+                cd_xa: true,
+                ear_checksums: true,
+                ..Default::default()
+            },
+            DirRecordOptions {
+                relaxed_names: true,
+                ..Default::default()
+            },
+            DirRecordOptions {
+                relaxed_names: true,
+                file_version: None,
+                ..Default::default()
+            },
+        ];
+        for opts in &option_sets {
+            for (name, flags) in [
+                (".", 0x02),
+                ("..", 0x02),
+                ("a", 0),
+                ("Mixed Case.txt", 0),
+                ("ünïcode.bin", 0),
+                ("dir", 0x02),
+            ] {
+                let entry = IsoDirEntry {
+                    lba: 20,
+                    size: 2048,
+                    flags,
+                    name,
+                };
+                for su_len in [0, 7, XA_FIELD_LEN] {
+                    let su = vec![0xAA; su_len];
+                    assert_eq!(
+                        entry.encoded_len(opts, su_len),
+                        entry.to_bytes_with_system_use(opts, &su).unwrap().len(),
+                        "{name} {opts:?} {su_len}"
+                    );
+                }
+            }
+        }
+        let entry = IsoDirEntry {
+            lba: 20,
+            size: 2048,
+            flags: 0,
+            name: "file.txt",
+        };
+        assert_eq!(
+            entry.encoded_len(&option_sets[0], 0),
+            entry.to_bytes_with(&option_sets[0]).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_recording_date() {
+        use std::time::Duration;
+        let at = |secs: u64| recording_date(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), [70, 1, 1, 0, 0, 0, 0]);
+        // 2000-02-29 12:34:56
+        assert_eq!(at(951_827_696), [100, 2, 29, 12, 34, 56, 0]);
+        // 2024-12-31 23:59:59
+        assert_eq!(at(1_735_689_599), [124, 12, 31, 23, 59, 59, 0]);
+        assert_eq!(
+            recording_date(UNIX_EPOCH - Duration::from_secs(1)),
+            [69, 12, 31, 23, 59, 59, 0]
+        );
+        assert_eq!(at(6_000_000_000), [0; 7]);
+
+        let opts = DirRecordOptions {
+            recorded: at(951_827_696),
+            ..Default::default()
+        };
+        let entry = IsoDirEntry {
+            lba: 20,
+            size: 1,
+            flags: 0,
+            name: "a",
+        };
+        assert_eq!(&entry.to_bytes_with(&opts).unwrap()[18..25], &opts.recorded);
+        assert_eq!(&entry.to_bytes().unwrap()[18..25], &[0; 7]);
+    }
+
     #[test]
     fn test_file_record() {
         let entry = IsoDirEntry {
@@ -67,7 +411,7 @@ mod tests {
             flags: 0,
             name: "file.txt",
         };
-        let bytes = entry.to_bytes();
+        let bytes = entry.to_bytes().unwrap();
 
         // Length: 33 + "FILE.TXT;1".len() (10) = 43, padded to 44
         assert_eq!(bytes.len(), 44);
@@ -82,6 +426,105 @@ mod tests {
         assert_eq!(bytes[43], 0); // Padding
     }
 
+    #[test]
+    fn test_file_record_without_version() {
+        let entry = IsoDirEntry {
+            lba: 123,
+            size: 456,
+            flags: 0,
+            name: "file.txt",
+        };
+        let bytes = entry
+            .to_bytes_with(&DirRecordOptions {
+                file_version: None,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(bytes[32], 8);
+        assert_eq!(&bytes[33..41], b"FILE.TXT");
+    }
+
+    #[test]
+    fn test_relaxed_names() {
+        let opts = DirRecordOptions {
+            file_version: None,
+            relaxed_names: true,
+            ..Default::default()
+        };
+        let name = "Mixed Case-name.tar.gz";
+        let entry = IsoDirEntry {
+            lba: 5,
+            size: 1,
+            flags: 0,
+            name,
+        };
+        let bytes = entry.to_bytes_with(&opts).unwrap();
+        assert_eq!(bytes[32] as usize, name.len());
+        assert_eq!(&bytes[33..33 + name.len()], name.as_bytes());
+
+        let long = "x".repeat(MAX_RELAXED_NAME_LEN + 1);
+        let entry = IsoDirEntry {
+            name: &long,
+            ..entry
+        };
+        let err = entry.to_bytes_with_system_use(&opts, &[]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_file_record_custom_version() {
+        let entry = IsoDirEntry {
+            lba: 123,
+            size: 456,
+            flags: 0,
+            name: "file.txt",
+        };
+        let bytes = entry
+            .to_bytes_with(&DirRecordOptions {
+                file_version: Some(3),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(bytes[32], 10);
+        assert_eq!(&bytes[33..43], b"FILE.TXT;3");
+    }
+
+    #[test]
+    fn test_record_with_system_use() -> io::Result<()> {
+        let entry = IsoDirEntry {
+            lba: 1,
+            size: 0,
+            flags: 0,
+            name: "ab",
+        };
+        let opts = DirRecordOptions::default();
+        // "AB;1" is 4 bytes: one padding byte, then 3 bytes rounded up to 4.
+        let bytes = entry.to_bytes_with_system_use(&opts, b"XYZ")?;
+        assert_eq!(bytes.len(), 42);
+        assert_eq!(bytes[0], 42);
+        assert_eq!(&bytes[38..41], b"XYZ");
+        assert!(entry.to_bytes_with_system_use(&opts, &[0; 250]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_transliterate_name() {
+        assert_eq!(
+            transliterate_name("my-file!.txt", false, '_'),
+            "MY_FILE_.TXT"
+        );
+        assert_eq!(transliterate_name("a.b.tar.gz", false, '_'), "A_B_TAR.GZ");
+        assert_eq!(transliterate_name("v1.0-dir", true, '_'), "V1_0_DIR");
+        assert_eq!(transliterate_name("café.efi", false, 'X'), "CAFX.EFI");
+        let entry = IsoDirEntry {
+            lba: 1,
+            size: 1,
+            flags: 0,
+            name: "a-b.txt",
+        };
+        assert_eq!(&entry.to_bytes().unwrap()[33..42], b"A_B.TXT;1");
+    }
+
     #[test]
     fn test_directory_record() {
         let entry = IsoDirEntry {
@@ -90,7 +533,7 @@ mod tests {
             flags: 0x02, // Directory flag
             name: "mydir",
         };
-        let bytes = entry.to_bytes();
+        let bytes = entry.to_bytes().unwrap();
 
         // Length: 33 + "MYDIR".len() (5) = 38
         assert_eq!(bytes.len(), 38);
@@ -108,7 +551,7 @@ mod tests {
             flags: 0x02,
             name: ".",
         };
-        let bytes = entry.to_bytes();
+        let bytes = entry.to_bytes().unwrap();
 
         // Length: 33 + 1 = 34
         assert_eq!(bytes.len(), 34);
@@ -125,7 +568,7 @@ mod tests {
             flags: 0x02,
             name: "..",
         };
-        let bytes = entry.to_bytes();
+        let bytes = entry.to_bytes().unwrap();
 
         // Length: 33 + 1 = 34
         assert_eq!(bytes.len(), 34);
@@ -0,0 +1,60 @@
+// isobemak/src/iso/discinfo.rs
+
+use std::io;
+
+/// Build metadata for installers that look for a `.discinfo` file at the
+/// root of the disc: the build timestamp, a release description and the
+/// architecture, one per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiscInfo {
+    /// Build time in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub arch: String,
+    pub description: String,
+}
+
+impl DiscInfo {
+    /// Path of the generated file inside the ISO.
+    pub const PATH: &'static str = ".discinfo";
+
+    /// Renders the file, or fails with `InvalidInput` if a field is empty
+    /// or spans several lines.
+    pub fn render(&self) -> io::Result<String> {
+        for (field, value) in [("arch", &self.arch), ("description", &self.description)] {
+            if value.is_empty() || value.contains(['\n', '\r']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("disc info {field} must be a single non-empty line: {value:?}"),
+                ));
+            }
+        }
+        Ok(format!(
+            "{}\n{}\n{}\n",
+            self.timestamp, self.description, self.arch
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() -> io::Result<()> {
+        let mut info = DiscInfo {
+            timestamp: 1_700_000_000,
+            arch: "x86_64".into(),
+            description: "Example Linux 1.0".into(),
+        };
+        assert_eq!(info.render()?, "1700000000\nExample Linux 1.0\nx86_64\n");
+        info.description = "two\nlines".into();
+        assert_eq!(
+            info.render().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        info.description = "one".into();
+        info.arch.clear();
+        assert!(info.render().unwrap_err().to_string().contains("arch"));
+        Ok(())
+    }
+}
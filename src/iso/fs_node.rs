@@ -1,6 +1,11 @@
 use crate::utils::ISO_SECTOR_SIZE;
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 /// Represents a file within the ISO filesystem.
 #[derive(Clone, Debug)]
@@ -8,6 +13,79 @@ pub struct IsoFile {
     pub path: PathBuf,
     pub size: u64,
     pub lba: u32,
+    /// Zero-filled sectors reserved after the file's data.  Not included in
+    /// `size` or the directory record.
+    pub padding_sectors: u32,
+    /// Zero bytes appended to the source's data and counted in `size`,
+    /// e.g. to pad a floppy emulation image to its full size.
+    pub zero_fill: u64,
+    /// Modification time of the source, recorded with
+    /// [`TimestampSource::FromSource`](crate::iso::dir_record::TimestampSource::FromSource).
+    pub modified: Option<SystemTime>,
+    /// Supplies the data instead of the file at `path`, which is then
+    /// empty.
+    pub reader: Option<ReaderSource>,
+    /// Recorded with mode `0o555` in a Rock Ridge `PX` entry, which is
+    /// written even without full Rock Ridge.
+    pub executable: bool,
+}
+
+impl IsoFile {
+    /// Opens the file's data: its reader if it has one, otherwise the file
+    /// at `path`.
+    pub fn open_source(&self) -> io::Result<Box<dyn Read + Send>> {
+        match &self.reader {
+            Some(reader) => reader.take(),
+            None => Ok(Box::new(File::open(&self.path)?)),
+        }
+    }
+
+    /// Names the source in error messages.
+    pub(crate) fn source_name(&self) -> String {
+        match &self.reader {
+            Some(_) => "reader".to_string(),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+/// A reader supplying a file's data, e.g. generated in memory or streamed
+/// from elsewhere.  It is read once, when the image is written; clones
+/// share it.
+#[derive(Clone)]
+pub struct ReaderSource(Arc<Mutex<Option<Box<dyn Read + Send>>>>);
+
+impl ReaderSource {
+    pub fn new(reader: Box<dyn Read + Send>) -> Self {
+        Self(Arc::new(Mutex::new(Some(reader))))
+    }
+
+    /// Takes the reader out, failing with `InvalidInput` if an earlier
+    /// build already consumed it.
+    fn take(&self) -> io::Result<Box<dyn Read + Send>> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::other("reader lock poisoned"))?
+            .take()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "reader already consumed by an earlier build",
+                )
+            })
+    }
+}
+
+impl fmt::Debug for ReaderSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReaderSource")
+    }
+}
+
+/// A symbolic link.  Without Rock Ridge it appears as an empty file.
+#[derive(Clone, Debug)]
+pub struct IsoSymlink {
+    pub target: String,
 }
 
 /// Represents a directory within the ISO filesystem.
@@ -33,10 +111,14 @@ impl IsoDirectory {
     }
 }
 
-/// A node in the ISO filesystem tree, either a file or a directory.
+/// A node in the ISO filesystem tree: a file, a directory or a symlink.
 pub enum IsoFsNode {
     File(IsoFile),
     Directory(IsoDirectory),
+    Symlink(IsoSymlink),
+    /// The El Torito boot catalog at the given LBA, listed as a one-sector
+    /// file (like `mkisofs -c`).  It has no data of its own in the tree.
+    BootCatalog(u32),
 }
 
 impl IsoFsNode {
@@ -45,6 +127,8 @@ impl IsoFsNode {
         match self {
             IsoFsNode::File(file) => file.lba,
             IsoFsNode::Directory(dir) => dir.lba,
+            IsoFsNode::Symlink(_) => 0,
+            IsoFsNode::BootCatalog(lba) => *lba,
         }
     }
 
@@ -53,6 +137,8 @@ impl IsoFsNode {
         match self {
             IsoFsNode::File(file) => file.size,
             IsoFsNode::Directory(dir) => dir.size as u64,
+            IsoFsNode::Symlink(_) => 0,
+            IsoFsNode::BootCatalog(_) => ISO_SECTOR_SIZE as u64,
         }
     }
 }
@@ -4,6 +4,9 @@ use uuid::Uuid;
 
 use super::partition_entry::uuid_to_gpt_mixed_endian;
 
+/// Size of the defined GPT header fields; the rest of the sector is reserved.
+pub const GPT_HEADER_MIN_SIZE: usize = 92;
+
 // GPT Header structure (92 bytes of actual fields + 420 reserved = 512 total with packed repr)
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
@@ -31,14 +34,33 @@ impl GptHeader {
         partition_entry_lba: u64,
         num_partition_entries: u32,
         partition_entry_size: u32,
+    ) -> Self {
+        Self::new_with_sector_size(
+            total_lbas,
+            partition_entry_lba,
+            num_partition_entries,
+            partition_entry_size,
+            512,
+        )
+    }
+
+    /// Like [`Self::new`], for a disk whose logical sector is `sector_size`
+    /// bytes (e.g. 4096 on 4Kn media).  All LBAs are in that unit.
+    pub fn new_with_sector_size(
+        total_lbas: u64,
+        partition_entry_lba: u64,
+        num_partition_entries: u32,
+        partition_entry_size: u32,
+        sector_size: u64,
     ) -> Self {
         let disk_guid_uuid = Uuid::new_v4();
         let disk_guid_bytes = uuid_to_gpt_mixed_endian(&disk_guid_uuid);
 
-        // Calculate partition array size in 512-byte sectors.
-        // Example: 128 entries * 128 bytes = 16384 bytes → 32 sectors.
+        // Calculate partition array size in logical sectors.
+        // Example: 128 entries * 128 bytes = 16384 bytes → 32 sectors of
+        // 512 bytes, or 4 sectors of 4096 bytes.
         let partition_array_sectors =
-            ((num_partition_entries as u64) * (partition_entry_size as u64)).div_ceil(512);
+            ((num_partition_entries as u64) * (partition_entry_size as u64)).div_ceil(sector_size);
 
         // Usable LBA range for GPT partitions:
         // - MBR at LBA 0 (1 sector)
@@ -109,6 +131,70 @@ impl GptHeader {
         bytes
     }
 
+    /// Parses a header from the start of `bytes`, the header's logical
+    /// sector.  Fails with `UnexpectedEof` if `bytes` is shorter than the
+    /// header it declares and `InvalidData` on a bad signature, size or CRC.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < GPT_HEADER_MIN_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("GPT header truncated ({} bytes)", bytes.len()),
+            ));
+        }
+        if &bytes[..8] != b"EFI PART" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing GPT signature",
+            ));
+        }
+        let u32_at = |o: usize| u32::from_le_bytes(bytes[o..o + 4].try_into().unwrap());
+        let u64_at = |o: usize| u64::from_le_bytes(bytes[o..o + 8].try_into().unwrap());
+        let header_size = u32_at(12) as usize;
+        if !(GPT_HEADER_MIN_SIZE..=mem::size_of::<GptHeader>()).contains(&header_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid GPT header size {header_size}"),
+            ));
+        }
+        if bytes.len() < header_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "GPT header truncated ({} < {header_size} bytes)",
+                    bytes.len()
+                ),
+            ));
+        }
+        let mut crc_input = bytes[..header_size].to_vec();
+        crc_input[16..20].fill(0);
+        if crc32fast::hash(&crc_input) != u32_at(16) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "GPT header CRC mismatch",
+            ));
+        }
+        let mut reserved = [0u8; 420];
+        let tail = &bytes[GPT_HEADER_MIN_SIZE..bytes.len().min(mem::size_of::<GptHeader>())];
+        reserved[..tail.len()].copy_from_slice(tail);
+        Ok(GptHeader {
+            signature: *b"EFI PART",
+            revision: u32_at(8),
+            header_size: header_size as u32,
+            header_crc32: u32_at(16),
+            _reserved0: u32_at(20),
+            current_lba: u64_at(24),
+            backup_lba: u64_at(32),
+            first_usable_lba: u64_at(40),
+            last_usable_lba: u64_at(48),
+            disk_guid: bytes[56..72].try_into().unwrap(),
+            partition_entry_lba: u64_at(72),
+            num_partition_entries: u32_at(80),
+            partition_entry_size: u32_at(84),
+            partition_array_crc32: u32_at(88),
+            _reserved1: reserved,
+        })
+    }
+
     pub fn write_to<W: Write + Seek>(&self, writer: &mut W) -> io::Result<()> {
         let header_bytes = self.to_bytes();
         writer.write_all(&header_bytes)?;
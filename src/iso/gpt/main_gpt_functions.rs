@@ -1,7 +1,9 @@
 use crate::iso::gpt::header::GptHeader;
-use crate::iso::gpt::partition_entry::GptPartitionEntry;
+use crate::iso::gpt::partition_entry::{GptPartitionEntry, uuid_to_gpt_mixed_endian};
+use crate::iso::mbr::create_mbr_for_gpt_hybrid;
 use crc32fast::Hasher;
-use std::io::{self, Seek, SeekFrom, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use uuid::Uuid;
 
 fn crc_header(h: &mut GptHeader) -> u32 {
     h.header_crc32 = 0;
@@ -11,64 +13,66 @@ fn crc_header(h: &mut GptHeader) -> u32 {
     hasher.finalize()
 }
 
-fn crc_parts(parts: &[GptPartitionEntry], n: u32, es: u32) -> u32 {
+/// Serialises `parts` into a zero-padded array of `n` entries of `es` bytes,
+/// exactly as stored on disk.
+fn partition_array(parts: &[GptPartitionEntry], n: u32, es: u32) -> Vec<u8> {
     let mut arr = vec![0u8; (n * es) as usize];
-    let mut off = 0;
-    for p in parts {
-        let pb = p.to_bytes();
-        arr[off..off + pb.len()].copy_from_slice(&pb);
-        off += pb.len();
+    for (slot, p) in arr.chunks_exact_mut(es as usize).zip(parts) {
+        slot.copy_from_slice(&p.to_bytes());
     }
+    arr
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
     let mut hasher = Hasher::new();
-    hasher.update(&arr);
+    hasher.update(bytes);
     hasher.finalize()
 }
 
 fn write_primary<W: Write + Seek>(
     w: &mut W,
     h: &GptHeader,
-    parts: &[GptPartitionEntry],
-    n: u32,
-    es: u32,
+    array: &[u8],
     alba: u64,
+    ss: u64,
 ) -> io::Result<()> {
-    w.seek(SeekFrom::Start(512))?;
+    w.seek(SeekFrom::Start(ss))?;
+    write_header_sector(w, h, ss)?;
+    w.seek(SeekFrom::Start(alba * ss))?;
+    w.write_all(array)
+}
+
+/// Writes `h` followed by zeros up to the end of its logical sector.
+fn write_header_sector<W: Write + Seek>(w: &mut W, h: &GptHeader, ss: u64) -> io::Result<()> {
     h.write_to(w)?;
-    w.seek(SeekFrom::Start(alba * 512))?;
-    for p in parts {
-        p.write_to(w)?;
-    }
-    let zero = vec![0u8; es as usize];
-    for _ in parts.len()..n as usize {
-        w.write_all(&zero)?;
-    }
+    let pad = ss.saturating_sub(std::mem::size_of::<GptHeader>() as u64);
+    io::copy(&mut io::repeat(0).take(pad), w)?;
     Ok(())
 }
 
+/// Writes the backup array just before the last LBA and the backup header
+/// in it.  The header's array CRC is computed from the backup array bytes
+/// rather than copied from the primary header.
 fn write_backup<W: Write + Seek>(
     w: &mut W,
     h: &GptHeader,
-    parts: &[GptPartitionEntry],
-    n: u32,
-    es: u32,
+    array: &[u8],
     total: u64,
+    ss: u64,
 ) -> io::Result<()> {
-    let arr_sectors = ((n as u64) * (es as u64)).div_ceil(512);
+    let arr_sectors = (array.len() as u64).div_ceil(ss);
+    let arr_lba = total.saturating_sub(1).saturating_sub(arr_sectors);
+    w.seek(SeekFrom::Start(arr_lba * ss))?;
+    w.write_all(array)?;
+
     let mut bh = *h;
     bh.current_lba = total - 1;
     bh.backup_lba = 1;
-    bh.partition_entry_lba = total.saturating_sub(1).saturating_sub(arr_sectors);
+    bh.partition_entry_lba = arr_lba;
+    bh.partition_array_crc32 = crc32(array);
     bh.header_crc32 = crc_header(&mut bh);
-    w.seek(SeekFrom::Start((total - 1) * 512))?;
-    bh.write_to(w)?;
-    w.seek(SeekFrom::Start((total - 1 - arr_sectors) * 512))?;
-    for p in parts {
-        p.write_to(w)?;
-    }
-    for _ in parts.len()..n as usize {
-        w.write_all(&vec![0u8; es as usize])?;
-    }
-    Ok(())
+    w.seek(SeekFrom::Start((total - 1) * ss))?;
+    write_header_sector(w, &bh, ss)
 }
 
 pub fn write_gpt_structures<W: Write + Seek>(
@@ -76,14 +80,283 @@ pub fn write_gpt_structures<W: Write + Seek>(
     total_lbas: u64,
     partitions: &[GptPartitionEntry],
 ) -> io::Result<()> {
-    let n: u32 = 128;
+    write_gpt_structures_with(w, total_lbas, partitions, &GptParams::default())
+}
+
+/// Like [`write_gpt_structures`], with `disk_guid` in both headers instead
+/// of a random one, e.g. for reproducible images.  Fails with
+/// `InvalidInput` on a malformed GUID.
+pub fn write_gpt_structures_with_disk_guid<W: Write + Seek>(
+    w: &mut W,
+    total_lbas: u64,
+    partitions: &[GptPartitionEntry],
+    disk_guid: &str,
+) -> io::Result<()> {
+    let params = GptParams {
+        disk_guid: Some(disk_guid.to_string()),
+        ..GptParams::default()
+    };
+    write_gpt_structures_with(w, total_lbas, partitions, &params)
+}
+
+/// Like [`write_gpt_structures`], with a partition array of `num_entries`
+/// entries instead of 128, e.g. fewer for compact images.  Fails with
+/// `InvalidInput` if the array would not fill whole 512-byte sectors (the
+/// count must be a multiple of 4) or cannot hold all `partitions`.
+pub fn write_gpt_structures_with_entry_count<W: Write + Seek>(
+    w: &mut W,
+    total_lbas: u64,
+    partitions: &[GptPartitionEntry],
+    num_entries: u32,
+) -> io::Result<()> {
+    let params = GptParams {
+        num_entries,
+        ..GptParams::default()
+    };
+    write_gpt_structures_with(w, total_lbas, partitions, &params)
+}
+
+/// Writes primary and backup GPT structures for a disk with `sector_size`
+/// byte logical sectors (512 or 4096 for 4Kn media).  `total_lbas` and the
+/// partition LBAs are in that unit.  Fails with `InvalidInput` if two
+/// partitions share a unique partition GUID.
+pub fn write_gpt_structures_with_sector_size<W: Write + Seek>(
+    w: &mut W,
+    total_lbas: u64,
+    partitions: &[GptPartitionEntry],
+    sector_size: u64,
+) -> io::Result<()> {
+    let params = GptParams {
+        sector_size,
+        ..GptParams::default()
+    };
+    write_gpt_structures_with(w, total_lbas, partitions, &params)
+}
+
+/// How [`write_gpt_structures_with`] lays out the GPT around the
+/// partitions.  The defaults give [`write_gpt_structures`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptParams {
+    /// Logical sector size in bytes, 512 or 4096.
+    pub sector_size: u64,
+    /// Disk GUID written to both headers; random if `None`.
+    pub disk_guid: Option<String>,
+    /// Raises the headers' first usable LBA above the end of the primary
+    /// partition array.
+    pub first_usable_lba: Option<u64>,
+    /// Number of entries in the partition array.
+    pub num_entries: u32,
+}
+
+impl Default for GptParams {
+    fn default() -> Self {
+        Self {
+            sector_size: 512,
+            disk_guid: None,
+            first_usable_lba: None,
+            num_entries: 128,
+        }
+    }
+}
+
+/// Writes primary and backup GPT structures laid out by `params`, which
+/// combine the options of the other `write_gpt_structures_*` functions.
+/// `total_lbas` and the partition LBAs are in `params.sector_size` units.
+/// Fails with `InvalidInput`, before writing anything, on an unsupported
+/// sector size, a malformed disk GUID, an entry count that does not fill
+/// whole sectors or cannot hold `partitions`, or two partitions sharing a
+/// unique partition GUID.
+pub fn write_gpt_structures_with<W: Write + Seek>(
+    w: &mut W,
+    total_lbas: u64,
+    partitions: &[GptPartitionEntry],
+    params: &GptParams,
+) -> io::Result<()> {
+    let sector_size = params.sector_size;
+    if sector_size != 512 && sector_size != 4096 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported GPT sector size {sector_size} (expected 512 or 4096)"),
+        ));
+    }
+    let disk_guid = params
+        .disk_guid
+        .as_deref()
+        .map(|g| {
+            Uuid::parse_str(g).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid disk GUID '{g}': {e}"),
+                )
+            })
+        })
+        .transpose()?;
+    for (i, p) in partitions.iter().enumerate() {
+        if let Some(j) = partitions[..i]
+            .iter()
+            .position(|q| q.unique_partition_guid == p.unique_partition_guid)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("GPT partitions {j} and {i} share a unique partition GUID"),
+            ));
+        }
+    }
+    let n = params.num_entries;
     let es = std::mem::size_of::<GptPartitionEntry>() as u32;
+    if n == 0 || !(n as u64 * es as u64).is_multiple_of(sector_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "a GPT partition array of {n} entries does not fill whole {sector_size}-byte sectors"
+            ),
+        ));
+    }
+    if partitions.len() > n as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} partitions do not fit a GPT partition array of {n} entries",
+                partitions.len()
+            ),
+        ));
+    }
     let alba: u64 = 2;
-    let mut h = GptHeader::new(total_lbas, alba, n, es);
-    h.partition_array_crc32 = crc_parts(partitions, n, es);
+    let array = partition_array(partitions, n, es);
+    let mut h = GptHeader::new_with_sector_size(total_lbas, alba, n, es, sector_size);
+    if let Some(guid) = disk_guid {
+        h.disk_guid = uuid_to_gpt_mixed_endian(&guid);
+    }
+    if let Some(lba) = params.first_usable_lba {
+        h.first_usable_lba = h.first_usable_lba.max(lba);
+    }
+    h.partition_array_crc32 = crc32(&array);
     h.header_crc32 = crc_header(&mut h);
-    write_primary(w, &h, partitions, n, es, alba)?;
-    write_backup(w, &h, partitions, n, es, total_lbas)
+    write_primary(w, &h, &array, alba, sector_size)?;
+    write_backup(w, &h, &array, total_lbas, sector_size)
+}
+
+/// First LBA of the partition written by [`wrap_image`] (1 MiB).
+const WRAP_START_LBA: u64 = 2048;
+
+/// First LBA after the primary GPT (header and 32-sector array).
+const MIN_WRAP_START_LBA: u64 = 34;
+
+/// Writes a disk image holding `fs_image` (e.g. a FAT or ext filesystem) as
+/// its only partition, of type `partition_type_guid`: a protective MBR,
+/// the primary GPT, the partition from LBA 2048, zero-padded to whole
+/// 512-byte sectors, and the backup GPT right after it.  Everything up to
+/// the partition is zeroed.  Fails with `InvalidInput` on an empty image or
+/// a malformed GUID.
+pub fn wrap_image<W: Write + Seek>(
+    w: &mut W,
+    fs_image: &[u8],
+    partition_type_guid: &str,
+) -> io::Result<()> {
+    wrap_image_at(w, fs_image, partition_type_guid, WRAP_START_LBA)
+}
+
+/// Like [`wrap_image`], with the partition starting at `start_lba` (in
+/// 512-byte sectors), e.g. 2048 to align it to 1 MiB.  The gap between the
+/// primary partition array and the partition is zero-filled and both GPT
+/// headers give `start_lba` as the first usable LBA.  Fails with
+/// `InvalidInput` if `start_lba` overlaps the primary GPT.
+pub fn wrap_image_at<W: Write + Seek>(
+    w: &mut W,
+    fs_image: &[u8],
+    partition_type_guid: &str,
+    start_lba: u64,
+) -> io::Result<()> {
+    if start_lba < MIN_WRAP_START_LBA {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("partition start LBA {start_lba} overlaps the primary GPT"),
+        ));
+    }
+    if fs_image.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot wrap an empty filesystem image",
+        ));
+    }
+    uuid::Uuid::parse_str(partition_type_guid).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid partition type GUID '{partition_type_guid}': {e}"),
+        )
+    })?;
+    let sectors = (fs_image.len() as u64).div_ceil(512);
+    let end = start_lba + sectors - 1;
+    // Backup partition array (32 sectors) and header.
+    let total = end + 1 + 33;
+    let part = GptPartitionEntry::new(
+        partition_type_guid,
+        &uuid::Uuid::new_v4().to_string(),
+        start_lba,
+        end,
+        "",
+        0,
+    );
+
+    w.seek(SeekFrom::Start(0))?;
+    io::copy(&mut io::repeat(0).take(start_lba * 512), w)?;
+    let mbr =
+        create_mbr_for_gpt_hybrid(u32::try_from(total).unwrap_or(u32::MAX), true, None, None)?;
+    w.seek(SeekFrom::Start(0))?;
+    mbr.write_to(w)?;
+    w.seek(SeekFrom::Start(start_lba * 512))?;
+    w.write_all(fs_image)?;
+    let pad = sectors * 512 - fs_image.len() as u64;
+    io::copy(&mut io::repeat(0).take(pad), w)?;
+    let params = GptParams {
+        first_usable_lba: Some(start_lba),
+        ..GptParams::default()
+    };
+    write_gpt_structures_with(w, total, &[part], &params)
+}
+
+/// Reads the GPT header at `lba` of `disk` (in `sector_size` units) and its
+/// partition array, verifying both CRCs.  Unused (all-zero type GUID)
+/// entries are skipped.  Truncated or malformed input yields
+/// `UnexpectedEof` or `InvalidData`, never a panic.
+pub fn parse_gpt(
+    disk: &[u8],
+    lba: u64,
+    sector_size: u64,
+) -> io::Result<(GptHeader, Vec<GptPartitionEntry>)> {
+    let eof =
+        |what: &str| io::Error::new(io::ErrorKind::UnexpectedEof, format!("{what} truncated"));
+    let start = lba
+        .checked_mul(sector_size)
+        .and_then(|o| usize::try_from(o).ok())
+        .ok_or_else(|| eof("GPT header"))?;
+    let h = GptHeader::from_bytes(disk.get(start..).ok_or_else(|| eof("GPT header"))?)?;
+
+    let es = { h.partition_entry_size } as usize;
+    if es < std::mem::size_of::<GptPartitionEntry>() || !es.is_power_of_two() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid GPT partition entry size {es}"),
+        ));
+    }
+    let array = { h.partition_entry_lba }
+        .checked_mul(sector_size)
+        .and_then(|o| usize::try_from(o).ok())
+        .zip((es).checked_mul({ h.num_partition_entries } as usize))
+        .and_then(|(o, len)| disk.get(o..o.checked_add(len)?))
+        .ok_or_else(|| eof("GPT partition array"))?;
+    if crc32(array) != { h.partition_array_crc32 } {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "GPT partition array CRC mismatch",
+        ));
+    }
+    let parts = array
+        .chunks_exact(es)
+        .filter(|e| e[..16].iter().any(|&b| b != 0))
+        .map(GptPartitionEntry::from_bytes)
+        .collect::<io::Result<_>>()?;
+    Ok((h, parts))
 }
 
 #[cfg(test)]
@@ -94,8 +367,12 @@ mod tests {
     use std::io::Cursor;
     use std::mem;
 
-    fn read_struct<T: Copy>(s: &[u8], off: usize) -> T {
-        unsafe { (s[off..off + mem::size_of::<T>()].as_ptr() as *const T).read_unaligned() }
+    fn header_at(d: &[u8], off: usize) -> GptHeader {
+        GptHeader::from_bytes(&d[off..]).expect("valid GPT header")
+    }
+
+    fn entry_at(d: &[u8], off: usize) -> GptPartitionEntry {
+        GptPartitionEntry::from_bytes(&d[off..]).expect("valid GPT entry")
     }
 
     #[test]
@@ -130,7 +407,7 @@ mod tests {
         let mut disk = Cursor::new(vec![0; total as usize * 512usize]);
         let parts = vec![GptPartitionEntry::new(
             EFI_SYSTEM_PARTITION_GUID,
-            &"A2A0D0D0-039B-42A0-BA42-A0D0D0D0D0A0",
+            "A2A0D0D0-039B-42A0-BA42-A0D0D0D0D0A0",
             2048,
             4095,
             "Test",
@@ -139,7 +416,7 @@ mod tests {
         write_gpt_structures(&mut disk, total, &parts)?;
         let d = disk.into_inner();
 
-        let ph: GptHeader = read_struct(&d, 512);
+        let ph = header_at(&d, 512);
         assert_eq!(&ph.signature, b"EFI PART");
         assert_eq!({ ph.header_size } as usize, 92);
         let mut hb = ph.to_bytes();
@@ -154,16 +431,324 @@ mod tests {
         hh2.update(&d[arr_offset..arr_offset + arr_size]);
         assert_eq!({ ph.partition_array_crc32 }, hh2.finalize());
 
-        let bh: GptHeader = read_struct(&d, (total as usize - 1) * 512);
+        let bh = header_at(&d, (total as usize - 1) * 512);
         assert_eq!(&bh.signature, b"EFI PART");
         assert_eq!({ bh.current_lba }, total - 1);
         assert_eq!({ bh.backup_lba }, 1);
 
         let arr_sectors = (n as u64 * es as u64).div_ceil(512);
         let b_arr = (total as usize - 1 - arr_sectors as usize) * 512;
-        let be: GptPartitionEntry = read_struct(&d, b_arr);
+        let be = entry_at(&d, b_arr);
         assert_eq!({ be.starting_lba }, 2048);
         assert_eq!({ be.ending_lba }, 4095);
+
+        // The backup header's CRC must cover the backup array as written.
+        assert_eq!({ bh.partition_entry_lba } as usize * 512, b_arr);
+        let mut hh3 = Hasher::new();
+        hh3.update(&d[b_arr..b_arr + arr_size]);
+        assert_eq!({ bh.partition_array_crc32 }, hh3.finalize());
+        let mut bb = bh.to_bytes();
+        bb[16..20].copy_from_slice(&[0; 4]);
+        let mut hh4 = Hasher::new();
+        hh4.update(&bb[..92]);
+        assert_eq!({ bh.header_crc32 }, hh4.finalize());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_partition_guids() -> io::Result<()> {
+        let total = 8192u64;
+        let part = |start, guid: &str| {
+            GptPartitionEntry::new(EFI_SYSTEM_PARTITION_GUID, guid, start, start + 1023, "P", 0)
+        };
+        let guids: Vec<_> = (0..3).map(|_| uuid::Uuid::new_v4().to_string()).collect();
+        let parts: Vec<_> = [2048, 3072, 4096]
+            .into_iter()
+            .zip(&guids)
+            .map(|(start, guid)| part(start, guid))
+            .collect();
+        let mut disk = Cursor::new(vec![0; total as usize * 512]);
+        write_gpt_structures(&mut disk, total, &parts)?;
+        let (_, read) = parse_gpt(disk.get_ref(), 1, 512)?;
+        let read: Vec<_> = read.iter().map(|p| p.unique_partition_guid).collect();
+        assert_eq!(read.len(), 3);
+        assert!(read[0] != read[1] && read[1] != read[2] && read[0] != read[2]);
+
+        let mut dup = parts;
+        dup[2] = part(4096, &guids[0]);
+        let err = write_gpt_structures(&mut disk, total, &dup).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("partitions 0 and 2"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_gpt_4kn() -> io::Result<()> {
+        let total = 1024u64;
+        let ss = 4096u64;
+        let mut disk = Cursor::new(vec![0; (total * ss) as usize]);
+        let parts = vec![GptPartitionEntry::new(
+            EFI_SYSTEM_PARTITION_GUID,
+            "A2A0D0D0-039B-42A0-BA42-A0D0D0D0D0A0",
+            256,
+            511,
+            "Test",
+            0,
+        )];
+        write_gpt_structures_with_sector_size(&mut disk, total, &parts, ss)?;
+        let d = disk.into_inner();
+
+        let ph = header_at(&d, ss as usize);
+        assert_eq!(&ph.signature, b"EFI PART");
+        // 128 × 128-byte entries fill 4 sectors of 4 KiB.
+        assert_eq!({ ph.first_usable_lba }, 6);
+        assert_eq!({ ph.last_usable_lba }, total - 2 - 4);
+        let pe = entry_at(&d, 2 * ss as usize);
+        assert_eq!({ pe.starting_lba }, 256);
+
+        let bh = header_at(&d, ((total - 1) * ss) as usize);
+        assert_eq!(&bh.signature, b"EFI PART");
+        assert_eq!({ bh.current_lba }, total - 1);
+        assert_eq!({ bh.partition_entry_lba }, total - 1 - 4);
+        let be = entry_at(&d, ((total - 5) * ss) as usize);
+        assert_eq!({ be.starting_lba }, 256);
+
+        let mut sink = Cursor::new(Vec::new());
+        assert!(write_gpt_structures_with_sector_size(&mut sink, total, &parts, 1024).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_gpt_64_entries() -> io::Result<()> {
+        let total = 2048u64;
+        let mut disk = Cursor::new(vec![0xFFu8; total as usize * 512]);
+        let parts = vec![GptPartitionEntry::new(
+            EFI_SYSTEM_PARTITION_GUID,
+            "A2A0D0D0-039B-42A0-BA42-A0D0D0D0D0A0",
+            64,
+            1023,
+            "Test",
+            0,
+        )];
+        write_gpt_structures_with_entry_count(&mut disk, total, &parts, 64)?;
+        let d = disk.into_inner();
+
+        // 64 × 128-byte entries fill 16 sectors.
+        let array = &d[2 * 512..18 * 512];
+        assert!(array[128..].iter().all(|&b| b == 0));
+        let (ph, ph_parts) = parse_gpt(&d, 1, 512)?;
+        assert_eq!({ ph.num_partition_entries }, 64);
+        assert_eq!({ ph.partition_array_crc32 }, crc32(array));
+        assert_eq!({ ph.first_usable_lba }, 18);
+        assert_eq!({ ph.last_usable_lba }, total - 2 - 16);
+        assert_eq!({ ph_parts[0].starting_lba }, 64);
+
+        let (bh, _) = parse_gpt(&d, total - 1, 512)?;
+        assert_eq!({ bh.num_partition_entries }, 64);
+        assert_eq!({ bh.partition_entry_lba }, total - 1 - 16);
+        let backup = &d[(total as usize - 17) * 512..(total as usize - 1) * 512];
+        assert_eq!(backup, array);
+        // The sector before the backup array was left alone.
+        assert!(
+            d[(total as usize - 18) * 512..(total as usize - 17) * 512]
+                .iter()
+                .all(|&b| b == 0xFF)
+        );
+
+        let mut sink = Cursor::new(Vec::new());
+        for n in [0, 3, 66] {
+            let err =
+                write_gpt_structures_with_entry_count(&mut sink, total, &parts, n).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        let many: Vec<_> = (0..5u64)
+            .map(|i| {
+                let guid = format!("A2A0D0D0-039B-42A0-BA42-A0D0D0D0D0A{i}");
+                GptPartitionEntry::new(EFI_SYSTEM_PARTITION_GUID, &guid, 64, 127, "", 0)
+            })
+            .collect();
+        let err = write_gpt_structures_with_entry_count(&mut sink, total, &many, 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_gpt_combined_params() -> io::Result<()> {
+        let total = 1024u64;
+        let ss = 4096u64;
+        let disk_guid = "0F1E2D3C-4B5A-6978-8796-A5B4C3D2E1F0";
+        let params = GptParams {
+            sector_size: ss,
+            disk_guid: Some(disk_guid.into()),
+            num_entries: 32,
+            ..GptParams::default()
+        };
+        let mut disk = Cursor::new(vec![0; (total * ss) as usize]);
+        let parts = vec![GptPartitionEntry::new(
+            EFI_SYSTEM_PARTITION_GUID,
+            "A2A0D0D0-039B-42A0-BA42-A0D0D0D0D0A0",
+            256,
+            511,
+            "Test",
+            0,
+        )];
+        write_gpt_structures_with(&mut disk, total, &parts, &params)?;
+        let d = disk.into_inner();
+
+        let guid = uuid_to_gpt_mixed_endian(&Uuid::parse_str(disk_guid).unwrap());
+        for lba in [1, total - 1] {
+            let (h, p) = parse_gpt(&d, lba, ss)?;
+            assert_eq!({ h.disk_guid }, guid);
+            assert_eq!({ h.num_partition_entries }, 32);
+            // 32 × 128-byte entries fill one 4 KiB sector.
+            assert_eq!({ h.first_usable_lba }, 3);
+            assert_eq!({ h.last_usable_lba }, total - 3);
+            assert_eq!({ p[0].starting_lba }, 256);
+        }
+
+        let mut sink = Cursor::new(Vec::new());
+        let bad = GptParams {
+            disk_guid: Some("not-a-guid".into()),
+            ..params
+        };
+        let err = write_gpt_structures_with(&mut sink, total, &parts, &bad).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(sink.get_ref().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_gpt_rejects_bad_input() -> io::Result<()> {
+        let total = 256u64;
+        let mut disk = Cursor::new(vec![0; total as usize * 512]);
+        let parts = vec![GptPartitionEntry::new(
+            EFI_SYSTEM_PARTITION_GUID,
+            "A2A0D0D0-039B-42A0-BA42-A0D0D0D0D0A0",
+            64,
+            127,
+            "ESP",
+            0,
+        )];
+        write_gpt_structures(&mut disk, total, &parts)?;
+        let d = disk.into_inner();
+        let (h, found) = parse_gpt(&d, 1, 512)?;
+        assert_eq!({ h.backup_lba }, total - 1);
+        assert_eq!(found.len(), 1);
+        assert_eq!({ found[0].starting_lba }, 64);
+        assert_eq!(parse_gpt(&d, total - 1, 512)?.1.len(), 1);
+
+        // Every truncation point fails cleanly.
+        for len in (0..3 * 512).chain((3 * 512..34 * 512).step_by(97)) {
+            let err = parse_gpt(&d[..len], 1, 512).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "len {len}");
+        }
+        // Corrupted bytes in the header or array, and pure garbage.
+        let mut seed = 0x2545_F491u32;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for _ in 0..500 {
+            let mut bad = d[..34 * 512].to_vec();
+            // Any bit of the CRC-covered header fields or the array.
+            let r = next() as usize % (92 + 32 * 512);
+            let off = if r < 92 { 512 + r } else { 1024 + r - 92 };
+            bad[off] ^= 1 << (next() % 8);
+            assert!(parse_gpt(&bad, 1, 512).is_err(), "flip at {off}");
+            let junk: Vec<u8> = (0..next() % 4096).map(|_| next() as u8).collect();
+            assert!(parse_gpt(&junk, (next() % 4) as u64, 512).is_err());
+        }
+        assert!(parse_gpt(&d, u64::MAX, 512).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrap_image() -> io::Result<()> {
+        let image: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        let mut disk = Cursor::new(vec![0xFFu8; 4 * 1024 * 1024]);
+        wrap_image(&mut disk, &image, EFI_SYSTEM_PARTITION_GUID)?;
+        let d = disk.into_inner();
+        // 10 000 bytes take 20 sectors; the backup GPT takes 33 more.
+        let total = 2048 + 20 + 33;
+
+        assert_eq!(&d[510..512], &[0x55, 0xAA]);
+        assert_eq!(d[446 + 4], 0xEE);
+        assert_eq!(
+            u32::from_le_bytes(d[446 + 12..446 + 16].try_into().unwrap()),
+            total as u32 - 1
+        );
+
+        let (ph, parts) = parse_gpt(&d, 1, 512)?;
+        assert_eq!({ ph.backup_lba }, total - 1);
+        assert_eq!(parts.len(), 1);
+        let p = parts[0];
+        assert_eq!({ p.starting_lba }, 2048);
+        assert_eq!({ p.ending_lba }, 2048 + 20 - 1);
+        assert!({ p.ending_lba } <= { ph.last_usable_lba });
+        assert_eq!(
+            p.partition_type_guid,
+            GptPartitionEntry::new(
+                EFI_SYSTEM_PARTITION_GUID,
+                &uuid::Uuid::nil().to_string(),
+                0,
+                0,
+                "",
+                0
+            )
+            .partition_type_guid
+        );
+        let (bh, backup_parts) = parse_gpt(&d, total - 1, 512)?;
+        assert_eq!({ bh.current_lba }, total - 1);
+        assert_eq!({ backup_parts[0].starting_lba }, 2048);
+
+        let start = 2048 * 512;
+        assert_eq!(&d[start..start + image.len()], &image[..]);
+        assert!(
+            d[start + image.len()..start + 20 * 512]
+                .iter()
+                .all(|&b| b == 0)
+        );
+        assert!(d[34 * 512..start].iter().all(|&b| b == 0));
+
+        let mut sink = Cursor::new(Vec::new());
+        for (img, guid) in [
+            (&[][..], EFI_SYSTEM_PARTITION_GUID),
+            (&image[..], "not-a-guid"),
+        ] {
+            let err = wrap_image(&mut sink, img, guid).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrap_image_at_aligned_start() -> io::Result<()> {
+        let image = vec![0xA5u8; 4096];
+        let mut disk = Cursor::new(vec![0xFFu8; 2 * 1024 * 1024]);
+        wrap_image_at(&mut disk, &image, EFI_SYSTEM_PARTITION_GUID, 2048)?;
+        let d = disk.into_inner();
+        let total = 2048 + 8 + 33;
+
+        assert!(d[34 * 512..2048 * 512].iter().all(|&b| b == 0));
+        assert_eq!(&d[2048 * 512..2048 * 512 + image.len()], &image[..]);
+        for lba in [1, total - 1] {
+            let (h, parts) = parse_gpt(&d, lba, 512)?;
+            assert_eq!({ h.first_usable_lba }, 2048);
+            assert_eq!({ h.last_usable_lba }, total - 34);
+            assert_eq!({ parts[0].starting_lba }, { h.first_usable_lba });
+            assert!({ parts[0].ending_lba } <= { h.last_usable_lba });
+        }
+
+        // Right after the primary partition array: no gap to fill.
+        let mut disk = Cursor::new(Vec::new());
+        wrap_image_at(&mut disk, &image, EFI_SYSTEM_PARTITION_GUID, 34)?;
+        let (h, _) = parse_gpt(disk.get_ref(), 1, 512)?;
+        assert_eq!({ h.first_usable_lba }, 34);
+
+        let err = wrap_image_at(&mut disk, &image, EFI_SYSTEM_PARTITION_GUID, 33).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
         Ok(())
     }
 }
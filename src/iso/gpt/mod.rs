@@ -1,3 +1,5 @@
 pub mod header;
 pub mod main_gpt_functions;
 pub mod partition_entry;
+
+pub use main_gpt_functions::{wrap_image, wrap_image_at};
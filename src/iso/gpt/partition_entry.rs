@@ -3,6 +3,27 @@ use std::mem;
 use uuid::Uuid;
 
 pub const EFI_SYSTEM_PARTITION_GUID: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+/// Partition type GUID of a Linux filesystem.
+pub const LINUX_FILESYSTEM_GUID: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+
+/// Attribute bit 0: the platform requires the partition to function.
+pub const GPT_ATTR_REQUIRED: u64 = 1 << 0;
+/// Attribute bit 2: legacy BIOS bootable.
+pub const GPT_ATTR_LEGACY_BIOS_BOOTABLE: u64 = 1 << 2;
+/// Attribute bit 60 (Microsoft basic data): read-only.
+pub const GPT_ATTR_READ_ONLY: u64 = 1 << 60;
+/// Attribute bit 62 (Microsoft basic data): hidden.
+pub const GPT_ATTR_HIDDEN: u64 = 1 << 62;
+/// Attribute bit 63 (Microsoft basic data): no drive letter is assigned, so
+/// the partition is not mounted automatically.
+pub const GPT_ATTR_NO_AUTOMOUNT: u64 = 1 << 63;
+/// Shift of the ESP's CRC32 within its attributes with
+/// [`IsoBuilder::set_esp_integrity_check`](crate::IsoBuilder::set_esp_integrity_check):
+/// bits 16-47, which UEFI reserves and no defined or type-specific
+/// attribute uses.  Not a standard field.
+pub const GPT_ATTR_ESP_CRC32_SHIFT: u32 = 16;
+/// Mask of the bits holding the ESP's CRC32 (see [`GPT_ATTR_ESP_CRC32_SHIFT`]).
+pub const GPT_ATTR_ESP_CRC32_MASK: u64 = (u32::MAX as u64) << GPT_ATTR_ESP_CRC32_SHIFT;
 
 // GPT Partition Entry structure
 #[repr(C, packed)]
@@ -69,6 +90,30 @@ impl GptPartitionEntry {
         bytes
     }
 
+    /// Parses an entry from the start of `bytes`.  Fails with
+    /// `UnexpectedEof` if fewer than 128 bytes are available.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < mem::size_of::<GptPartitionEntry>() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("GPT partition entry truncated ({} bytes)", bytes.len()),
+            ));
+        }
+        let u64_at = |o: usize| u64::from_le_bytes(bytes[o..o + 8].try_into().unwrap());
+        let mut partition_name = [0u16; 36];
+        for (i, c) in partition_name.iter_mut().enumerate() {
+            *c = u16::from_le_bytes([bytes[56 + 2 * i], bytes[57 + 2 * i]]);
+        }
+        Ok(GptPartitionEntry {
+            partition_type_guid: bytes[0..16].try_into().unwrap(),
+            unique_partition_guid: bytes[16..32].try_into().unwrap(),
+            starting_lba: u64_at(32),
+            ending_lba: u64_at(40),
+            attributes: u64_at(48),
+            partition_name,
+        })
+    }
+
     pub fn write_to<W: Write + Seek>(&self, writer: &mut W) -> io::Result<()> {
         let partition_bytes = self.to_bytes();
         writer.write_all(&partition_bytes)?;
@@ -95,3 +140,28 @@ pub(crate) fn uuid_to_gpt_mixed_endian(uuid: &Uuid) -> [u8; 16] {
     bytes[8..16].copy_from_slice(rest);
     bytes
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attribute_bits_round_trip() -> io::Result<()> {
+        let attrs = GPT_ATTR_REQUIRED | GPT_ATTR_HIDDEN | GPT_ATTR_NO_AUTOMOUNT;
+        let e = GptPartitionEntry::new(
+            EFI_SYSTEM_PARTITION_GUID,
+            "01234567-89AB-CDEF-0123-456789ABCDEF",
+            64,
+            127,
+            "EFI System Partition",
+            attrs,
+        );
+        let bytes = e.to_bytes();
+        let raw = u64::from_le_bytes(bytes[48..56].try_into().unwrap());
+        assert_eq!(raw >> 63, 1);
+        assert_eq!(bytes[55], 0xC0);
+        assert_eq!(raw, attrs);
+        assert_eq!({ GptPartitionEntry::from_bytes(&bytes)?.attributes }, attrs);
+        Ok(())
+    }
+}
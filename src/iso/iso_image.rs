@@ -1,9 +1,14 @@
 use crate::iso::boot_info::BootInfo;
+use crate::iso::builder_utils::iso_path_components;
 use crate::iso::layout_profile::IsoLayoutProfile;
+use std::collections::HashSet;
+use std::io;
 use std::path::PathBuf; // Import BootInfo
 
 /// Configuration for a file to be added to the ISO.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct IsoImageFile {
     pub source: PathBuf,
     pub destination: String,
@@ -21,3 +26,113 @@ pub struct IsoImage {
     /// For QEMU/OVMF, use [IsoLayoutProfile::emulator] (GPT enabled).
     pub layout_profile: IsoLayoutProfile,
 }
+
+impl IsoImage {
+    /// Adds `other` on top of this image: its files are appended, and its
+    /// volume ID and BIOS and UEFI boot configurations replace these
+    /// where `other` has them.  The layout profile is kept.  Fails with
+    /// `AlreadyExists`, leaving `self` unchanged, if a destination of
+    /// `other` is already taken (paths are compared as the builder
+    /// resolves them, so `/a/b` and `a\b` collide).
+    pub fn merge(&mut self, other: IsoImage) -> io::Result<()> {
+        let normalize = |dest: &str| iso_path_components(dest).collect::<Vec<_>>().join("/");
+        let mut taken: HashSet<String> = self
+            .files
+            .iter()
+            .map(|f| normalize(&f.destination))
+            .collect();
+        for f in &other.files {
+            if !taken.insert(normalize(&f.destination)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("'{}' is already in the image", f.destination),
+                ));
+            }
+        }
+        self.files.extend(other.files);
+        if other.volume_id.is_some() {
+            self.volume_id = other.volume_id;
+        }
+        if other.boot_info.bios_boot.is_some() {
+            self.boot_info.bios_boot = other.boot_info.bios_boot;
+        }
+        if other.boot_info.uefi_boot.is_some() {
+            self.boot_info.uefi_boot = other.boot_info.uefi_boot;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iso::boot_info::{BiosBootInfo, UefiBootInfo};
+
+    fn file(source: &str, destination: &str) -> IsoImageFile {
+        IsoImageFile {
+            source: source.into(),
+            destination: destination.into(),
+        }
+    }
+
+    #[test]
+    fn test_merge() -> io::Result<()> {
+        let mut base = IsoImage {
+            volume_id: Some("BASE".into()),
+            files: vec![file("isolinux.cfg", "isolinux/isolinux.cfg")],
+            boot_info: BootInfo {
+                bios_boot: Some(BiosBootInfo {
+                    boot_image: "isolinux.bin".into(),
+                    destination_in_iso: "isolinux/isolinux.bin".into(),
+                    ..Default::default()
+                }),
+                uefi_boot: None,
+            },
+            layout_profile: IsoLayoutProfile::emulator(),
+        };
+        let overlay = IsoImage {
+            volume_id: None,
+            files: vec![
+                file("vmlinuz", "boot/vmlinuz"),
+                file("initrd", "boot/initrd"),
+            ],
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: Some(UefiBootInfo {
+                    boot_image: "BOOTX64.EFI".into(),
+                    destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                    ..Default::default()
+                }),
+            },
+            layout_profile: IsoLayoutProfile::hardware(),
+        };
+
+        base.merge(overlay.clone())?;
+        let dests: Vec<_> = base.files.iter().map(|f| f.destination.as_str()).collect();
+        assert_eq!(
+            dests,
+            ["isolinux/isolinux.cfg", "boot/vmlinuz", "boot/initrd"]
+        );
+        assert_eq!(base.volume_id.as_deref(), Some("BASE"));
+        let bios = base.boot_info.bios_boot.as_ref().unwrap();
+        assert_eq!(bios.destination_in_iso, "isolinux/isolinux.bin");
+        let uefi = base.boot_info.uefi_boot.as_ref().unwrap();
+        assert_eq!(uefi.destination_in_iso, "EFI/BOOT/BOOTX64.EFI");
+        assert_eq!(
+            base.layout_profile.hidden_sectors_mode,
+            IsoLayoutProfile::emulator().hidden_sectors_mode
+        );
+
+        // Conflicting destinations fail without changing the image.
+        let mut conflict = overlay;
+        conflict.files = vec![
+            file("other", "docs/readme"),
+            file("vmlinuz2", "/boot\\vmlinuz"),
+        ];
+        let err = base.merge(conflict).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(err.to_string().contains("vmlinuz"), "{err}");
+        assert_eq!(base.files.len(), 3);
+        Ok(())
+    }
+}
@@ -1,126 +1,782 @@
 use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::mpsc;
+use std::thread;
 
-use crate::iso::boot_catalog::{BootCatalogEntry, write_boot_catalog};
-use crate::iso::dir_record::IsoDirEntry;
-use crate::iso::fs_node::{IsoDirectory, IsoFsNode};
-use crate::iso::volume_descriptor::{update_total_sectors_in_pvd, write_volume_descriptors};
-use crate::utils::{ISO_SECTOR_SIZE, seek_to_lba};
+use crate::iso::boot_catalog::{BootCatalogEntry, render_boot_catalog_with_id};
+use crate::iso::builder_utils::{MAX_DIR_DEPTH, RR_MOVED, dir_count, files_in_lba_order};
+use crate::iso::dir_record::{
+    DirRecordOptions, IsoDirEntry, MAX_EXTENT_SIZE, TimestampSource, XA_FIELD_LEN, ear_with_crc32,
+    recording_date, xa_field,
+};
+use crate::iso::fs_node::{IsoDirectory, IsoFile, IsoFsNode};
+use crate::iso::rock_ridge;
+use crate::iso::volume_descriptor::{
+    PvdOptions, VolumeDescriptor, update_total_sectors_in_pvd, write_volume_descriptors,
+};
+use crate::utils::{ISO_SECTOR_SIZE, SectorWriter, seek_to_lba};
 
-/// Writes all ISO volume descriptors.
-pub fn write_descriptors(
-    iso_file: &mut File,
+/// Writes all ISO volume descriptors: an El Torito boot record for each of
+/// `boot_catalog_lbas`, and the Enhanced Volume Descriptor only with an
+/// `enhanced_root_lba` (see [`write_enhanced_directories`]).
+pub fn write_descriptors<W: Write + Seek>(
+    iso_file: &mut W,
     volume_id: Option<&str>,
     root_lba: u32,
     total_sectors: u32,
+    pvd_opts: &PvdOptions,
+    boot_catalog_lbas: &[u32],
+    enhanced_root_lba: Option<u32>,
 ) -> io::Result<()> {
-    let root_entry = IsoDirEntry {
-        lba: root_lba,
+    let root_entry = |lba| IsoDirEntry {
+        lba,
         size: ISO_SECTOR_SIZE as u32,
         flags: 0x02,
         name: ".",
     };
-    write_volume_descriptors(iso_file, volume_id, total_sectors, &root_entry)
+    let root = root_entry(root_lba);
+    let enhanced_root = enhanced_root_lba.map(root_entry);
+    let mut descriptors = vec![VolumeDescriptor::Primary { root: &root }];
+    descriptors.extend(
+        boot_catalog_lbas
+            .iter()
+            .map(|&lba| VolumeDescriptor::BootRecordAt(lba)),
+    );
+    if let Some(root) = &enhanced_root {
+        descriptors.push(VolumeDescriptor::Enhanced { root });
+    }
+    write_volume_descriptors(iso_file, volume_id, total_sectors, pvd_opts, &descriptors)?;
+    Ok(())
 }
 
-/// Writes the El Torito boot catalog.
-pub fn write_boot_catalog_to_iso(
-    iso_file: &mut File,
+/// Writes the El Torito boot catalog, with `validation_id` overriding the
+/// validation entry's ID string if set.
+pub fn write_boot_catalog_to_iso<W: Write + Seek>(
+    iso_file: &mut W,
     boot_catalog_lba: u32,
     boot_entries: Vec<BootCatalogEntry>,
+    validation_id: Option<[u8; 24]>,
 ) -> io::Result<()> {
     if !boot_entries.is_empty() {
         iso_file.seek(SeekFrom::Start(
             (boot_catalog_lba as u64) * ISO_SECTOR_SIZE as u64,
         ))?;
-        write_boot_catalog(iso_file, boot_entries)?;
+        iso_file.write_all(&render_boot_catalog_with_id(&boot_entries, validation_id)?)?;
     }
     Ok(())
 }
 
 /// Writes the directory records for the ISO filesystem.
-pub fn write_directories(
-    iso_file: &mut File,
+///
+/// Directories nested deeper than [`MAX_DIR_DEPTH`] levels are relocated
+/// into the root's [`RR_MOVED`] directory the Rock Ridge way: the original
+/// parent keeps an empty placeholder with a `CL` entry, the moved
+/// directory's `..` record carries a `PL` entry back to it, and its record
+/// in `rr_moved` an `RE` entry.  This needs Rock Ridge and an existing
+/// `rr_moved` directory; otherwise deep trees are an error.
+pub fn write_directories<W: Write + Seek>(
+    iso_file: &mut W,
     dir: &IsoDirectory,
     parent_lba: u32,
+    opts: &DirRecordOptions,
 ) -> io::Result<()> {
-    seek_to_lba(iso_file, dir.lba)?;
+    let rr_moved_lba = match dir.children.get(RR_MOVED) {
+        Some(IsoFsNode::Directory(d)) if opts.rock_ridge && dir.lba == parent_lba => Some(d.lba),
+        _ => None,
+    };
+    let mut writer = DirWriter {
+        opts,
+        px_only: !opts.rock_ridge && has_executable(dir),
+        rr_moved_lba,
+        moved: Vec::new(),
+    };
+    writer.write_dir(iso_file, dir, parent_lba, None, 1)?;
 
-    let mut dir_entries = Vec::new();
-    // Self-reference
-    dir_entries.push(IsoDirEntry {
-        lba: dir.lba,
-        size: ISO_SECTOR_SIZE as u32,
-        flags: 0x02,
-        name: ".",
-    });
-    // Parent directory
-    dir_entries.push(IsoDirEntry {
-        lba: parent_lba,
-        size: ISO_SECTOR_SIZE as u32,
-        flags: 0x02,
-        name: "..",
-    });
+    if let Some(lba) = rr_moved_lba
+        && !writer.moved.is_empty()
+    {
+        let mut moved = std::mem::take(&mut writer.moved);
+        moved.sort();
+        let mut records = writer.dot_records(lba, dir.lba, None)?;
+        for (dir_lba, name) in moved {
+            let su = writer.system_use(
+                true,
+                [
+                    rock_ridge::px_entry(rock_ridge::MODE_DIR, 2),
+                    rock_ridge::nm_entry(&name)?,
+                    rock_ridge::re_entry(),
+                ]
+                .concat(),
+            );
+            // Named by LBA so that equally named directories cannot clash.
+            let id = dir_lba.to_string();
+            let entry = IsoDirEntry {
+                lba: dir_lba,
+                size: ISO_SECTOR_SIZE as u32,
+                flags: 0x02,
+                name: &id,
+            };
+            records.push(entry.to_bytes_with_system_use(opts, &su)?);
+        }
+        write_dir_sector(iso_file, lba, &records)?;
+    }
+    Ok(())
+}
+
+/// `opts` with the recording date taken from `file`'s source, if
+/// [`TimestampSource::FromSource`] asks for it and the time is known.
+fn file_record_opts(opts: &DirRecordOptions, file: &IsoFile) -> Option<DirRecordOptions> {
+    match (opts.timestamps, file.modified) {
+        (TimestampSource::FromSource, Some(time)) => Some(DirRecordOptions {
+            recorded: recording_date(time),
+            ..opts.clone()
+        }),
+        _ => None,
+    }
+}
+
+/// Size and flags of the first directory record of `file`.  Files over
+/// `u32::MAX` bytes need [`DirRecordOptions::multi_extent`]; their first
+/// record is then flagged as continued.
+fn first_extent(name: &str, file: &IsoFile, opts: &DirRecordOptions) -> io::Result<(u32, u8)> {
+    match u32::try_from(file.size) {
+        Ok(size) => Ok((size, 0x00)),
+        // Multi-extent: another record of the same file follows.
+        Err(_) if opts.multi_extent => Ok((MAX_EXTENT_SIZE, 0x80)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "File '{name}' is too large for ISO9660 (exceeds u32::MAX bytes); \
+                 ISO level 3 splits it into several extents"
+            ),
+        )),
+    }
+}
+
+/// Records for the extents of `file` after the one described by
+/// [`first_extent`], at consecutive LBAs, all but the last flagged as
+/// continued.  Only the first extent is preceded by an extended attribute
+/// record.
+fn continuation_records(
+    name: &str,
+    file: &IsoFile,
+    opts: &DirRecordOptions,
+    system_use: &[u8],
+) -> io::Result<Vec<Vec<u8>>> {
+    let mut records = Vec::new();
+    if file.size <= u32::MAX as u64 {
+        return Ok(records);
+    }
+    let opts = DirRecordOptions {
+        ear_checksums: false,
+        ..opts.clone()
+    };
+    let mut offset = MAX_EXTENT_SIZE as u64;
+    while offset < file.size {
+        let size = (file.size - offset).min(MAX_EXTENT_SIZE as u64);
+        let last = offset + size == file.size;
+        let entry = IsoDirEntry {
+            lba: file.lba + (offset / ISO_SECTOR_SIZE as u64) as u32,
+            size: size as u32,
+            flags: if last { 0x00 } else { 0x80 },
+            name,
+        };
+        records.push(entry.to_bytes_with_system_use(&opts, system_use)?);
+        offset += size;
+    }
+    Ok(records)
+}
 
+/// Writes the ISO 9660:1999 directory hierarchy referenced by the Enhanced
+/// Volume Descriptor: the same tree and file extents as the primary one, in
+/// [`dir_count`] consecutive sectors from `start_lba`, with names kept as
+/// given (see [`DirRecordOptions::relaxed_names`]) and no version suffix.
+/// Its depth is not limited, so nothing is relocated.
+pub fn write_enhanced_directories<W: Write + Seek>(
+    iso_file: &mut W,
+    root: &IsoDirectory,
+    start_lba: u32,
+    opts: &DirRecordOptions,
+) -> io::Result<()> {
+    let opts = DirRecordOptions {
+        file_version: None,
+        rock_ridge: false,
+        cd_xa: false,
+        relaxed_names: true,
+        ..opts.clone()
+    };
+    write_enhanced_dir(iso_file, root, start_lba, start_lba, &opts)
+}
+
+fn write_enhanced_dir<W: Write + Seek>(
+    iso_file: &mut W,
+    dir: &IsoDirectory,
+    lba: u32,
+    parent_lba: u32,
+    opts: &DirRecordOptions,
+) -> io::Result<()> {
+    let no_ear = DirRecordOptions {
+        ear_checksums: false,
+        ..opts.clone()
+    };
+    let mut records = Vec::new();
+    for (name, target) in [(".", lba), ("..", parent_lba)] {
+        let entry = IsoDirEntry {
+            lba: target,
+            size: ISO_SECTOR_SIZE as u32,
+            flags: 0x02,
+            name,
+        };
+        records.push(entry.to_bytes_with_system_use(opts, &[])?);
+    }
+    let mut next = lba + 1;
+    let mut subdirs = Vec::new();
     for_sorted_children!(dir, |name, node| {
-        let (lba, size, flags) = match node {
+        let file_opts = match node {
+            IsoFsNode::File(file) => file_record_opts(opts, file),
+            _ => None,
+        };
+        let (entry_lba, size, flags, record_opts) = match node {
             IsoFsNode::File(file) => {
-                let file_size_u32 = u32::try_from(file.size).map_err(|_| {
-                    io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        format!(
-                            "File '{}' is too large for ISO9660 (exceeds u32::MAX bytes)",
-                            name
-                        ),
-                    )
-                })?;
-                (file.lba, file_size_u32, 0x00)
+                let (size, flags) = first_extent(name, file, opts)?;
+                let record_opts = file_opts.as_ref().unwrap_or(opts);
+                (
+                    file.lba - u32::from(opts.ear_checksums),
+                    size,
+                    flags,
+                    record_opts,
+                )
+            }
+            IsoFsNode::Directory(subdir) => {
+                let sub_lba = next;
+                next += dir_count(subdir);
+                subdirs.push((subdir, sub_lba));
+                (sub_lba, ISO_SECTOR_SIZE as u32, 0x02, opts)
             }
-            IsoFsNode::Directory(subdir) => (subdir.lba, ISO_SECTOR_SIZE as u32, 0x02),
+            IsoFsNode::Symlink(_) => (0, 0, 0x00, &no_ear),
+            IsoFsNode::BootCatalog(cat) => (*cat, ISO_SECTOR_SIZE as u32, 0x00, &no_ear),
         };
-        dir_entries.push(IsoDirEntry {
-            lba,
+        let entry = IsoDirEntry {
+            lba: entry_lba,
             size,
             flags,
             name: name.as_str(),
-        });
+        };
+        records.push(entry.to_bytes_with_system_use(record_opts, &[])?);
+        if let IsoFsNode::File(file) = node {
+            records.extend(continuation_records(name, file, record_opts, &[])?);
+        }
     });
+    write_dir_sector(iso_file, lba, &records)?;
+    for (subdir, sub_lba) in subdirs {
+        write_enhanced_dir(iso_file, subdir, sub_lba, lba, opts)?;
+    }
+    Ok(())
+}
+
+/// Level, counting the root as 1, of a directory relocated into
+/// [`RR_MOVED`].
+const RELOCATED_LEVEL: usize = 3;
 
+/// State shared by the recursion of [`write_directories`].
+struct DirWriter<'a> {
+    opts: &'a DirRecordOptions,
+    /// Without Rock Ridge, executable files still get a `PX` entry; the
+    /// root then announces SUSP and RRIP so readers look for it.
+    px_only: bool,
+    rr_moved_lba: Option<u32>,
+    /// `(lba, name)` of every relocated directory.
+    moved: Vec<(u32, String)>,
+}
+
+impl DirWriter<'_> {
+    /// Prefixes `susp` with the CD-XA field when enabled; it precedes any
+    /// SUSP entries.
+    fn system_use(&self, is_dir: bool, susp: Vec<u8>) -> Vec<u8> {
+        let mut su = Vec::with_capacity(XA_FIELD_LEN + susp.len());
+        if self.opts.cd_xa {
+            su.extend_from_slice(&xa_field(is_dir));
+        }
+        su.extend(susp);
+        su
+    }
+
+    /// The `.` and `..` records of the directory at `lba`.  `pl` is the
+    /// original parent of a relocated directory.
+    fn dot_records(&self, lba: u32, parent_lba: u32, pl: Option<u32>) -> io::Result<Vec<Vec<u8>>> {
+        let opts = self.opts;
+        let is_root = lba == parent_lba;
+        let xa_skip = if opts.cd_xa { XA_FIELD_LEN } else { 0 };
+        let dir_px = || rock_ridge::px_entry(rock_ridge::MODE_DIR, 2);
+        // Self-reference; the root's also announces the SUSP/RRIP extensions.
+        let self_su = self.system_use(
+            true,
+            match (opts.rock_ridge, is_root) {
+                (false, true) if self.px_only => {
+                    [rock_ridge::sp_entry(xa_skip as u8), rock_ridge::er_entry()].concat()
+                }
+                (false, _) => Vec::new(),
+                (true, true) => [
+                    rock_ridge::sp_entry(xa_skip as u8),
+                    dir_px(),
+                    rock_ridge::er_entry(),
+                ]
+                .concat(),
+                (true, false) => dir_px(),
+            },
+        );
+        let parent_su = self.system_use(
+            true,
+            match (opts.rock_ridge, pl) {
+                (false, _) => Vec::new(),
+                (true, None) => dir_px(),
+                (true, Some(pl)) => [dir_px(), rock_ridge::pl_entry(pl)].concat(),
+            },
+        );
+        Ok(vec![
+            IsoDirEntry {
+                lba,
+                size: ISO_SECTOR_SIZE as u32,
+                flags: 0x02,
+                name: ".",
+            }
+            .to_bytes_with_system_use(opts, &self_su)?,
+            // Parent directory
+            IsoDirEntry {
+                lba: parent_lba,
+                size: ISO_SECTOR_SIZE as u32,
+                flags: 0x02,
+                name: "..",
+            }
+            .to_bytes_with_system_use(opts, &parent_su)?,
+        ])
+    }
+
+    /// Writes `dir`, at `level` in the hierarchy, and everything below it.
+    fn write_dir<W: Write + Seek>(
+        &mut self,
+        iso_file: &mut W,
+        dir: &IsoDirectory,
+        parent_lba: u32,
+        pl: Option<u32>,
+        level: usize,
+    ) -> io::Result<()> {
+        let opts = self.opts;
+        let relocate = level + 1 > MAX_DIR_DEPTH;
+        if relocate
+            && self.rr_moved_lba.is_none()
+            && dir
+                .children
+                .values()
+                .any(|n| matches!(n, IsoFsNode::Directory(_)))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Directories are nested more than {MAX_DIR_DEPTH} levels deep; \
+                     relocating them needs Rock Ridge and an '{RR_MOVED}' directory"
+                ),
+            ));
+        }
+
+        let mut records = self.dot_records(dir.lba, parent_lba, pl)?;
+        // Symlinks and relocation placeholders have no data and therefore
+        // no extended attribute record.
+        let no_ear = DirRecordOptions {
+            ear_checksums: false,
+            ..opts.clone()
+        };
+        for_sorted_children!(dir, |name, node| {
+            let placeholder = relocate && matches!(node, IsoFsNode::Directory(_));
+            let (lba, size, flags, mode) = match node {
+                IsoFsNode::File(file) => {
+                    let (size, flags) = first_extent(name, file, opts)?;
+                    let ear = u32::from(opts.ear_checksums);
+                    let mode = if file.executable {
+                        rock_ridge::MODE_EXECUTABLE
+                    } else {
+                        rock_ridge::MODE_FILE
+                    };
+                    (file.lba - ear, size, flags, mode)
+                }
+                IsoFsNode::Directory(subdir) if placeholder => {
+                    (subdir.lba, 0, 0x00, rock_ridge::MODE_DIR)
+                }
+                IsoFsNode::Directory(subdir) => (
+                    subdir.lba,
+                    ISO_SECTOR_SIZE as u32,
+                    0x02,
+                    rock_ridge::MODE_DIR,
+                ),
+                IsoFsNode::Symlink(_) => (0, 0, 0x00, rock_ridge::MODE_SYMLINK),
+                IsoFsNode::BootCatalog(cat) => {
+                    (*cat, ISO_SECTOR_SIZE as u32, 0x00, rock_ridge::MODE_FILE)
+                }
+            };
+            let mut su = Vec::new();
+            if opts.rock_ridge {
+                let links = if mode == rock_ridge::MODE_DIR { 2 } else { 1 };
+                su.extend(rock_ridge::px_entry(mode, links));
+                su.extend(rock_ridge::nm_entry(name)?);
+                if let IsoFsNode::Symlink(link) = node {
+                    su.extend(rock_ridge::sl_entry(&link.target)?);
+                }
+                if placeholder {
+                    su.extend(rock_ridge::cl_entry(lba));
+                }
+            } else if mode == rock_ridge::MODE_EXECUTABLE {
+                su.extend(rock_ridge::px_entry(mode, 1));
+            }
+            let entry = IsoDirEntry {
+                lba,
+                size,
+                flags,
+                name: name.as_str(),
+            };
+            let file_opts = match node {
+                IsoFsNode::File(file) => file_record_opts(opts, file),
+                _ => None,
+            };
+            let record_opts = if flags & 0x02 == 0 && !matches!(node, IsoFsNode::File(_)) {
+                &no_ear
+            } else {
+                file_opts.as_ref().unwrap_or(opts)
+            };
+            let su = self.system_use(flags & 0x02 != 0, su);
+            let used: usize = records.iter().map(Vec::len).sum();
+            if used + entry.encoded_len(record_opts, su.len()) > ISO_SECTOR_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Directory records do not fit in a single sector (at '{name}')"),
+                ));
+            }
+            records.push(entry.to_bytes_with_system_use(record_opts, &su)?);
+            if let IsoFsNode::File(file) = node {
+                records.extend(continuation_records(name, file, record_opts, &su)?);
+            }
+        });
+
+        write_dir_sector(iso_file, dir.lba, &records)?;
+
+        for_sorted_children!(dir, |name, node| {
+            if let IsoFsNode::Directory(subdir) = node {
+                match self.rr_moved_lba {
+                    Some(moved_lba) if relocate => {
+                        self.moved.push((subdir.lba, name.clone()));
+                        self.write_dir(
+                            iso_file,
+                            subdir,
+                            moved_lba,
+                            Some(dir.lba),
+                            RELOCATED_LEVEL,
+                        )?;
+                    }
+                    _ => self.write_dir(iso_file, subdir, dir.lba, None, level + 1)?,
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Whether any file below `dir` is marked executable.
+fn has_executable(dir: &IsoDirectory) -> bool {
+    dir.children.values().any(|node| match node {
+        IsoFsNode::File(file) => file.executable,
+        IsoFsNode::Directory(d) => has_executable(d),
+        _ => false,
+    })
+}
+
+/// Packs `records` into one directory sector and writes it at `lba`.
+fn write_dir_sector<W: Write + Seek>(
+    iso_file: &mut W,
+    lba: u32,
+    records: &[Vec<u8>],
+) -> io::Result<()> {
     let mut dir_sector = [0u8; ISO_SECTOR_SIZE];
     let mut offset = 0;
+    for record in records {
+        if offset + record.len() > ISO_SECTOR_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Directory records do not fit in a single sector",
+            ));
+        }
+        dir_sector[offset..offset + record.len()].copy_from_slice(record);
+        offset += record.len();
+    }
+    SectorWriter::new(iso_file).write_at_lba(lba, &dir_sector)
+}
 
-    for entry in &dir_entries {
-        let entry_bytes = entry.to_bytes();
-        dir_sector[offset..offset + entry_bytes.len()].copy_from_slice(&entry_bytes);
-        offset += entry_bytes.len();
+/// Copies all file contents to the ISO image.
+pub fn copy_files<W: Write + Seek>(iso_file: &mut W, dir: &IsoDirectory) -> io::Result<()> {
+    for file in files_in_lba_order(dir) {
+        seek_to_lba(iso_file, file.lba)?;
+        copy_file(iso_file, file)?;
     }
-    iso_file.write_all(&dir_sector)?;
+    Ok(())
+}
+
+fn copy_file<W: Write + Seek>(iso_file: &mut W, file: &IsoFile) -> io::Result<()> {
+    let mut src = file.open_source()?;
+    // Never write past the recorded extent, even if the source
+    // grew; probe one extra byte afterwards to detect growth.
+    let mut copied = io::copy(&mut (&mut src).take(source_len(file)), iso_file)?;
+    copied += src.read(&mut [0u8; 1])? as u64;
+    check_copied_size(file, copied)?;
+    io::copy(&mut io::repeat(0).take(file.zero_fill), iso_file)?;
+    write_padding(iso_file, file)
+}
 
-    for_sorted_children!(dir, |_name, node| {
-        if let IsoFsNode::Directory(subdir) = node {
-            write_directories(iso_file, subdir, dir.lba)?;
+/// Bytes of `file` that come from its source, i.e. without its
+/// [`IsoFile::zero_fill`].
+fn source_len(file: &IsoFile) -> u64 {
+    file.size - file.zero_fill
+}
+
+/// Fails if the number of bytes read from a source file differs from the
+/// size recorded when it was added, which would desynchronise the directory
+/// record from the data written.
+fn check_copied_size(file: &IsoFile, copied: u64) -> io::Result<()> {
+    let expected = source_len(file);
+    if copied != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Source file {} changed size since it was added ({} bytes recorded, {}{} read)",
+                file.source_name(),
+                expected,
+                copied,
+                if copied > expected { "+" } else { "" }
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Writes a CRC32 extended attribute record in the sector before every
+/// file, computed from the data already copied into the image.
+pub fn write_ear_checksums<W: Read + Write + Seek>(
+    iso_file: &mut W,
+    dir: &IsoDirectory,
+) -> io::Result<()> {
+    let mut buf = vec![0u8; 64 * 1024];
+    for file in files_in_lba_order(dir) {
+        seek_to_lba(iso_file, file.lba)?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut remaining = file.size;
+        while remaining > 0 {
+            let n = (remaining as usize).min(buf.len());
+            iso_file.read_exact(&mut buf[..n])?;
+            hasher.update(&buf[..n]);
+            remaining -= n as u64;
         }
-    });
+        SectorWriter::new(&mut *iso_file)
+            .write_at_lba(file.lba - 1, &ear_with_crc32(hasher.finalize()))?;
+    }
+    Ok(())
+}
 
+/// Zero-fills the rest of `file`'s last sector and its reserved padding
+/// sectors.  Expects the stream to sit at the end of the file's data.
+fn write_padding<W: Write + Seek>(iso_file: &mut W, file: &IsoFile) -> io::Result<()> {
+    if file.padding_sectors == 0 {
+        return Ok(());
+    }
+    let sector = ISO_SECTOR_SIZE as u64;
+    let gap =
+        file.size.div_ceil(sector) * sector - file.size + file.padding_sectors as u64 * sector;
+    io::copy(&mut io::repeat(0).take(gap), iso_file)?;
     Ok(())
 }
 
-/// Copies all file contents to the ISO image.
-pub fn copy_files(iso_file: &mut File, dir: &IsoDirectory) -> io::Result<()> {
-    for_sorted_children!(dir, |_name, node| {
-        match node {
-            IsoFsNode::File(file) => {
-                seek_to_lba(iso_file, file.lba)?;
-                let mut real_file = File::open(&file.path)?;
-                io::copy(&mut real_file, iso_file)?;
+/// Like [`copy_files`], but skips the holes of sparse source files (Linux
+/// with the `sparse` feature; elsewhere every file is copied densely).
+///
+/// A hole is only skipped where the output has not been written yet, i.e.
+/// at or beyond the stream's current end, which reads back as zeros once the
+/// stream grows; holes over existing bytes are written out as zeros.
+pub fn copy_files_sparse<W: Write + Seek>(iso_file: &mut W, dir: &IsoDirectory) -> io::Result<()> {
+    for file in files_in_lba_order(dir) {
+        copy_file_sparse(iso_file, file)?;
+    }
+    Ok(())
+}
+
+fn copy_file_sparse<W: Write + Seek>(iso_file: &mut W, file: &IsoFile) -> io::Result<()> {
+    if file.reader.is_some() {
+        seek_to_lba(iso_file, file.lba)?;
+        return copy_file(iso_file, file);
+    }
+    let mut src = File::open(&file.path)?;
+    check_copied_size(file, src.metadata()?.len())?;
+    let base = file.lba as u64 * ISO_SECTOR_SIZE as u64;
+    let written_end = iso_file.seek(SeekFrom::End(0))?;
+    let mut pos = 0;
+    // Any zero fill past the source's data is left to the trailing hole.
+    for (start, end) in data_regions(&src, source_len(file))? {
+        zero_fill_hole(iso_file, base + pos, base + start, written_end)?;
+        src.seek(SeekFrom::Start(start))?;
+        iso_file.seek(SeekFrom::Start(base + start))?;
+        let copied = io::copy(&mut (&mut src).take(end - start), iso_file)?;
+        if copied != end - start {
+            check_copied_size(file, start + copied)?;
+        }
+        pos = end;
+    }
+    zero_fill_hole(iso_file, base + pos, base + file.size, written_end)?;
+    // A trailing hole must still extend the stream to the end of the file.
+    if pos < file.size && base + file.size > written_end {
+        iso_file.seek(SeekFrom::Start(base + file.size - 1))?;
+        iso_file.write_all(&[0])?;
+    }
+    iso_file.seek(SeekFrom::Start(base + file.size))?;
+    write_padding(iso_file, file)
+}
+
+/// Writes zeros over the part of `from..to` below `written_end`.
+fn zero_fill_hole<W: Write + Seek>(
+    iso_file: &mut W,
+    from: u64,
+    to: u64,
+    written_end: u64,
+) -> io::Result<()> {
+    let to = to.min(written_end);
+    if from < to {
+        iso_file.seek(SeekFrom::Start(from))?;
+        io::copy(&mut io::repeat(0).take(to - from), iso_file)?;
+    }
+    Ok(())
+}
+
+/// Byte ranges of `src` below `size` that hold data, found with
+/// `SEEK_DATA`/`SEEK_HOLE`.
+#[cfg(all(target_os = "linux", feature = "sparse"))]
+fn data_regions(src: &File, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    use std::os::fd::AsRawFd;
+
+    // Returns `None` when there is no further data (or hole) past `off`.
+    let seek = |off: u64, whence: libc::c_int| -> io::Result<Option<u64>> {
+        // SAFETY: lseek only moves the offset of a descriptor we own.
+        let r = unsafe { libc::lseek(src.as_raw_fd(), off as libc::off_t, whence) };
+        if r >= 0 {
+            return Ok(Some(r as u64));
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::ENXIO) => Ok(None),
+            _ => Err(err),
+        }
+    };
+    let mut regions = Vec::new();
+    let mut off = 0;
+    while off < size {
+        let start = match seek(off, libc::SEEK_DATA) {
+            Ok(Some(start)) if start < size => start,
+            Ok(_) => break,
+            // Kernels without SEEK_DATA support: copy densely.
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) && off == 0 => {
+                return Ok(vec![(0, size)]);
             }
-            IsoFsNode::Directory(subdir) => {
-                copy_files(iso_file, subdir)?;
+            Err(e) => return Err(e),
+        };
+        let end = seek(start, libc::SEEK_HOLE)?.unwrap_or(size).min(size);
+        regions.push((start, end));
+        off = end;
+    }
+    Ok(regions)
+}
+
+#[cfg(not(all(target_os = "linux", feature = "sparse")))]
+fn data_regions(_src: &File, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    Ok(vec![(0, size)])
+}
+
+/// Size of the chunks [`copy_files_prefetched`] reads ahead.
+const PREFETCH_CHUNK: u64 = 1024 * 1024;
+
+/// A prefetched chunk of file data; `None` ends the current file.
+type Prefetched = io::Result<Option<Vec<u8>>>;
+
+/// Copies all file contents to the ISO image, prefetching up to `depth`
+/// 1 MiB chunks on a background thread, so at most `depth + 2` chunks are
+/// held in memory whatever the file sizes.
+///
+/// Writes remain single-threaded and ordered by LBA; only the reads of the
+/// source files overlap with the writes.  The output is byte-identical to
+/// [`copy_files`].  A `depth` of 0 falls back to the sequential path.
+pub fn copy_files_prefetched<W: Write + Seek>(
+    iso_file: &mut W,
+    dir: &IsoDirectory,
+    depth: usize,
+) -> io::Result<()> {
+    if depth == 0 {
+        return copy_files(iso_file, dir);
+    }
+
+    let files = files_in_lba_order(dir);
+    let jobs: Vec<IsoFile> = files.iter().map(|&f| f.clone()).collect();
+
+    let (tx, rx) = mpsc::sync_channel::<Prefetched>(depth);
+    let reader = thread::spawn(move || {
+        for file in &jobs {
+            match prefetch_file(file, &tx) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    break;
+                }
             }
         }
     });
 
-    Ok(())
+    let mut write_all_files = || -> io::Result<()> {
+        for file in &files {
+            seek_to_lba(iso_file, file.lba)?;
+            while let Some(chunk) = rx
+                .recv()
+                .map_err(|_| io::Error::other("file prefetch thread stopped"))??
+            {
+                iso_file.write_all(&chunk)?;
+            }
+            io::copy(&mut io::repeat(0).take(file.zero_fill), iso_file)?;
+            write_padding(iso_file, file)?;
+        }
+        Ok(())
+    };
+    let result = write_all_files();
+    // Dropping the receiver unblocks the reader if we bailed out early.
+    drop(rx);
+    reader
+        .join()
+        .map_err(|_| io::Error::other("file prefetch thread panicked"))?;
+    result
+}
+
+/// Sends the source data of `file` to `tx` in chunks of at most
+/// [`PREFETCH_CHUNK`] bytes followed by `None`.  Like [`copy_file`], it
+/// never reads past the recorded size and fails if the source changed
+/// size.  Returns `false` once the receiver has gone.
+fn prefetch_file(file: &IsoFile, tx: &mpsc::SyncSender<Prefetched>) -> io::Result<bool> {
+    let mut src = file.open_source()?;
+    let mut copied = 0;
+    loop {
+        let want = PREFETCH_CHUNK.min(source_len(file) - copied);
+        let mut chunk = Vec::with_capacity(want as usize);
+        let n = (&mut src).take(want).read_to_end(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        copied += n as u64;
+        if tx.send(Ok(Some(chunk))).is_err() {
+            return Ok(false);
+        }
+    }
+    copied += src.read(&mut [0u8; 1])? as u64;
+    check_copied_size(file, copied)?;
+    Ok(tx.send(Ok(None)).is_ok())
 }
 
 const PVD_LBA: u32 = 16;
@@ -141,8 +797,8 @@ const PVD_LBA: u32 = 16;
 /// | 16     | 4    | Boot image length  |
 /// | 20     | 4    | Checksum of bytes 64+ |
 /// | 24     | 32   | Reserved (zero)    |
-pub fn write_boot_info_table(
-    iso_file: &mut File,
+pub fn write_boot_info_table<W: Read + Write + Seek>(
+    iso_file: &mut W,
     boot_image_lba: u32,
     boot_image_size: u64,
 ) -> io::Result<()> {
@@ -176,10 +832,73 @@ pub fn write_boot_info_table(
     iso_file.write_all(&table)
 }
 
+/// Writes `lba` as a little-endian `u32` at byte `offset` of the file whose
+/// extent starts at `lba`.
+pub fn write_lba_patch<W: Write + Seek>(iso_file: &mut W, lba: u32, offset: u64) -> io::Result<()> {
+    iso_file.seek(SeekFrom::Start(
+        lba as u64 * ISO_SECTOR_SIZE as u64 + offset,
+    ))?;
+    iso_file.write_all(&lba.to_le_bytes())
+}
+
+/// A stream whose length can be cut or extended, like [`File::set_len`].
+/// Lets a build truncate an output that previously held a larger image.
+pub trait SetLen {
+    /// Truncates or zero-extends the stream to `len` bytes.  The position
+    /// is left unchanged.
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl SetLen for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+}
+
+impl SetLen for io::Cursor<Vec<u8>> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().resize(len as usize, 0);
+        Ok(())
+    }
+}
+
+impl SetLen for io::Cursor<&mut Vec<u8>> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().resize(len as usize, 0);
+        Ok(())
+    }
+}
+
+impl<T: SetLen + ?Sized> SetLen for &mut T {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        (**self).set_len(len)
+    }
+}
+
+/// Finalizes the ISO image by padding it to whole sectors, truncating
+/// anything a previous, larger image left past the end, and updating the
+/// total sector count in the PVD.
+pub fn finalize_iso<W: Write + Seek + SetLen>(
+    iso_file: &mut W,
+    total_sectors: &mut u32,
+) -> io::Result<()> {
+    let mut w = SectorWriter::new(&mut *iso_file);
+    let end = w
+        .current_lba()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "ISO image too large"))?;
+    w.pad_to_lba(end)?;
+    iso_file.set_len(end as u64 * ISO_SECTOR_SIZE as u64)?;
+    *total_sectors = end;
+    update_total_sectors_in_pvd(iso_file, *total_sectors)?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::io::Read;
+    use std::path::PathBuf;
     use tempfile::NamedTempFile;
 
     fn read_sector(file: &mut File, lba: u32) -> io::Result<[u8; ISO_SECTOR_SIZE]> {
@@ -189,6 +908,179 @@ mod tests {
         Ok(buf)
     }
 
+    #[test]
+    fn test_multi_extent_records() -> io::Result<()> {
+        let size = 2 * MAX_EXTENT_SIZE as u64 + 5000;
+        let mut root = IsoDirectory::new();
+        root.lba = 20;
+        root.children.insert(
+            "big.img".into(),
+            IsoFsNode::File(IsoFile {
+                path: PathBuf::new(),
+                size,
+                lba: 30,
+                padding_sectors: 0,
+                zero_fill: 0,
+                modified: None,
+                reader: None,
+                executable: false,
+            }),
+        );
+        let mut out = io::Cursor::new(Vec::new());
+        let err = write_directories(&mut out, &root, 20, &DirRecordOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let opts = DirRecordOptions {
+            multi_extent: true,
+            ear_checksums: true,
+            ..Default::default()
+        };
+        write_directories(&mut out, &root, 20, &opts)?;
+        write_enhanced_directories(&mut out, &root, 21, &opts)?;
+        let data = out.into_inner();
+        let extent_sectors = MAX_EXTENT_SIZE / ISO_SECTOR_SIZE as u32;
+        for (lba, id) in [(20, &b"BIG.IMG;1"[..]), (21, b"big.img")] {
+            let mut off = lba * ISO_SECTOR_SIZE;
+            // Skip `.` and `..`.
+            off += data[off] as usize;
+            off += data[off] as usize;
+            let mut extents = Vec::new();
+            while data[off] != 0 {
+                let rec = &data[off..off + data[off] as usize];
+                assert_eq!(&rec[33..33 + rec[32] as usize], id);
+                extents.push((
+                    rec[1],
+                    crate::utils::read_both_endian_u32(rec, 2)?,
+                    crate::utils::read_both_endian_u32(rec, 10)?,
+                    rec[25],
+                ));
+                off += rec.len();
+            }
+            assert_eq!(
+                extents,
+                [
+                    (1, 29, MAX_EXTENT_SIZE, 0x80),
+                    (0, 30 + extent_sectors, MAX_EXTENT_SIZE, 0x80),
+                    (0, 30 + 2 * extent_sectors, 5000, 0x00),
+                ]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_directory_sector_overflow() {
+        let mut root = IsoDirectory::new();
+        root.lba = 20;
+        // 54-byte records: after `.` and `..` only 36 of them fit.
+        for i in 0..40 {
+            root.children.insert(
+                format!("file_number_{i:02}.bin"),
+                IsoFsNode::BootCatalog(30),
+            );
+        }
+        let mut out = io::Cursor::new(Vec::new());
+        let err = write_directories(&mut out, &root, 20, &DirRecordOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("'file_number_36.bin'"), "{err}");
+    }
+
+    #[test]
+    fn test_deep_tree_lbas_and_copy() -> io::Result<()> {
+        use crate::iso::builder_utils::calculate_lbas;
+        const DEPTH: u32 = 5000;
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("leaf.bin");
+        std::fs::write(&src, b"leaf")?;
+        let file = |size| {
+            IsoFsNode::File(IsoFile {
+                path: src.clone(),
+                size,
+                lba: 0,
+                padding_sectors: 0,
+                zero_fill: 0,
+                modified: None,
+                reader: None,
+                executable: false,
+            })
+        };
+        // Every level holds a file `a` and a subdirectory `d`, built bottom
+        // up so that nothing here recurses.
+        let mut root = IsoDirectory::new();
+        for _ in 0..DEPTH {
+            let mut parent = IsoDirectory::new();
+            parent.children.insert("a".into(), file(4));
+            parent
+                .children
+                .insert("d".into(), IsoFsNode::Directory(root));
+            root = parent;
+        }
+
+        let end = calculate_lbas(20, &mut root)?;
+        assert_eq!(end, 20 + 2 * DEPTH + 1);
+        let mut cur = &root;
+        for level in 0..DEPTH {
+            assert_eq!(cur.lba, 20 + 2 * level);
+            let (Some(IsoFsNode::File(f)), Some(IsoFsNode::Directory(sub))) =
+                (cur.children.get("a"), cur.children.get("d"))
+            else {
+                panic!("level {level} malformed");
+            };
+            assert_eq!(f.lba, cur.lba + 1);
+            cur = sub;
+        }
+
+        let mut out = io::Cursor::new(Vec::new());
+        copy_files(&mut out, &root)?;
+        let data = out.into_inner();
+        let last = (end - 2) as usize * ISO_SECTOR_SIZE;
+        assert_eq!(&data[last..last + 4], b"leaf");
+
+        // Take the chain apart iteratively; dropping it whole would recurse.
+        let mut cur = root;
+        while let Some(IsoFsNode::Directory(sub)) = cur.children.remove("d") {
+            cur = sub;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_detects_source_size_change() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.bin");
+        std::fs::write(&src, vec![1u8; 100])?;
+        let mut root = IsoDirectory::new();
+        root.children.insert(
+            "A.BIN".into(),
+            IsoFsNode::File(IsoFile {
+                path: src.clone(),
+                size: 100,
+                lba: 20,
+                padding_sectors: 0,
+                zero_fill: 0,
+                modified: None,
+                reader: None,
+                executable: false,
+            }),
+        );
+        let mut f = NamedTempFile::new()?;
+
+        for new_len in [50usize, 5000] {
+            std::fs::write(&src, vec![2u8; new_len])?;
+            for depth in [0, 2] {
+                let err = copy_files_prefetched(f.as_file_mut(), &root, depth).unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                assert!(err.to_string().contains("changed size"), "{err}");
+            }
+        }
+        // Nothing beyond the recorded 100 bytes was written at LBA 20.
+        assert!(f.as_file().metadata()?.len() <= 20 * ISO_SECTOR_SIZE as u64 + 100);
+
+        std::fs::write(&src, vec![3u8; 100])?;
+        copy_files_prefetched(f.as_file_mut(), &root, 0)?;
+        Ok(())
+    }
+
     #[test]
     fn test_boot_info_table_structure() -> io::Result<()> {
         let mut f = NamedTempFile::new()?;
@@ -200,8 +1092,8 @@ mod tests {
 
         let mut boot_data = vec![0u8; boot_size as usize];
         // Fill bytes 64.. with a known pattern for checksum verification.
-        for i in 64..boot_size as usize {
-            boot_data[i] = (i as u8).wrapping_mul(3).wrapping_add(0xAB);
+        for (i, b) in boot_data.iter_mut().enumerate().skip(64) {
+            *b = (i as u8).wrapping_mul(3).wrapping_add(0xAB);
         }
         f.seek(SeekFrom::Start(boot_offset))?;
         f.write_all(&boot_data)?;
@@ -282,7 +1174,7 @@ mod tests {
 
         // Pre-fill the sector with 0xAA so we can detect unintended writes.
         let boot_offset = boot_lba as u64 * ISO_SECTOR_SIZE as u64;
-        let sector = [0xAAu8; ISO_SECTOR_SIZE as usize];
+        let sector = [0xAAu8; ISO_SECTOR_SIZE];
         f.seek(SeekFrom::Start(boot_offset))?;
         f.write_all(&sector)?;
 
@@ -298,21 +1190,3 @@ mod tests {
         Ok(())
     }
 }
-
-/// Finalizes the ISO image by padding and updating the total sector count in the PVD.
-pub fn finalize_iso(iso_file: &mut File, total_sectors: &mut u32) -> io::Result<()> {
-    let current_pos = iso_file.stream_position()?;
-    let remainder = current_pos % ISO_SECTOR_SIZE as u64;
-    if remainder != 0 {
-        let padding_bytes = ISO_SECTOR_SIZE as u64 - remainder;
-        io::copy(&mut io::repeat(0).take(padding_bytes), iso_file)?;
-    }
-
-    let final_pos = iso_file.stream_position()?;
-    let total_sectors_u64 = final_pos.div_ceil(ISO_SECTOR_SIZE as u64);
-    *total_sectors = u32::try_from(total_sectors_u64)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "ISO image too large"))?;
-    update_total_sectors_in_pvd(iso_file, *total_sectors)?;
-
-    Ok(())
-}
@@ -9,6 +9,9 @@ pub struct IsoLayoutProfile {
     pub mbr_mode: MbrMode,
     pub hidden_sectors_mode: HiddenSectorMode,
     pub uefi_boot_strategy: UefiBootStrategy,
+    /// Cluster size of the generated FAT ESP in bytes (a power of two from
+    /// 512 to 32768); `None` uses [`crate::fat::DEFAULT_CLUSTER_SIZE`].
+    pub esp_cluster_size: Option<u16>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -30,6 +33,44 @@ pub enum HiddenSectorMode {
     PartitionOffset,
 }
 
+/// Which hybrid (USB-bootable) structures are written around the ISO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HybridMode {
+    /// Plain ISO 9660; the system area is left untouched.
+    #[default]
+    None,
+    /// Classic BIOS-only isohybrid: an MBR with one partition covering the
+    /// whole image and the BIOS boot image's first 440 bytes as boot code.
+    BiosMbr,
+    /// Protective MBR + GPT with an embedded FAT EFI System Partition.
+    GptUefi,
+    /// `GptUefi` plus BIOS boot code in the MBR.
+    Both,
+}
+
+impl HybridMode {
+    /// Whether a FAT ESP image is created and partitioned in GPT.
+    pub fn uses_gpt_esp(self) -> bool {
+        matches!(self, HybridMode::GptUefi | HybridMode::Both)
+    }
+
+    /// Whether the MBR boot code is taken from the BIOS boot image.
+    pub fn has_bios_boot_code(self) -> bool {
+        matches!(self, HybridMode::BiosMbr | HybridMode::Both)
+    }
+}
+
+/// `true` maps to the historical isohybrid behaviour ([`HybridMode::GptUefi`]).
+impl From<bool> for HybridMode {
+    fn from(is_isohybrid: bool) -> Self {
+        if is_isohybrid {
+            HybridMode::GptUefi
+        } else {
+            HybridMode::None
+        }
+    }
+}
+
 impl Default for IsoLayoutProfile {
     fn default() -> Self {
         Self::hardware()
@@ -46,6 +87,7 @@ impl IsoLayoutProfile {
             mbr_mode: MbrMode::HybridLinuxEsp,
             hidden_sectors_mode: HiddenSectorMode::PartitionOffset,
             uefi_boot_strategy: UefiBootStrategy::ElToritoDirectEfi,
+            esp_cluster_size: None,
         }
     }
     pub fn hardware() -> Self {
@@ -57,6 +99,7 @@ impl IsoLayoutProfile {
             mbr_mode: MbrMode::HybridLinuxEsp,
             hidden_sectors_mode: HiddenSectorMode::Zero,
             uefi_boot_strategy: UefiBootStrategy::EspPartition,
+            esp_cluster_size: None,
         }
     }
 }
@@ -0,0 +1,160 @@
+// isobemak/src/iso/manifest.rs
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::iso::boot_info::{BiosBootInfo, BootInfo, UefiBootInfo};
+use crate::iso::iso_image::{IsoImage, IsoImageFile};
+use crate::iso::layout_profile::{HybridMode, IsoLayoutProfile};
+
+/// Named [`IsoLayoutProfile`] presets a manifest can select.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum Profile {
+    #[default]
+    Hardware,
+    Emulator,
+}
+
+/// On-disk form of an image description.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    volume_id: Option<String>,
+    #[serde(default)]
+    files: Vec<IsoImageFile>,
+    bios_boot: Option<BiosBootInfo>,
+    uefi_boot: Option<UefiBootInfo>,
+    #[serde(default)]
+    isohybrid: bool,
+    #[serde(default)]
+    profile: Profile,
+}
+
+/// Reads the JSON manifest at `path` and returns the image it describes
+/// with the [`HybridMode`] selected by its `isohybrid` flag, ready for
+/// [`crate::build_iso`].  Relative source paths are taken relative to the
+/// manifest's directory.  Fails with `InvalidData` if the manifest does not
+/// parse or has unknown fields.
+pub fn load_manifest(path: &Path) -> io::Result<(IsoImage, HybridMode)> {
+    let text = std::fs::read_to_string(path)?;
+    let m: Manifest = serde_json::from_str(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", path.display()),
+        )
+    })?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let rebase = |p: &mut PathBuf| *p = base.join(&*p);
+
+    let mut files = m.files;
+    files.iter_mut().for_each(|f| rebase(&mut f.source));
+    let mut bios_boot = m.bios_boot;
+    if let Some(bios) = &mut bios_boot {
+        rebase(&mut bios.boot_image);
+    }
+    let mut uefi_boot = m.uefi_boot;
+    if let Some(uefi) = &mut uefi_boot {
+        rebase(&mut uefi.boot_image);
+        uefi.kernel_image.iter_mut().for_each(rebase);
+        for (_, src) in &mut uefi.additional_efi_boot_files {
+            rebase(src);
+        }
+        for (src, _) in &mut uefi.esp_files {
+            rebase(src);
+        }
+    }
+    let image = IsoImage {
+        volume_id: m.volume_id,
+        files,
+        boot_info: BootInfo {
+            bios_boot,
+            uefi_boot,
+        },
+        layout_profile: match m.profile {
+            Profile::Hardware => IsoLayoutProfile::hardware(),
+            Profile::Emulator => IsoLayoutProfile::emulator(),
+        },
+    };
+    Ok((image, m.isohybrid.into()))
+}
+
+impl IsoImage {
+    /// Like [`load_manifest`], keeping only the image.
+    pub fn from_manifest(path: &Path) -> io::Result<IsoImage> {
+        load_manifest(path).map(|(image, _)| image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iso::builder::build_iso;
+    use crate::iso::reader::IsoReader;
+    use crate::utils::test_utils::efi_stub;
+    use std::fs::File;
+
+    #[test]
+    fn test_manifest_round_trip() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("BOOTX64.EFI"), efi_stub(4096))?;
+        std::fs::write(dir.path().join("isolinux.bin"), vec![0u8; 2048])?;
+        std::fs::write(dir.path().join("readme.txt"), b"hello")?;
+        let manifest = dir.path().join("image.json");
+        std::fs::write(
+            &manifest,
+            r#"{
+                "volume_id": "MANIFEST",
+                "files": [{ "source": "readme.txt", "destination": "docs/readme.txt" }],
+                "bios_boot": {
+                    "boot_image": "isolinux.bin",
+                    "destination_in_iso": "isolinux/isolinux.bin",
+                    "boot_catalog": "isolinux/boot.cat",
+                    "load_sectors": null
+                },
+                "uefi_boot": {
+                    "boot_image": "BOOTX64.EFI",
+                    "kernel_image": null,
+                    "destination_in_iso": "EFI/BOOT/BOOTX64.EFI",
+                    "grub_cfg_content": null
+                },
+                "isohybrid": true,
+                "profile": "emulator"
+            }"#,
+        )?;
+
+        let (image, hybrid) = load_manifest(&manifest)?;
+        assert_eq!(hybrid, HybridMode::GptUefi);
+        assert_eq!(image.volume_id.as_deref(), Some("MANIFEST"));
+        assert_eq!(image.files[0].source, dir.path().join("readme.txt"));
+        assert_eq!(image.files[0].destination, "docs/readme.txt");
+        let bios = image.boot_info.bios_boot.as_ref().unwrap();
+        assert_eq!(bios.platform_id, 0);
+        assert_eq!(bios.boot_catalog.as_deref(), Some("isolinux/boot.cat"));
+        let uefi = image.boot_info.uefi_boot.as_ref().unwrap();
+        assert_eq!(uefi.boot_image, dir.path().join("BOOTX64.EFI"));
+        assert!(uefi.esp_files.is_empty() && !uefi.skip_pe_validation);
+        assert_eq!(
+            image.layout_profile.hidden_sectors_mode,
+            IsoLayoutProfile::emulator().hidden_sectors_mode
+        );
+        assert_eq!(
+            IsoImage::from_manifest(&manifest)?.volume_id.as_deref(),
+            Some("MANIFEST")
+        );
+
+        let iso_path = dir.path().join("out.iso");
+        build_iso(&iso_path, &image, hybrid)?;
+        let data = std::fs::read(&iso_path)?;
+        assert_eq!(&data[16 * 2048 + 40..16 * 2048 + 48], b"MANIFEST");
+        IsoReader::new(File::open(&iso_path)?).extract_esp()?;
+
+        std::fs::write(&manifest, r#"{ "files": [], "isohybird": true }"#)?;
+        let err = IsoImage::from_manifest(&manifest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("isohybird"), "{err}");
+        Ok(())
+    }
+}
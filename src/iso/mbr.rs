@@ -125,6 +125,21 @@ pub fn create_mbr_for_gpt_hybrid(
     Ok(mbr)
 }
 
+/// Builds a classic BIOS-only isohybrid MBR: a single bootable type-0x17
+/// partition spanning the whole image, with `boot_code` in bytes 0..440.
+pub fn create_mbr_for_bios_hybrid(total_lbas: u32, boot_code: &[u8]) -> io::Result<Mbr> {
+    if boot_code.len() > 440 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("MBR boot code too large ({} > 440 bytes)", boot_code.len()),
+        ));
+    }
+    let mut mbr = Mbr::new();
+    mbr.boot_code[..boot_code.len()].copy_from_slice(boot_code);
+    set_part(&mut mbr.partition_table[0], 0x80, 0x17, 0, total_lbas);
+    Ok(mbr)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -163,6 +178,19 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_bios_hybrid() -> io::Result<()> {
+        let mbr = create_mbr_for_bios_hybrid(4000, &[0xFA, 0x33, 0xC0])?;
+        assert_eq!(&mbr.boot_code[..3], &[0xFA, 0x33, 0xC0]);
+        let p0 = &mbr.partition_table[0];
+        assert_eq!({ p0.bootable }, 0x80);
+        assert_eq!({ p0.partition_type }, 0x17);
+        assert_eq!({ p0.starting_lba }, 0);
+        assert_eq!({ p0.size_in_lba }, 4000);
+        assert!(create_mbr_for_bios_hybrid(4000, &[0u8; 441]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_write() -> io::Result<()> {
         let mbr = Mbr::new();
@@ -1,14 +1,23 @@
+pub mod aligned_writer;
+pub mod apm;
+pub mod autoconfig;
 pub mod boot_catalog;
 pub mod boot_info;
 pub mod builder;
 pub mod builder_utils;
 pub mod constants;
 pub mod dir_record;
+pub mod discinfo;
 pub mod disk_layout;
 pub mod fs_node;
 pub mod gpt; // Re-add this to make the gpt module accessible
 pub mod iso_image;
 pub mod iso_writer;
 pub mod layout_profile;
+#[cfg(feature = "serde")]
+pub mod manifest;
 pub mod mbr;
+pub mod pe;
+pub mod reader;
+pub mod rock_ridge;
 pub mod volume_descriptor;
@@ -0,0 +1,120 @@
+// isobemak/src/iso/pe.rs
+
+use std::io;
+use std::path::Path;
+
+/// PE Optional Header magic for PE32+ (64-bit) images.
+pub const PE32_PLUS_MAGIC: u16 = 0x20B;
+/// PE Optional Header magic for PE32 images (e.g. `BOOTIA32.EFI`).
+pub const PE32_MAGIC: u16 = 0x10B;
+/// Optional Header subsystem of an EFI application.
+pub const IMAGE_SUBSYSTEM_EFI_APPLICATION: u16 = 10;
+
+const E_LFANEW: usize = 0x3C;
+const COFF_HEADER_LEN: usize = 20;
+const SUBSYSTEM_OFFSET: usize = 68;
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("not a UEFI application: {what}"),
+    )
+}
+
+fn u16_at(b: &[u8], o: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(b.get(o..o + 2)?.try_into().ok()?))
+}
+
+/// Checks that `image` starts with DOS (`MZ`) and PE (`PE\0\0`) headers and
+/// that its Optional Header is PE32+ (or PE32) with the EFI Application
+/// subsystem.
+pub fn validate_efi_application(image: &[u8]) -> io::Result<()> {
+    if !image.starts_with(b"MZ") {
+        return Err(invalid("missing MZ signature"));
+    }
+    let pe = image
+        .get(E_LFANEW..E_LFANEW + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or_else(|| invalid("truncated DOS header"))?;
+    if image.get(pe..pe.saturating_add(4)) != Some(b"PE\0\0") {
+        return Err(invalid("missing PE signature"));
+    }
+    let coff = pe + 4;
+    let opt_len = u16_at(image, coff + 16).ok_or_else(|| invalid("truncated COFF header"))?;
+    if (opt_len as usize) < SUBSYSTEM_OFFSET + 2 {
+        return Err(invalid("Optional Header too short"));
+    }
+    let opt = coff + COFF_HEADER_LEN;
+    match u16_at(image, opt) {
+        Some(PE32_PLUS_MAGIC | PE32_MAGIC) => {}
+        Some(m) => return Err(invalid(&format!("unknown Optional Header magic {m:#x}"))),
+        None => return Err(invalid("truncated Optional Header")),
+    }
+    match u16_at(image, opt + SUBSYSTEM_OFFSET) {
+        Some(IMAGE_SUBSYSTEM_EFI_APPLICATION) => Ok(()),
+        Some(s) => Err(invalid(&format!(
+            "subsystem is {s}, expected {IMAGE_SUBSYSTEM_EFI_APPLICATION} (EFI application)"
+        ))),
+        None => Err(invalid("truncated Optional Header")),
+    }
+}
+
+/// Like [`validate_efi_application`] for the file at `path`; the error names
+/// the file.
+pub fn validate_efi_application_file(path: &Path) -> io::Result<()> {
+    let image = std::fs::read(path)?;
+    validate_efi_application(&image)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::efi_stub;
+
+    #[test]
+    fn test_valid_stub() {
+        validate_efi_application(&efi_stub(1024)).unwrap();
+        let mut ia32 = efi_stub(1024);
+        ia32[0x98..0x9A].copy_from_slice(&PE32_MAGIC.to_le_bytes());
+        validate_efi_application(&ia32).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_non_efi() {
+        let cases: Vec<(Vec<u8>, &str)> = vec![
+            (vec![0u8; 4096], "MZ"),
+            (b"MZ".to_vec(), "DOS header"),
+            (
+                {
+                    let mut b = efi_stub(1024);
+                    b[0x3C..0x40].copy_from_slice(&u32::MAX.to_le_bytes());
+                    b
+                },
+                "PE signature",
+            ),
+            (
+                {
+                    let mut b = efi_stub(1024);
+                    b[0x98..0x9A].copy_from_slice(&0x107u16.to_le_bytes());
+                    b
+                },
+                "magic",
+            ),
+            (
+                {
+                    let mut b = efi_stub(1024);
+                    b[0xDC..0xDE].copy_from_slice(&3u16.to_le_bytes());
+                    b
+                },
+                "subsystem is 3",
+            ),
+            (efi_stub(1024)[..0xC0].to_vec(), "truncated"),
+        ];
+        for (image, needle) in cases {
+            let err = validate_efi_application(&image).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains(needle), "{err} !~ {needle}");
+        }
+    }
+}
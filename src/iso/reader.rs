@@ -0,0 +1,339 @@
+// isobemak/src/iso/reader.rs
+
+use std::collections::HashSet;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use uuid::Uuid;
+
+use crate::iso::gpt::header::GptHeader;
+use crate::iso::gpt::main_gpt_functions::parse_gpt;
+use crate::iso::gpt::partition_entry::{
+    EFI_SYSTEM_PARTITION_GUID, GptPartitionEntry, uuid_to_gpt_mixed_endian,
+};
+use crate::utils::{ISO_SECTOR_SIZE, read_both_endian_u32};
+
+/// Bytes from the start of the image that may hold the primary GPT; bounds
+/// the read for corrupt headers.
+const MAX_GPT_AREA: u64 = 2 << 20;
+
+/// Offset of the root directory record within the PVD.
+const PVD_ROOT_RECORD: usize = 156;
+
+/// Reads structures back from a built image.
+pub struct IsoReader<R: Read + Seek> {
+    inner: R,
+    strict: bool,
+}
+
+/// A directory record as read by [`IsoReader::list_paths`].
+struct Record {
+    name: String,
+    lba: u32,
+    size: u32,
+    is_dir: bool,
+}
+
+impl<R: Read + Seek> IsoReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            strict: false,
+        }
+    }
+
+    /// In strict mode, reading a directory also fails with `InvalidData`
+    /// on a record of odd length or one whose even-length identifier is not
+    /// followed by a zero pad byte, as ECMA-119 9.1.12 requires.
+    pub fn set_strict(&mut self, v: bool) {
+        self.strict = v;
+    }
+
+    /// Paths of every file and directory in the primary volume's tree,
+    /// depth first in on-disk order.  Directories end in `/` and version
+    /// suffixes (`;1`) are dropped.  Fails with `InvalidData` on a
+    /// malformed or looping tree, and in strict mode (see
+    /// [`Self::set_strict`]) on badly padded records.
+    pub fn list_paths(&mut self) -> io::Result<Vec<String>> {
+        let mut pvd = [0u8; ISO_SECTOR_SIZE];
+        self.inner
+            .seek(SeekFrom::Start(16 * ISO_SECTOR_SIZE as u64))?;
+        self.inner.read_exact(&mut pvd)?;
+        if pvd[0] != 1 || &pvd[1..6] != b"CD001" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no Primary Volume Descriptor at LBA 16",
+            ));
+        }
+        let root = &pvd[PVD_ROOT_RECORD..PVD_ROOT_RECORD + 34];
+        let (lba, size) = (
+            read_both_endian_u32(root, 2)?,
+            read_both_endian_u32(root, 10)?,
+        );
+
+        let mut paths = Vec::new();
+        let mut visited = HashSet::from([lba]);
+        let mut stack = vec![(String::from("/"), self.read_records(lba, size)?.into_iter())];
+        while let Some((prefix, records)) = stack.last_mut() {
+            let Some(rec) = records.next() else {
+                stack.pop();
+                continue;
+            };
+            let path = format!("{prefix}{}", rec.name);
+            if !rec.is_dir {
+                paths.push(path);
+                continue;
+            }
+            if !visited.insert(rec.lba) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("directory '{path}' at LBA {} was already read", rec.lba),
+                ));
+            }
+            let children = self.read_records(rec.lba, rec.size)?;
+            paths.push(format!("{path}/"));
+            stack.push((format!("{path}/"), children.into_iter()));
+        }
+        Ok(paths)
+    }
+
+    /// Reads the directory extent of `size` bytes at `lba` and returns its
+    /// records other than `.` and `..`.
+    fn read_records(&mut self, lba: u32, size: u32) -> io::Result<Vec<Record>> {
+        let bad = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        self.inner
+            .seek(SeekFrom::Start(lba as u64 * ISO_SECTOR_SIZE as u64))?;
+        let mut extent = Vec::new();
+        (&mut self.inner)
+            .take(size as u64)
+            .read_to_end(&mut extent)?;
+        if extent.len() != size as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("directory at LBA {lba} extends past the end of the image"),
+            ));
+        }
+        let mut records = Vec::new();
+        let mut off = 0;
+        while off < extent.len() {
+            let len = extent[off] as usize;
+            // Records do not cross sectors; the rest of this one is unused.
+            if len == 0 {
+                off = (off / ISO_SECTOR_SIZE + 1) * ISO_SECTOR_SIZE;
+                continue;
+            }
+            let at = || format!("LBA {lba} offset {off}");
+            let rec = extent
+                .get(off..off + len)
+                .filter(|_| len >= 34)
+                .ok_or_else(|| bad(format!("truncated directory record at {}", at())))?;
+            let id_len = rec[32] as usize;
+            let id = rec
+                .get(33..33 + id_len)
+                .ok_or_else(|| bad(format!("identifier overruns the record at {}", at())))?;
+            if self.strict {
+                if !len.is_multiple_of(2) {
+                    return Err(bad(format!(
+                        "odd directory record length {len} at {}",
+                        at()
+                    )));
+                }
+                if id_len.is_multiple_of(2) && rec.get(33 + id_len) != Some(&0) {
+                    return Err(bad(format!(
+                        "missing pad byte after identifier at {}",
+                        at()
+                    )));
+                }
+            }
+            if id != [0] && id != [1] {
+                let name = String::from_utf8_lossy(id);
+                records.push(Record {
+                    name: name.split(';').next().unwrap_or_default().to_string(),
+                    lba: read_both_endian_u32(rec, 2)?,
+                    size: read_both_endian_u32(rec, 10)?,
+                    is_dir: rec[25] & 0x02 != 0,
+                });
+            }
+            off += len;
+        }
+        Ok(records)
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Returns the bytes of the EFI System Partition listed in the image's
+    /// GPT (512- or 4096-byte logical sectors), e.g. to open it with
+    /// `fatfs`.  Fails with `NotFound` if there is no GPT or no ESP entry.
+    pub fn extract_esp(&mut self) -> io::Result<Vec<u8>> {
+        let esp_type = uuid_to_gpt_mixed_endian(
+            &Uuid::parse_str(EFI_SYSTEM_PARTITION_GUID).map_err(io::Error::other)?,
+        );
+        let mut last_err = None;
+        for sector_size in [512u64, 4096] {
+            let entries = match self.read_gpt(sector_size) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    last_err = Some(e);
+                    continue;
+                }
+            };
+            let esp = entries
+                .iter()
+                .find(|e| e.partition_type_guid == esp_type)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "GPT has no EFI System Partition")
+                })?;
+            let (start, end) = (esp.starting_lba, esp.ending_lba);
+            let range = end
+                .checked_sub(start)
+                .and_then(|n| (n + 1).checked_mul(sector_size))
+                .zip(start.checked_mul(sector_size));
+            let Some((len, offset)) = range else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid ESP range {start}..={end}"),
+                ));
+            };
+            self.inner.seek(SeekFrom::Start(offset))?;
+            let mut esp_bytes = Vec::new();
+            (&mut self.inner).take(len).read_to_end(&mut esp_bytes)?;
+            if esp_bytes.len() as u64 != len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "ESP extends past the end of the image",
+                ));
+            }
+            return Ok(esp_bytes);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no valid GPT found: {}", last_err.unwrap()),
+        ))
+    }
+
+    /// Reads the sectors holding the primary GPT and returns its used
+    /// partition entries.
+    fn read_gpt(&mut self, sector_size: u64) -> io::Result<Vec<GptPartitionEntry>> {
+        let mut sector = vec![0u8; sector_size as usize];
+        self.inner.seek(SeekFrom::Start(sector_size))?;
+        self.inner.read_exact(&mut sector)?;
+        let header = GptHeader::from_bytes(&sector)?;
+        let (lba, count, size) = (
+            header.partition_entry_lba,
+            header.num_partition_entries as u64,
+            header.partition_entry_size as u64,
+        );
+        let end = lba
+            .checked_mul(sector_size)
+            .and_then(|o| o.checked_add(count * size))
+            .filter(|&end| end <= MAX_GPT_AREA)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "GPT partition array out of range",
+                )
+            })?;
+        let mut disk = Vec::new();
+        self.inner.seek(SeekFrom::Start(0))?;
+        (&mut self.inner).take(end).read_to_end(&mut disk)?;
+        parse_gpt(&disk, 1, sector_size).map(|(_, entries)| entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iso::boot_info::{BootInfo, UefiBootInfo};
+    use crate::iso::builder::build_iso;
+    use crate::iso::iso_image::IsoImage;
+    use crate::iso::layout_profile::IsoLayoutProfile;
+    use crate::utils::test_utils::efi_stub;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_strict_directory_padding() -> io::Result<()> {
+        use crate::iso::builder::IsoBuilder;
+
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("a.txt");
+        std::fs::write(&src, b"data")?;
+        let mut b = IsoBuilder::new();
+        // Identifiers of 7 (`A.TXT;1`), 8 (`AB.TXT;1`) and 2 (`DD`) bytes.
+        b.add_file("A.TXT", &src)?;
+        b.add_file("AB.TXT", &src)?;
+        b.add_file("DD/ABC", &src)?;
+        let mut out = Cursor::new(Vec::new());
+        b.build_to(&mut out, None, None)?;
+        let mut data = out.into_inner();
+
+        let strict_paths = |data: &[u8]| {
+            let mut reader = IsoReader::new(Cursor::new(data));
+            reader.set_strict(true);
+            reader.list_paths()
+        };
+        assert_eq!(
+            strict_paths(&data)?,
+            ["/A.TXT", "/AB.TXT", "/DD/", "/DD/ABC"]
+        );
+
+        // The record of `AB.TXT;1`, whose pad byte follows the identifier.
+        let root_lba = read_both_endian_u32(&data[16 * 2048 + PVD_ROOT_RECORD..], 2)? as usize;
+        let mut off = root_lba * 2048;
+        while &data[off + 33..off + 33 + data[off + 32] as usize] != b"AB.TXT;1" {
+            off += data[off] as usize;
+        }
+        let pad = off + 33 + 8;
+        data[pad] = 0xFF;
+        let err = strict_paths(&data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("pad byte"), "{err}");
+        assert_eq!(IsoReader::new(Cursor::new(&data)).list_paths()?.len(), 4);
+
+        data[pad] = 0;
+        data[off] -= 1;
+        let err = strict_paths(&data).unwrap_err();
+        assert!(err.to_string().contains("odd"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_esp() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let loader = dir.path().join("BOOTX64.EFI");
+        std::fs::write(&loader, efi_stub(4096))?;
+        let image = IsoImage {
+            volume_id: None,
+            files: Vec::new(),
+            boot_info: BootInfo {
+                bios_boot: None,
+                uefi_boot: Some(UefiBootInfo {
+                    boot_image: loader.clone(),
+                    destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
+                    ..Default::default()
+                }),
+            },
+            layout_profile: IsoLayoutProfile::default(),
+        };
+        let iso_path = dir.path().join("esp.iso");
+        build_iso(&iso_path, &image, true)?;
+
+        let esp = IsoReader::new(File::open(&iso_path)?).extract_esp()?;
+        let fs = fatfs::FileSystem::new(Cursor::new(esp), fatfs::FsOptions::new())
+            .map_err(io::Error::other)?;
+        let mut content = Vec::new();
+        fs.root_dir()
+            .open_file("EFI/BOOT/BOOTX64.EFI")?
+            .read_to_end(&mut content)?;
+        assert_eq!(content, std::fs::read(&loader)?);
+
+        // A plain ISO has no GPT.
+        let err = IsoReader::new(Cursor::new(vec![0u8; 64 * 1024]))
+            .extract_esp()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+// isobemak/src/iso/rock_ridge.rs
+
+//! Minimal Rock Ridge (RRIP 1.10 over SUSP) System Use entries.
+
+use std::io;
+
+const SUSP_VERSION: u8 = 1;
+
+/// Extension identifier announced in the root `ER` entry.
+pub const RRIP_ID: &[u8] = b"RRIP_1991A";
+const RRIP_DESCRIPTOR: &[u8] = b"ROCK RIDGE POSIX EXTENSIONS";
+const RRIP_SOURCE: &[u8] = b"IEEE P1282";
+
+/// POSIX file modes stored in `PX` entries.
+pub const MODE_DIR: u32 = 0o040_555;
+pub const MODE_FILE: u32 = 0o100_444;
+pub const MODE_EXECUTABLE: u32 = 0o100_555;
+pub const MODE_SYMLINK: u32 = 0o120_777;
+
+const SL_CURRENT: u8 = 0x02;
+const SL_PARENT: u8 = 0x04;
+const SL_ROOT: u8 = 0x08;
+
+fn entry(sig: &[u8; 2], body: &[u8]) -> Vec<u8> {
+    let mut e = Vec::with_capacity(4 + body.len());
+    e.extend_from_slice(sig);
+    e.push((4 + body.len()) as u8);
+    e.push(SUSP_VERSION);
+    e.extend_from_slice(body);
+    e
+}
+
+fn both_endian(v: u32) -> [u8; 8] {
+    let mut b = [0u8; 8];
+    b[..4].copy_from_slice(&v.to_le_bytes());
+    b[4..].copy_from_slice(&v.to_be_bytes());
+    b
+}
+
+/// `SP` entry; must open the SUSP entries of the root's `.` record.
+/// `skip` is the number of bytes preceding the SUSP entries in every System
+/// Use area (e.g. a CD-ROM XA field).
+pub fn sp_entry(skip: u8) -> Vec<u8> {
+    entry(b"SP", &[0xBE, 0xEF, skip])
+}
+
+/// `ER` entry identifying the Rock Ridge extension.
+pub fn er_entry() -> Vec<u8> {
+    let mut body = vec![
+        RRIP_ID.len() as u8,
+        RRIP_DESCRIPTOR.len() as u8,
+        RRIP_SOURCE.len() as u8,
+        1,
+    ];
+    body.extend_from_slice(RRIP_ID);
+    body.extend_from_slice(RRIP_DESCRIPTOR);
+    body.extend_from_slice(RRIP_SOURCE);
+    entry(b"ER", &body)
+}
+
+/// `PX` entry with the given mode and link count, owned by uid/gid 0.
+pub fn px_entry(mode: u32, links: u32) -> Vec<u8> {
+    let mut body = Vec::with_capacity(32);
+    body.extend_from_slice(&both_endian(mode));
+    body.extend_from_slice(&both_endian(links));
+    body.extend_from_slice(&both_endian(0));
+    body.extend_from_slice(&both_endian(0));
+    entry(b"PX", &body)
+}
+
+/// `CL` entry on the placeholder left where a deep directory was moved
+/// from; points at the relocated directory.
+pub fn cl_entry(dir_lba: u32) -> Vec<u8> {
+    entry(b"CL", &both_endian(dir_lba))
+}
+
+/// `PL` entry on the `..` record of a relocated directory; points at its
+/// original parent.
+pub fn pl_entry(parent_lba: u32) -> Vec<u8> {
+    entry(b"PL", &both_endian(parent_lba))
+}
+
+/// `RE` entry marking a relocated directory's record in `rr_moved`.
+pub fn re_entry() -> Vec<u8> {
+    entry(b"RE", &[])
+}
+
+/// `NM` entry carrying the unmangled file name.
+pub fn nm_entry(name: &str) -> io::Result<Vec<u8>> {
+    let mut body = vec![0u8];
+    body.extend_from_slice(name.as_bytes());
+    checked_entry(b"NM", &body, name)
+}
+
+/// `SL` entry encoding `target` as a sequence of path components.
+pub fn sl_entry(target: &str) -> io::Result<Vec<u8>> {
+    if target.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "symlink target is empty",
+        ));
+    }
+    let mut body = vec![0u8];
+    if target.starts_with('/') {
+        body.extend_from_slice(&[SL_ROOT, 0]);
+    }
+    for comp in target.split('/').filter(|c| !c.is_empty()) {
+        match comp {
+            "." => body.extend_from_slice(&[SL_CURRENT, 0]),
+            ".." => body.extend_from_slice(&[SL_PARENT, 0]),
+            _ => {
+                body.extend_from_slice(&[0, comp.len() as u8]);
+                body.extend_from_slice(comp.as_bytes());
+            }
+        }
+    }
+    checked_entry(b"SL", &body, target)
+}
+
+fn checked_entry(sig: &[u8; 2], body: &[u8], what: &str) -> io::Result<Vec<u8>> {
+    if 4 + body.len() > u8::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "'{what}' is too long for a single {} entry",
+                String::from_utf8_lossy(sig)
+            ),
+        ));
+    }
+    Ok(entry(sig, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_entries() {
+        assert_eq!(sp_entry(0), [b'S', b'P', 7, 1, 0xBE, 0xEF, 0]);
+        let px = px_entry(MODE_FILE, 1);
+        assert_eq!(px.len(), 36);
+        assert_eq!(px[2], 36);
+        assert_eq!(px[4..8], MODE_FILE.to_le_bytes());
+        assert_eq!(px[8..12], MODE_FILE.to_be_bytes());
+        let er = er_entry();
+        assert_eq!(er[2] as usize, er.len());
+        assert_eq!(&er[8..8 + RRIP_ID.len()], RRIP_ID);
+        assert_eq!(re_entry(), [b'R', b'E', 4, 1]);
+        let cl = cl_entry(0x1234);
+        assert_eq!(&cl[..4], [b'C', b'L', 12, 1]);
+        assert_eq!(cl[4..8], 0x1234u32.to_le_bytes());
+        assert_eq!(cl[8..12], 0x1234u32.to_be_bytes());
+        assert_eq!(&pl_entry(7)[..4], [b'P', b'L', 12, 1]);
+    }
+
+    #[test]
+    fn test_sl_components() -> io::Result<()> {
+        let sl = sl_entry("/usr/../lib/./x")?;
+        assert_eq!(&sl[..2], b"SL");
+        assert_eq!(sl[2] as usize, sl.len());
+        assert_eq!(
+            &sl[5..],
+            [
+                &[SL_ROOT, 0][..],
+                &[0, 3],
+                b"usr",
+                &[SL_PARENT, 0],
+                &[0, 3],
+                b"lib",
+                &[SL_CURRENT, 0],
+                &[0, 1],
+                b"x",
+            ]
+            .concat()
+        );
+        assert!(sl_entry("").is_err());
+        assert!(sl_entry(&"a/".repeat(200)).is_err());
+        Ok(())
+    }
+}
@@ -1,9 +1,11 @@
 use crate::iso::boot_catalog::LBA_BOOT_CATALOG;
-use crate::iso::dir_record::IsoDirEntry;
-use crate::utils::{ISO_SECTOR_SIZE, seek_to_lba};
-use std::fs::File;
+use crate::iso::dir_record::{IsoDirEntry, recording_date};
+use crate::utils::{ISO_SECTOR_SIZE, SectorWriter};
 use std::io::{self, Seek, SeekFrom, Write};
+use std::time::SystemTime;
 
+/// LBA of the Primary Volume Descriptor, the first of the set.
+const PVD_LBA: u32 = 16;
 const PVD_VOL_ID: usize = 40;
 const PVD_TOTAL_SEC: usize = 80;
 const PVD_ROOT_DIR: usize = 156;
@@ -11,6 +13,131 @@ const PVD_VOL_SET_SIZE: usize = 120;
 const PVD_VOL_SEQ_NUM: usize = 124;
 const PVD_LOGICAL_BLOCK: usize = 128;
 const PVD_PATH_TABLE: usize = 132;
+const PVD_PUBLISHER_ID: usize = 318;
+const PVD_PREPARER_ID: usize = 446;
+const PVD_APPLICATION_ID: usize = 574;
+const PVD_ID_FIELD_LEN: usize = 128;
+const PVD_CREATION_DATE: usize = 813;
+const PVD_MODIFICATION_DATE: usize = 830;
+const PVD_EXPIRATION_DATE: usize = 847;
+const PVD_EFFECTIVE_DATE: usize = 864;
+/// Written for an unset creation or modification date.
+const DEFAULT_VOLUME_DATE: &[u8; 17] = b"2024010100000000\x00";
+const PVD_APPLICATION_USE: usize = 883;
+/// Size of the PVD application-use area (bytes 883–1394).
+pub const PVD_APPLICATION_USE_LEN: usize = 512;
+/// Offset of the CD-ROM XA signature within the PVD (inside the
+/// application-use area).
+pub const PVD_CD_XA_OFFSET: usize = 1024;
+/// Signature identifying a CD-ROM XA volume.
+pub const CD_XA_SIGNATURE: &[u8; 8] = b"CD-XA001";
+
+/// Optional PVD fields that callers may stamp into the descriptor.
+#[derive(Debug, Clone, Default)]
+pub struct PvdOptions {
+    pub publisher_id: Option<String>,
+    pub preparer_id: Option<String>,
+    pub application_id: Option<String>,
+    /// Raw bytes for the application-use area (≤512 bytes, zero-padded).
+    pub application_use: Option<Vec<u8>>,
+    /// Stamp [`CD_XA_SIGNATURE`] at [`PVD_CD_XA_OFFSET`].
+    pub cd_xa: bool,
+    pub dates: VolumeDates,
+}
+
+/// The four volume date fields of the PVD.  An unset creation or
+/// modification date is written as 2024-01-01, so that images stay
+/// reproducible; unset expiration and effective dates are left zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VolumeDates {
+    pub creation: Option<SystemTime>,
+    pub modification: Option<SystemTime>,
+    /// After this time the volume is considered obsolete.
+    pub expiration: Option<SystemTime>,
+    /// Before this time the volume should not be used.
+    pub effective: Option<SystemTime>,
+}
+
+/// Encodes `time` as a 17-byte volume date (ECMA-119 8.4.26.1): the ASCII
+/// digits `YYYYMMDDHHMMSSHH`, down to hundredths of a second, and a GMT
+/// offset of zero.  Fails with `InvalidInput` for times before 1900 or
+/// after 2155, which directory record dates cannot hold either.
+pub fn volume_date(time: SystemTime) -> io::Result<[u8; 17]> {
+    let [year, month, day, hour, minute, second, _] = recording_date(time);
+    if month == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Volume date {time:?} is outside 1900-2155"),
+        ));
+    }
+    let hundredths = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_millis() / 10);
+    let digits = format!(
+        "{:04}{month:02}{day:02}{hour:02}{minute:02}{second:02}{hundredths:02}",
+        1900 + u32::from(year)
+    );
+    let mut date = [0u8; 17];
+    date[..16].copy_from_slice(digits.as_bytes());
+    Ok(date)
+}
+
+fn is_a_character(c: char) -> bool {
+    c.is_ascii_uppercase()
+        || c.is_ascii_digit()
+        || matches!(
+            c,
+            ' ' | '_'
+                | '!'
+                | '"'
+                | '%'
+                | '&'
+                | '\''
+                | '('
+                | ')'
+                | '*'
+                | '+'
+                | ','
+                | '-'
+                | '.'
+                | '/'
+                | ':'
+                | ';'
+                | '<'
+                | '='
+                | '>'
+                | '?'
+        )
+}
+
+/// Validates a PVD identifier field: at most 128 ISO 9660 a-characters.
+pub fn validate_pvd_identifier(field: &str, value: &str) -> io::Result<()> {
+    if value.len() > PVD_ID_FIELD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{field} is too long ({} > {PVD_ID_FIELD_LEN} bytes)",
+                value.len()
+            ),
+        ));
+    }
+    if let Some(c) = value.chars().find(|&c| !is_a_character(c)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{field} contains invalid a-character {c:?}"),
+        ));
+    }
+    Ok(())
+}
+
+fn write_identifier(buf: &mut [u8], off: usize, value: Option<&str>) {
+    if let Some(v) = value {
+        let field = &mut buf[off..off + PVD_ID_FIELD_LEN];
+        field.fill(b' ');
+        let n = v.len().min(PVD_ID_FIELD_LEN);
+        field[..n].copy_from_slice(&v.as_bytes()[..n]);
+    }
+}
 
 fn write_dual(buf: &mut [u8], off: usize, val: u32, len: usize) {
     let le = val.to_le_bytes();
@@ -24,17 +151,46 @@ fn write_dual(buf: &mut [u8], off: usize, val: u32, len: usize) {
     }
 }
 
-pub fn write_primary_volume_descriptor(
-    iso: &mut File,
+pub fn write_primary_volume_descriptor<W: Write + Seek>(
+    iso: &mut W,
     volume_id: Option<&str>,
     total_sectors: u32,
     root_entry: &IsoDirEntry,
+    opts: &PvdOptions,
 ) -> io::Result<()> {
-    seek_to_lba(iso, 16)?;
+    let pvd = render_volume_descriptor(volume_id, total_sectors, root_entry, opts, false)?;
+    SectorWriter::new(iso).write_at_lba(16, &pvd)
+}
+
+/// Writes an ISO 9660:1999 Enhanced Volume Descriptor (type 2, version 2)
+/// at `lba`: the PVD's fields with `root_entry` pointing at the directory
+/// hierarchy with relaxed names.
+pub fn write_enhanced_volume_descriptor<W: Write + Seek>(
+    iso: &mut W,
+    lba: u32,
+    volume_id: Option<&str>,
+    total_sectors: u32,
+    root_entry: &IsoDirEntry,
+    opts: &PvdOptions,
+) -> io::Result<()> {
+    let evd = render_volume_descriptor(volume_id, total_sectors, root_entry, opts, true)?;
+    SectorWriter::new(iso).write_at_lba(lba, &evd)
+}
+
+/// Renders the PVD, or with `enhanced` the EVD, which differs only in its
+/// type and version fields.
+fn render_volume_descriptor(
+    volume_id: Option<&str>,
+    total_sectors: u32,
+    root_entry: &IsoDirEntry,
+    opts: &PvdOptions,
+    enhanced: bool,
+) -> io::Result<[u8; ISO_SECTOR_SIZE]> {
+    let version = if enhanced { 2 } else { 1 };
     let mut pvd = [0u8; ISO_SECTOR_SIZE];
-    pvd[0] = 1; // primary
+    pvd[0] = if enhanced { 2 } else { 1 };
     pvd[1..6].copy_from_slice(b"CD001");
-    pvd[6] = 1;
+    pvd[6] = version;
 
     let name = volume_id.map_or(b"ISOBEMAKI" as &[u8], |id| {
         &id.as_bytes()[..id.len().min(32)]
@@ -49,56 +205,192 @@ pub fn write_primary_volume_descriptor(
     write_dual(&mut pvd, PVD_LOGICAL_BLOCK, ISO_SECTOR_SIZE as u32, 2);
     write_dual(&mut pvd, PVD_PATH_TABLE, 0, 4);
 
-    let re = root_entry.to_bytes();
+    let re = root_entry.to_bytes()?;
     pvd[PVD_ROOT_DIR..PVD_ROOT_DIR + re.len()].copy_from_slice(&re);
-    pvd[881] = 1;
-    pvd[813..830].copy_from_slice(b"2024010100000000\x00");
-    pvd[830..847].copy_from_slice(b"2024010100000000\x00");
-    iso.write_all(&pvd)
+    write_identifier(&mut pvd, PVD_PUBLISHER_ID, opts.publisher_id.as_deref());
+    write_identifier(&mut pvd, PVD_PREPARER_ID, opts.preparer_id.as_deref());
+    write_identifier(&mut pvd, PVD_APPLICATION_ID, opts.application_id.as_deref());
+    if let Some(data) = &opts.application_use {
+        let n = data.len().min(PVD_APPLICATION_USE_LEN);
+        pvd[PVD_APPLICATION_USE..PVD_APPLICATION_USE + n].copy_from_slice(&data[..n]);
+        if opts.cd_xa && PVD_APPLICATION_USE + n > PVD_CD_XA_OFFSET {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "application use data overlaps the CD-XA signature (max {} bytes with CD-XA)",
+                    PVD_CD_XA_OFFSET - PVD_APPLICATION_USE
+                ),
+            ));
+        }
+    }
+    if opts.cd_xa {
+        pvd[PVD_CD_XA_OFFSET..PVD_CD_XA_OFFSET + CD_XA_SIGNATURE.len()]
+            .copy_from_slice(CD_XA_SIGNATURE);
+    }
+    pvd[881] = version; // file structure version
+    let dates = &opts.dates;
+    for (offset, date, default) in [
+        (PVD_CREATION_DATE, dates.creation, Some(DEFAULT_VOLUME_DATE)),
+        (
+            PVD_MODIFICATION_DATE,
+            dates.modification,
+            Some(DEFAULT_VOLUME_DATE),
+        ),
+        (PVD_EXPIRATION_DATE, dates.expiration, None),
+        (PVD_EFFECTIVE_DATE, dates.effective, None),
+    ] {
+        let field = match date {
+            Some(time) => volume_date(time)?,
+            None => *default.unwrap_or(&[0; 17]),
+        };
+        pvd[offset..offset + 17].copy_from_slice(&field);
+    }
+    Ok(pvd)
+}
+
+pub fn update_total_sectors_in_pvd<W: Write + Seek>(
+    iso: &mut W,
+    total_sectors: u32,
+) -> io::Result<()> {
+    update_total_sectors_in_vd(iso, PVD_LBA, total_sectors)
 }
 
-pub fn update_total_sectors_in_pvd(iso: &mut File, total_sectors: u32) -> io::Result<()> {
-    let base = 16 * ISO_SECTOR_SIZE as u64;
+/// Like [`update_total_sectors_in_pvd`] for the volume descriptor at `lba`,
+/// e.g. an Enhanced Volume Descriptor.
+pub fn update_total_sectors_in_vd<W: Write + Seek>(
+    iso: &mut W,
+    lba: u32,
+    total_sectors: u32,
+) -> io::Result<()> {
+    let base = lba as u64 * ISO_SECTOR_SIZE as u64;
     iso.seek(SeekFrom::Start(base + PVD_TOTAL_SEC as u64))?;
     iso.write_all(&total_sectors.to_le_bytes())?;
     iso.seek(SeekFrom::Start(base + PVD_TOTAL_SEC as u64 + 4))?;
     iso.write_all(&total_sectors.to_be_bytes())
 }
 
-fn write_boot_record_vd(iso: &mut File) -> io::Result<()> {
-    seek_to_lba(iso, 17)?;
+fn render_boot_record_vd(catalog_lba: u32) -> [u8; ISO_SECTOR_SIZE] {
     let mut brvd = [0u8; ISO_SECTOR_SIZE];
     brvd[0] = 0;
     brvd[1..6].copy_from_slice(b"CD001");
     brvd[6] = 1;
     brvd[7..30].copy_from_slice(b"EL TORITO SPECIFICATION");
-    brvd[71..75].copy_from_slice(&LBA_BOOT_CATALOG.to_le_bytes());
-    iso.write_all(&brvd)
+    brvd[71..75].copy_from_slice(&catalog_lba.to_le_bytes());
+    brvd
 }
 
-fn write_terminator(iso: &mut File) -> io::Result<()> {
-    seek_to_lba(iso, 18)?;
+fn write_terminator<W: Write + Seek>(iso: &mut W, lba: u32) -> io::Result<()> {
     let mut t = [0u8; ISO_SECTOR_SIZE];
     t[0] = 255;
     t[1..6].copy_from_slice(b"CD001");
     t[6] = 1;
-    iso.write_all(&t)
+    SectorWriter::new(iso).write_at_lba(lba, &t)
+}
+
+/// LBA of the volume descriptor set terminator, which directly follows the
+/// PVD (LBA 16), the El Torito boot record (LBA 17) and the Enhanced Volume
+/// Descriptor, each only when present.
+pub fn terminator_lba(el_torito: bool, enhanced: bool) -> u32 {
+    17 + u32::from(el_torito) + u32::from(enhanced)
+}
+
+/// LBA of the boot catalog: [`LBA_BOOT_CATALOG`], or the sector after it
+/// when an Enhanced Volume Descriptor pushes the terminator onto it.
+pub fn boot_catalog_lba(enhanced: bool) -> u32 {
+    LBA_BOOT_CATALOG + u32::from(enhanced)
 }
 
-pub fn write_volume_descriptors(
-    iso: &mut File,
+/// One descriptor of the volume descriptor set.
+#[derive(Clone)]
+pub enum VolumeDescriptor<'a> {
+    /// The Primary Volume Descriptor with `root` as its root directory.
+    Primary { root: &'a IsoDirEntry<'a> },
+    /// The El Torito boot record, pointing at the catalog in the sector
+    /// right after the terminator.
+    BootRecord,
+    /// An El Torito boot record pointing at the catalog at the given LBA,
+    /// which must lie past the terminator, e.g. to leave room for more
+    /// descriptors.
+    BootRecordAt(u32),
+    /// An ISO 9660:1999 Enhanced Volume Descriptor with `root` as its root.
+    Enhanced { root: &'a IsoDirEntry<'a> },
+    /// A descriptor rendered by the caller, e.g. a Joliet Supplementary
+    /// Volume Descriptor, written as is.
+    Raw(Box<[u8; ISO_SECTOR_SIZE]>),
+}
+
+/// Writes `descriptors` in order from LBA 16, followed by the set
+/// terminator, and returns the first LBA after the terminator: where a boot
+/// record expects the catalog, and otherwise the first free sector.  The
+/// PVD must come first and appear once.  There may be several boot records,
+/// e.g. a second catalog for firmware that scans every boot record, but at
+/// most one [`VolumeDescriptor::BootRecord`], and no two may point at the
+/// same catalog.  A [`VolumeDescriptor::BootRecordAt`] catalog at or before
+/// the terminator fails with `InvalidInput`.
+pub fn write_volume_descriptors<W: Write + Seek>(
+    iso: &mut W,
     volume_id: Option<&str>,
     total_sectors: u32,
-    root_entry: &IsoDirEntry,
-) -> io::Result<()> {
-    write_primary_volume_descriptor(iso, volume_id, total_sectors, root_entry)?;
-    write_boot_record_vd(iso)?;
-    write_terminator(iso)
+    opts: &PvdOptions,
+    descriptors: &[VolumeDescriptor],
+) -> io::Result<u32> {
+    let count = |f: fn(&VolumeDescriptor) -> bool| descriptors.iter().filter(|d| f(d)).count();
+    if !matches!(descriptors.first(), Some(VolumeDescriptor::Primary { .. }))
+        || count(|d| matches!(d, VolumeDescriptor::Primary { .. })) != 1
+        || count(|d| matches!(d, VolumeDescriptor::BootRecord)) > 1
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "volume descriptor set must start with its only PVD and have at most one \
+             implicitly placed boot record",
+        ));
+    }
+    let terminator = PVD_LBA + descriptors.len() as u32;
+    let mut catalogs = Vec::new();
+    for d in descriptors {
+        let lba = match d {
+            VolumeDescriptor::BootRecord => terminator + 1,
+            VolumeDescriptor::BootRecordAt(lba) => *lba,
+            _ => continue,
+        };
+        if lba <= terminator {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("boot catalog LBA {lba} is not past the terminator at LBA {terminator}"),
+            ));
+        }
+        if catalogs.contains(&lba) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("two boot records point at the boot catalog at LBA {lba}"),
+            ));
+        }
+        catalogs.push(lba);
+    }
+    let mut w = SectorWriter::new(iso);
+    for (lba, d) in (PVD_LBA..).zip(descriptors) {
+        let sector = match d {
+            VolumeDescriptor::Primary { root } => {
+                render_volume_descriptor(volume_id, total_sectors, root, opts, false)?
+            }
+            VolumeDescriptor::BootRecord => render_boot_record_vd(terminator + 1),
+            VolumeDescriptor::BootRecordAt(catalog_lba) => render_boot_record_vd(*catalog_lba),
+            VolumeDescriptor::Enhanced { root } => {
+                render_volume_descriptor(volume_id, total_sectors, root, opts, true)?
+            }
+            VolumeDescriptor::Raw(sector) => **sector,
+        };
+        w.write_at_lba(lba, &sector)?;
+    }
+    write_terminator(w.into_inner(), terminator)?;
+    Ok(terminator + 1)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::read_both_endian_u32;
+    use std::fs::File;
     use std::io::Read;
     use tempfile::NamedTempFile;
 
@@ -118,12 +410,12 @@ mod tests {
             flags: 2,
             name: ".",
         };
-        write_primary_volume_descriptor(f.as_file_mut(), None, 1000, &re)?;
+        write_primary_volume_descriptor(f.as_file_mut(), None, 1000, &re, &PvdOptions::default())?;
         let s = read_sector(f.as_file_mut(), 16)?;
         assert_eq!(s[0], 1);
         assert_eq!(&s[1..6], b"CD001");
         assert_eq!(&s[PVD_TOTAL_SEC..PVD_TOTAL_SEC + 4], &1000u32.to_le_bytes());
-        let r = re.to_bytes();
+        let r = re.to_bytes()?;
         assert_eq!(&s[PVD_ROOT_DIR..PVD_ROOT_DIR + r.len()], &r);
         Ok(())
     }
@@ -137,22 +429,160 @@ mod tests {
             flags: 2,
             name: ".",
         };
-        write_primary_volume_descriptor(f.as_file_mut(), None, 1000, &re)?;
+        write_primary_volume_descriptor(f.as_file_mut(), None, 1000, &re, &PvdOptions::default())?;
         update_total_sectors_in_pvd(f.as_file_mut(), 2500)?;
         let s = read_sector(f.as_file_mut(), 16)?;
+        assert_eq!(read_both_endian_u32(&s, PVD_TOTAL_SEC)?, 2500);
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_vds() -> io::Result<()> {
+        let re = IsoDirEntry {
+            lba: 20,
+            size: 2048,
+            flags: 2,
+            name: ".",
+        };
+        let write = |descriptors: &[VolumeDescriptor]| -> io::Result<(NamedTempFile, u32)> {
+            let mut f = NamedTempFile::new()?;
+            let next = write_volume_descriptors(
+                f.as_file_mut(),
+                None,
+                1234,
+                &PvdOptions::default(),
+                descriptors,
+            )?;
+            Ok((f, next))
+        };
+        let pvd = VolumeDescriptor::Primary { root: &re };
+
+        let (mut f, next) = write(&[pvd.clone(), VolumeDescriptor::BootRecord])?;
+        assert_eq!(read_sector(f.as_file_mut(), 16)?[0], 1);
+        assert_eq!(read_sector(f.as_file_mut(), 17)?[0], 0);
+        assert_eq!(read_sector(f.as_file_mut(), 18)?[0], 255);
+        assert_eq!(next, 19);
+        assert_eq!(next, terminator_lba(true, false) + 1);
+
+        let (mut f, next) = write(std::slice::from_ref(&pvd))?;
+        assert_eq!(read_sector(f.as_file_mut(), 17)?[0], 255);
+        assert_eq!(next, 18);
+
+        // The EVD follows the boot record and moves the catalog back.
+        let enhanced_root = IsoDirEntry { lba: 30, ..re };
+        let (mut f, next) = write(&[
+            pvd.clone(),
+            VolumeDescriptor::BootRecord,
+            VolumeDescriptor::Enhanced {
+                root: &enhanced_root,
+            },
+        ])?;
+        assert_eq!(next, boot_catalog_lba(true));
+        let br = read_sector(f.as_file_mut(), 17)?;
+        assert_eq!(br[0], 0);
+        assert_eq!(br[71..75], 20u32.to_le_bytes());
+        let evd = read_sector(f.as_file_mut(), 18)?;
+        assert_eq!((evd[0], evd[6], evd[881]), (2, 2, 2));
+        assert_eq!(&evd[1..6], b"CD001");
+        let r = enhanced_root.to_bytes()?;
+        assert_eq!(&evd[PVD_ROOT_DIR..PVD_ROOT_DIR + r.len()], &r);
+        assert_eq!(read_sector(f.as_file_mut(), 19)?[0], 255);
+        update_total_sectors_in_vd(f.as_file_mut(), 18, 4321)?;
+        let evd = read_sector(f.as_file_mut(), 18)?;
+        assert_eq!(read_both_endian_u32(&evd, PVD_TOTAL_SEC)?, 4321);
+
+        // A relocated catalog; it cannot sit on the terminator.
+        let (mut f, next) = write(&[pvd.clone(), VolumeDescriptor::BootRecordAt(25)])?;
+        assert_eq!(
+            read_sector(f.as_file_mut(), 17)?[71..75],
+            25u32.to_le_bytes()
+        );
+        assert_eq!(next, 19);
+        let err = write(&[pvd.clone(), VolumeDescriptor::BootRecordAt(18)]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // A second boot record for another catalog moves the terminator.
+        let (mut f, next) = write(&[
+            pvd.clone(),
+            VolumeDescriptor::BootRecord,
+            VolumeDescriptor::BootRecordAt(25),
+        ])?;
         assert_eq!(
-            u32::from_le_bytes(s[PVD_TOTAL_SEC..PVD_TOTAL_SEC + 4].try_into().unwrap()),
-            2500
+            read_sector(f.as_file_mut(), 17)?[71..75],
+            20u32.to_le_bytes()
         );
         assert_eq!(
-            u32::from_be_bytes(s[PVD_TOTAL_SEC + 4..PVD_TOTAL_SEC + 8].try_into().unwrap()),
-            2500
+            read_sector(f.as_file_mut(), 18)?[71..75],
+            25u32.to_le_bytes()
         );
+        assert_eq!(read_sector(f.as_file_mut(), 19)?[0], 255);
+        assert_eq!(next, 20);
+
+        // The PVD must lead, once.
+        for bad in [
+            vec![VolumeDescriptor::BootRecord, pvd.clone()],
+            vec![pvd.clone(), pvd.clone()],
+            vec![
+                pvd.clone(),
+                VolumeDescriptor::BootRecord,
+                VolumeDescriptor::BootRecord,
+            ],
+            vec![
+                pvd.clone(),
+                VolumeDescriptor::BootRecord,
+                VolumeDescriptor::BootRecordAt(20),
+            ],
+            vec![
+                pvd.clone(),
+                VolumeDescriptor::BootRecordAt(25),
+                VolumeDescriptor::BootRecordAt(25),
+            ],
+            vec![],
+        ] {
+            let err = write(&bad).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
         Ok(())
     }
 
     #[test]
-    fn test_all_vds() -> io::Result<()> {
+    fn test_extra_descriptor_moves_terminator() -> io::Result<()> {
+        let re = IsoDirEntry {
+            lba: 21,
+            size: 2048,
+            flags: 2,
+            name: ".",
+        };
+        // A Joliet Supplementary Volume Descriptor rendered by the caller.
+        let mut svd = Box::new([0u8; ISO_SECTOR_SIZE]);
+        svd[0] = 2;
+        svd[1..6].copy_from_slice(b"CD001");
+        svd[6] = 1;
+        svd[88..91].copy_from_slice(b"%/E");
+        let mut f = NamedTempFile::new()?;
+        let next = write_volume_descriptors(
+            f.as_file_mut(),
+            None,
+            100,
+            &PvdOptions::default(),
+            &[
+                VolumeDescriptor::Primary { root: &re },
+                VolumeDescriptor::BootRecord,
+                VolumeDescriptor::Raw(svd.clone()),
+            ],
+        )?;
+        assert_eq!(read_sector(f.as_file_mut(), 18)?, *svd);
+        assert_eq!(read_sector(f.as_file_mut(), 19)?[0], 255);
+        assert_eq!(next, 20);
+        assert_eq!(
+            read_sector(f.as_file_mut(), 17)?[71..75],
+            20u32.to_le_bytes()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pvd_identifiers() -> io::Result<()> {
         let mut f = NamedTempFile::new()?;
         let re = IsoDirEntry {
             lba: 20,
@@ -160,10 +590,50 @@ mod tests {
             flags: 2,
             name: ".",
         };
-        write_volume_descriptors(f.as_file_mut(), None, 1234, &re)?;
-        assert_eq!(read_sector(f.as_file_mut(), 16)?[0], 1);
-        assert_eq!(read_sector(f.as_file_mut(), 17)?[0], 0);
-        assert_eq!(read_sector(f.as_file_mut(), 18)?[0], 255);
+        let opts = PvdOptions {
+            publisher_id: Some("ACME LINUX PROJECT".into()),
+            preparer_id: Some("BUILD-42".into()),
+            application_id: Some("ISOBEMAK".into()),
+            ..Default::default()
+        };
+        write_primary_volume_descriptor(f.as_file_mut(), None, 1000, &re, &opts)?;
+        let s = read_sector(f.as_file_mut(), 16)?;
+        for (off, want) in [
+            (PVD_PUBLISHER_ID, "ACME LINUX PROJECT"),
+            (PVD_PREPARER_ID, "BUILD-42"),
+            (PVD_APPLICATION_ID, "ISOBEMAK"),
+        ] {
+            let field = &s[off..off + PVD_ID_FIELD_LEN];
+            assert_eq!(&field[..want.len()], want.as_bytes());
+            assert!(field[want.len()..].iter().all(|&b| b == b' '));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_pvd_application_use() -> io::Result<()> {
+        let mut f = NamedTempFile::new()?;
+        let re = IsoDirEntry {
+            lba: 20,
+            size: 2048,
+            flags: 2,
+            name: ".",
+        };
+        let opts = PvdOptions {
+            application_use: Some(b"BUILD-ID:0123456789abcdef".to_vec()),
+            ..Default::default()
+        };
+        write_primary_volume_descriptor(f.as_file_mut(), None, 1000, &re, &opts)?;
+        let s = read_sector(f.as_file_mut(), 16)?;
+        assert_eq!(&s[883..908], b"BUILD-ID:0123456789abcdef");
+        assert!(s[908..1395].iter().all(|&b| b == 0));
         Ok(())
     }
+
+    #[test]
+    fn test_validate_pvd_identifier() {
+        assert!(validate_pvd_identifier("Publisher ID", "ACME 1.0").is_ok());
+        assert!(validate_pvd_identifier("Publisher ID", "lowercase").is_err());
+        assert!(validate_pvd_identifier("Publisher ID", &"A".repeat(129)).is_err());
+    }
 }
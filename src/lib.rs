@@ -7,20 +7,37 @@ pub mod fat;
 pub mod iso;
 
 // Re-export the main function for external use.
-pub use iso::boot_info::{BiosBootInfo, BootInfo, UefiBootInfo};
-pub use iso::builder::IsoBuilder;
+pub use iso::aligned_writer::AlignedWriter;
+pub use iso::autoconfig::{AutoConfig, AutoConfigFormat};
+pub use iso::boot_catalog::BootEmulation;
+pub use iso::boot_info::{BiosBootInfo, BootInfo, BootModes, UefiBootInfo};
 pub use iso::builder::build_iso;
+#[cfg(feature = "tokio")]
+pub use iso::builder::build_iso_async;
+pub use iso::builder::{BuildOutput, EspImage, IsoBuilder, PostProcess};
 pub use iso::constants::BACKUP_GPT_RESERVED_512;
 pub use iso::constants::DISK_SECTOR_SIZE;
 pub use iso::constants::ESP_START_LBA_512;
 pub use iso::constants::GPT_RESERVED_512_SECTORS;
 pub use iso::constants::ISO_SECTOR_SIZE;
+pub use iso::constants::SYSTEM_AREA_SECTORS;
+pub use iso::constants::SYSTEM_AREA_SIZE;
 pub use iso::constants::disk512_to_iso;
 pub use iso::constants::iso_to_512;
+pub use iso::dir_record::TimestampSource;
+pub use iso::discinfo::DiscInfo;
 pub use iso::disk_layout::{DiskLayout, IsoRegion, Partition, UefiBootStrategy};
-pub use iso::fs_node::{IsoDirectory, IsoFile, IsoFsNode};
+pub use iso::fs_node::{IsoDirectory, IsoFile, IsoFsNode, IsoSymlink, ReaderSource};
 pub use iso::iso_image::{IsoImage, IsoImageFile}; // Re-export ESP_START_LBA
-pub use iso::layout_profile::{ElToritoMode, EspMode, HiddenSectorMode, IsoLayoutProfile, MbrMode};
+pub use iso::iso_writer::SetLen;
+pub use iso::layout_profile::{
+    ElToritoMode, EspMode, HiddenSectorMode, HybridMode, IsoLayoutProfile, MbrMode,
+};
+#[cfg(feature = "serde")]
+pub use iso::manifest::load_manifest;
+pub use iso::pe::{validate_efi_application, validate_efi_application_file};
+pub use iso::reader::IsoReader;
+pub use iso::volume_descriptor::VolumeDates;
 
 #[cfg(test)]
 mod tests {
@@ -46,6 +63,10 @@ mod tests {
         let isolinux_bin_path = files.get("isolinux.bin").unwrap().clone();
         let isolinux_cfg_path = files.get("isolinux.cfg").unwrap().clone();
         let bootx64_efi_path = files.get("BOOTX64.EFI").unwrap().clone();
+        std::fs::write(
+            &bootx64_efi_path,
+            crate::utils::test_utils::efi_stub(64 * 1024),
+        )?;
         let kernel_path = files.get("kernel").unwrap().clone();
         let initrd_img_path = files.get("initrd.img").unwrap().clone();
 
@@ -74,13 +95,13 @@ mod tests {
                 bios_boot: Some(BiosBootInfo {
                     boot_image: isolinux_bin_path.clone(),
                     destination_in_iso: "isolinux/isolinux.bin".to_string(),
+                    ..Default::default()
                 }),
                 uefi_boot: Some(UefiBootInfo {
                     boot_image: bootx64_efi_path.clone(),
-                    kernel_image: kernel_path.clone(),
+                    kernel_image: Some(kernel_path.clone()),
                     destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
-                    additional_efi_boot_files: Vec::new(),
-                    grub_cfg_content: None,
+                    ..Default::default()
                 }),
             },
             layout_profile: IsoLayoutProfile::default(),
@@ -111,9 +132,7 @@ mod tests {
 
         let mut iso_file = std::fs::File::open(&iso_output_path)?;
         let mut catalog_sector = [0u8; ISO_SECTOR_SIZE as usize];
-        iso_file.seek(SeekFrom::Start(
-            LBA_BOOT_CATALOG as u64 * ISO_SECTOR_SIZE as u64,
-        ))?;
+        iso_file.seek(SeekFrom::Start(LBA_BOOT_CATALOG as u64 * ISO_SECTOR_SIZE))?;
         iso_file.read_exact(&mut catalog_sector)?;
 
         // The Initial/Default Entry is at offset 32 in the catalog.
@@ -127,9 +146,7 @@ mod tests {
 
         // Read the boot info table at offset 8 within the boot image's sector.
         let mut table = [0u8; 56];
-        iso_file.seek(SeekFrom::Start(
-            boot_image_lba as u64 * ISO_SECTOR_SIZE as u64 + 8,
-        ))?;
+        iso_file.seek(SeekFrom::Start(boot_image_lba as u64 * ISO_SECTOR_SIZE + 8))?;
         iso_file.read_exact(&mut table)?;
 
         // PVD is always at LBA 16.
@@ -154,7 +171,7 @@ mod tests {
         let boot_image_size = size as u64;
         let mut expected_checksum = 0u32;
         if boot_image_size > 64 {
-            let sample_offset = boot_image_lba as u64 * ISO_SECTOR_SIZE as u64 + 64;
+            let sample_offset = boot_image_lba as u64 * ISO_SECTOR_SIZE + 64;
             let mut buf = vec![0u8; (boot_image_size - 64) as usize];
             iso_file.seek(SeekFrom::Start(sample_offset))?;
             iso_file.read_exact(&mut buf)?;
@@ -1,13 +1,106 @@
-use std::fs::File;
-use std::io::{self, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 
 pub const ISO_SECTOR_SIZE: usize = 2048;
 
-pub fn seek_to_lba(file: &mut File, lba: u32) -> io::Result<u64> {
+pub fn seek_to_lba<S: Seek>(file: &mut S, lba: u32) -> io::Result<u64> {
     let target_pos = lba as u64 * ISO_SECTOR_SIZE as u64;
     file.seek(SeekFrom::Start(target_pos))
 }
 
+/// A `Write + Seek` stream addressed in ISO sectors.
+///
+/// Wrap `&mut W` to use it on a borrowed stream; it is `Write + Seek`
+/// itself, so byte-level writes can be mixed with the sector helpers.
+pub struct SectorWriter<W> {
+    inner: W,
+}
+
+impl<W: Write + Seek> SectorWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the wrapped stream.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes `data` starting at the first byte of sector `lba`; the stream
+    /// is left just past it.
+    pub fn write_at_lba(&mut self, lba: u32, data: &[u8]) -> io::Result<()> {
+        seek_to_lba(&mut self.inner, lba)?;
+        self.inner.write_all(data)
+    }
+
+    /// Zero-fills from the current position up to the start of sector
+    /// `lba`.  Fails with `InvalidInput` if the position is already past it.
+    pub fn pad_to_lba(&mut self, lba: u32) -> io::Result<()> {
+        let pos = self.inner.stream_position()?;
+        let target = lba as u64 * ISO_SECTOR_SIZE as u64;
+        let gap = target.checked_sub(pos).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("stream position {pos} is already past LBA {lba}"),
+            )
+        })?;
+        io::copy(&mut io::repeat(0).take(gap), &mut self.inner)?;
+        Ok(())
+    }
+
+    /// First sector starting at or after the current position: the sector
+    /// being written if the position is sector-aligned, else the next one.
+    pub fn current_lba(&mut self) -> io::Result<u32> {
+        let pos = self.inner.stream_position()?;
+        u32::try_from(pos.div_ceil(ISO_SECTOR_SIZE as u64)).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("stream position {pos} is beyond the last addressable LBA"),
+            )
+        })
+    }
+}
+
+impl<W: Write> Write for SectorWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.inner.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for SectorWriter<W> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(to)
+    }
+}
+
+/// Reads an ISO 9660 both-byte-order `u32` (7.3.3): the little-endian value
+/// at `le_offset` followed by the big-endian copy.  Fails with
+/// `UnexpectedEof` if `buf` is too short and `InvalidData` if the halves
+/// disagree.
+pub fn read_both_endian_u32(buf: &[u8], le_offset: usize) -> io::Result<u32> {
+    let field = le_offset
+        .checked_add(8)
+        .and_then(|end| buf.get(le_offset..end))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("both-endian field at offset {le_offset} exceeds buffer"),
+            )
+        })?;
+    let le = u32::from_le_bytes(field[..4].try_into().unwrap());
+    let be = u32::from_be_bytes(field[4..].try_into().unwrap());
+    if le != be {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("both-endian field at offset {le_offset} disagrees (LE {le}, BE {be})"),
+        ));
+    }
+    Ok(le)
+}
+
 /// Helper macro to create consistent IO errors
 #[macro_export]
 macro_rules! io_error {
@@ -81,6 +174,21 @@ pub mod test_utils {
         Ok(path)
     }
 
+    /// Returns a minimal PE32+ EFI application image of `size` bytes (at
+    /// least 512): DOS header, PE signature, AMD64 COFF header and an
+    /// Optional Header with the EFI Application subsystem.
+    pub fn efi_stub(size: usize) -> Vec<u8> {
+        let mut b = vec![0u8; size.max(512)];
+        b[0..2].copy_from_slice(b"MZ");
+        b[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        b[0x80..0x84].copy_from_slice(b"PE\0\0");
+        b[0x84..0x86].copy_from_slice(&0x8664u16.to_le_bytes());
+        b[0x94..0x96].copy_from_slice(&240u16.to_le_bytes());
+        b[0x98..0x9A].copy_from_slice(&0x20Bu16.to_le_bytes());
+        b[0xDC..0xDE].copy_from_slice(&10u16.to_le_bytes());
+        b
+    }
+
     /// A macro to simplify the creation of multiple dummy files.
     #[macro_export]
     macro_rules! create_dummy_files {
@@ -96,3 +204,61 @@ pub mod test_utils {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sector_writer() -> io::Result<()> {
+        let mut w = SectorWriter::new(Cursor::new(vec![0xFFu8; 10]));
+        assert_eq!(w.current_lba()?, 0);
+        w.write_at_lba(2, b"abc")?;
+        assert_eq!(w.stream_position()?, 2 * 2048 + 3);
+        assert_eq!(w.current_lba()?, 3);
+
+        w.pad_to_lba(3)?;
+        assert_eq!(w.current_lba()?, 3);
+        w.pad_to_lba(3)?;
+        assert_eq!(w.stream_position()?, 3 * 2048);
+        let err = w.pad_to_lba(1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let data = w.into_inner().into_inner();
+        assert_eq!(data.len(), 3 * 2048);
+        assert_eq!(data[..10], [0xFF; 10]);
+        assert!(data[10..2 * 2048].iter().all(|&b| b == 0));
+        assert_eq!(&data[2 * 2048..2 * 2048 + 3], b"abc");
+        assert!(data[2 * 2048 + 3..].iter().all(|&b| b == 0));
+
+        let mut w = SectorWriter::new(Cursor::new(Vec::new()));
+        w.seek(SeekFrom::Start(u32::MAX as u64 * 2048))?;
+        assert!(w.current_lba().is_ok());
+        w.seek(SeekFrom::Current(1))?;
+        assert_eq!(
+            w.current_lba().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_both_endian_u32() {
+        let mut buf = vec![0xEE; 2];
+        buf.extend_from_slice(&0x1234_5678u32.to_le_bytes());
+        buf.extend_from_slice(&0x1234_5678u32.to_be_bytes());
+        assert_eq!(read_both_endian_u32(&buf, 2).unwrap(), 0x1234_5678);
+
+        let err = read_both_endian_u32(&buf, 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            read_both_endian_u32(&buf, usize::MAX).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        buf[9] ^= 1;
+        let err = read_both_endian_u32(&buf, 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
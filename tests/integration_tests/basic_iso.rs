@@ -1,3 +1,4 @@
+use isobemak::iso::boot_catalog::validation_checksum;
 use std::path::Path;
 use std::{
     fs::File,
@@ -47,10 +48,9 @@ fn test_create_disk_and_iso() -> io::Result<()> {
             bios_boot: None, // Not testing BIOS boot in this specific test
             uefi_boot: Some(UefiBootInfo {
                 boot_image: bootx64_path.clone(),
-                kernel_image: kernel_path.clone(),
+                kernel_image: Some(kernel_path.clone()),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
-                additional_efi_boot_files: Vec::new(),
-                grub_cfg_content: None,
+                ..Default::default()
             }),
         },
         layout_profile: IsoLayoutProfile::default(),
@@ -142,12 +142,11 @@ fn test_create_disk_and_iso() -> io::Result<()> {
     let mut boot_catalog = [0u8; 32]; // Only need the validation entry
     iso_file.read_exact(&mut boot_catalog)?;
 
-    let mut sum: u16 = 0;
-    for chunk in boot_catalog.chunks_exact(2) {
-        sum = sum.wrapping_add(u16::from_le_bytes(chunk.try_into().unwrap()));
-    }
-
-    assert_eq!(sum, 0, "Boot catalog validation entry checksum should be 0");
+    assert_eq!(
+        validation_checksum(&boot_catalog),
+        u16::from_le_bytes([boot_catalog[28], boot_catalog[29]]),
+        "Boot catalog validation entry checksum should match"
+    );
 
     // Perform deeper binary verification of ISO structures
     verify_iso_binary_structures(&mut iso_file)?;
@@ -0,0 +1,264 @@
+//! Boots built images under QEMU (`--features qemu-tests`).
+//!
+//! The other integration tests inspect the image with `isoinfo`, `7z` and
+//! `dumpet`; this harness goes one step further and lets firmware load the
+//! image.  Tests are skipped, not failed, when `qemu-system-x86_64` or an
+//! OVMF build is not installed.  Set `OVMF_CODE` to use a firmware image
+//! outside the usual distribution paths.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use isobemak::{BiosBootInfo, BootInfo, IsoImage, IsoLayoutProfile, UefiBootInfo, build_iso};
+use tempfile::tempdir;
+
+/// Printed on the serial console by [`marker_efi_stub`] and
+/// [`marker_bios_stub`].
+pub const BOOT_MARKER: &str = "ISOBEMAK-BOOT-OK";
+
+/// How long QEMU may run before the marker is considered missing.
+const BOOT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// OVMF images shipped by common distributions, in order of preference.
+const OVMF_PATHS: &[&str] = &[
+    "/usr/share/OVMF/OVMF_CODE.fd",
+    "/usr/share/OVMF/OVMF_CODE_4M.fd",
+    "/usr/share/ovmf/OVMF.fd",
+    "/usr/share/edk2/ovmf/OVMF_CODE.fd",
+    "/usr/share/edk2/x64/OVMF_CODE.fd",
+    "/usr/share/edk2-ovmf/x64/OVMF_CODE.fd",
+    "/usr/share/qemu/OVMF.fd",
+];
+
+/// Firmware QEMU boots the image with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    /// QEMU's built-in SeaBIOS.
+    Bios,
+    /// OVMF, located with [`find_ovmf`].
+    Uefi,
+}
+
+#[derive(Debug)]
+pub enum BootResult {
+    /// QEMU or the firmware is not installed; nothing was run.
+    Skipped(String),
+    /// QEMU ran; `marker_found` tells whether [`BOOT_MARKER`] appeared on
+    /// the serial console before the timeout.
+    Ran { marker_found: bool, serial: String },
+}
+
+/// The OVMF image named by `OVMF_CODE`, or the first of [`OVMF_PATHS`] that
+/// exists.
+pub fn find_ovmf() -> Option<PathBuf> {
+    std::env::var_os("OVMF_CODE")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(OVMF_PATHS.iter().map(PathBuf::from))
+        .find(|p| p.is_file())
+}
+
+/// Boots `iso_path` as a CD-ROM under `qemu-system-x86_64` with `firmware`
+/// and the serial console captured to a file, and watches it for
+/// [`BOOT_MARKER`] until it appears or [`BOOT_TIMEOUT`] passes.  QEMU is
+/// killed either way.
+pub fn try_qemu_boot(iso_path: &Path, firmware: Firmware) -> io::Result<BootResult> {
+    let log_dir = tempdir()?;
+    let serial_log = log_dir.path().join("serial.log");
+
+    let mut cmd = Command::new("qemu-system-x86_64");
+    cmd.args(["-machine", "q35", "-m", "256", "-display", "none"])
+        .args(["-monitor", "none", "-no-reboot", "-cdrom"])
+        .arg(iso_path)
+        .arg("-serial")
+        .arg(format!("file:{}", serial_log.display()))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if firmware == Firmware::Uefi {
+        let Some(ovmf) = find_ovmf() else {
+            return Ok(BootResult::Skipped("OVMF not found".into()));
+        };
+        cmd.arg("-drive").arg(format!(
+            "if=pflash,format=raw,readonly=on,file={}",
+            ovmf.display()
+        ));
+    }
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(BootResult::Skipped("qemu-system-x86_64 not found".into()));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let deadline = Instant::now() + BOOT_TIMEOUT;
+    let serial = loop {
+        let serial = std::fs::read(&serial_log)
+            .map(|b| String::from_utf8_lossy(&b).into_owned())
+            .unwrap_or_default();
+        let exited = child.try_wait()?.is_some();
+        if serial.contains(BOOT_MARKER) || exited || Instant::now() >= deadline {
+            break serial;
+        }
+        thread::sleep(Duration::from_millis(200));
+    };
+    // QEMU may already have exited, in which case kill fails harmlessly.
+    let _ = child.kill();
+    child.wait()?;
+    Ok(BootResult::Ran {
+        marker_found: serial.contains(BOOT_MARKER),
+        serial,
+    })
+}
+
+/// A minimal x86-64 PE32+ EFI application that prints [`BOOT_MARKER`] with
+/// `ConOut->OutputString` and returns `EFI_SUCCESS`.  OVMF mirrors the
+/// console to the serial port.
+pub fn marker_efi_stub() -> Vec<u8> {
+    let mut b = vec![0u8; 0x400];
+    let put16 = |b: &mut [u8], o: usize, v: u16| b[o..o + 2].copy_from_slice(&v.to_le_bytes());
+    let put32 = |b: &mut [u8], o: usize, v: u32| b[o..o + 4].copy_from_slice(&v.to_le_bytes());
+
+    b[0..2].copy_from_slice(b"MZ");
+    put32(&mut b, 0x3C, 0x40);
+    b[0x40..0x44].copy_from_slice(b"PE\0\0");
+    // COFF header.
+    put16(&mut b, 0x44, 0x8664); // AMD64
+    put16(&mut b, 0x46, 1); // NumberOfSections
+    put16(&mut b, 0x54, 240); // SizeOfOptionalHeader
+    put16(&mut b, 0x56, 0x0022); // executable, large address aware
+    // PE32+ optional header.
+    let opt = 0x58;
+    put16(&mut b, opt, 0x20B);
+    put32(&mut b, opt + 4, 0x200); // SizeOfCode
+    put32(&mut b, opt + 16, 0x1000); // AddressOfEntryPoint
+    put32(&mut b, opt + 20, 0x1000); // BaseOfCode
+    b[opt + 24..opt + 32].copy_from_slice(&0x1000_0000u64.to_le_bytes()); // ImageBase
+    put32(&mut b, opt + 32, 0x1000); // SectionAlignment
+    put32(&mut b, opt + 36, 0x200); // FileAlignment
+    put32(&mut b, opt + 56, 0x2000); // SizeOfImage
+    put32(&mut b, opt + 60, 0x200); // SizeOfHeaders
+    put16(&mut b, opt + 68, 10); // EFI application
+    put32(&mut b, opt + 108, 16); // NumberOfRvaAndSizes
+    // The .text section header.
+    let sec = opt + 240;
+    b[sec..sec + 8].copy_from_slice(b".text\0\0\0");
+    put32(&mut b, sec + 8, 0x200); // VirtualSize
+    put32(&mut b, sec + 12, 0x1000); // VirtualAddress
+    put32(&mut b, sec + 16, 0x200); // SizeOfRawData
+    put32(&mut b, sec + 20, 0x200); // PointerToRawData
+    put32(&mut b, sec + 36, 0x6000_0020); // code, execute, read
+
+    // efi_main(ImageHandle in rcx, SystemTable in rdx); the message
+    // follows at offset 0x20 of the section.
+    let code: [u8; 25] = [
+        0x48, 0x83, 0xEC, 0x28, // sub rsp, 0x28
+        0x48, 0x8B, 0x4A, 0x40, // mov rcx, [rdx + 0x40] ; ConOut
+        0x48, 0x8D, 0x15, 0x11, 0x00, 0x00, 0x00, // lea rdx, [rip + 0x11] ; message
+        0xFF, 0x51, 0x08, // call [rcx + 0x08] ; OutputString
+        0x48, 0x83, 0xC4, 0x28, // add rsp, 0x28
+        0x31, 0xC0, // xor eax, eax
+        0xC3, // ret
+    ];
+    b[0x200..0x200 + code.len()].copy_from_slice(&code);
+    let message: Vec<u8> = format!("{BOOT_MARKER}\r\n\0")
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    b[0x220..0x220 + message.len()].copy_from_slice(&message);
+    b
+}
+
+/// A no-emulation El Torito boot image that writes [`BOOT_MARKER`] to COM1
+/// and halts.  The code starts after the boot information table (offsets
+/// 8-63), which the builder patches in.
+pub fn marker_bios_stub() -> Vec<u8> {
+    let mut b = vec![0u8; 2048];
+    b[0..2].copy_from_slice(&[0xEB, 0x3E]); // jmp 0x40
+    let code: [u8; 22] = [
+        0xE8, 0x00, 0x00, // call next
+        0x5E, // next: pop si
+        0x83, 0xC6, 0x13, // add si, message - next
+        0xBA, 0xF8, 0x03, // mov dx, 0x3F8
+        0x2E, 0xAC, // print: lodsb (from cs:si)
+        0x84, 0xC0, // test al, al
+        0x74, 0x03, // jz halt
+        0xEE, // out dx, al
+        0xEB, 0xF7, // jmp print
+        0xF4, // halt: hlt
+        0xEB, 0xFD, // jmp halt
+    ];
+    b[0x40..0x40 + code.len()].copy_from_slice(&code);
+    let message = format!("{BOOT_MARKER}\r\n\0");
+    b[0x40 + code.len()..][..message.len()].copy_from_slice(message.as_bytes());
+    b
+}
+
+/// Fails the calling test unless the boot printed [`BOOT_MARKER`]; a skipped
+/// boot only logs why.
+fn assert_booted(result: BootResult) {
+    match result {
+        BootResult::Skipped(reason) => eprintln!("skipping QEMU boot test: {reason}"),
+        BootResult::Ran {
+            marker_found,
+            serial,
+        } => assert!(
+            marker_found,
+            "{BOOT_MARKER} not printed; serial output:\n{serial}"
+        ),
+    }
+}
+
+#[test]
+fn test_bios_boot_under_qemu() -> io::Result<()> {
+    let dir = tempdir()?;
+    let stub = dir.path().join("boot.bin");
+    std::fs::write(&stub, marker_bios_stub())?;
+    let iso_path = dir.path().join("boot.iso");
+    let image = IsoImage {
+        volume_id: None,
+        files: Vec::new(),
+        boot_info: BootInfo {
+            bios_boot: Some(BiosBootInfo {
+                boot_image: stub,
+                destination_in_iso: "boot/boot.bin".to_string(),
+                ..Default::default()
+            }),
+            uefi_boot: None,
+        },
+        layout_profile: IsoLayoutProfile::default(),
+    };
+    build_iso(&iso_path, &image, false)?;
+    assert_booted(try_qemu_boot(&iso_path, Firmware::Bios)?);
+    Ok(())
+}
+
+#[test]
+fn test_uefi_boot_under_qemu() -> io::Result<()> {
+    let dir = tempdir()?;
+    let stub = dir.path().join("BOOTX64.EFI");
+    std::fs::write(&stub, marker_efi_stub())?;
+    let iso_path = dir.path().join("boot.iso");
+    let image = IsoImage {
+        volume_id: None,
+        files: Vec::new(),
+        boot_info: BootInfo {
+            bios_boot: None,
+            uefi_boot: Some(UefiBootInfo {
+                boot_image: stub,
+                destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
+                ..Default::default()
+            }),
+        },
+        layout_profile: IsoLayoutProfile::default(),
+    };
+    build_iso(&iso_path, &image, true)?;
+    assert_booted(try_qemu_boot(&iso_path, Firmware::Uefi)?);
+    Ok(())
+}
@@ -43,7 +43,7 @@ fn find_in_dir_flat(
     name: &[u8],
     scratch: &mut [u8; ISO_SECTOR_SIZE],
 ) -> Option<(u32, u32)> {
-    let total_sectors = ((dir_size as u64 + 2047) / 2048) as u32;
+    let total_sectors = (dir_size as u64).div_ceil(2048) as u32;
     for s in 0..total_sectors {
         *scratch = read_file_iso_sector(file, (dir_lba + s) as u64).ok()?;
         let mut offset: usize = 0;
@@ -69,7 +69,7 @@ fn find_in_dir_flat(
                 && scratch[name_offset..name_offset + effective_len]
                     .iter()
                     .zip(name.iter())
-                    .all(|(a, b)| a.to_ascii_uppercase() == b.to_ascii_uppercase())
+                    .all(|(a, b)| a.eq_ignore_ascii_case(b))
             {
                 let child_extent =
                     u32::from_le_bytes(scratch[offset + 2..offset + 6].try_into().unwrap());
@@ -228,9 +228,9 @@ echo 'gaveup' >>/tmp/choosable.log
             && bytes[i + 3] == b'O'
             && bytes[i + 4] == b'D'
         {
-            for j in 0..sr_mod_len {
+            for &b in &sr_mod_line[..sr_mod_len] {
                 if pos < 4095 {
-                    script[pos] = sr_mod_line[j];
+                    script[pos] = b;
                     pos += 1;
                 }
             }
@@ -243,9 +243,9 @@ echo 'gaveup' >>/tmp/choosable.log
             && bytes[i + 4] == b'E'
             && bytes[i + 5] == b'T'
         {
-            for j in off_start..21 {
+            for &b in &off_str[off_start..21] {
                 if pos < 4095 {
-                    script[pos] = off_str[j];
+                    script[pos] = b;
                     pos += 1;
                 }
             }
@@ -321,7 +321,7 @@ fn find_first_overwritable_file_sim(
     dir_size: u32,
     scratch: &mut [u8; ISO_SECTOR_SIZE],
 ) -> Option<(u32, u32, [u8; 16], usize)> {
-    let total_sectors = ((dir_size as u64 + 2047) / 2048) as u32;
+    let total_sectors = (dir_size as u64).div_ceil(2048) as u32;
     for s in 0..total_sectors {
         *scratch = read_file_iso_sector(file, (dir_lba + s) as u64).ok()?;
         let mut offset: usize = 0;
@@ -359,10 +359,10 @@ fn find_first_overwritable_file_sim(
                 }
                 let is_boot_cat = &upper[..cl] == b"BOOT.CATALOG" || &upper[..cl] == b"BOOT.CAT";
                 let has_cfg = eff_len >= 4
-                    && scratch[name_offset + eff_len - 4].to_ascii_uppercase() == b'.'
-                    && scratch[name_offset + eff_len - 3].to_ascii_uppercase() == b'C'
-                    && scratch[name_offset + eff_len - 2].to_ascii_uppercase() == b'F'
-                    && scratch[name_offset + eff_len - 1].to_ascii_uppercase() == b'G';
+                    && scratch[name_offset + eff_len - 4].eq_ignore_ascii_case(&b'.')
+                    && scratch[name_offset + eff_len - 3].eq_ignore_ascii_case(&b'C')
+                    && scratch[name_offset + eff_len - 2].eq_ignore_ascii_case(&b'F')
+                    && scratch[name_offset + eff_len - 1].eq_ignore_ascii_case(&b'G');
                 let is_efi = &upper[..cl] == b"BOOTX64.EFI" || &upper[..cl] == b"BOOTIA32.EFI";
                 if !is_boot_cat && !has_cfg && !is_efi {
                     return Some((dir_lba + s, offset as u32, upper, eff_len));
@@ -381,7 +381,7 @@ fn find_eod_in_dir_sim(
     scratch: &mut [u8; ISO_SECTOR_SIZE],
     dir_size_out: &mut u32,
 ) -> Option<(u32, u32)> {
-    let total_sectors = ((dir_size as u64 + 2047) / 2048) as u32;
+    let total_sectors = (dir_size as u64).div_ceil(2048) as u32;
     let mut walked = 0u32;
     for s in 0..total_sectors {
         *scratch = read_file_iso_sector(file, (dir_lba + s) as u64).ok()?;
@@ -431,10 +431,9 @@ fn make_test_iso_image(bootx64: std::path::PathBuf, kernel: std::path::PathBuf)
             bios_boot: None,
             uefi_boot: Some(UefiBootInfo {
                 boot_image: bootx64,
-                kernel_image: kernel,
+                kernel_image: Some(kernel),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".into(),
-                additional_efi_boot_files: vec![],
-                grub_cfg_content: None,
+                ..Default::default()
             }),
         },
         layout_profile: IsoLayoutProfile::hardware(),
@@ -446,7 +445,7 @@ fn test_choosable_can_resolve_efi_boot_in_isohybrid_iso() -> io::Result<()> {
     let temp_dir = tempdir()?;
     let (bootx64_path, kernel_path, iso_path) = setup_integration_test_files(temp_dir.path())?;
     let image = make_test_iso_image(bootx64_path, kernel_path);
-    let (_iso_path_buf, _temp_holder, _iso_file, _) = build_iso(&iso_path, &image, true)?;
+    let _output = build_iso(&iso_path, &image, true)?;
     let mut file = File::open(&iso_path)?;
     let (efi_lba, efi_size) = resolve_efi_boot_flat(&mut file)?;
     assert!(efi_lba > 0);
@@ -459,7 +458,7 @@ fn test_choosable_can_resolve_efi_boot_in_flat_iso() -> io::Result<()> {
     let temp_dir = tempdir()?;
     let (bootx64_path, kernel_path, iso_path) = setup_integration_test_files(temp_dir.path())?;
     let image = make_test_iso_image(bootx64_path, kernel_path);
-    let (_iso_path_buf, _temp_holder, _iso_file, _) = build_iso(&iso_path, &image, false)?;
+    let _output = build_iso(&iso_path, &image, false)?;
     let mut file = File::open(&iso_path)?;
     let (efi_lba, efi_size) = resolve_efi_boot_flat(&mut file)?;
     assert!(efi_lba > 0);
@@ -522,12 +521,38 @@ fn test_build_premount_cpio_entry() {
     assert_eq!(blob[46], 0); // EOD
 }
 
+#[test]
+fn test_find_first_overwritable_file_in_efi_boot() -> io::Result<()> {
+    let temp_dir = tempdir()?;
+    let (bootx64_path, kernel_path, iso_path) = setup_integration_test_files(temp_dir.path())?;
+    let image = make_test_iso_image(bootx64_path, kernel_path);
+    let _output = build_iso(&iso_path, &image, true)?;
+    let mut file = File::open(&iso_path)?;
+    let pvd = read_file_iso_sector(&mut file, 16)?;
+    let root_lba = u32::from_le_bytes(pvd[158..162].try_into().unwrap());
+    let root_size = u32::from_le_bytes(pvd[166..170].try_into().unwrap());
+    let mut scratch = [0u8; ISO_SECTOR_SIZE];
+    let (efi_lba, efi_size) =
+        find_in_dir_flat(&mut file, root_lba, root_size, b"EFI", &mut scratch).unwrap();
+    let (boot_lba, boot_size) =
+        find_in_dir_flat(&mut file, efi_lba, efi_size, b"BOOT", &mut scratch).unwrap();
+    // BOOTX64.EFI is skipped; the kernel is the first file Choosable may
+    // overwrite.
+    let (sector, offset, upper, len) =
+        find_first_overwritable_file_sim(&mut file, boot_lba, boot_size, &mut scratch)
+            .expect("an overwritable file in /EFI/BOOT");
+    assert_eq!(&upper[..len], b"KERNEL.EFI");
+    let record = read_file_iso_sector(&mut file, sector as u64)?;
+    assert_eq!(&record[offset as usize + 33..][..len], b"KERNEL.EFI");
+    Ok(())
+}
+
 #[test]
 fn test_find_eod_in_isohybrid_root_dir() -> io::Result<()> {
     let temp_dir = tempdir()?;
     let (bootx64_path, kernel_path, iso_path) = setup_integration_test_files(temp_dir.path())?;
     let image = make_test_iso_image(bootx64_path, kernel_path);
-    let (_iso_path_buf, _temp_holder, _iso_file, _) = build_iso(&iso_path, &image, true)?;
+    let _output = build_iso(&iso_path, &image, true)?;
     let mut file = File::open(&iso_path)?;
     // isobemak writes the ISO9660 filesystem first, then overwrites
     // the file head with MBR+GPT via write_hybrid_structures.
@@ -558,19 +583,20 @@ fn test_pvd_must_be_locatable() -> io::Result<()> {
     let image = make_test_iso_image(bootx64_path, kernel_path);
     for isohybrid in [false, true] {
         let p = temp_dir.path().join(format!("pvd_{}.iso", isohybrid));
-        let (_a, _b, _c, _d) = build_iso(&p, &image, isohybrid)?;
+        let _output = build_iso(&p, &image, isohybrid)?;
         let mut file = File::open(&p)?;
         let mut found = false;
         for n in 0..64 {
-            if let Ok(s) = read_file_iso_sector(&mut file, 16 + n) {
-                if s[0] == 1 && &s[1..6] == b"CD001" {
-                    found = true;
-                    let vs = u32::from_le_bytes(s[80..84].try_into().unwrap());
-                    let rs = u32::from_le_bytes(s[166..170].try_into().unwrap());
-                    assert!(vs > 0);
-                    assert!(rs > 0);
-                    break;
-                }
+            if let Ok(s) = read_file_iso_sector(&mut file, 16 + n)
+                && s[0] == 1
+                && &s[1..6] == b"CD001"
+            {
+                found = true;
+                let vs = u32::from_le_bytes(s[80..84].try_into().unwrap());
+                let rs = u32::from_le_bytes(s[166..170].try_into().unwrap());
+                assert!(vs > 0);
+                assert!(rs > 0);
+                break;
             }
         }
         assert!(found, "PVD must be locatable (isohybrid={})", isohybrid);
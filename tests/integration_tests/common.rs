@@ -1,6 +1,7 @@
+use isobemak::iso::boot_catalog::validation_checksum;
 use std::{
     fs::File,
-    io::{self, Error, ErrorKind, Read, Seek, SeekFrom},
+    io::{self, Error, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
     process::Command,
 };
@@ -11,23 +12,34 @@ pub fn run_command(command: &str, args: &[&str]) -> io::Result<String> {
     if output.status.success() {
         Ok(String::from_utf8_lossy(&output.stdout).into_owned())
     } else {
-        Err(Error::new(
-            ErrorKind::Other,
-            format!(
-                "Command `{}` failed with exit code {:?}\nStdout: {}\nStderr: {}",
-                command,
-                output.status.code(),
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            ),
-        ))
+        Err(Error::other(format!(
+            "Command `{}` failed with exit code {:?}\nStdout: {}\nStderr: {}",
+            command,
+            output.status.code(),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        )))
     }
 }
 
+/// Writes a zero-padded minimal PE32+ EFI application of `size` bytes, which
+/// passes the loader check `build_iso` runs on isohybrid builds.
+pub fn write_efi_stub(path: &Path, size: usize) -> io::Result<()> {
+    let mut b = vec![0u8; size];
+    b[0..2].copy_from_slice(b"MZ");
+    b[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+    b[0x80..0x84].copy_from_slice(b"PE\0\0");
+    b[0x84..0x86].copy_from_slice(&0x8664u16.to_le_bytes()); // AMD64
+    b[0x94..0x96].copy_from_slice(&240u16.to_le_bytes()); // SizeOfOptionalHeader
+    b[0x98..0x9A].copy_from_slice(&0x20Bu16.to_le_bytes()); // PE32+
+    b[0xDC..0xDE].copy_from_slice(&10u16.to_le_bytes()); // EFI application
+    std::fs::write(path, b)
+}
+
 pub fn setup_integration_test_files(temp_dir: &Path) -> io::Result<(PathBuf, PathBuf, PathBuf)> {
     // Create dummy files needed for the ISO image
     let bootx64_path = temp_dir.join("bootx64.efi");
-    std::fs::write(&bootx64_path, vec![0u8; 64 * 1024])?;
+    write_efi_stub(&bootx64_path, 64 * 1024)?;
 
     let kernel_path = temp_dir.join("kernel.elf");
     std::fs::write(&kernel_path, vec![0u8; 16 * 1024])?;
@@ -73,13 +85,10 @@ pub fn verify_iso_binary_structures(iso_file: &mut File) -> io::Result<()> {
     let mut boot_catalog = [0u8; 32]; // Only need the validation entry
     iso_file.read_exact(&mut boot_catalog)?;
 
-    let mut sum: u16 = 0;
-    for chunk in boot_catalog.chunks_exact(2) {
-        sum = sum.wrapping_add(u16::from_le_bytes(chunk.try_into().unwrap()));
-    }
     assert_eq!(
-        sum, 0,
-        "Boot catalog validation entry checksum should be 0 (re-verification)"
+        validation_checksum(&boot_catalog),
+        u16::from_le_bytes([boot_catalog[28], boot_catalog[29]]),
+        "Boot catalog validation entry checksum should match (re-verification)"
     );
 
     Ok(())
@@ -9,6 +9,7 @@
 //! 4. Linux loop partition recognition (requires root, ignored)
 //! 5. blkid-based partition detection (requires root, ignored)
 
+use isobemak::iso::boot_catalog::validation_checksum;
 use std::{
     fs::File,
     io::{self, Read, Seek, SeekFrom},
@@ -43,16 +44,15 @@ fn build_test_iso() -> io::Result<(std::path::PathBuf, tempfile::TempDir)> {
             bios_boot: None,
             uefi_boot: Some(UefiBootInfo {
                 boot_image: temp_dir_path.join("bootx64.efi"),
-                kernel_image: temp_dir_path.join("kernel.elf"),
+                kernel_image: Some(temp_dir_path.join("kernel.elf")),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
-                additional_efi_boot_files: Vec::new(),
-                grub_cfg_content: None,
+                ..Default::default()
             }),
         },
         layout_profile: IsoLayoutProfile::default(),
     };
 
-    let (_fat_image_path, _temp_fat, _iso_file, _) = build_iso(&iso_path, &iso_image, true)?;
+    let _output = build_iso(&iso_path, &iso_image, true)?;
     assert!(iso_path.exists());
     Ok((iso_path, temp_dir))
 }
@@ -422,7 +422,7 @@ fn test_firmware_style_esp_discovery() -> io::Result<()> {
     iso_file.read_exact(&mut esp_data)?;
 
     let fs = FileSystem::new(std::io::Cursor::new(esp_data), FsOptions::new())
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("FAT mount failed: {:?}", e)))?;
+        .map_err(|e| io::Error::other(format!("FAT mount failed: {:?}", e)))?;
 
     let root = fs.root_dir();
     assert!(
@@ -549,11 +549,11 @@ fn test_ventoy_style_strict_parser() -> io::Result<()> {
     let mut catalog = [0u8; 32];
     iso_file.read_exact(&mut catalog)?;
 
-    let mut sum: u16 = 0;
-    for c in catalog.chunks_exact(2) {
-        sum = sum.wrapping_add(u16::from_le_bytes(c.try_into().unwrap()));
-    }
-    assert_eq!(sum, 0, "El Torito boot catalog checksum mismatch");
+    assert_eq!(
+        validation_checksum(&catalog),
+        u16::from_le_bytes([catalog[28], catalog[29]]),
+        "El Torito boot catalog checksum mismatch"
+    );
 
     println!("Ventoy-style strict parser PASSED");
     Ok(())
@@ -6,7 +6,7 @@ use std::{
 use isobemak::build_iso;
 use tempfile::tempdir;
 
-use crate::integration_tests::common::run_command;
+use crate::integration_tests::common::{run_command, write_efi_stub};
 
 /// Read PVD Volume Space Size (offset 80, 4 bytes LE + 4 bytes BE) from LBA 16.
 fn read_pvd_volume_space_size(file: &mut File) -> io::Result<u32> {
@@ -32,7 +32,7 @@ fn test_iso_integrity_and_boot_modes() -> io::Result<()> {
     std::fs::write(&bios_cfg_path, b"default menu.c32")?;
 
     let bootx64_path = temp_dir_path.join("bootx64.efi");
-    std::fs::write(&bootx64_path, vec![0u8; 64 * 1024])?;
+    write_efi_stub(&bootx64_path, 64 * 1024)?;
 
     let kernel_path = temp_dir_path.join("kernel.elf");
     std::fs::write(&kernel_path, vec![0u8; 16 * 1024])?;
@@ -59,13 +59,13 @@ fn test_iso_integrity_and_boot_modes() -> io::Result<()> {
             bios_boot: Some(isobemak::BiosBootInfo {
                 boot_image: bios_boot_image_path.clone(),
                 destination_in_iso: "isolinux/isolinux.bin".to_string(),
+                ..Default::default()
             }),
             uefi_boot: Some(isobemak::UefiBootInfo {
                 boot_image: bootx64_path.clone(),
-                kernel_image: kernel_path.clone(),
+                kernel_image: Some(kernel_path.clone()),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
-                additional_efi_boot_files: Vec::new(),
-                grub_cfg_content: None,
+                ..Default::default()
             }),
         },
         layout_profile: isobemak::IsoLayoutProfile::default(),
@@ -189,7 +189,7 @@ fn test_iso9660_volume_space_size_matches_file_size() -> io::Result<()> {
     let temp_dir_path = temp_dir.path();
 
     let bootx64_path = temp_dir_path.join("bootx64.efi");
-    std::fs::write(&bootx64_path, vec![0u8; 64 * 1024])?;
+    write_efi_stub(&bootx64_path, 64 * 1024)?;
 
     let kernel_path = temp_dir_path.join("kernel.elf");
     std::fs::write(&kernel_path, vec![0u8; 16 * 1024])?;
@@ -203,10 +203,9 @@ fn test_iso9660_volume_space_size_matches_file_size() -> io::Result<()> {
             bios_boot: None,
             uefi_boot: Some(isobemak::UefiBootInfo {
                 boot_image: bootx64_path.clone(),
-                kernel_image: kernel_path.clone(),
+                kernel_image: Some(kernel_path.clone()),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
-                additional_efi_boot_files: Vec::new(),
-                grub_cfg_content: None,
+                ..Default::default()
             }),
         },
         layout_profile: isobemak::IsoLayoutProfile::default(),
@@ -262,7 +261,7 @@ fn test_efi_fat_image_validation() -> io::Result<()> {
     let temp_dir_path = temp_dir.path();
 
     let bootx64_path = temp_dir_path.join("bootx64.efi");
-    std::fs::write(&bootx64_path, vec![0u8; 64 * 1024])?;
+    write_efi_stub(&bootx64_path, 64 * 1024)?;
     let kernel_path = temp_dir_path.join("kernel.elf");
     std::fs::write(&kernel_path, vec![0u8; 16 * 1024])?;
 
@@ -276,10 +275,9 @@ fn test_efi_fat_image_validation() -> io::Result<()> {
             bios_boot: None,
             uefi_boot: Some(isobemak::UefiBootInfo {
                 boot_image: bootx64_path.clone(),
-                kernel_image: kernel_path.clone(),
+                kernel_image: Some(kernel_path.clone()),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
-                additional_efi_boot_files: Vec::new(),
-                grub_cfg_content: None,
+                ..Default::default()
             }),
         },
         layout_profile: isobemak::IsoLayoutProfile::default(),
@@ -301,7 +299,7 @@ fn test_efi_fat_image_validation() -> io::Result<()> {
             extracted_img.to_str().unwrap(),
         ],
     );
-    if let Err(_) = extract {
+    if extract.is_err() {
         // xorriso may refuse extraction with "Detected El-Torito boot information
         // which currently is set to be discarded".  Try with -abort_on NEVER.
         run_command(
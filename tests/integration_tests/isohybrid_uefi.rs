@@ -1,3 +1,4 @@
+use isobemak::iso::boot_catalog::validation_checksum;
 use std::{
     fs::File,
     io::{self, Read, Seek, SeekFrom},
@@ -13,8 +14,7 @@ use crate::integration_tests::common::{
 
 fn verify_fat_image_has_file(fat_img_path: &std::path::Path, fat_path: &str) -> io::Result<()> {
     let fat_file = File::open(fat_img_path)?;
-    let fs = FileSystem::new(fat_file, FsOptions::new())
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let fs = FileSystem::new(fat_file, FsOptions::new()).map_err(io::Error::other)?;
     let root_dir = fs.root_dir();
     // fatfs uses "/" as path separator
     root_dir.open_file(fat_path).map_err(|e| {
@@ -51,10 +51,9 @@ fn test_create_isohybrid_uefi_iso() -> io::Result<()> {
             bios_boot: None,
             uefi_boot: Some(UefiBootInfo {
                 boot_image: bootx64_path.clone(),
-                kernel_image: kernel_path.clone(),
+                kernel_image: Some(kernel_path.clone()),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
-                additional_efi_boot_files: Vec::new(),
-                grub_cfg_content: None,
+                ..Default::default()
             }),
         },
         layout_profile: IsoLayoutProfile::default(),
@@ -67,8 +66,7 @@ fn test_create_isohybrid_uefi_iso() -> io::Result<()> {
     {
         // Drop returned handles before verification so the OS flushes
         // GPT/MBR structures written via write_hybrid_structures.
-        let (_fat_image_path, _temp_fat, _iso_file, _logical_size) =
-            build_iso(&iso_path, &iso_image, true)?;
+        let _output = build_iso(&iso_path, &iso_image, true)?;
     }
     assert!(iso_path.exists());
 
@@ -241,12 +239,11 @@ fn test_create_isohybrid_uefi_iso() -> io::Result<()> {
     let mut boot_catalog = [0u8; 32]; // Only need the validation entry
     iso_file.read_exact(&mut boot_catalog)?;
 
-    let mut sum: u16 = 0;
-    for chunk in boot_catalog.chunks_exact(2) {
-        sum = sum.wrapping_add(u16::from_le_bytes(chunk.try_into().unwrap()));
-    }
-
-    assert_eq!(sum, 0, "Boot catalog validation entry checksum should be 0");
+    assert_eq!(
+        validation_checksum(&boot_catalog),
+        u16::from_le_bytes([boot_catalog[28], boot_catalog[29]]),
+        "Boot catalog validation entry checksum should match"
+    );
 
     // Perform deeper binary verification of ISO structures
     verify_iso_binary_structures(&mut iso_file)?;
@@ -288,16 +285,16 @@ fn test_create_isohybrid_with_additional_efi_files() -> io::Result<()> {
             bios_boot: None,
             uefi_boot: Some(UefiBootInfo {
                 boot_image: bootx64_path.clone(),
-                kernel_image: kernel_path.clone(),
+                kernel_image: Some(kernel_path.clone()),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
                 additional_efi_boot_files: vec![("GRUBX64.EFI".to_string(), grub_path.clone())],
-                grub_cfg_content: None,
+                ..Default::default()
             }),
         },
         layout_profile: IsoLayoutProfile::default(),
     };
 
-    let (_iso_path_buf, temp_holder, _iso_file, _) = build_iso(&iso_path, &iso_image, true)?;
+    let temp_holder = build_iso(&iso_path, &iso_image, true)?.esp;
     assert!(iso_path.exists());
 
     // Get the actual FAT image path from the NamedTempFile holder.
@@ -355,16 +352,16 @@ menuentry "Kernel" {
             bios_boot: None,
             uefi_boot: Some(UefiBootInfo {
                 boot_image: bootx64_path.clone(),
-                kernel_image: kernel_path.clone(),
+                kernel_image: Some(kernel_path.clone()),
                 destination_in_iso: "EFI/BOOT/BOOTX64.EFI".to_string(),
-                additional_efi_boot_files: Vec::new(),
                 grub_cfg_content: Some(grub_config.to_string()),
+                ..Default::default()
             }),
         },
         layout_profile: IsoLayoutProfile::default(),
     };
 
-    let (_iso_path_buf, temp_holder, _iso_file, _) = build_iso(&iso_path, &iso_image, true)?;
+    let temp_holder = build_iso(&iso_path, &iso_image, true)?.esp;
     assert!(iso_path.exists());
 
     let fat_img_path = temp_holder.as_ref().unwrap().path().to_path_buf();
@@ -374,16 +371,15 @@ menuentry "Kernel" {
     verify_fat_image_has_file(&fat_img_path, "EFI/BOOT/grub.cfg")?;
     // Verify the content of grub.cfg
     let fat_file = File::open(&fat_img_path)?;
-    let fs = FileSystem::new(fat_file, FsOptions::new())
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let fs = FileSystem::new(fat_file, FsOptions::new()).map_err(io::Error::other)?;
     let root_dir = fs.root_dir();
     let mut grub_file = root_dir
         .open_file("EFI/BOOT/grub.cfg")
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        .map_err(io::Error::other)?;
     let mut content = String::new();
     grub_file
         .read_to_string(&mut content)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        .map_err(io::Error::other)?;
     assert!(
         content.contains("Boot from ISO"),
         "grub.cfg content mismatch"
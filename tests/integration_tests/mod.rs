@@ -1,4 +1,6 @@
 pub mod basic_iso;
+#[cfg(feature = "qemu-tests")]
+pub mod boot_test;
 pub mod choosable_simulation;
 pub mod common;
 pub mod firmware_simulation;